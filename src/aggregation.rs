@@ -0,0 +1,440 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    completion_marker::SessionStatus,
+    object_store::ObjectStore,
+    upload::UploadOptions,
+};
+use anyhow::Result;
+use slog_scope::{
+    error,
+    info,
+};
+use std::{
+    fmt,
+    mem,
+    sync::{
+        Arc,
+        Mutex,
+    },
+    thread,
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+use tokio::runtime::Runtime;
+
+/// Size of the blocks a tar archive consists of.
+const TAR_BLOCK_SIZE: usize = 512;
+/// Maximum length of a name in a plain ustar header, longer names use a PAX extended header.
+const TAR_NAME_MAXIMUM_LENGTH: usize = 100;
+
+/// When small files are aggregated, and when the aggregate is written to S3.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct AggregationOptions {
+    /// Files up to this size are aggregated, larger files are uploaded as individual objects.
+    pub(crate) max_file_size: u64,
+    /// Write the aggregate once it reaches this size.
+    pub(crate) flush_size: u64,
+    /// Write the aggregate at least this often, if it is not empty.
+    pub(crate) flush_interval: Duration,
+}
+
+#[derive(Default)]
+struct Aggregate {
+    archive: Vec<u8>,
+    files: u64,
+}
+
+/// Concatenates small files into tar archives, which are written to S3 as a single object.
+pub(crate) struct Aggregator {
+    s3: Arc<dyn ObjectStore>,
+    bucket: String,
+    prefix: Option<String>,
+    options: AggregationOptions,
+    upload_options: UploadOptions,
+    session_status: Arc<SessionStatus>,
+    aggregate: Mutex<Aggregate>,
+}
+
+impl fmt::Debug for Aggregator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Aggregator")
+            .field("bucket", &self.bucket)
+            .field("prefix", &self.prefix)
+            .field("options", &self.options)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Aggregator {
+    pub(crate) fn new(
+        s3: Arc<dyn ObjectStore>,
+        bucket: String,
+        prefix: Option<String>,
+        options: AggregationOptions,
+        upload_options: UploadOptions,
+        session_status: Arc<SessionStatus>,
+    ) -> Aggregator {
+        Aggregator {
+            s3,
+            bucket,
+            prefix,
+            options,
+            upload_options,
+            session_status,
+            aggregate: Mutex::new(Aggregate::default()),
+        }
+    }
+
+    /// Files up to this size are aggregated.
+    pub(crate) fn max_file_size(&self) -> u64 {
+        self.options.max_file_size
+    }
+
+    /// Append a file to the aggregate, writing the aggregate to S3 if it reached the flush size and
+    /// uploads are allowed right now.
+    ///
+    /// The file is kept in the aggregate even if writing it fails, such that it is written with the
+    /// next flush.
+    pub(crate) fn append(&self, name: &str, mtime: SystemTime, content: &[u8]) -> Result<()> {
+        let flush = {
+            let mut aggregate = self
+                .aggregate
+                .lock()
+                .map_err(|_| anyhow::anyhow!("failed to lock aggregate"))?;
+            aggregate
+                .archive
+                .extend_from_slice(&tar_entry(name, mtime, content));
+            aggregate.files += 1;
+            aggregate.archive.len() as u64 >= self.options.flush_size
+        };
+        // While uploads are held back, the aggregate grows until the periodic flush writes it.
+        if flush && self.upload_options.uploads_allowed() {
+            if let Err(error) = self.flush() {
+                error!("Failed to upload aggregate of small files, keeping it for the next flush";
+                       "error" => format!("{:#}", error));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the aggregate to S3, if it is not empty, once uploads are allowed.
+    ///
+    /// If writing it fails, the aggregate is put back, such that it is written with the next flush.
+    pub(crate) fn flush(&self) -> Result<()> {
+        let Aggregate { mut archive, files } = mem::take(
+            &mut *self
+                .aggregate
+                .lock()
+                .map_err(|_| anyhow::anyhow!("failed to lock aggregate"))?,
+        );
+        if files == 0 {
+            return Ok(());
+        }
+        // Two empty blocks mark the end of the archive.
+        archive.resize(archive.len() + 2 * TAR_BLOCK_SIZE, 0);
+
+        let key = self.key();
+        let result = Runtime::new()
+            .map_err(anyhow::Error::from)
+            .and_then(|runtime| {
+                self.upload_options
+                    .wait_until_uploads_allowed(&runtime, &key);
+                runtime.block_on(self.upload_options.retry.retry("PutObject", || {
+                    let mut request = self.upload_options.put_object_request(
+                        self.bucket.clone(),
+                        key.clone(),
+                        archive.clone(),
+                    );
+                    request.content_type = Some("application/x-tar".to_owned());
+                    self.s3.put_object(request)
+                }))
+            });
+        if let Err(error) = result {
+            archive.truncate(archive.len() - 2 * TAR_BLOCK_SIZE);
+            self.put_back(archive, files);
+            return Err(error);
+        }
+        for _ in 0..files {
+            self.session_status.upload_succeeded();
+        }
+        info!("Uploaded aggregate of small files: {}", key; "files" => files);
+
+        Ok(())
+    }
+
+    /// Write the remaining aggregate to S3 when the filesystem is unmounted. Files that can't be
+    /// written are counted as failed uploads, since there is no next flush.
+    pub(crate) fn finish(&self) -> Result<()> {
+        let result = self.flush();
+        if let Err(error) = &result {
+            let files = self.aggregate.lock().map_or(0, |aggregate| aggregate.files);
+            for _ in 0..files {
+                self.session_status.upload_failed_with(error);
+            }
+        }
+
+        result
+    }
+
+    /// Put an `archive` of `files` that couldn't be written back in front of the aggregate.
+    fn put_back(&self, mut archive: Vec<u8>, files: u64) {
+        match self.aggregate.lock() {
+            Ok(mut aggregate) => {
+                archive.append(&mut aggregate.archive);
+                aggregate.archive = archive;
+                aggregate.files += files;
+            }
+            Err(_) => {
+                error!("Failed to put back aggregate of small files, they are lost";
+                       "files" => files);
+                for _ in 0..files {
+                    self.session_status.upload_failed();
+                }
+            }
+        }
+    }
+
+    /// Write the aggregate to S3 every flush interval.
+    pub(crate) fn flush_periodically(self: &Arc<Self>) -> Result<()> {
+        let aggregator = Arc::clone(self);
+        thread::Builder::new()
+            .name("aggregation-flush".to_owned())
+            .spawn(move || loop {
+                thread::sleep(aggregator.options.flush_interval);
+                if let Err(error) = aggregator.flush() {
+                    error!("Failed to upload aggregate of small files"; "error" => %error);
+                }
+            })?;
+
+        Ok(())
+    }
+
+    /// A unique key for the next aggregate.
+    fn key(&self) -> String {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let name = format!(
+            "aggregate-{}-{:09}.tar",
+            timestamp.as_secs(),
+            timestamp.subsec_nanos()
+        );
+        match &self.prefix {
+            Some(prefix) => [prefix.as_str(), &name].join("/"),
+            None => name,
+        }
+    }
+}
+
+/// A single file in a tar archive, i.e. its header followed by its content padded to full blocks.
+///
+/// Names longer than a plain ustar header allows are stored in a PAX extended header.
+fn tar_entry(name: &str, mtime: SystemTime, content: &[u8]) -> Vec<u8> {
+    let mtime = mtime
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut entry = vec![];
+    if name.len() > TAR_NAME_MAXIMUM_LENGTH {
+        let record = pax_record("path", name);
+        entry.extend_from_slice(&tar_header(
+            b"././@PaxHeader",
+            record.len() as u64,
+            mtime,
+            b'x',
+        ));
+        append_padded(&mut entry, record.as_bytes());
+    }
+    let truncated_name = &name.as_bytes()[..name.len().min(TAR_NAME_MAXIMUM_LENGTH)];
+    entry.extend_from_slice(&tar_header(
+        truncated_name,
+        content.len() as u64,
+        mtime,
+        b'0',
+    ));
+    append_padded(&mut entry, content);
+    entry
+}
+
+fn tar_header(name: &[u8], size: u64, mtime: u64, typeflag: u8) -> [u8; TAR_BLOCK_SIZE] {
+    let mut header = [0; TAR_BLOCK_SIZE];
+    header[..name.len()].copy_from_slice(name);
+    write_octal(&mut header[100..108], 0o644);
+    write_octal(&mut header[108..116], 0);
+    write_octal(&mut header[116..124], 0);
+    write_octal(&mut header[124..136], size);
+    write_octal(&mut header[136..148], mtime);
+    header[156] = typeflag;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    // The checksum is calculated with the checksum field itself filled with spaces.
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = header.iter().map(|&byte| u32::from(byte)).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    header
+}
+
+/// Write `value` as zero-padded octal number, terminated by a NUL byte.
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    field.copy_from_slice(format!("{:0digits$o}\0", value, digits = digits).as_bytes());
+}
+
+/// A PAX extended header record, `"<length> <key>=<value>\n"`, where the length includes itself.
+fn pax_record(key: &str, value: &str) -> String {
+    let without_length = format!(" {}={}\n", key, value);
+    let mut length = without_length.len();
+    while (length.to_string().len() + without_length.len()) != length {
+        length = length.to_string().len() + without_length.len();
+    }
+    format!("{}{}", length, without_length)
+}
+
+fn append_padded(archive: &mut Vec<u8>, data: &[u8]) {
+    archive.extend_from_slice(data);
+    let padding = (TAR_BLOCK_SIZE - data.len() % TAR_BLOCK_SIZE) % TAR_BLOCK_SIZE;
+    archive.resize(archive.len() + padding, 0);
+}
+
+#[test]
+fn files_are_framed_as_tar_entries() {
+    let mtime = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let entry = tar_entry("metrics.json", mtime, b"{}");
+
+    assert_eq!(entry.len(), 2 * TAR_BLOCK_SIZE);
+    assert_eq!(&entry[..13], b"metrics.json\0");
+    assert_eq!(&entry[124..136], b"00000000002\0");
+    assert_eq!(&entry[136..148], b"14524770400\0");
+    assert_eq!(entry[156], b'0');
+    assert_eq!(&entry[257..263], b"ustar\0");
+    let checksum = std::str::from_utf8(&entry[148..154]).unwrap();
+    let expected: u32 = entry[..TAR_BLOCK_SIZE]
+        .iter()
+        .enumerate()
+        .map(|(index, &byte)| match index {
+            148..=155 => u32::from(b' '),
+            _ => u32::from(byte),
+        })
+        .sum();
+    assert_eq!(u32::from_str_radix(checksum, 8).unwrap(), expected);
+    assert_eq!(&entry[TAR_BLOCK_SIZE..TAR_BLOCK_SIZE + 2], b"{}");
+}
+
+#[test]
+fn long_names_use_pax_headers() {
+    let name = "a".repeat(150);
+    let entry = tar_entry(&name, UNIX_EPOCH, b"");
+
+    assert_eq!(entry.len(), 3 * TAR_BLOCK_SIZE);
+    assert_eq!(entry[156], b'x');
+    let record = format!("160 path={}\n", name);
+    assert_eq!(
+        &entry[TAR_BLOCK_SIZE..TAR_BLOCK_SIZE + record.len()],
+        record.as_bytes()
+    );
+    assert_eq!(entry[2 * TAR_BLOCK_SIZE + 156], b'0');
+}
+
+#[test]
+fn aggregates_are_flushed_at_the_flush_size() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let s3 = Arc::new(MockObjectStore::default());
+    let session_status = Arc::new(SessionStatus::default());
+    let aggregator = Aggregator::new(
+        s3.clone(),
+        "my-bucket".to_owned(),
+        Some("prefix".to_owned()),
+        AggregationOptions {
+            max_file_size: 1024,
+            flush_size: 4 * TAR_BLOCK_SIZE as u64,
+            flush_interval: Duration::from_secs(60),
+        },
+        UploadOptions::default(),
+        Arc::clone(&session_status),
+    );
+
+    aggregator.append("first", UNIX_EPOCH, b"first").unwrap();
+    assert!(s3.calls().is_empty());
+    aggregator.append("second", UNIX_EPOCH, b"second").unwrap();
+    aggregator.flush().unwrap();
+
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::PutObject(put_object, body)] => {
+            assert!(put_object.key.starts_with("prefix/aggregate-"));
+            assert!(put_object.key.ends_with(".tar"));
+            assert_eq!(body.len(), 6 * TAR_BLOCK_SIZE);
+            assert_eq!(&body[..6], b"first\0");
+            assert_eq!(
+                &body[2 * TAR_BLOCK_SIZE..2 * TAR_BLOCK_SIZE + 7],
+                b"second\0"
+            );
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn aggregates_are_kept_if_they_cannot_be_written() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let s3 = Arc::new(MockObjectStore::default());
+    let session_status = Arc::new(SessionStatus::default());
+    let aggregator = Aggregator::new(
+        s3.clone(),
+        "my-bucket".to_owned(),
+        None,
+        AggregationOptions {
+            max_file_size: 1024,
+            flush_size: 2 * TAR_BLOCK_SIZE as u64,
+            flush_interval: Duration::from_secs(60),
+        },
+        UploadOptions::default(),
+        Arc::clone(&session_status),
+    );
+
+    s3.reject_puts(1);
+    aggregator.append("first", UNIX_EPOCH, b"first").unwrap();
+    assert!(s3.calls().is_empty());
+    aggregator.append("second", UNIX_EPOCH, b"second").unwrap();
+
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::PutObject(_, body)] => {
+            assert_eq!(body.len(), 6 * TAR_BLOCK_SIZE);
+            assert_eq!(&body[..6], b"first\0");
+            assert_eq!(
+                &body[2 * TAR_BLOCK_SIZE..2 * TAR_BLOCK_SIZE + 7],
+                b"second\0"
+            );
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+    assert_eq!(0, session_status.exit_code());
+}
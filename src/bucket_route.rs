@@ -0,0 +1,154 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{
+    anyhow,
+    bail,
+    Result,
+};
+use std::str::FromStr;
+
+/// Uploads files matching a condition to a different bucket, given as `<condition>=<bucket>`.
+///
+/// Supported conditions are:
+///
+/// * `ext:<extension>[,<extension>...]`: the file name ends in one of the extensions, ignoring
+///   case,
+/// * `prefix:<prefix>`: the key, including the prefix of the mount, starts with `<prefix>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BucketRoute {
+    condition: Condition,
+    bucket: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Condition {
+    Extension(Vec<String>),
+    Prefix(String),
+}
+
+impl Condition {
+    fn matches(&self, key: &str) -> bool {
+        match self {
+            Condition::Extension(extensions) => {
+                let filename = key.rsplit('/').next().unwrap_or(key);
+                match filename.rsplit_once('.') {
+                    Some((stem, extension)) if !stem.is_empty() => extensions
+                        .iter()
+                        .any(|candidate| candidate.eq_ignore_ascii_case(extension)),
+                    _ => false,
+                }
+            }
+            Condition::Prefix(prefix) => key.starts_with(prefix.as_str()),
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, argument) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("condition must be given as kind:argument"))?;
+        match kind {
+            "ext" => {
+                let extensions: Vec<_> = argument
+                    .split(',')
+                    .map(|extension| extension.trim().trim_start_matches('.').to_owned())
+                    .collect();
+                if extensions.iter().any(String::is_empty) {
+                    bail!("empty extension in condition '{}'", s);
+                }
+                Ok(Condition::Extension(extensions))
+            }
+            "prefix" if !argument.is_empty() => Ok(Condition::Prefix(argument.to_owned())),
+            "prefix" => bail!("empty prefix in condition '{}'", s),
+            _ => bail!("unknown condition '{}', expected ext or prefix", kind),
+        }
+    }
+}
+
+impl FromStr for BucketRoute {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Bucket names can't contain `=`, but prefixes can.
+        let (condition, bucket) = s
+            .rsplit_once('=')
+            .ok_or_else(|| anyhow!("bucket route must be given as condition=bucket"))?;
+        if bucket.is_empty() {
+            bail!("empty bucket in route '{}'", s);
+        }
+
+        Ok(BucketRoute {
+            condition: condition.parse()?,
+            bucket: bucket.to_owned(),
+        })
+    }
+}
+
+/// The bucket of the first route matching `key`, if any.
+pub(crate) fn route<'a>(routes: &'a [BucketRoute], key: &str) -> Option<&'a str> {
+    routes
+        .iter()
+        .find(|route| route.condition.matches(key))
+        .map(|route| route.bucket.as_str())
+}
+
+#[test]
+fn routes_are_parsed() {
+    assert_eq!(
+        BucketRoute {
+            condition: Condition::Extension(vec!["mp4".to_owned(), "mkv".to_owned()]),
+            bucket: "videos".to_owned(),
+        },
+        "ext:.mp4, mkv=videos".parse().unwrap()
+    );
+    assert_eq!(
+        BucketRoute {
+            condition: Condition::Prefix("a=b/".to_owned()),
+            bucket: "other".to_owned(),
+        },
+        "prefix:a=b/=other".parse().unwrap()
+    );
+    for invalid in [
+        "videos",
+        "ext:mp4=",
+        "ext:=videos",
+        "ext:mp4,=videos",
+        "prefix:=videos",
+        "size:1G=videos",
+    ] {
+        assert!(invalid.parse::<BucketRoute>().is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn first_matching_route_wins() {
+    let routes: Vec<BucketRoute> = ["ext:MP4=videos", "prefix:archive/=archive", "ext:log=logs"]
+        .iter()
+        .map(|route| route.parse().unwrap())
+        .collect();
+
+    assert_eq!(Some("videos"), route(&routes, "archive/clip.mp4"));
+    assert_eq!(Some("archive"), route(&routes, "archive/app.log"));
+    assert_eq!(Some("logs"), route(&routes, "app.log"));
+    assert_eq!(None, route(&routes, "report.pdf"));
+    // Neither hidden files nor directories have an extension.
+    assert_eq!(None, route(&routes, ".mp4"));
+    assert_eq!(None, route(&routes, "clip.mp4/file"));
+}
@@ -0,0 +1,150 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{
+    anyhow,
+    Context,
+    Result,
+};
+use rusoto_s3::{
+    HeadBucketRequest,
+    S3Client,
+    S3,
+};
+use std::{
+    env,
+    fs,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+use tokio::runtime::Runtime;
+
+/// Nagios plugin exit code for a successful check.
+const OK: i32 = 0;
+/// Nagios plugin exit code for a failed check.
+const CRITICAL: i32 = 2;
+
+const MOUNT_TYPE: &str = "fuse.s3wofs";
+
+/// Check that the mountpoint is an active s3-write-only-fs mount and that its bucket is reachable.
+///
+/// Prints a one-line status and returns the exit code following the Nagios plugin conventions.
+pub(crate) fn run(s3: &S3Client, mountpoint: &Path) -> i32 {
+    match check(s3, mountpoint) {
+        Ok(status) => {
+            println!("OK - {}", status);
+            OK
+        }
+        Err(error) => {
+            println!("CRITICAL - {:#}", error);
+            CRITICAL
+        }
+    }
+}
+
+fn check(s3: &S3Client, mountpoint: &Path) -> Result<String> {
+    // Don't canonicalize the mountpoint, since that would access the (possibly hanging) mount.
+    let mountpoint = if mountpoint.is_absolute() {
+        mountpoint.to_owned()
+    } else {
+        env::current_dir()?.join(mountpoint)
+    };
+    let mounts = fs::read_to_string("/proc/self/mounts").context("failed to read mount table")?;
+    let bucket = mounted_bucket(&mounts, &mountpoint)
+        .ok_or_else(|| anyhow!("{} is not mounted as {}", mountpoint.display(), MOUNT_TYPE))?;
+
+    Runtime::new()?
+        .block_on(s3.head_bucket(HeadBucketRequest {
+            bucket: bucket.clone(),
+            ..Default::default()
+        }))
+        .with_context(|| format!("bucket '{}' is not reachable", bucket))?;
+
+    Ok(format!(
+        "{} is mounted from bucket '{}', which is reachable",
+        mountpoint.display(),
+        bucket
+    ))
+}
+
+/// Find the bucket mounted at `mountpoint` in the mount table.
+///
+/// If multiple filesystems are mounted on top of each other, only the last one is visible, so it
+/// has to be ours.
+fn mounted_bucket(mounts: &str, mountpoint: &Path) -> Option<String> {
+    let (fsname, mount_type) = mounts
+        .lines()
+        .rev()
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let fsname = fields.next()?;
+            let dir = fields.next()?;
+            let mount_type = fields.next()?;
+            Some((fsname, unescape(dir), mount_type))
+        })
+        .filter(|(_, dir, _)| dir == mountpoint)
+        .map(|(fsname, _, mount_type)| (fsname, mount_type))
+        .next()?;
+
+    if mount_type == MOUNT_TYPE {
+        Some(unescape(fsname).to_string_lossy().into_owned())
+    } else {
+        None
+    }
+}
+
+/// Undo the octal escaping of whitespace and backslashes in the mount table.
+fn unescape(field: &str) -> PathBuf {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(index) = rest.find('\\') {
+        unescaped.push_str(&rest[..index]);
+        let escaped = rest.get(index + 1..index + 4);
+        match escaped.and_then(|octal| u8::from_str_radix(octal, 8).ok()) {
+            Some(byte) => {
+                unescaped.push(byte as char);
+                rest = &rest[index + 4..];
+            }
+            None => {
+                unescaped.push('\\');
+                rest = &rest[index + 1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+
+    PathBuf::from(unescaped)
+}
+
+#[test]
+fn mounted_bucket_is_found() {
+    let mounts = "\
+proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0
+my-bucket /mnt/upload\\040here fuse.s3wofs rw,nosuid,nodev,relatime,user_id=0,group_id=0 0 0
+other-bucket /mnt/shadowed fuse.s3wofs rw,relatime 0 0
+tmpfs /mnt/shadowed tmpfs rw,relatime 0 0
+";
+
+    assert_eq!(
+        Some("my-bucket".to_owned()),
+        mounted_bucket(mounts, Path::new("/mnt/upload here"))
+    );
+    assert_eq!(None, mounted_bucket(mounts, Path::new("/mnt/shadowed")));
+    assert_eq!(None, mounted_bucket(mounts, Path::new("/proc")));
+    assert_eq!(None, mounted_bucket(mounts, Path::new("/mnt")));
+}
@@ -0,0 +1,81 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::content_hash::{
+    to_hex,
+    ContentHash,
+};
+use clap::ValueEnum;
+
+/// What happens if the checksum sidecar can't be written, while the object itself was uploaded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum SidecarFailurePolicy {
+    /// Report the upload as failed, keeping the object.
+    #[default]
+    #[value(name = "fail")]
+    Fail,
+    /// Delete the object again and report the upload as failed, such that no object exists
+    /// without its sidecar.
+    #[value(name = "delete")]
+    Delete,
+    /// Only log a warning and report the upload as successful.
+    #[value(name = "warn")]
+    Warn,
+}
+
+/// A small object written next to every uploaded object, containing the SHA-256 digest of its
+/// content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ChecksumSidecar {
+    /// Appended to the key of the object to get the key of its sidecar, e.g. `.sha256`.
+    pub(crate) suffix: String,
+    pub(crate) on_failure: SidecarFailurePolicy,
+}
+
+impl ChecksumSidecar {
+    pub(crate) fn key(&self, key: &str) -> String {
+        format!("{}{}", key, self.suffix)
+    }
+
+    /// The content of the sidecar of the object at `key`, in the format of `sha256sum`, such that
+    /// a downloaded object can be verified with `sha256sum -c`.
+    pub(crate) fn body(&self, key: &str, hash: &ContentHash) -> Vec<u8> {
+        let filename = key.rsplit('/').next().unwrap_or(key);
+        format!("{}  {}\n", to_hex(hash), filename).into_bytes()
+    }
+}
+
+#[test]
+fn sidecar_is_compatible_with_sha256sum() {
+    use crate::content_hash::ContentHasher;
+
+    let sidecar = ChecksumSidecar {
+        suffix: ".sha256".to_owned(),
+        on_failure: SidecarFailurePolicy::Fail,
+    };
+    let mut hasher = ContentHasher::default();
+    hasher.update(b"content");
+    let (hash, _) = hasher.finalize();
+
+    assert_eq!(
+        "uploads/report.pdf.sha256",
+        sidecar.key("uploads/report.pdf")
+    );
+    assert_eq!(
+        "ed7002b439e9ac845f22357d822bac1444730fbdb6016d3ec9432297b9ec9f73  report.pdf\n",
+        String::from_utf8(sidecar.body("uploads/report.pdf", &hash)).unwrap()
+    );
+}
@@ -0,0 +1,231 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    errno,
+    object_store::ObjectStore,
+    signals,
+    upload::UploadOptions,
+};
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Serialize;
+use slog_scope::{
+    error,
+    info,
+    warn,
+};
+use std::sync::{
+    atomic::{
+        AtomicU64,
+        Ordering,
+    },
+    Arc,
+};
+use tokio::runtime::Runtime;
+
+/// When to write the completion marker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum CompletionMarkerPolicy {
+    /// Only write the marker if no upload failed during the session.
+    #[value(name = "on-success")]
+    OnSuccess,
+    /// Always write the marker, with its status indicating whether any upload failed.
+    #[value(name = "always")]
+    Always,
+}
+
+/// Exit code if all uploads of the session succeeded.
+const EXIT_SUCCESS: i32 = 0;
+/// Exit code if uploads failed because the credentials were missing, invalid or not permitted.
+const EXIT_AUTH_FAILURE: i32 = 3;
+/// Exit code if uploads failed because S3 was unreachable.
+const EXIT_NETWORK_FAILURE: i32 = 4;
+/// Exit code if some uploads failed for other reasons, while others succeeded.
+const EXIT_PARTIAL_SUCCESS: i32 = 5;
+/// Exit code if all uploads failed for other reasons.
+const EXIT_FAILURE: i32 = 6;
+
+/// Counts the uploads of the current session.
+#[derive(Debug, Default)]
+pub(crate) struct SessionStatus {
+    uploaded: AtomicU64,
+    failed: AtomicU64,
+    auth_failed: AtomicU64,
+    network_failed: AtomicU64,
+}
+
+impl SessionStatus {
+    pub(crate) fn upload_succeeded(&self) {
+        self.uploaded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn upload_failed(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count an upload that failed because of `error`, remembering whether it was caused by the
+    /// credentials or by S3 being unreachable.
+    pub(crate) fn upload_failed_with(&self, error: &anyhow::Error) {
+        self.upload_failed();
+        if errno::is_auth_failure(error) {
+            self.auth_failed.fetch_add(1, Ordering::Relaxed);
+        } else if errno::is_unreachable(error) {
+            self.network_failed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The exit code reflecting the outcome of the uploads of the session.
+    ///
+    /// Authentication failures take precedence over network failures, which take precedence over
+    /// any other failure.
+    pub(crate) fn exit_code(&self) -> i32 {
+        if self.failed.load(Ordering::Relaxed) == 0 {
+            EXIT_SUCCESS
+        } else if self.auth_failed.load(Ordering::Relaxed) > 0 {
+            EXIT_AUTH_FAILURE
+        } else if self.network_failed.load(Ordering::Relaxed) > 0 {
+            EXIT_NETWORK_FAILURE
+        } else if self.uploaded.load(Ordering::Relaxed) > 0 {
+            EXIT_PARTIAL_SUCCESS
+        } else {
+            EXIT_FAILURE
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Marker {
+    status: &'static str,
+    uploaded: u64,
+    failed: u64,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    session_id: String,
+}
+
+/// A small object signaling downstream consumers that the session has finished.
+pub(crate) struct CompletionMarker {
+    pub(crate) s3: Arc<dyn ObjectStore>,
+    pub(crate) bucket: String,
+    pub(crate) key: String,
+    pub(crate) policy: CompletionMarkerPolicy,
+    pub(crate) upload_options: UploadOptions,
+    pub(crate) session_status: Arc<SessionStatus>,
+}
+
+impl CompletionMarker {
+    /// Write the marker, unless the policy forbids it because an upload failed.
+    pub(crate) fn write(&self) -> Result<()> {
+        let marker = Marker {
+            status: "success",
+            uploaded: self.session_status.uploaded.load(Ordering::Relaxed),
+            failed: self.session_status.failed.load(Ordering::Relaxed),
+            session_id: self.upload_options.metadata.session_id().to_owned(),
+        };
+        let marker = if marker.failed == 0 {
+            marker
+        } else if self.policy == CompletionMarkerPolicy::Always {
+            Marker {
+                status: "failure",
+                ..marker
+            }
+        } else {
+            warn!(
+                "Not writing completion marker '{}', since {} uploads failed",
+                self.key, marker.failed
+            );
+            return Ok(());
+        };
+
+        let mut request = self.upload_options.put_object_request(
+            self.bucket.clone(),
+            self.key.clone(),
+            serde_json::to_vec(&marker)?,
+        );
+        request.content_type = Some("application/json".to_owned());
+        Runtime::new()?.block_on(self.s3.put_object(request))?;
+        info!("Wrote completion marker '{}'", self.key;
+              "status" => marker.status,
+              "uploaded" => marker.uploaded,
+              "failed" => marker.failed);
+
+        Ok(())
+    }
+
+    /// Write the marker whenever the process receives SIGUSR2.
+    pub(crate) fn write_on_signal(self: &Arc<Self>) -> Result<()> {
+        let completion_marker = Arc::clone(self);
+        signals::spawn_handler("completion-marker", &[libc::SIGUSR2], move |_| {
+            if let Err(error) = completion_marker.write() {
+                error!("Failed to write completion marker"; "error" => %error);
+            }
+        })
+    }
+}
+
+#[test]
+fn completion_marker_respects_policy() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let s3 = Arc::new(MockObjectStore::default());
+    let session_status = Arc::new(SessionStatus::default());
+    let mut completion_marker = CompletionMarker {
+        s3: s3.clone(),
+        bucket: "my-bucket".to_owned(),
+        key: "_SUCCESS".to_owned(),
+        policy: CompletionMarkerPolicy::OnSuccess,
+        upload_options: UploadOptions::default(),
+        session_status: Arc::clone(&session_status),
+    };
+    session_status.upload_succeeded();
+    session_status.upload_failed();
+
+    completion_marker.write().unwrap();
+    assert!(s3.calls().is_empty());
+
+    completion_marker.policy = CompletionMarkerPolicy::Always;
+    completion_marker.write().unwrap();
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::PutObject(put_object, body)] => {
+            assert_eq!(put_object.key, "_SUCCESS");
+            assert_eq!(
+                br#"{"status":"failure","uploaded":1,"failed":1}"#,
+                &body[..]
+            );
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn exit_code_reflects_outcomes() {
+    let session_status = SessionStatus::default();
+    assert_eq!(EXIT_SUCCESS, session_status.exit_code());
+    session_status.upload_failed_with(&anyhow::anyhow!("failed to upload"));
+    assert_eq!(EXIT_FAILURE, session_status.exit_code());
+    session_status.upload_succeeded();
+    assert_eq!(EXIT_PARTIAL_SUCCESS, session_status.exit_code());
+    session_status
+        .network_failed
+        .fetch_add(1, Ordering::Relaxed);
+    assert_eq!(EXIT_NETWORK_FAILURE, session_status.exit_code());
+    session_status.auth_failed.fetch_add(1, Ordering::Relaxed);
+    assert_eq!(EXIT_AUTH_FAILURE, session_status.exit_code());
+}
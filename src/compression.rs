@@ -0,0 +1,201 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{
+    bail,
+    Context,
+    Result,
+};
+use clap::ValueEnum;
+use flate2::{
+    write::GzEncoder,
+    Compression,
+};
+use std::{
+    fmt,
+    io::{
+        self,
+        Write,
+    },
+    path::Path,
+    sync::Arc,
+};
+
+/// The algorithm files are compressed with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum CompressionAlgorithm {
+    #[default]
+    #[value(name = "gzip")]
+    Gzip,
+    #[value(name = "zstd")]
+    Zstd,
+}
+
+/// How files are compressed, i.e. the algorithm along with its settings.
+#[derive(Clone, Default)]
+pub(crate) enum Codec {
+    #[default]
+    Gzip,
+    Zstd {
+        /// A precomputed dictionary, which improves the compression of small files that are
+        /// similar to the samples it was trained on.
+        dictionary: Option<Arc<Vec<u8>>>,
+    },
+}
+
+impl fmt::Debug for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Codec::Gzip => write!(f, "Gzip"),
+            Codec::Zstd { dictionary } => f
+                .debug_struct("Zstd")
+                .field("dictionary_len", &dictionary.as_ref().map(|d| d.len()))
+                .finish(),
+        }
+    }
+}
+
+impl Codec {
+    pub(crate) fn new(algorithm: CompressionAlgorithm, dictionary: Option<&Path>) -> Result<Codec> {
+        match (algorithm, dictionary) {
+            (CompressionAlgorithm::Gzip, None) => Ok(Codec::Gzip),
+            (CompressionAlgorithm::Gzip, Some(_)) => {
+                bail!("a zstd dictionary can only be used with `--compression zstd`")
+            }
+            (CompressionAlgorithm::Zstd, dictionary) => {
+                let dictionary = dictionary
+                    .map(|path| {
+                        std::fs::read(path).with_context(|| {
+                            format!("failed to read zstd dictionary '{}'", path.display())
+                        })
+                    })
+                    .transpose()?
+                    .map(Arc::new);
+                let codec = Codec::Zstd { dictionary };
+                // Reject invalid dictionaries right away, rather than failing every upload.
+                codec.encoder().context("invalid zstd dictionary")?;
+                Ok(codec)
+            }
+        }
+    }
+
+    /// The value of the `Content-Encoding` header of compressed objects.
+    pub(crate) fn content_encoding(&self) -> &'static str {
+        match self {
+            Codec::Gzip => "gzip",
+            Codec::Zstd { .. } => "zstd",
+        }
+    }
+
+    /// A streaming encoder, which writes the compressed data to a buffer.
+    pub(crate) fn encoder(&self) -> io::Result<Encoder> {
+        Ok(match self {
+            Codec::Gzip => Encoder::Gzip(GzEncoder::new(vec![], Compression::default())),
+            Codec::Zstd { dictionary: None } => {
+                Encoder::Zstd(zstd::Encoder::new(vec![], zstd::DEFAULT_COMPRESSION_LEVEL)?)
+            }
+            Codec::Zstd {
+                dictionary: Some(dictionary),
+            } => Encoder::Zstd(zstd::Encoder::with_dictionary(
+                vec![],
+                zstd::DEFAULT_COMPRESSION_LEVEL,
+                dictionary,
+            )?),
+        })
+    }
+
+    /// Compress `data` at once.
+    pub(crate) fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut encoder = self.encoder()?;
+        encoder.write_all(data)?;
+        encoder.finish()
+    }
+}
+
+/// A streaming encoder, holding the compressed data that has not been taken yet.
+pub(crate) enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Zstd(zstd::Encoder<'static, Vec<u8>>),
+}
+
+impl Encoder {
+    /// The compressed data written so far.
+    pub(crate) fn get_ref(&self) -> &Vec<u8> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.get_ref(),
+            Encoder::Zstd(encoder) => encoder.get_ref(),
+        }
+    }
+
+    /// The compressed data written so far, which can be taken out to be uploaded.
+    pub(crate) fn get_mut(&mut self) -> &mut Vec<u8> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.get_mut(),
+            Encoder::Zstd(encoder) => encoder.get_mut(),
+        }
+    }
+
+    /// Finish the compressed stream, returning the remaining compressed data.
+    pub(crate) fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl Write for Encoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[test]
+fn dictionaries_improve_compression_of_small_files() {
+    let record = |id: u32| {
+        format!(
+            r#"{{"id":{},"type":"order","status":"shipped","warehouse":"eu-central","items":[]}}"#,
+            id
+        )
+    };
+    // A dictionary can also consist of raw content, rather than being trained on samples.
+    let dictionary = (0..20).map(record).collect::<String>().into_bytes();
+    let with_dictionary = Codec::Zstd {
+        dictionary: Some(Arc::new(dictionary.clone())),
+    };
+    let without_dictionary = Codec::Zstd { dictionary: None };
+
+    let content = record(42).into_bytes();
+    let compressed = with_dictionary.compress(&content).unwrap();
+    assert!(compressed.len() < without_dictionary.compress(&content).unwrap().len());
+
+    let mut decoder =
+        zstd::Decoder::with_dictionary(io::BufReader::new(&compressed[..]), &dictionary).unwrap();
+    let mut decompressed = vec![];
+    io::copy(&mut decoder, &mut decompressed).unwrap();
+    assert_eq!(content, decompressed);
+    assert_eq!("zstd", with_dictionary.content_encoding());
+}
@@ -0,0 +1,95 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use sha2::{
+    Digest,
+    Sha256,
+};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+};
+
+/// SHA-256 digest of the full contents of an uploaded file.
+pub(crate) type ContentHash = [u8; 32];
+
+/// Format `hash` as lowercase hexadecimal digits, as printed by `sha256sum`.
+pub(crate) fn to_hex(hash: &ContentHash) -> String {
+    hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Incrementally hashes the contents written to an upload.
+#[derive(Clone, Default)]
+pub(crate) struct ContentHasher {
+    sha256: Sha256,
+    length: u64,
+}
+
+impl ContentHasher {
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        self.sha256.update(data);
+        self.length += data.len() as u64;
+    }
+
+    /// Returns the hash of the content and its length in bytes.
+    pub(crate) fn finalize(self) -> (ContentHash, u64) {
+        (self.sha256.finalize().into(), self.length)
+    }
+}
+
+/// An object in the [`ContentIndex`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct IndexedObject {
+    pub(crate) bucket: String,
+    pub(crate) key: String,
+    /// The entity tag of the object when it was uploaded, such that it is only copied as long as
+    /// it hasn't been replaced.
+    pub(crate) e_tag: Option<String>,
+    /// The version of the object, if the bucket is versioned.
+    pub(crate) version_id: Option<String>,
+}
+
+/// Index of the contents uploaded during this session, mapping the content hash to the object the
+/// content was uploaded to.
+///
+/// The index lives in memory only, so duplicates can only be detected within a single session.
+#[derive(Debug, Default)]
+pub(crate) struct ContentIndex(Mutex<HashMap<ContentHash, IndexedObject>>);
+
+impl ContentIndex {
+    pub(crate) fn get(&self, hash: &ContentHash) -> Option<IndexedObject> {
+        self.0
+            .lock()
+            .ok()
+            .and_then(|index| index.get(hash).cloned())
+    }
+
+    /// Record that `object` holds the content with `hash`, replacing what was recorded for the
+    /// same key before.
+    pub(crate) fn insert(&self, hash: ContentHash, object: IndexedObject) {
+        if let Ok(mut index) = self.0.lock() {
+            index.retain(|_, indexed| indexed.bucket != object.bucket || indexed.key != object.key);
+            index.insert(hash, object);
+        }
+    }
+
+    /// Forget the content of the object at `key`, since it is being replaced.
+    pub(crate) fn forget(&self, bucket: &str, key: &str) {
+        if let Ok(mut index) = self.0.lock() {
+            index.retain(|_, indexed| indexed.bucket != bucket || indexed.key != key);
+        }
+    }
+}
@@ -0,0 +1,216 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{
+    anyhow,
+    bail,
+};
+use std::{
+    collections::HashMap,
+    str::FromStr,
+};
+
+/// MIME types of common file extensions, used unless overridden.
+const BUILTIN_CONTENT_TYPES: &[(&str, &str)] = &[
+    ("7z", "application/x-7z-compressed"),
+    ("avif", "image/avif"),
+    ("bmp", "image/bmp"),
+    ("bz2", "application/x-bzip2"),
+    ("css", "text/css"),
+    ("csv", "text/csv"),
+    ("gif", "image/gif"),
+    ("gz", "application/gzip"),
+    ("htm", "text/html"),
+    ("html", "text/html"),
+    ("ico", "image/vnd.microsoft.icon"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("js", "text/javascript"),
+    ("json", "application/json"),
+    ("log", "text/plain"),
+    ("md", "text/markdown"),
+    ("mjs", "text/javascript"),
+    ("mov", "video/quicktime"),
+    ("mp3", "audio/mpeg"),
+    ("mp4", "video/mp4"),
+    ("ogg", "audio/ogg"),
+    ("otf", "font/otf"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    ("svg", "image/svg+xml"),
+    ("tar", "application/x-tar"),
+    ("tgz", "application/gzip"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    ("tsv", "text/tab-separated-values"),
+    ("ttf", "font/ttf"),
+    ("txt", "text/plain"),
+    ("wasm", "application/wasm"),
+    ("wav", "audio/wav"),
+    ("webm", "video/webm"),
+    ("webp", "image/webp"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("xml", "application/xml"),
+    ("xz", "application/x-xz"),
+    ("yaml", "application/yaml"),
+    ("yml", "application/yaml"),
+    ("zip", "application/zip"),
+    ("zst", "application/zstd"),
+];
+
+/// An entry of the override table, given as `extension:type`, e.g. `log:text/plain`.
+///
+/// With an empty type, e.g. `log:`, objects with the extension are uploaded without a content
+/// type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ContentTypeOverride {
+    extension: String,
+    content_type: Option<String>,
+}
+
+impl FromStr for ContentTypeOverride {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (extension, content_type) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("content type must be given as extension:type"))?;
+        let extension = extension.trim_start_matches('.');
+        if extension.is_empty() || extension.contains(['/', '.']) {
+            bail!("invalid extension '{}'", extension);
+        }
+        let content_type = match content_type.trim() {
+            "" => None,
+            content_type => {
+                let (kind, subtype) = content_type
+                    .split_once('/')
+                    .ok_or_else(|| anyhow!("content type must be given as type/subtype"))?;
+                if kind.is_empty()
+                    || subtype.is_empty()
+                    || !content_type.chars().all(|c| (' '..='~').contains(&c))
+                {
+                    bail!("invalid content type '{}'", content_type);
+                }
+                Some(content_type.to_owned())
+            }
+        };
+
+        Ok(ContentTypeOverride {
+            extension: extension.to_ascii_lowercase(),
+            content_type,
+        })
+    }
+}
+
+/// Infers the `Content-Type` of uploaded objects from the extension of their key.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ContentTypes {
+    overrides: HashMap<String, Option<String>>,
+}
+
+impl ContentTypes {
+    pub(crate) fn new(overrides: Vec<ContentTypeOverride>) -> Self {
+        ContentTypes {
+            overrides: overrides
+                .into_iter()
+                .map(|entry| (entry.extension, entry.content_type))
+                .collect(),
+        }
+    }
+
+    /// The content type of an object with `key`, if its extension is known.
+    pub(crate) fn infer(&self, key: &str) -> Option<String> {
+        let extension = extension(key)?.to_ascii_lowercase();
+        match self.overrides.get(&extension) {
+            Some(content_type) => content_type.clone(),
+            None => BUILTIN_CONTENT_TYPES
+                .binary_search_by_key(&extension.as_str(), |&(extension, _)| extension)
+                .ok()
+                .map(|index| BUILTIN_CONTENT_TYPES[index].1.to_owned()),
+        }
+    }
+}
+
+/// The extension of the file name of `key`. Hidden files like `.profile` have no extension.
+fn extension(key: &str) -> Option<&str> {
+    let file_name = key.rsplit('/').next().unwrap_or(key);
+    match file_name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && !extension.is_empty() => Some(extension),
+        _ => None,
+    }
+}
+
+#[test]
+fn builtin_content_types_are_sorted() {
+    assert!(BUILTIN_CONTENT_TYPES
+        .windows(2)
+        .all(|pair| pair[0].0 < pair[1].0));
+}
+
+#[test]
+fn content_type_is_inferred_from_extension() {
+    let content_types = ContentTypes::default();
+
+    assert_eq!(
+        Some("application/pdf".to_owned()),
+        content_types.infer("reports/2024.Q1.PDF")
+    );
+    assert_eq!(
+        Some("text/html".to_owned()),
+        content_types.infer("index.html")
+    );
+    assert_eq!(None, content_types.infer("reports/.profile"));
+    assert_eq!(None, content_types.infer("reports.d/README"));
+    assert_eq!(None, content_types.infer("archive.unknown"));
+}
+
+#[test]
+fn content_type_overrides_take_precedence() {
+    let content_types = ContentTypes::new(vec![
+        ".LOG:text/plain; charset=utf-8".parse().unwrap(),
+        "parquet:application/vnd.apache.parquet".parse().unwrap(),
+        "json:".parse().unwrap(),
+    ]);
+
+    assert_eq!(
+        Some("text/plain; charset=utf-8".to_owned()),
+        content_types.infer("app.log")
+    );
+    assert_eq!(
+        Some("application/vnd.apache.parquet".to_owned()),
+        content_types.infer("data/part-0.parquet")
+    );
+    assert_eq!(None, content_types.infer("data/events.json"));
+    assert_eq!(
+        Some("image/png".to_owned()),
+        content_types.infer("image.png")
+    );
+}
+
+#[test]
+fn content_type_override_is_validated() {
+    assert!("log".parse::<ContentTypeOverride>().is_err());
+    assert!(":text/plain".parse::<ContentTypeOverride>().is_err());
+    assert!("tar.gz:application/gzip"
+        .parse::<ContentTypeOverride>()
+        .is_err());
+    assert!("log:text".parse::<ContentTypeOverride>().is_err());
+    assert!("log:/plain".parse::<ContentTypeOverride>().is_err());
+    assert!("log:text/pl\u{e4}in"
+        .parse::<ContentTypeOverride>()
+        .is_err());
+}
@@ -0,0 +1,124 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{
+    anyhow,
+    bail,
+    Context,
+    Result,
+};
+use rusoto_core::credential::StaticProvider;
+use serde::Deserialize;
+use std::{
+    fs::File,
+    io::Read,
+    os::unix::io::{
+        FromRawFd,
+        RawFd,
+    },
+};
+use zeroize::Zeroize;
+
+/// Credentials in the JSON format used by the `credential_process` setting of the AWS CLI.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct JsonCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+/// Read credentials from the given file descriptor, which is closed afterwards.
+///
+/// The credentials can either be provided as JSON in the format of `credential_process`, or as
+/// `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and optionally `AWS_SESSION_TOKEN` in env-format.
+/// The buffer the credentials were read into is zeroed afterwards.
+pub(crate) fn read_from_fd(fd: RawFd) -> Result<StaticProvider> {
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    let mut contents = String::new();
+    let result = file
+        .read_to_string(&mut contents)
+        .with_context(|| format!("failed to read credentials from file descriptor {}", fd))
+        .and_then(|_| parse(&contents));
+    contents.zeroize();
+
+    result
+}
+
+fn parse(contents: &str) -> Result<StaticProvider> {
+    let (access_key_id, secret_access_key, session_token) =
+        if contents.trim_start().starts_with('{') {
+            let credentials: JsonCredentials =
+                serde_json::from_str(contents).context("failed to parse credentials as JSON")?;
+            (
+                credentials.access_key_id,
+                credentials.secret_access_key,
+                credentials.session_token,
+            )
+        } else {
+            let mut access_key_id = None;
+            let mut secret_access_key = None;
+            let mut session_token = None;
+            for line in contents.lines() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let line = line.strip_prefix("export ").unwrap_or(line);
+                let (name, value) = line
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("credentials must be JSON or lines of NAME=VALUE"))?;
+                let value = value.trim_matches(|c| c == '"' || c == '\'').to_owned();
+                match name.trim() {
+                    "AWS_ACCESS_KEY_ID" => access_key_id = Some(value),
+                    "AWS_SECRET_ACCESS_KEY" => secret_access_key = Some(value),
+                    "AWS_SESSION_TOKEN" => session_token = Some(value),
+                    _ => {}
+                }
+            }
+            match (access_key_id, secret_access_key) {
+                (Some(access_key_id), Some(secret_access_key)) => {
+                    (access_key_id, secret_access_key, session_token)
+                }
+                _ => bail!("credentials must contain AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY"),
+            }
+        };
+
+    Ok(StaticProvider::new(
+        access_key_id,
+        secret_access_key,
+        session_token,
+        None,
+    ))
+}
+
+#[test]
+fn credentials_are_parsed() {
+    let json = parse(
+        r#"{"Version": 1, "AccessKeyId": "AKIDEXAMPLE", "SecretAccessKey": "secret", "SessionToken": "token"}"#,
+    )
+    .unwrap();
+    assert_eq!("AKIDEXAMPLE", json.get_aws_access_key_id());
+    assert_eq!("secret", json.get_aws_secret_access_key());
+    assert_eq!(&Some("token".to_owned()), json.get_token());
+
+    let env = parse("# injected by systemd\nexport AWS_ACCESS_KEY_ID=AKIDEXAMPLE\nAWS_SECRET_ACCESS_KEY=\"secret\"\n").unwrap();
+    assert_eq!("AKIDEXAMPLE", env.get_aws_access_key_id());
+    assert_eq!("secret", env.get_aws_secret_access_key());
+    assert_eq!(&None, env.get_token());
+
+    assert!(parse("AWS_ACCESS_KEY_ID=AKIDEXAMPLE\n").is_err());
+}
@@ -0,0 +1,152 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{
+    Context,
+    Result,
+};
+use serde_json::json;
+use std::{
+    fs::{
+        File,
+        OpenOptions,
+    },
+    io::{
+        self,
+        Write,
+    },
+    path::Path,
+    sync::Mutex,
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+/// Writes a log line in the CloudWatch Embedded Metric Format (EMF) for every uploaded file.
+///
+/// When the log is shipped to CloudWatch Logs, e.g. by the CloudWatch agent, CloudWatch extracts
+/// the metrics `BytesUploaded` and `UploadDuration` from it, with the bucket as dimension.
+#[derive(Debug)]
+pub(crate) struct EmfWriter {
+    file: Mutex<File>,
+    namespace: String,
+}
+
+impl EmfWriter {
+    /// Open the log at `path`, appending to it if it already exists.
+    pub(crate) fn open(path: &Path, namespace: String) -> Result<EmfWriter> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open EMF log '{}'", path.display()))?;
+
+        Ok(EmfWriter {
+            file: Mutex::new(file),
+            namespace,
+        })
+    }
+
+    /// Record the metrics of an uploaded file, which took `duration` from its creation until it was
+    /// uploaded.
+    pub(crate) fn record_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        size: u64,
+        duration: Duration,
+    ) -> io::Result<()> {
+        let line = emf_line(
+            &self.namespace,
+            bucket,
+            key,
+            size,
+            duration,
+            SystemTime::now(),
+        );
+        let mut file = self
+            .file
+            .lock()
+            .map_err(|_| io::Error::other("failed to lock EMF log"))?;
+        // A single write per line, such that lines of concurrent uploads can't interleave.
+        file.write_all(line.as_bytes())
+    }
+}
+
+/// Format a single EMF log line. The key is included as a property, which can be queried in
+/// CloudWatch Logs Insights, but is no dimension, since every key would be a separate metric.
+fn emf_line(
+    namespace: &str,
+    bucket: &str,
+    key: &str,
+    size: u64,
+    duration: Duration,
+    timestamp: SystemTime,
+) -> String {
+    let timestamp = timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let line = json!({
+        "_aws": {
+            "Timestamp": timestamp,
+            "CloudWatchMetrics": [{
+                "Namespace": namespace,
+                "Dimensions": [["Bucket"]],
+                "Metrics": [
+                    {"Name": "BytesUploaded", "Unit": "Bytes"},
+                    {"Name": "UploadDuration", "Unit": "Milliseconds"},
+                ],
+            }],
+        },
+        "Bucket": bucket,
+        "Key": key,
+        "BytesUploaded": size,
+        "UploadDuration": duration.as_millis() as u64,
+    });
+
+    format!("{}\n", line)
+}
+
+#[test]
+fn emf_line_follows_the_specification() {
+    let line = emf_line(
+        "s3-write-only-fs",
+        "my-bucket",
+        "uploads/report.pdf",
+        1024,
+        Duration::from_millis(1500),
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+    );
+    assert!(line.ends_with('\n'));
+    assert_eq!(1, line.lines().count());
+
+    let line: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(1_700_000_000_000u64, line["_aws"]["Timestamp"]);
+    let directive = &line["_aws"]["CloudWatchMetrics"][0];
+    assert_eq!("s3-write-only-fs", directive["Namespace"]);
+    assert_eq!(json!([["Bucket"]]), directive["Dimensions"]);
+    // Every dimension and metric must be a member of the root object.
+    assert_eq!("my-bucket", line["Bucket"]);
+    for metric in directive["Metrics"].as_array().unwrap() {
+        assert!(line[metric["Name"].as_str().unwrap()].is_u64());
+    }
+    assert_eq!(1024, line["BytesUploaded"]);
+    assert_eq!(1500, line["UploadDuration"]);
+    assert_eq!("uploads/report.pdf", line["Key"]);
+}
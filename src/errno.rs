@@ -0,0 +1,256 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    retry::RetryBudgetExhausted,
+    spool::SpoolFull,
+};
+use libc::{
+    c_int,
+    EACCES,
+    EAGAIN,
+    EDQUOT,
+    EFBIG,
+    EIO,
+    ENOSPC,
+    ETIME,
+};
+use rusoto_core::{
+    request::BufferedHttpResponse,
+    RusotoError,
+};
+use rusoto_s3::{
+    CompleteMultipartUploadError,
+    CopyObjectError,
+    CreateMultipartUploadError,
+    DeleteObjectError,
+    GetObjectError,
+    PutObjectError,
+    UploadPartError,
+};
+use std::error::Error;
+
+/// Determine the errno to report for an error that occurred while uploading.
+///
+/// Errors that are caused by the bucket rejecting the object, rather than by a transient failure,
+/// are mapped to a more specific errno, so that the user can tell them apart. Everything else is
+/// reported as `EIO`.
+pub(crate) fn errno(error: &anyhow::Error) -> c_int {
+    if error.downcast_ref::<RetryBudgetExhausted>().is_some() {
+        return EAGAIN;
+    }
+    if error.downcast_ref::<SpoolFull>().is_some() {
+        return ENOSPC;
+    }
+
+    error
+        .chain()
+        .find_map(unknown_response)
+        .and_then(|response| s3_error(response.body_as_str()))
+        .map(|(code, message)| errno_for_s3_error(&code, &message))
+        .unwrap_or(EIO)
+}
+
+/// The message of the error, if S3 was denied access to the KMS key the object is encrypted with.
+///
+/// This happens with SSE-KMS when the key is disabled, or its key policy or grants don't permit the
+/// uploader to use it, possibly only after part of a multipart upload succeeded.
+pub(crate) fn kms_access_denied(error: &anyhow::Error) -> Option<String> {
+    error
+        .chain()
+        .find_map(unknown_response)
+        .and_then(|response| s3_error(response.body_as_str()))
+        .filter(|(code, message)| is_kms_access_denied(code, message))
+        .map(|(_, message)| message)
+}
+
+fn is_kms_access_denied(code: &str, message: &str) -> bool {
+    code.starts_with("KMS.") || (code == "AccessDenied" && message.contains("kms:"))
+}
+
+/// Get the raw HTTP response of an S3 error that rusoto did not map to a specific variant.
+fn unknown_response<'a>(error: &'a (dyn Error + 'static)) -> Option<&'a BufferedHttpResponse> {
+    fn downcast<'a, E: Error + 'static>(
+        error: &'a (dyn Error + 'static),
+    ) -> Option<&'a BufferedHttpResponse> {
+        match error.downcast_ref::<RusotoError<E>>() {
+            Some(RusotoError::Unknown(response)) => Some(response),
+            _ => None,
+        }
+    }
+
+    downcast::<PutObjectError>(error)
+        .or_else(|| downcast::<CreateMultipartUploadError>(error))
+        .or_else(|| downcast::<UploadPartError>(error))
+        .or_else(|| downcast::<CompleteMultipartUploadError>(error))
+        .or_else(|| downcast::<CopyObjectError>(error))
+        .or_else(|| downcast::<GetObjectError>(error))
+        .or_else(|| downcast::<DeleteObjectError>(error))
+}
+
+/// The HTTP status, error code and message of the S3 error response that caused `error`, if any.
+pub(crate) fn s3_error_response(error: &anyhow::Error) -> Option<(u16, String, String)> {
+    let response = error.chain().find_map(unknown_response)?;
+    let (code, message) = s3_error(response.body_as_str()).unwrap_or_default();
+    Some((response.status.as_u16(), code, message))
+}
+
+/// Whether `error` was caused by the multipart upload not existing (anymore).
+pub(crate) fn is_no_such_upload(error: &anyhow::Error) -> bool {
+    s3_error_response(error).is_some_and(|(_, code, _)| code == "NoSuchUpload")
+}
+
+/// Whether `error` was caused by S3 not being reachable at all, rather than S3 rejecting a request.
+pub(crate) fn is_unreachable(error: &anyhow::Error) -> bool {
+    fn downcast<E: Error + 'static>(error: &(dyn Error + 'static)) -> bool {
+        matches!(
+            error.downcast_ref::<RusotoError<E>>(),
+            Some(RusotoError::HttpDispatch(_))
+        )
+    }
+
+    error.chain().any(|error| {
+        downcast::<PutObjectError>(error)
+            || downcast::<CreateMultipartUploadError>(error)
+            || downcast::<UploadPartError>(error)
+            || downcast::<CompleteMultipartUploadError>(error)
+            || downcast::<CopyObjectError>(error)
+            || downcast::<GetObjectError>(error)
+            || downcast::<DeleteObjectError>(error)
+    })
+}
+
+/// Whether `error` was caused by missing or invalid credentials, or by the credentials not being
+/// permitted to make the request.
+pub(crate) fn is_auth_failure(error: &anyhow::Error) -> bool {
+    fn downcast<E: Error + 'static>(error: &(dyn Error + 'static)) -> bool {
+        matches!(
+            error.downcast_ref::<RusotoError<E>>(),
+            Some(RusotoError::Credentials(_))
+        )
+    }
+
+    error.chain().any(|error| {
+        downcast::<PutObjectError>(error)
+            || downcast::<CreateMultipartUploadError>(error)
+            || downcast::<UploadPartError>(error)
+            || downcast::<CompleteMultipartUploadError>(error)
+            || downcast::<CopyObjectError>(error)
+            || downcast::<GetObjectError>(error)
+            || downcast::<DeleteObjectError>(error)
+    }) || s3_error_response(error)
+        .is_some_and(|(status, code, _)| status == 403 || is_auth_error_code(&code))
+}
+
+fn is_auth_error_code(code: &str) -> bool {
+    matches!(
+        code,
+        "AccessDenied"
+            | "InvalidAccessKeyId"
+            | "SignatureDoesNotMatch"
+            | "ExpiredToken"
+            | "InvalidToken"
+            | "TokenRefreshRequired"
+    ) || code.starts_with("KMS.")
+}
+
+/// Extract the error code and message from an S3 XML error response.
+pub(crate) fn s3_error(body: &str) -> Option<(String, String)> {
+    Some((
+        s3_error_element(body, "Code")?,
+        s3_error_element(body, "Message").unwrap_or_default(),
+    ))
+}
+
+/// Extract an element like `ServerTime` from an S3 XML error response.
+pub(crate) fn s3_error_element(body: &str, name: &str) -> Option<String> {
+    let start_tag = format!("<{}>", name);
+    let end_tag = format!("</{}>", name);
+    let start = body.find(&start_tag)? + start_tag.len();
+    let end = start + body[start..].find(&end_tag)?;
+    Some(body[start..end].to_owned())
+}
+
+fn errno_for_s3_error(code: &str, message: &str) -> c_int {
+    match code {
+        _ if is_kms_access_denied(code, message) => EACCES,
+        "EntityTooLarge" => EFBIG,
+        // A bucket policy explicitly denying the upload, e.g. because the object exceeds the size
+        // allowed by an `s3:content-length-range` condition, is effectively a quota.
+        "AccessDenied" if message.contains("explicit deny") => EDQUOT,
+        // The request signature is only valid within 15 minutes of the server time, so the local
+        // clock is off. Reported separately, since retrying won't help until it has been synced.
+        "RequestTimeTooSkewed" => ETIME,
+        _ => EIO,
+    }
+}
+
+#[test]
+fn s3_error_is_parsed() {
+    let body = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Error><Code>AccessDenied</Code><Message>User: arn:aws:iam::123456789012:user/uploader is not authorized to perform: s3:PutObject on resource: "arn:aws:s3:::my-bucket/my-file" with an explicit deny in a resource-based policy</Message><RequestId>0123456789ABCDEF</RequestId></Error>"#;
+
+    let (code, message) = s3_error(body).unwrap();
+    assert_eq!("AccessDenied", code);
+    assert_eq!(EDQUOT, errno_for_s3_error(&code, &message));
+    assert_eq!(EFBIG, errno_for_s3_error("EntityTooLarge", ""));
+    assert_eq!(EIO, errno_for_s3_error("AccessDenied", "Access Denied"));
+    assert_eq!(EIO, errno_for_s3_error("InternalError", ""));
+    assert_eq!(None, s3_error("not xml"));
+}
+
+#[test]
+fn clock_skew_is_reported() {
+    let body = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Error><Code>RequestTimeTooSkewed</Code><Message>The difference between the request time and the current time is too large.</Message><RequestTime>20250101T120000Z</RequestTime><ServerTime>2025-01-01T12:20:00Z</ServerTime><MaxAllowedSkewMilliseconds>900000</MaxAllowedSkewMilliseconds></Error>"#;
+
+    let (code, message) = s3_error(body).unwrap();
+    assert_eq!(ETIME, errno_for_s3_error(&code, &message));
+    assert_eq!(
+        Some("2025-01-01T12:20:00Z".to_owned()),
+        s3_error_element(body, "ServerTime")
+    );
+}
+
+#[test]
+fn kms_access_denied_is_reported() {
+    let body = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Error><Code>AccessDenied</Code><Message>User: arn:aws:iam::123456789012:user/uploader is not authorized to perform: kms:GenerateDataKey on resource: arn:aws:kms:eu-central-1:123456789012:key/0123 with an explicit deny in a resource-based policy</Message><RequestId>0123456789ABCDEF</RequestId></Error>"#;
+
+    let (code, message) = s3_error(body).unwrap();
+    assert!(is_kms_access_denied(&code, &message));
+    assert_eq!(EACCES, errno_for_s3_error(&code, &message));
+    assert_eq!(
+        EACCES,
+        errno_for_s3_error("KMS.DisabledException", "The key is disabled.")
+    );
+    assert!(!is_kms_access_denied("AccessDenied", "Access Denied"));
+}
+
+#[test]
+fn auth_failures_are_recognized() {
+    assert!(is_auth_error_code("AccessDenied"));
+    assert!(is_auth_error_code("ExpiredToken"));
+    assert!(is_auth_error_code("KMS.DisabledException"));
+    assert!(!is_auth_error_code("EntityTooLarge"));
+    assert!(!is_auth_error_code("InternalError"));
+    assert!(!is_auth_failure(&anyhow::anyhow!("failed to upload part")));
+    assert!(is_auth_failure(&anyhow::Error::new(RusotoError::<
+        PutObjectError,
+    >::Credentials(
+        rusoto_core::credential::CredentialsError::new("no credentials")
+    ))));
+}
@@ -0,0 +1,305 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{
+    anyhow,
+    bail,
+    Context,
+    Result,
+};
+use rusoto_core::{
+    signature::SignedRequest,
+    Client,
+    Region,
+    RusotoError,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use slog_scope::{
+    debug,
+    error,
+    warn,
+};
+use std::{
+    convert::Infallible,
+    fmt,
+    sync::{
+        mpsc::{
+            self,
+            Receiver,
+            Sender,
+        },
+        Arc,
+        Mutex,
+    },
+    thread::{
+        self,
+        JoinHandle,
+    },
+};
+use tokio::runtime::Runtime;
+
+/// The maximum number of entries of a single `PutEvents` request.
+const MAX_ENTRIES_PER_REQUEST: usize = 10;
+/// The detail type of the events emitted for uploaded objects.
+const DETAIL_TYPE: &str = "Object Uploaded";
+
+/// An object that was uploaded successfully.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct UploadEvent {
+    bucket: String,
+    key: String,
+    size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version_id: Option<String>,
+    session_id: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PutEventsRequest<'a> {
+    entries: Vec<PutEventsRequestEntry<'a>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PutEventsRequestEntry<'a> {
+    source: &'a str,
+    detail_type: &'a str,
+    /// The event itself, as a JSON document embedded in a string.
+    detail: String,
+    event_bus_name: &'a str,
+    resources: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PutEventsResponse {
+    #[serde(default)]
+    failed_entry_count: u64,
+    #[serde(default)]
+    entries: Vec<PutEventsResultEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PutEventsResultEntry {
+    error_code: Option<String>,
+    error_message: Option<String>,
+}
+
+/// Emits an EventBridge event for every uploaded object.
+///
+/// Events are sent by a background thread, such that neither the time it takes nor any failure
+/// affects the uploads. Failures are only logged.
+pub(crate) struct EventBridge {
+    client: Client,
+    region: Region,
+    event_bus_name: String,
+    source: String,
+    session_id: String,
+    sender: Mutex<Option<Sender<UploadEvent>>>,
+    receiver: Mutex<Option<Receiver<UploadEvent>>>,
+    worker: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl fmt::Debug for EventBridge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventBridge")
+            .field("region", &self.region)
+            .field("event_bus_name", &self.event_bus_name)
+            .field("source", &self.source)
+            .finish()
+    }
+}
+
+impl EventBridge {
+    pub(crate) fn new(
+        client: Client,
+        region: Region,
+        event_bus_name: &str,
+        source: &str,
+        session_id: &str,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        EventBridge {
+            client,
+            region,
+            event_bus_name: event_bus_name.to_owned(),
+            source: source.to_owned(),
+            session_id: session_id.to_owned(),
+            sender: Mutex::new(Some(sender)),
+            receiver: Mutex::new(Some(receiver)),
+            worker: Mutex::new(None),
+        }
+    }
+
+    /// Queue the event for an object of `size` bytes that was uploaded to `key`.
+    pub(crate) fn uploaded(&self, bucket: &str, key: &str, size: u64, version_id: Option<&str>) {
+        let event = UploadEvent {
+            bucket: bucket.to_owned(),
+            key: key.to_owned(),
+            size,
+            version_id: version_id.map(str::to_owned),
+            session_id: self.session_id.clone(),
+        };
+        let sent = match self.sender.lock() {
+            Ok(sender) => sender
+                .as_ref()
+                .is_some_and(|sender| sender.send(event).is_ok()),
+            Err(_) => false,
+        };
+        if !sent {
+            warn!("Not emitting EventBridge event, since events are no longer sent");
+        }
+    }
+
+    /// Send the queued events in a background thread.
+    pub(crate) fn send_in_background(self: &Arc<Self>) -> Result<()> {
+        let receiver = self
+            .receiver
+            .lock()
+            .map_err(|_| anyhow!("failed to acquire lock on EventBridge queue"))?
+            .take()
+            .ok_or_else(|| anyhow!("EventBridge events are already sent in the background"))?;
+        let event_bridge = Arc::clone(self);
+        let worker = thread::Builder::new()
+            .name("event-bridge".to_owned())
+            .spawn(move || event_bridge.send_all(receiver))?;
+        if let Ok(mut slot) = self.worker.lock() {
+            *slot = Some(worker);
+        }
+
+        Ok(())
+    }
+
+    /// Send the events that are still queued, and stop sending events.
+    pub(crate) fn finish(&self) {
+        if let Ok(mut sender) = self.sender.lock() {
+            sender.take();
+        }
+        let worker = self.worker.lock().ok().and_then(|mut worker| worker.take());
+        if let Some(worker) = worker {
+            if worker.join().is_err() {
+                error!("Failed to send the remaining EventBridge events");
+            }
+        }
+    }
+
+    fn send_all(&self, receiver: Receiver<UploadEvent>) {
+        let runtime = match Runtime::new() {
+            Ok(runtime) => runtime,
+            Err(error) => {
+                error!("Failed to create runtime for EventBridge events"; "error" => %error);
+                return;
+            }
+        };
+        while let Ok(event) = receiver.recv() {
+            let mut events = vec![event];
+            events.extend(receiver.try_iter().take(MAX_ENTRIES_PER_REQUEST - 1));
+            match runtime.block_on(self.put_events(&events)) {
+                Ok(()) => debug!("Emitted {} EventBridge events", events.len()),
+                Err(error) => {
+                    error!("Failed to emit EventBridge events";
+                           "keys" => ?events.iter().map(|event| &event.key).collect::<Vec<_>>(),
+                           "error" => format!("{:#}", error))
+                }
+            }
+        }
+    }
+
+    async fn put_events(&self, events: &[UploadEvent]) -> Result<()> {
+        let mut request = SignedRequest::new("POST", "events", &self.region, "/");
+        request.set_content_type("application/x-amz-json-1.1".to_owned());
+        request.add_header("x-amz-target", "AWSEvents.PutEvents");
+        request.set_payload(Some(put_events_body(
+            &self.event_bus_name,
+            &self.source,
+            events,
+        )?));
+
+        let mut response = self
+            .client
+            .sign_and_dispatch(request)
+            .await
+            .map_err(RusotoError::<Infallible>::from)?;
+        let response = response.buffer().await?;
+        if !response.status.is_success() {
+            bail!(
+                "PutEvents failed with status {}: {}",
+                response.status,
+                String::from_utf8_lossy(&response.body)
+            );
+        }
+        let response: PutEventsResponse =
+            serde_json::from_slice(&response.body).context("failed to parse PutEvents response")?;
+        if response.failed_entry_count > 0 {
+            let failure = response
+                .entries
+                .iter()
+                .find_map(|entry| entry.error_code.as_ref().map(|code| (code, entry)));
+            bail!(
+                "{} of {} events were rejected, e.g. with {}: {}",
+                response.failed_entry_count,
+                events.len(),
+                failure.map_or("unknown error", |(code, _)| code),
+                failure
+                    .and_then(|(_, entry)| entry.error_message.as_deref())
+                    .unwrap_or_default()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// The body of a `PutEvents` request emitting `events`.
+fn put_events_body(event_bus_name: &str, source: &str, events: &[UploadEvent]) -> Result<Vec<u8>> {
+    let entries = events
+        .iter()
+        .map(|event| {
+            Ok(PutEventsRequestEntry {
+                source,
+                detail_type: DETAIL_TYPE,
+                detail: serde_json::to_string(event)?,
+                event_bus_name,
+                resources: vec![format!("arn:aws:s3:::{}/{}", event.bucket, event.key)],
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(serde_json::to_vec(&PutEventsRequest { entries })?)
+}
+
+#[test]
+fn upload_events_are_encoded_as_put_events_entries() {
+    let event = UploadEvent {
+        bucket: "my-bucket".to_owned(),
+        key: "prefix/my-file".to_owned(),
+        size: 42,
+        version_id: None,
+        session_id: "0123".to_owned(),
+    };
+
+    let body = put_events_body("uploads", "s3-write-only-fs", &[event]).unwrap();
+
+    assert_eq!(
+        r#"{"Entries":[{"Source":"s3-write-only-fs","DetailType":"Object Uploaded","Detail":"{\"bucket\":\"my-bucket\",\"key\":\"prefix/my-file\",\"size\":42,\"session_id\":\"0123\"}","EventBusName":"uploads","Resources":["arn:aws:s3:::my-bucket/prefix/my-file"]}]}"#,
+        String::from_utf8(body).unwrap()
+    );
+}
@@ -0,0 +1,120 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::s3_write_only_filesystem::BucketAndPrefix;
+use anyhow::{
+    bail,
+    Context,
+    Result,
+};
+use percent_encoding::{
+    utf8_percent_encode,
+    NON_ALPHANUMERIC,
+};
+use std::{
+    env,
+    ffi::CString,
+    fs::{
+        File,
+        OpenOptions,
+    },
+    io,
+    os::unix::{
+        ffi::OsStrExt,
+        io::AsRawFd,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+/// Directory for lock files if it is writable, otherwise the temporary directory is used.
+const LOCK_DIRECTORY: &str = "/run/lock";
+
+/// Advisory lock preventing the same bucket and prefix from being mounted twice on this host.
+///
+/// The lock is held for as long as this value is alive, or until the process exits.
+#[derive(Debug)]
+pub(crate) struct ExclusiveLock {
+    _file: File,
+}
+
+impl ExclusiveLock {
+    pub(crate) fn acquire(bucket_and_prefix: &BucketAndPrefix) -> Result<ExclusiveLock> {
+        let path = lock_path(bucket_and_prefix);
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("failed to open lock file '{}'", path.display()))?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::WouldBlock {
+                bail!(
+                    "bucket '{}' with prefix '{}' is already mounted by another process (lock file: \
+                     '{}')",
+                    bucket_and_prefix.s3_bucket_name,
+                    bucket_and_prefix.prefix_path.as_deref().unwrap_or(""),
+                    path.display()
+                );
+            }
+            return Err(error).context("failed to lock lock file");
+        }
+
+        Ok(ExclusiveLock { _file: file })
+    }
+}
+
+fn lock_path(bucket_and_prefix: &BucketAndPrefix) -> PathBuf {
+    let directory = Path::new(LOCK_DIRECTORY);
+    let directory = if is_writable(directory) {
+        directory.to_owned()
+    } else {
+        env::temp_dir()
+    };
+    let name = format!(
+        "{}:{}",
+        bucket_and_prefix.s3_bucket_name,
+        bucket_and_prefix.prefix_path.as_deref().unwrap_or("")
+    );
+
+    directory.join(format!(
+        "s3wofs-{}.lock",
+        utf8_percent_encode(&name, NON_ALPHANUMERIC)
+    ))
+}
+
+fn is_writable(directory: &Path) -> bool {
+    CString::new(directory.as_os_str().as_bytes())
+        .map(|directory| unsafe { libc::access(directory.as_ptr(), libc::W_OK) } == 0)
+        .unwrap_or(false)
+}
+
+#[test]
+fn second_lock_is_refused() {
+    let bucket_and_prefix: BucketAndPrefix =
+        format!("my-bucket:exclusive-test/{}", std::process::id())
+            .parse()
+            .unwrap();
+
+    let lock = ExclusiveLock::acquire(&bucket_and_prefix).unwrap();
+    assert!(ExclusiveLock::acquire(&bucket_and_prefix).is_err());
+    drop(lock);
+    assert!(ExclusiveLock::acquire(&bucket_and_prefix).is_ok());
+    std::fs::remove_file(lock_path(&bucket_and_prefix)).unwrap();
+}
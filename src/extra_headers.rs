@@ -0,0 +1,115 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{
+    anyhow,
+    bail,
+};
+use rusoto_core::{
+    request::DispatchSignedRequestFuture,
+    signature::SignedRequest,
+    DispatchSignedRequest,
+};
+use std::{
+    str::FromStr,
+    time::Duration,
+};
+
+/// A header that is attached to every request sent to S3, given as `name:value`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ExtraHeader {
+    name: String,
+    value: String,
+}
+
+impl FromStr for ExtraHeader {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("header must be given as name:value"))?;
+        let name = name.trim();
+        let value = value.trim();
+
+        // Header names are tokens as defined in RFC 7230.
+        let is_token_char = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+        if name.is_empty() || !name.chars().all(is_token_char) {
+            bail!("invalid header name '{}'", name);
+        }
+        // The headers are added after the request has been signed, but S3 requires all `x-amz-*`
+        // headers to be signed.
+        let lowercase_name = name.to_ascii_lowercase();
+        if lowercase_name.starts_with("x-amz-")
+            || lowercase_name == "authorization"
+            || lowercase_name == "host"
+        {
+            bail!("header '{}' can't be set as an extra header", name);
+        }
+        if !value.chars().all(|c| c == '\t' || (' '..='~').contains(&c)) {
+            bail!("invalid value for header '{}'", name);
+        }
+
+        Ok(ExtraHeader {
+            name: name.to_owned(),
+            value: value.to_owned(),
+        })
+    }
+}
+
+/// Dispatcher that attaches the extra headers to every request before passing it on.
+pub(crate) struct ExtraHeadersDispatcher<D> {
+    inner: D,
+    extra_headers: Vec<ExtraHeader>,
+}
+
+impl<D> ExtraHeadersDispatcher<D> {
+    pub(crate) fn new(inner: D, extra_headers: Vec<ExtraHeader>) -> Self {
+        ExtraHeadersDispatcher {
+            inner,
+            extra_headers,
+        }
+    }
+}
+
+impl<D: DispatchSignedRequest> DispatchSignedRequest for ExtraHeadersDispatcher<D> {
+    fn dispatch(
+        &self,
+        mut request: SignedRequest,
+        timeout: Option<Duration>,
+    ) -> DispatchSignedRequestFuture {
+        for extra_header in &self.extra_headers {
+            request.add_header(&extra_header.name, &extra_header.value);
+        }
+        self.inner.dispatch(request, timeout)
+    }
+}
+
+#[test]
+fn extra_header_is_validated() {
+    assert_eq!(
+        ExtraHeader {
+            name: "X-Tenant-Id".to_owned(),
+            value: "my tenant".to_owned(),
+        },
+        "X-Tenant-Id: my tenant".parse().unwrap()
+    );
+    assert!("X-Tenant-Id".parse::<ExtraHeader>().is_err());
+    assert!(": value".parse::<ExtraHeader>().is_err());
+    assert!("X Tenant:value".parse::<ExtraHeader>().is_err());
+    assert!("X-Amz-Meta-Tenant:value".parse::<ExtraHeader>().is_err());
+    assert!("X-Tenant-Id:line\nbreak".parse::<ExtraHeader>().is_err());
+}
@@ -0,0 +1,66 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{
+    bail,
+    Context,
+    Result,
+};
+use std::{
+    ffi::CString,
+    io,
+    os::unix::ffi::OsStrExt,
+    path::Path,
+};
+
+/// The device through which the kernel talks to FUSE filesystems.
+const FUSE_DEVICE: &str = "/dev/fuse";
+
+/// Check that the FUSE device exists and can be opened by the current user, such that mounting
+/// fails with an actionable error instead of a cryptic one.
+pub(crate) fn check() -> Result<()> {
+    check_device(Path::new(FUSE_DEVICE))
+}
+
+fn check_device(path: &Path) -> Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .with_context(|| format!("invalid device path '{}'", path.display()))?;
+    if unsafe { libc::access(c_path.as_ptr(), libc::R_OK | libc::W_OK) } == 0 {
+        return Ok(());
+    }
+
+    let error = io::Error::last_os_error();
+    match error.raw_os_error() {
+        Some(libc::ENOENT) => bail!(
+            "{} does not exist: install FUSE (e.g. the `fuse` or `fuse3` package) and load the \
+             kernel module with `modprobe fuse`; in a container, pass the device with \
+             `--device /dev/fuse`",
+            path.display()
+        ),
+        Some(libc::EACCES) | Some(libc::EPERM) => bail!(
+            "permission denied on {}: add the user to the group owning it (usually `fuse`), or \
+             run with sufficient permissions",
+            path.display()
+        ),
+        _ => Err(error).with_context(|| format!("failed to access {}", path.display())),
+    }
+}
+
+#[test]
+fn missing_device_is_reported() {
+    let error = check_device(Path::new("/dev/does-not-exist-s3wofs")).unwrap_err();
+    assert!(error.to_string().contains("install FUSE"), "{}", error);
+}
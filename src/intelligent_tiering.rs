@@ -0,0 +1,124 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{
+    bail,
+    Result,
+};
+use percent_encoding::{
+    utf8_percent_encode,
+    AsciiSet,
+    NON_ALPHANUMERIC,
+};
+use std::str::FromStr;
+
+/// The storage class objects are uploaded with for S3 Intelligent-Tiering.
+pub(crate) const STORAGE_CLASS: &str = "INTELLIGENT_TIERING";
+
+/// Characters that have to be percent-encoded in the `x-amz-tagging` header, which is formatted
+/// like a URL query.
+const TAGGING_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// Upload objects with the `INTELLIGENT_TIERING` storage class, such that S3 moves them to cheaper
+/// access tiers once they are not accessed anymore.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct IntelligentTiering {
+    /// If set, objects are tagged with this tag, which opts them into the archive access tiers.
+    ///
+    /// The archive access tiers have to be enabled by an Intelligent-Tiering configuration of the
+    /// bucket, filtered by this tag, since objects in them are not immediately accessible.
+    pub(crate) archive_tag: Option<ArchiveTag>,
+}
+
+impl IntelligentTiering {
+    /// The value of the `x-amz-tagging` header for uploaded objects, if any.
+    pub(crate) fn tagging(&self) -> Option<String> {
+        self.archive_tag.as_ref().map(|tag| {
+            format!(
+                "{}={}",
+                utf8_percent_encode(&tag.key, TAGGING_ENCODE_SET),
+                utf8_percent_encode(&tag.value, TAGGING_ENCODE_SET)
+            )
+        })
+    }
+}
+
+/// An object tag in the form `<key>=<value>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ArchiveTag {
+    key: String,
+    value: String,
+}
+
+impl FromStr for ArchiveTag {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (key, value) = match s.split_once('=') {
+            Some((key, value)) => (key, value),
+            None => bail!("expected a tag in the form <key>=<value>, got '{}'", s),
+        };
+        // Limits of S3 object tags.
+        if key.is_empty() || key.chars().count() > 128 {
+            bail!(
+                "tag key must be between 1 and 128 characters long, got '{}'",
+                key
+            );
+        }
+        if value.chars().count() > 256 {
+            bail!(
+                "tag value must be at most 256 characters long, got '{}'",
+                value
+            );
+        }
+        if key.starts_with("aws:") {
+            bail!("tag keys starting with 'aws:' are reserved by AWS");
+        }
+
+        Ok(ArchiveTag {
+            key: key.to_owned(),
+            value: value.to_owned(),
+        })
+    }
+}
+
+#[test]
+fn archive_tags_are_encoded_for_the_tagging_header() {
+    let intelligent_tiering = IntelligentTiering {
+        archive_tag: Some("tiering=archive after 90 days".parse().unwrap()),
+    };
+    assert_eq!(
+        Some("tiering=archive%20after%2090%20days"),
+        intelligent_tiering.tagging().as_deref()
+    );
+    assert_eq!(None, IntelligentTiering::default().tagging());
+
+    assert_eq!(
+        "archive=",
+        IntelligentTiering {
+            archive_tag: Some("archive=".parse().unwrap()),
+        }
+        .tagging()
+        .unwrap()
+    );
+    assert!("archive".parse::<ArchiveTag>().is_err());
+    assert!("=archive".parse::<ArchiveTag>().is_err());
+    assert!("aws:archive=true".parse::<ArchiveTag>().is_err());
+}
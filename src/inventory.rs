@@ -0,0 +1,135 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{
+    Context,
+    Result,
+};
+use chrono::{
+    DateTime,
+    SecondsFormat,
+    Utc,
+};
+use percent_encoding::{
+    utf8_percent_encode,
+    AsciiSet,
+    NON_ALPHANUMERIC,
+};
+use std::{
+    fs::{
+        File,
+        OpenOptions,
+    },
+    io::{
+        self,
+        Write,
+    },
+    path::Path,
+    sync::Mutex,
+    time::SystemTime,
+};
+
+/// Characters that S3 Inventory leaves unencoded in keys, i.e. everything else is encoded as in
+/// `application/x-www-form-urlencoded`.
+const INVENTORY_KEY_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'*');
+
+/// Writes a CSV listing of all objects uploaded during the session, following the S3 Inventory
+/// CSV schema with the fields `Bucket`, `Key`, `Size`, `LastModifiedDate` and `ETag`.
+///
+/// This allows diffing what has been written against the inventory reports of the bucket.
+#[derive(Debug)]
+pub(crate) struct InventoryWriter {
+    file: Mutex<File>,
+}
+
+impl InventoryWriter {
+    /// Open the listing at `path`, appending to it if it already exists.
+    pub(crate) fn open(path: &Path) -> Result<InventoryWriter> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open inventory listing '{}'", path.display()))?;
+
+        Ok(InventoryWriter {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Record an uploaded object.
+    pub(crate) fn record(
+        &self,
+        bucket: &str,
+        key: &str,
+        size: u64,
+        last_modified: SystemTime,
+        e_tag: Option<&str>,
+    ) -> io::Result<()> {
+        let line = csv_line(bucket, key, size, last_modified, e_tag);
+        let mut file = self
+            .file
+            .lock()
+            .map_err(|_| io::Error::other("failed to lock inventory listing"))?;
+        // A single write per line, such that lines of concurrent uploads can't interleave.
+        file.write_all(line.as_bytes())
+    }
+}
+
+/// Format a single line of the listing. Like S3 Inventory, every field is quoted and keys are
+/// URL-encoded, such that no further escaping is required.
+fn csv_line(
+    bucket: &str,
+    key: &str,
+    size: u64,
+    last_modified: SystemTime,
+    e_tag: Option<&str>,
+) -> String {
+    format!(
+        "\"{}\",\"{}\",\"{}\",\"{}\",\"{}\"\n",
+        bucket,
+        encode_key(key),
+        size,
+        DateTime::<Utc>::from(last_modified).to_rfc3339_opts(SecondsFormat::Millis, true),
+        e_tag.unwrap_or_default().trim_matches('"'),
+    )
+}
+
+fn encode_key(key: &str) -> String {
+    utf8_percent_encode(key, INVENTORY_KEY_ENCODE_SET)
+        .to_string()
+        .replace("%20", "+")
+}
+
+#[test]
+fn lines_follow_the_inventory_schema() {
+    use std::time::Duration;
+
+    let last_modified = SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+    assert_eq!(
+        "\"my-bucket\",\"prefix%2Fmy+file%2C%22quoted%22.txt\",\"42\",\"2023-11-14T22:13:20.123Z\",\"d41d8cd98f00b204e9800998ecf8427e\"\n",
+        csv_line(
+            "my-bucket",
+            "prefix/my file,\"quoted\".txt",
+            42,
+            last_modified,
+            Some("\"d41d8cd98f00b204e9800998ecf8427e\""),
+        )
+    );
+}
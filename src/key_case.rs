@@ -0,0 +1,71 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use clap::ValueEnum;
+
+/// How to fold the case of file names before they are used as keys.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum KeyCase {
+    /// Keep the file name as-is.
+    #[default]
+    #[value(name = "preserve")]
+    Preserve,
+    /// Convert the file name to lowercase.
+    #[value(name = "lower")]
+    Lower,
+    /// Convert the file name to uppercase.
+    #[value(name = "upper")]
+    Upper,
+}
+
+impl KeyCase {
+    /// Fold the case of a file name. The prefix is not part of `name` and thus never affected.
+    pub(crate) fn apply(&self, name: &str) -> String {
+        match self {
+            Self::Preserve => name.to_owned(),
+            Self::Lower => name.to_lowercase(),
+            Self::Upper => name.to_uppercase(),
+        }
+    }
+}
+
+#[test]
+fn preserve_keeps_the_name() {
+    assert_eq!(
+        "Report File.TXT",
+        KeyCase::Preserve.apply("Report File.TXT")
+    );
+}
+
+#[test]
+fn lower_folds_to_lowercase() {
+    assert_eq!("report file.txt", KeyCase::Lower.apply("Report File.TXT"));
+    assert_eq!("größe.txt", KeyCase::Lower.apply("GRÖßE.TXT"));
+    assert_eq!(
+        KeyCase::Lower.apply("File.TXT"),
+        KeyCase::Lower.apply("file.txt")
+    );
+}
+
+#[test]
+fn upper_folds_to_uppercase() {
+    assert_eq!("REPORT FILE.TXT", KeyCase::Upper.apply("Report File.txt"));
+    assert_eq!("GRÖSSE.TXT", KeyCase::Upper.apply("Größe.txt"));
+    assert_eq!(
+        KeyCase::Upper.apply("File.TXT"),
+        KeyCase::Upper.apply("file.txt")
+    );
+}
@@ -0,0 +1,110 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use clap::ValueEnum;
+
+/// How to handle file names with characters outside of the safe alphabet for keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum KeyCharsetPolicy {
+    /// Refuse to create the file.
+    #[value(name = "reject")]
+    Reject,
+    /// Replace unsafe characters, e.g. `ä` with `ae` and spaces with `_`.
+    #[value(name = "transliterate")]
+    Transliterate,
+}
+
+impl KeyCharsetPolicy {
+    /// Apply the policy to a file name, returning `None` if it has to be rejected.
+    pub(crate) fn apply(&self, name: &str) -> Option<String> {
+        match self {
+            Self::Reject if name.chars().all(is_safe) => Some(name.to_owned()),
+            Self::Reject => None,
+            Self::Transliterate => Some(name.chars().map(transliterate).collect()),
+        }
+    }
+}
+
+/// Whether the character is part of the safe alphabet for keys.
+fn is_safe(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/')
+}
+
+fn transliterate(c: char) -> String {
+    match c {
+        c if is_safe(c) => c.to_string(),
+        'ä' => "ae".to_owned(),
+        'ö' => "oe".to_owned(),
+        'ü' => "ue".to_owned(),
+        'Ä' => "Ae".to_owned(),
+        'Ö' => "Oe".to_owned(),
+        'Ü' => "Ue".to_owned(),
+        'ß' => "ss".to_owned(),
+        'à' | 'á' | 'â' | 'ã' | 'å' => "a".to_owned(),
+        'è' | 'é' | 'ê' | 'ë' => "e".to_owned(),
+        'ì' | 'í' | 'î' | 'ï' => "i".to_owned(),
+        'ò' | 'ó' | 'ô' | 'õ' | 'ø' => "o".to_owned(),
+        'ù' | 'ú' | 'û' => "u".to_owned(),
+        'ç' => "c".to_owned(),
+        'ñ' => "n".to_owned(),
+        _ => "_".to_owned(),
+    }
+}
+
+#[test]
+fn safe_names_are_accepted() {
+    for policy in [KeyCharsetPolicy::Reject, KeyCharsetPolicy::Transliterate] {
+        assert_eq!(
+            Some("report_2024-01.v2.csv".to_owned()),
+            policy.apply("report_2024-01.v2.csv")
+        );
+    }
+}
+
+#[test]
+fn spaces_are_rejected_or_replaced() {
+    assert_eq!(None, KeyCharsetPolicy::Reject.apply("my report.csv"));
+    assert_eq!(
+        Some("my_report.csv".to_owned()),
+        KeyCharsetPolicy::Transliterate.apply("my report.csv")
+    );
+}
+
+#[test]
+fn unicode_is_rejected_or_transliterated() {
+    assert_eq!(None, KeyCharsetPolicy::Reject.apply("Größe.txt"));
+    assert_eq!(
+        Some("Groesse.txt".to_owned()),
+        KeyCharsetPolicy::Transliterate.apply("Größe.txt")
+    );
+    assert_eq!(
+        Some("cafe_.txt".to_owned()),
+        KeyCharsetPolicy::Transliterate.apply("café😀.txt")
+    );
+}
+
+#[test]
+fn reserved_characters_are_rejected_or_replaced() {
+    for reserved in [
+        "a&b", "a$b", "a@b", "a=b", "a;b", "a:b", "a+b", "a,b", "a?b", "a\\b",
+    ] {
+        assert_eq!(None, KeyCharsetPolicy::Reject.apply(reserved));
+        assert_eq!(
+            Some("a_b".to_owned()),
+            KeyCharsetPolicy::Transliterate.apply(reserved)
+        );
+    }
+}
@@ -0,0 +1,228 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::object_store::ObjectStore;
+use anyhow::{
+    Context,
+    Result,
+};
+use clap::ValueEnum;
+use libc::{
+    c_int,
+    EEXIST,
+};
+use rusoto_core::RusotoError;
+use rusoto_s3::{
+    HeadObjectError,
+    HeadObjectRequest,
+};
+use slog_scope::{
+    debug,
+    warn,
+};
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{
+        Hash,
+        Hasher,
+    },
+    sync::atomic::{
+        AtomicU64,
+        Ordering,
+    },
+};
+use tokio::runtime::Runtime;
+
+/// The rate of false positives the filter is sized for, at the expected number of keys.
+const FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// What happens when a file is created with a key that was already uploaded during this session.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum KeyCollisionPolicy {
+    /// Upload the file, replacing the earlier object.
+    #[default]
+    #[value(name = "allow")]
+    Allow,
+    /// Upload the file, but log a warning.
+    #[value(name = "warn")]
+    Warn,
+    /// Reject the file with `EEXIST`.
+    #[value(name = "reject")]
+    Reject,
+}
+
+/// Detects files that are created with a key that was already uploaded during this session.
+///
+/// The uploaded keys are kept in a bloom filter, such that the check is cheap and the memory used
+/// is fixed, at the cost of occasional false positives. These can optionally be ruled out with a
+/// `HeadObject` for every probable collision.
+#[derive(Debug)]
+pub(crate) struct KeyCollisions {
+    policy: KeyCollisionPolicy,
+    filter: KeyFilter,
+    verify: bool,
+}
+
+impl KeyCollisions {
+    /// Detect collisions according to `policy`, sizing the filter for `expected_keys` uploads.
+    pub(crate) fn new(policy: KeyCollisionPolicy, expected_keys: u64, verify: bool) -> Self {
+        KeyCollisions {
+            policy,
+            filter: KeyFilter::new(expected_keys),
+            verify,
+        }
+    }
+
+    /// Remember that an object was uploaded to `key`.
+    pub(crate) fn record(&self, bucket: &str, key: &str) {
+        if self.policy != KeyCollisionPolicy::Allow {
+            self.filter.insert(bucket, key);
+        }
+    }
+
+    /// Check whether a file created at `key` collides with an object uploaded earlier, returning
+    /// the error to reply with if it is rejected.
+    pub(crate) fn check(
+        &self,
+        runtime: &Runtime,
+        s3: &dyn ObjectStore,
+        bucket: &str,
+        key: &str,
+    ) -> Result<Option<c_int>> {
+        if self.policy == KeyCollisionPolicy::Allow || !self.filter.may_contain(bucket, key) {
+            return Ok(None);
+        }
+        if self.verify && !object_exists(runtime, s3, bucket, key)? {
+            debug!("Ruled out probable key collision: s3://{}/{}", bucket, key);
+            return Ok(None);
+        }
+        match self.policy {
+            KeyCollisionPolicy::Allow => Ok(None),
+            KeyCollisionPolicy::Warn => {
+                warn!(
+                    "Overwriting file uploaded earlier in this session: s3://{}/{}",
+                    bucket, key
+                );
+                Ok(None)
+            }
+            KeyCollisionPolicy::Reject => Ok(Some(EEXIST)),
+        }
+    }
+}
+
+/// Whether an object exists at `key`.
+fn object_exists(runtime: &Runtime, s3: &dyn ObjectStore, bucket: &str, key: &str) -> Result<bool> {
+    let result = runtime.block_on(s3.head_object(HeadObjectRequest {
+        bucket: bucket.to_owned(),
+        key: key.to_owned(),
+        ..Default::default()
+    }));
+    match result {
+        Ok(_) => Ok(true),
+        // `HEAD` responses have no body, so a missing object is usually reported as a bare 404.
+        Err(RusotoError::Service(HeadObjectError::NoSuchKey(_))) => Ok(false),
+        Err(RusotoError::Unknown(response)) if response.status.as_u16() == 404 => Ok(false),
+        Err(error) => {
+            Err(error).with_context(|| format!("failed to check whether '{}' exists", key))
+        }
+    }
+}
+
+/// A bloom filter of the keys uploaded during this session.
+#[derive(Debug)]
+struct KeyFilter {
+    bits: Vec<AtomicU64>,
+    hashes: u32,
+}
+
+impl KeyFilter {
+    /// A filter with a false positive rate of [`FALSE_POSITIVE_RATE`] once it holds
+    /// `expected_keys` keys.
+    fn new(expected_keys: u64) -> Self {
+        let expected_keys = expected_keys.max(1) as f64;
+        let ln_2 = std::f64::consts::LN_2;
+        let bits = (-expected_keys * FALSE_POSITIVE_RATE.ln() / (ln_2 * ln_2)).ceil() as u64;
+        let hashes = ((bits as f64 / expected_keys) * ln_2).round().max(1.0) as u32;
+        KeyFilter {
+            bits: (0..bits.div_ceil(64)).map(|_| AtomicU64::new(0)).collect(),
+            hashes,
+        }
+    }
+
+    fn insert(&self, bucket: &str, key: &str) {
+        for bit in self.bit_indices(bucket, key) {
+            self.bits[bit / 64].fetch_or(1 << (bit % 64), Ordering::Relaxed);
+        }
+    }
+
+    fn may_contain(&self, bucket: &str, key: &str) -> bool {
+        self.bit_indices(bucket, key)
+            .all(|bit| self.bits[bit / 64].load(Ordering::Relaxed) & (1 << (bit % 64)) != 0)
+    }
+
+    /// The bits of `key` in `bucket`, derived from two hashes by double hashing.
+    fn bit_indices(&self, bucket: &str, key: &str) -> impl Iterator<Item = usize> {
+        let hash = |seed: u64| {
+            let mut hasher = DefaultHasher::new();
+            (seed, bucket, key).hash(&mut hasher);
+            hasher.finish()
+        };
+        let (first, second) = (hash(0), hash(1));
+        let len = self.bits.len() as u64 * 64;
+        (0..u64::from(self.hashes))
+            .map(move |i| (first.wrapping_add(i.wrapping_mul(second)) % len) as usize)
+    }
+}
+
+#[test]
+fn uploaded_keys_are_detected() {
+    use crate::object_store::mock::MockObjectStore;
+
+    let runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let key_collisions = KeyCollisions::new(KeyCollisionPolicy::Reject, 1000, false);
+    key_collisions.record("my-bucket", "data/file");
+    let check = |bucket, key| key_collisions.check(&runtime, &s3, bucket, key).unwrap();
+
+    assert_eq!(Some(EEXIST), check("my-bucket", "data/file"));
+    assert_eq!(None, check("my-bucket", "data/other-file"));
+    assert_eq!(None, check("other-bucket", "data/file"));
+    assert!(s3.calls().is_empty());
+
+    let key_collisions = KeyCollisions::new(KeyCollisionPolicy::Warn, 1000, true);
+    key_collisions.record("my-bucket", "data/file");
+    assert_eq!(
+        None,
+        key_collisions
+            .check(&runtime, &s3, "my-bucket", "data/file")
+            .unwrap()
+    );
+    assert_eq!(1, s3.calls().len());
+}
+
+#[test]
+fn false_positives_are_rare() {
+    let filter = KeyFilter::new(10_000);
+    for i in 0..10_000 {
+        filter.insert("my-bucket", &format!("uploaded/{}", i));
+    }
+
+    assert!((0..10_000).all(|i| filter.may_contain("my-bucket", &format!("uploaded/{}", i))));
+    let false_positives = (0..10_000)
+        .filter(|i| filter.may_contain("my-bucket", &format!("other/{}", i)))
+        .count();
+    assert!(false_positives < 300, "{} false positives", false_positives);
+}
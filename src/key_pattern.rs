@@ -0,0 +1,314 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{
+    anyhow,
+    bail,
+    Result,
+};
+use chrono::{
+    DateTime,
+    Datelike,
+    Timelike,
+    Utc,
+};
+use std::{
+    ffi::CStr,
+    str::FromStr,
+};
+
+/// A pattern from which the key of a file is derived, e.g. `{year}/{month}/{stem}-{uuid}.{ext}`.
+///
+/// The pattern consists of literal text and tokens in braces, which are replaced when a file is
+/// created:
+///
+/// * `{filename}`: the name of the file,
+/// * `{stem}`: the name of the file without its extension,
+/// * `{ext}`: the extension of the file without the leading dot, empty if there is none,
+/// * `{uuid}`: a random UUID, different for every file,
+/// * `{hostname}`: the name of the host the filesystem is mounted on,
+/// * `{year}`, `{month}`, `{day}`, `{hour}`, `{minute}`, `{second}`: the current time in UTC,
+///   zero-padded to two digits (four for the year).
+///
+/// Literal braces are written as `{{` and `}}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct KeyPattern {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Token(Token),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Filename,
+    Stem,
+    Extension,
+    Uuid,
+    Hostname,
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+}
+
+impl FromStr for Token {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "filename" => Token::Filename,
+            "stem" => Token::Stem,
+            "ext" => Token::Extension,
+            "uuid" => Token::Uuid,
+            "hostname" => Token::Hostname,
+            "year" => Token::Year,
+            "month" => Token::Month,
+            "day" => Token::Day,
+            "hour" => Token::Hour,
+            "minute" => Token::Minute,
+            "second" => Token::Second,
+            _ => bail!("unknown token '{{{}}}'", s),
+        })
+    }
+}
+
+impl FromStr for KeyPattern {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut segments = vec![];
+        let mut literal = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| anyhow!("unclosed '{{' in key pattern"))?;
+                    let token = rest[..end].parse()?;
+                    chars = rest[end + 1..].chars();
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Token(token));
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '}' => bail!("unmatched '}}' in key pattern, use '}}}}' for a literal brace"),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        if s.starts_with('/') {
+            bail!("key pattern must not start with '/'");
+        }
+        let is_unique = |segment: &Segment| {
+            matches!(
+                segment,
+                Segment::Token(Token::Filename | Token::Stem | Token::Uuid)
+            )
+        };
+        if !segments.iter().any(is_unique) {
+            bail!(
+                "key pattern must contain {{filename}}, {{stem}} or {{uuid}}, otherwise all files \
+                 would be written to the same key"
+            );
+        }
+
+        Ok(KeyPattern { segments })
+    }
+}
+
+/// Values of the tokens that don't depend on the file name.
+struct Context {
+    time: DateTime<Utc>,
+    hostname: String,
+    uuid: String,
+}
+
+impl Context {
+    fn current(pattern: &KeyPattern) -> Result<Self> {
+        let uses = |token| pattern.segments.contains(&Segment::Token(token));
+        Ok(Context {
+            time: Utc::now(),
+            hostname: if uses(Token::Hostname) {
+                hostname()?
+            } else {
+                String::new()
+            },
+            uuid: if uses(Token::Uuid) {
+                random_uuid()?
+            } else {
+                String::new()
+            },
+        })
+    }
+}
+
+impl KeyPattern {
+    /// Derive the key for the file called `filename`, relative to the prefix.
+    pub(crate) fn expand(&self, filename: &str) -> Result<String> {
+        Ok(self.expand_with(filename, &Context::current(self)?))
+    }
+
+    fn expand_with(&self, filename: &str, context: &Context) -> String {
+        let (stem, extension) = match filename.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() => (stem, extension),
+            _ => (filename, ""),
+        };
+        let time = &context.time;
+        let mut key = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => key.push_str(literal),
+                Segment::Token(token) => match token {
+                    Token::Filename => key.push_str(filename),
+                    Token::Stem => key.push_str(stem),
+                    Token::Extension => key.push_str(extension),
+                    Token::Uuid => key.push_str(&context.uuid),
+                    Token::Hostname => key.push_str(&context.hostname),
+                    Token::Year => key.push_str(&format!("{:04}", time.year())),
+                    Token::Month => key.push_str(&format!("{:02}", time.month())),
+                    Token::Day => key.push_str(&format!("{:02}", time.day())),
+                    Token::Hour => key.push_str(&format!("{:02}", time.hour())),
+                    Token::Minute => key.push_str(&format!("{:02}", time.minute())),
+                    Token::Second => key.push_str(&format!("{:02}", time.second())),
+                },
+            }
+        }
+
+        key
+    }
+}
+
+pub(crate) fn hostname() -> Result<String> {
+    let mut buffer = [0u8; 256];
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) };
+    if result != 0 {
+        return Err(anyhow::Error::new(std::io::Error::last_os_error())
+            .context("failed to determine hostname"));
+    }
+    let hostname =
+        CStr::from_bytes_until_nul(&buffer).map_err(|_| anyhow!("hostname is not terminated"))?;
+
+    Ok(hostname.to_string_lossy().into_owned())
+}
+
+/// A random (version 4) UUID.
+pub(crate) fn random_uuid() -> Result<String> {
+    let mut bytes = [0u8; 16];
+    let result = unsafe { libc::getrandom(bytes.as_mut_ptr().cast(), bytes.len(), 0) };
+    if result != bytes.len() as isize {
+        return Err(anyhow::Error::new(std::io::Error::last_os_error())
+            .context("failed to generate random UUID"));
+    }
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+
+    Ok(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    ))
+}
+
+#[cfg(test)]
+fn test_context() -> Context {
+    use chrono::TimeZone;
+
+    Context {
+        time: Utc.with_ymd_and_hms(2025, 3, 7, 8, 5, 9).unwrap(),
+        hostname: "uploader-1".to_owned(),
+        uuid: "0f8fad5b-d9cb-469f-a165-70867728950e".to_owned(),
+    }
+}
+
+#[test]
+fn tokens_are_expanded() {
+    let expand = |pattern: &str, filename: &str| {
+        pattern
+            .parse::<KeyPattern>()
+            .unwrap()
+            .expand_with(filename, &test_context())
+    };
+
+    assert_eq!("report.pdf", expand("{filename}", "report.pdf"));
+    assert_eq!(
+        "2025/03/07/report.pdf",
+        expand("{year}/{month}/{day}/{filename}", "report.pdf")
+    );
+    assert_eq!(
+        "uploader-1/08-05-09/report-0f8fad5b-d9cb-469f-a165-70867728950e.pdf",
+        expand(
+            "{hostname}/{hour}-{minute}-{second}/{stem}-{uuid}.{ext}",
+            "report.pdf"
+        )
+    );
+    assert_eq!("archive.tar/gz", expand("{stem}/{ext}", "archive.tar.gz"));
+    assert_eq!(".bashrc/", expand("{stem}/{ext}", ".bashrc"));
+    assert_eq!("README/", expand("{stem}/{ext}", "README"));
+}
+
+#[test]
+fn braces_are_escaped() {
+    let pattern: KeyPattern = "{{literal}}/{filename}".parse().unwrap();
+    assert_eq!(
+        "{literal}/report.pdf",
+        pattern.expand_with("report.pdf", &test_context())
+    );
+}
+
+#[test]
+fn invalid_patterns_are_rejected() {
+    assert!("{filename".parse::<KeyPattern>().is_err());
+    assert!("{filename}}".parse::<KeyPattern>().is_err());
+    assert!("{unknown}/{filename}".parse::<KeyPattern>().is_err());
+    assert!("{}/{filename}".parse::<KeyPattern>().is_err());
+    assert!("/{filename}".parse::<KeyPattern>().is_err());
+    assert!("{year}/{month}/{hostname}".parse::<KeyPattern>().is_err());
+    assert!("".parse::<KeyPattern>().is_err());
+}
+
+#[test]
+fn random_uuids_are_version_4() {
+    let uuid = random_uuid().unwrap();
+    assert_eq!(36, uuid.len());
+    assert_eq!(Some('4'), uuid.chars().nth(14));
+    assert_ne!(uuid, random_uuid().unwrap());
+}
@@ -16,46 +16,896 @@
 
 #![deny(unused_must_use)]
 
+mod aggregation;
+mod bucket_route;
+mod check;
+mod checksum_sidecar;
+mod completion_marker;
+mod compression;
+mod content_hash;
+mod content_type;
+mod credentials;
+mod emf;
+mod errno;
+mod event_bridge;
+mod exclusive;
+mod extra_headers;
+mod fuse_device;
 mod id_generator;
+mod intelligent_tiering;
+mod inventory;
+mod key_case;
+mod key_charset;
+mod key_collisions;
+mod key_pattern;
+mod metrics;
+mod network_mountpoint;
+mod object_metadata;
+mod object_store;
+mod object_url;
+mod prefix_map;
+mod prefix_shadowing;
+mod receipts;
+mod request_rate;
+mod retry;
+mod s3_express;
 mod s3_write_only_filesystem;
+mod self_test;
+mod signals;
+mod spool;
+mod sse_customer;
+mod trace_file;
+mod unmount;
 mod upload;
+mod upload_pause;
+mod upload_sink;
+mod upload_window;
+mod windows_names;
 
-use crate::s3_write_only_filesystem::{
-    BucketAndPrefix,
-    S3WriteOnlyFilesystem,
+use crate::{
+    aggregation::{
+        AggregationOptions,
+        Aggregator,
+    },
+    bucket_route::BucketRoute,
+    checksum_sidecar::{
+        ChecksumSidecar,
+        SidecarFailurePolicy,
+    },
+    completion_marker::{
+        CompletionMarker,
+        CompletionMarkerPolicy,
+        SessionStatus,
+    },
+    compression::{
+        Codec,
+        CompressionAlgorithm,
+    },
+    content_hash::ContentIndex,
+    content_type::ContentTypes,
+    emf::EmfWriter,
+    event_bridge::EventBridge,
+    exclusive::ExclusiveLock,
+    extra_headers::{
+        ExtraHeader,
+        ExtraHeadersDispatcher,
+    },
+    intelligent_tiering::{
+        ArchiveTag,
+        IntelligentTiering,
+    },
+    inventory::InventoryWriter,
+    key_case::KeyCase,
+    key_charset::KeyCharsetPolicy,
+    key_collisions::{
+        KeyCollisionPolicy,
+        KeyCollisions,
+    },
+    key_pattern::KeyPattern,
+    metrics::Metrics,
+    object_metadata::{
+        parse_replication_group,
+        parse_session_id,
+        MetadataEntry,
+        ObjectMetadata,
+        Replication,
+    },
+    object_url::{
+        UrlPrinter,
+        UrlStyle,
+    },
+    prefix_map::PrefixMap,
+    receipts::Receipts,
+    request_rate::{
+        RateLimitedDispatcher,
+        RequestRateLimiter,
+    },
+    retry::{
+        RetryBudget,
+        RetryPolicy,
+    },
+    s3_express::S3ExpressDispatcher,
+    s3_write_only_filesystem::{
+        parse_inode_base,
+        BucketAndPrefix,
+        ConcurrentCreates,
+        FilesystemOptions,
+        FinalizeOn,
+        FlushBehavior,
+        S3WriteOnlyFilesystem,
+        UploadLogLevel,
+    },
+    spool::Spool,
+    sse_customer::SseCustomerKey,
+    trace_file::OperationTraces,
+    upload::{
+        CannedAcl,
+        InterruptedWrites,
+        ServerSideEncryption,
+        StorageClass,
+        UploadOptions,
+        MULTIPART_MINIMUM_PART_SIZE,
+    },
+    upload_pause::UploadPause,
+    upload_sink::{
+        HttpRouter,
+        UploadSink,
+    },
+    upload_window::UploadWindow,
+    windows_names::WindowsSafeKeys,
+};
+use anyhow::{
+    anyhow,
+    bail,
+    Context,
+    Result,
+};
+use clap::{
+    Parser,
+    Subcommand,
+    ValueEnum,
+};
+use hyper::Uri;
+use rusoto_core::{
+    credential::{
+        DefaultCredentialsProvider,
+        StaticProvider,
+    },
+    Client,
+    HttpClient,
+    Region,
+};
+use rusoto_s3::{
+    GetBucketOwnershipControlsRequest,
+    HeadBucketRequest,
+    S3Client,
+    S3,
 };
-use anyhow::Result;
-use clap::Parser;
-use rusoto_core::Region;
-use rusoto_s3::S3Client;
 use slog::{
     o,
     Drain,
+    Level,
+    LevelFilter,
 };
 use slog_scope::{
     debug,
     error,
     info,
+    warn,
 };
 use std::{
     env,
     ffi::OsString,
+    net::SocketAddr,
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::{
+        Arc,
+        RwLock,
+    },
+    time::{
+        Duration,
+        UNIX_EPOCH,
+    },
+};
+
+/// The mount options that configure the S3 client or the uploads rather than FUSE, see `region`
+/// and `server_side_encryption`.
+const CLIENT_MOUNT_OPTIONS: &[&str] = &[
+    "region=",
+    "endpoint=",
+    "sse=",
+    "kms-key-id=",
+    "storageclass=",
+    "content-type=",
+];
+/// The most detailed level logged to the terminal or journald. Traces are only logged by debug
+/// builds, or to the file given with `--trace-file`.
+const MAIN_LOG_MAX_LEVEL: Level = if cfg!(debug_assertions) {
+    Level::Trace
+} else {
+    Level::Debug
 };
 
 #[derive(Debug, Parser)]
-#[clap(author, version, about)]
+#[clap(
+    author,
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Opts {
+    #[clap(subcommand)]
+    command: Option<Command>,
     /// S3 bucket (with optional prefix) to mount the write-only filesystem against.
     ///
     /// If you want to mount the root of a bucket, you can simply provide `my-bucket-name`. If you
     /// want to mount a sub-directory (prefix), you can provide it after a colon, e.g.:
     /// `my-bucket-name:prefix/path/`.
-    device: String,
+    #[clap(required = true)]
+    device: Option<String>,
     /// Mountpoint to mount the filesystem to.
-    mountpoint: OsString,
+    #[clap(required = true)]
+    mountpoint: Option<OsString>,
     /// Don't daemonize, i.e. continue to run in the foreground
     #[clap(long = "foreground")]
     foreground: bool,
+    /// The working directory of the daemon.
+    ///
+    /// Defaults to `/`, such that the daemon doesn't keep the filesystem of the directory it was
+    /// started from busy, which would prevent unmounting it. Not used with `--foreground`.
+    #[clap(long = "working-directory", value_name = "PATH", default_value = "/")]
+    working_directory: PathBuf,
+    /// Refuse to mount if the same bucket and prefix is already mounted by another process on this
+    /// host.
+    #[clap(long = "exclusive")]
+    exclusive: bool,
+    /// Mount even if the mountpoint resides on a network or virtual filesystem (e.g. NFS, CIFS or
+    /// another FUSE filesystem), logging a warning instead.
+    ///
+    /// FUSE mounts on top of such filesystems behave oddly and can deadlock, so this is refused by
+    /// default.
+    #[clap(long = "allow-network-mountpoint")]
+    allow_network_mountpoint: bool,
+    /// The AWS region of the bucket, e.g. `us-east-1`.
+    ///
+    /// Can also be given as the mount option `region=<REGION>`, or through the environment
+    /// variables `AWS_REGION` and `AWS_DEFAULT_REGION`, in this order. Defaults to `eu-central-1`.
+    #[clap(long = "region", value_name = "REGION", global = true)]
+    region: Option<String>,
+    /// Send all requests to this endpoint instead of AWS, e.g. `http://localhost:9000` for MinIO,
+    /// Ceph RGW or LocalStack.
+    ///
+    /// Can also be given as the mount option `endpoint=<URL>`. Buckets are always addressed
+    /// path-style, i.e. as `<URL>/<bucket>/<key>`, as S3-compatible stores usually require.
+    #[clap(long = "endpoint-url", value_name = "URL", global = true)]
+    endpoint_url: Option<String>,
+    /// Read the AWS credentials from the given file descriptor instead of the default credential
+    /// chain.
+    ///
+    /// The credentials can be provided as JSON in the format of the AWS CLI `credential_process`,
+    /// or as `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN` in env-format.
+    #[clap(long = "credentials-fd", value_name = "FD", global = true)]
+    credentials_fd: Option<i32>,
+    /// Attach an additional header to every request sent to S3, e.g. `X-Tenant-Id:my-tenant`.
+    ///
+    /// This is meant for S3-compatible object stores that require proprietary headers. The headers
+    /// are not signed, which is why `x-amz-*` headers can't be set this way. Can be given multiple
+    /// times.
+    #[clap(long = "extra-header", value_name = "NAME:VALUE")]
+    extra_headers: Vec<ExtraHeader>,
+    /// Send at most this many requests per second to S3, across all uploads.
+    ///
+    /// S3 throttles requests per prefix beyond a certain rate, which bulk uploads of small files
+    /// can reach. Up to one second worth of requests can be sent in a burst.
+    #[clap(
+        long = "max-requests-per-second",
+        value_name = "REQUESTS",
+        value_parser = clap::value_parser!(u32).range(1..),
+        global = true
+    )]
+    max_requests_per_second: Option<u32>,
+    /// Seconds to wait for the filesystem to become idle when unmounting on SIGTERM/SIGINT.
+    ///
+    /// If the filesystem is still busy afterwards, it is unmounted lazily.
+    #[clap(long = "unmount-grace-period", default_value_t = 10)]
+    unmount_grace_period: u64,
+    /// Canned ACL to apply to uploaded objects.
+    ///
+    /// If the bucket has ACLs disabled (object ownership `BucketOwnerEnforced`), the ACL is
+    /// ignored, since S3 would reject every upload that specifies one.
+    #[clap(long = "acl", global = true)]
+    acl: Option<CannedAcl>,
+    /// Server-side encryption to request for uploaded objects.
+    ///
+    /// Can also be given as the mount option `sse=<SSE>`.
+    #[clap(long = "sse", global = true)]
+    server_side_encryption: Option<ServerSideEncryption>,
+    /// ID or ARN of the KMS key to use for SSE-KMS.
+    ///
+    /// Only valid together with `--sse aws:kms`. If not provided, the AWS managed key for S3 is
+    /// used. Can also be given as the mount option `kms-key-id=<ID>`.
+    #[clap(long = "sse-kms-key-id", global = true)]
+    ssekms_key_id: Option<String>,
+    /// Use an S3 Bucket Key for SSE-KMS, reducing the number of requests made to KMS.
+    ///
+    /// Only meaningful together with `--sse aws:kms`.
+    #[clap(long = "bucket-key-enabled", global = true)]
+    bucket_key_enabled: bool,
+    /// Encrypt uploaded objects with the customer-provided key (SSE-C) read from this file.
+    ///
+    /// The file has to contain the base64-encoded 256-bit key. The same key is required to read the
+    /// objects again.
+    #[clap(
+        long = "sse-customer-key-file",
+        value_name = "PATH",
+        conflicts_with = "server_side_encryption",
+        global = true
+    )]
+    sse_customer_key_file: Option<PathBuf>,
+    /// Upload objects with the `INTELLIGENT_TIERING` storage class, such that S3 moves them to
+    /// cheaper access tiers once they are not accessed anymore.
+    ///
+    /// Objects smaller than 128 KiB are never moved, and directory buckets don't support it.
+    #[clap(long = "intelligent-tiering", global = true)]
+    intelligent_tiering: bool,
+    /// Tag uploaded objects with this tag, given as `key=value`, to opt them into the archive
+    /// access tiers of S3 Intelligent-Tiering.
+    ///
+    /// The bucket needs an Intelligent-Tiering configuration filtered by this tag that enables the
+    /// archive access tiers. Archived objects have to be restored before they can be read.
+    #[clap(
+        long = "intelligent-tiering-archive-tag",
+        value_name = "KEY=VALUE",
+        requires = "intelligent_tiering",
+        global = true
+    )]
+    intelligent_tiering_archive_tag: Option<ArchiveTag>,
+    /// Upload objects with this storage class, e.g. `STANDARD_IA` for files that are rarely read.
+    ///
+    /// Can also be given as the mount option `storageclass=<CLASS>`. Defaults to the default
+    /// storage class of the bucket, usually `STANDARD`.
+    #[clap(long = "storage-class", value_name = "CLASS", global = true)]
+    storage_class: Option<StorageClass>,
+    /// Copy files whose content was already uploaded during this session instead of uploading them
+    /// again.
+    ///
+    /// The content of all files uploaded is hashed, and if a file with identical content has
+    /// already been uploaded, the existing object is copied server-side to the new key. Only
+    /// uploads of the current session are considered, files uploaded before the filesystem was
+    /// mounted are not detected.
+    #[clap(long = "detect-duplicate-content-within-session")]
+    detect_duplicate_content_within_session: bool,
+    /// Write every file to a key derived from the SHA-256 hash of its content, i.e. the file name
+    /// is replaced by the hexadecimal hash.
+    ///
+    /// Files uploaded with a multipart upload are first written to their regular key, and then
+    /// copied to the final key once their content is complete.
+    #[clap(long = "content-addressed", conflicts_with = "aggregate_small_files")]
+    content_addressed: bool,
+    /// Restrict file names to alphanumerics, `-`, `_`, `.` and `/`, for downstream consumers that
+    /// can't handle other characters in keys.
+    ///
+    /// With `reject` (the default if no value is given), creating a file with any other character
+    /// in its name fails with `EINVAL`. With `transliterate`, the characters are replaced instead,
+    /// e.g. `ä` with `ae` and spaces with `_`.
+    #[clap(
+        long = "strict-key-charset",
+        value_name = "POLICY",
+        num_args = 0..=1,
+        default_missing_value = "reject"
+    )]
+    strict_key_charset: Option<KeyCharsetPolicy>,
+    /// Fold the case of file names before they are used as keys.
+    ///
+    /// Only the file name is affected, not the prefix. Note that file names differing only in
+    /// case, e.g. `File.TXT` and `file.txt`, are then uploaded to the same key.
+    #[clap(long = "key-case", value_name = "CASE", default_value = "preserve")]
+    key_case: KeyCase,
+    /// Derive the key of every file from this pattern, relative to the prefix, e.g.
+    /// `{year}/{month}/{day}/{hostname}-{filename}`.
+    ///
+    /// Supported tokens are `{filename}`, `{stem}`, `{ext}`, `{uuid}`, `{hostname}` and `{year}`,
+    /// `{month}`, `{day}`, `{hour}`, `{minute}`, `{second}` (UTC). Literal braces are written as
+    /// `{{` and `}}`.
+    #[clap(long = "key-pattern", value_name = "PATTERN")]
+    key_pattern: Option<KeyPattern>,
+    /// Bypass the page cache of the kernel for files being uploaded.
+    ///
+    /// Writes are then passed to the filesystem as the application issued them, rather than being
+    /// split into pages, which can improve the throughput of large writes. Note that the kernel
+    /// then no longer caches anything of the file either.
+    #[clap(long = "direct-io")]
+    direct_io: bool,
+    /// Upload files created in directories as if they were created in the root directory, i.e.
+    /// drop the directory from the key and keep only the file name.
+    #[clap(long = "flatten")]
+    flatten: bool,
+    /// Upload files matching a condition to a different bucket, given as `<condition>=<bucket>`.
+    ///
+    /// Supported conditions are `ext:<extension>[,<extension>...]`, matching the extension of the
+    /// file name, and `prefix:<prefix>`, matching the start of the key. Can be given multiple
+    /// times, the first matching route wins. Files matching no route are uploaded to the bucket
+    /// of the mount.
+    #[clap(long = "bucket-route", value_name = "CONDITION=BUCKET")]
+    bucket_routes: Vec<BucketRoute>,
+    /// Reject files whose key collides with existing objects, as it would on a filesystem.
+    ///
+    /// Creating a file fails with `EISDIR` if objects already exist below its key, i.e. it would
+    /// shadow a prefix, and with `ENOTDIR` if an object exists at one of its parent prefixes. This
+    /// requires `s3:ListBucket` and costs a list request per level of the key on every create.
+    #[clap(long = "detect-prefix-shadowing")]
+    detect_prefix_shadowing: bool,
+    /// Reject (the default) or rewrite file names that can't be represented on Windows, i.e.
+    /// reserved device names like `CON` or `LPT1` and names with trailing dots or spaces.
+    ///
+    /// Use this if the mount is re-exported to Windows clients, e.g. through SMB.
+    #[clap(
+        long = "windows-safe-keys",
+        value_name = "POLICY",
+        num_args = 0..=1,
+        default_missing_value = "reject"
+    )]
+    windows_safe_keys: Option<WindowsSafeKeys>,
+    /// Whether to upload files that were closed without any data written to them.
+    ///
+    /// Set to `false` to discard empty files rather than creating zero-byte objects.
+    #[clap(
+        long = "upload-empty-files",
+        value_name = "BOOL",
+        default_value_t = true,
+        action = clap::ArgAction::Set
+    )]
+    upload_empty_files: bool,
+    /// Compress files before uploading them, setting `Content-Encoding` accordingly.
+    #[clap(long = "compress")]
+    compress: bool,
+    /// The algorithm files are compressed with.
+    #[clap(
+        long = "compression",
+        value_name = "ALGORITHM",
+        default_value = "gzip",
+        requires = "compress"
+    )]
+    compression: CompressionAlgorithm,
+    /// A precomputed zstd dictionary, which improves the compression of many small, similar
+    /// files, e.g. trained with `zstd --train` on samples of them.
+    ///
+    /// Objects compressed with a dictionary can only be decompressed with the same dictionary.
+    #[clap(long = "zstd-dictionary", value_name = "PATH", requires = "compress")]
+    zstd_dictionary: Option<PathBuf>,
+    /// Only compress files of at least this size (in bytes), since small files don't benefit from
+    /// compression.
+    ///
+    /// Files are buffered until they reach this size before the multipart upload is started, so
+    /// large values increase the memory used per file.
+    #[clap(
+        long = "compress-min-size",
+        value_name = "BYTES",
+        default_value_t = 0,
+        requires = "compress"
+    )]
+    compress_min_size: u64,
+    /// Print the URL of every uploaded object to stdout once the upload has completed.
+    ///
+    /// Only useful together with `--foreground`, since a daemonized process has no stdout.
+    #[clap(long = "print-urls")]
+    print_urls: Option<UrlStyle>,
+    /// Append every uploaded object to a CSV listing at this path, following the S3 Inventory
+    /// schema (bucket, key, size, last modified date and ETag).
+    ///
+    /// The listing can be diffed against the inventory reports of the bucket to reconcile what
+    /// has been written.
+    #[clap(long = "inventory-csv", value_name = "PATH")]
+    inventory_csv: Option<PathBuf>,
+    /// Write a JSON receipt file (bucket, key, size, ETag, version, time of the upload and SHA-256
+    /// digest) into this existing directory for every uploaded file.
+    ///
+    /// Receipts are written in the background; failing to write one is logged, but doesn't fail
+    /// the upload.
+    #[clap(long = "receipts-dir", value_name = "PATH")]
+    receipts_dir: Option<PathBuf>,
+    /// Append the metrics of every uploaded file to a log at this path, in the CloudWatch
+    /// Embedded Metric Format (EMF).
+    ///
+    /// Ship the log to CloudWatch Logs, e.g. with the CloudWatch agent, to get the metrics
+    /// `BytesUploaded` and `UploadDuration` per bucket without a separate exporter.
+    #[clap(long = "emf-log", value_name = "PATH")]
+    emf_log: Option<PathBuf>,
+    /// The CloudWatch namespace of the metrics in the EMF log.
+    #[clap(
+        long = "emf-namespace",
+        value_name = "NAMESPACE",
+        default_value = "s3-write-only-fs",
+        requires = "emf_log"
+    )]
+    emf_namespace: String,
+    /// Emit an event to the given EventBridge event bus (name or ARN) for every uploaded file,
+    /// with its bucket, key, size and the session ID.
+    ///
+    /// Events are sent in the background; failing to send them is logged, but doesn't fail the
+    /// upload. Requires `events:PutEvents` on the event bus.
+    #[clap(long = "eventbridge-bus", value_name = "NAME")]
+    eventbridge_bus: Option<String>,
+    /// The source of the events emitted to EventBridge, to match on in rules.
+    #[clap(
+        long = "eventbridge-source",
+        value_name = "SOURCE",
+        default_value = "s3-write-only-fs",
+        requires = "eventbridge_bus"
+    )]
+    eventbridge_source: String,
+    /// Expose virtual directories whose files are uploaded below aliased prefixes, as mapped in
+    /// this JSON file, e.g. `{"reports": "archive/reports", "reports/daily": "daily"}`.
+    ///
+    /// Prefixes are relative to the bucket and must not overlap. The file is reloaded on SIGHUP.
+    #[clap(long = "prefix-map", value_name = "PATH")]
+    prefix_map: Option<PathBuf>,
+    /// Create a zero-byte marker object `<prefix>/` for every virtual directory of the prefix map
+    /// when mounting, such that the S3 console and similar tools show it as a folder even while
+    /// it is empty.
+    #[clap(long = "create-directory-markers", requires = "prefix_map")]
+    create_directory_markers: bool,
+    /// Report the mode that files were created with, e.g. for `default_permissions`, instead of
+    /// reporting every file as write-only (`0220`).
+    #[clap(long = "honor-mode")]
+    honor_mode: bool,
+    /// Ask this HTTP endpoint where to upload every file, instead of deriving the location from
+    /// the mount.
+    ///
+    /// The derived location is `POST`ed as `{"bucket": "...", "key": "..."}` when a file is
+    /// created, and the endpoint responds with the final location in the same format. If the
+    /// endpoint fails, creating the file fails with `EIO`.
+    #[clap(long = "upload-router", value_name = "URL")]
+    upload_router: Option<Uri>,
+    /// Aggregate files up to this size (in bytes) into tar archives, instead of uploading every
+    /// file as an individual object.
+    ///
+    /// The archives are uploaded as `aggregate-<timestamp>.tar` below the prefix, once they reach
+    /// `--aggregate-flush-size`, every `--aggregate-flush-interval`, and on unmount.
+    #[clap(long = "aggregate-small-files", value_name = "BYTES")]
+    aggregate_small_files: Option<u64>,
+    /// Upload the aggregate of small files once it reaches this size (in bytes).
+    #[clap(long = "aggregate-flush-size", value_name = "BYTES", default_value_t = 16 * 1024 * 1024)]
+    aggregate_flush_size: u64,
+    /// Upload the aggregate of small files at least this often (in seconds), if it is not empty.
+    #[clap(
+        long = "aggregate-flush-interval",
+        value_name = "SECONDS",
+        default_value_t = 60
+    )]
+    aggregate_flush_interval: u64,
+    /// Treat requests by the given anonymous uid as if they were made by the user that mounted the
+    /// filesystem.
+    ///
+    /// This is required when the filesystem is mounted with `allow_other` and re-exported via NFS
+    /// or used from containers, where root-squash maps writers to an anonymous uid. If no uid is
+    /// given, `65534` (`nobody`) is used.
+    #[clap(
+        long = "map-anonymous-uid",
+        value_name = "UID",
+        num_args = 0..=1,
+        default_missing_value = "65534"
+    )]
+    map_anonymous_uid: Option<u32>,
+    /// Reject all further uploads once a single upload has failed.
+    ///
+    /// This makes a bulk copy fail quickly rather than continuing to upload the remaining files.
+    #[clap(long = "fail-fast", conflicts_with = "best_effort")]
+    fail_fast: bool,
+    /// Continue accepting uploads if an upload failed (the default).
+    #[clap(long = "best-effort")]
+    best_effort: bool,
+    /// Exit with a non-zero code after unmounting if any upload of the session failed.
+    ///
+    /// The code is 3 if uploads failed because of the credentials, 4 if S3 was unreachable, 5 if
+    /// other uploads succeeded and 6 if all uploads failed. Only useful with `--foreground`.
+    #[clap(long = "upload-exit-codes")]
+    upload_exit_codes: bool,
+    /// When to finalize an upload: on every `release`, only once the last open handle to the file
+    /// is closed, or only once the file is committed.
+    ///
+    /// Use `last-close` for tools that open the same file more than once while writing to it.
+    /// With `commit`, a closed file is only uploaded once it is renamed to its name with
+    /// `.committed` appended, and discarded if it is deleted instead.
+    #[clap(long = "finalize-on", value_name = "WHEN", default_value = "release")]
+    finalize_on: FinalizeOn,
+    /// What to do with buffered data when a file is flushed or synced: hold it until a part is
+    /// full, or upload it as a part right away.
+    ///
+    /// S3 only accepts parts smaller than 5 MiB as the last part of an upload, so with
+    /// `partial-upload` less buffered data than that is still held.
+    #[clap(
+        long = "flush-behavior",
+        value_name = "BEHAVIOR",
+        default_value = "hold"
+    )]
+    flush_behavior: FlushBehavior,
+    /// The level at which every successful upload is logged, or `off` to not log them at all.
+    ///
+    /// Failed uploads are still logged as errors, so this can silence per-file messages of
+    /// high-volume ingestion without hiding problems.
+    #[clap(
+        long = "upload-log-level",
+        value_name = "LEVEL",
+        default_value = "info"
+    )]
+    upload_log_level: UploadLogLevel,
+    /// What to do when a file is created while another file with the same key is still being
+    /// written: upload both (`allow`, the last one to finish wins), accept the later file but
+    /// discard everything written to it (`discard`), or reject it with `EBUSY` (`reject`).
+    ///
+    /// Useful for scatter-gather workloads, where several processes may produce the same file.
+    #[clap(
+        long = "concurrent-creates",
+        value_name = "POLICY",
+        default_value = "allow"
+    )]
+    concurrent_creates: ConcurrentCreates,
+    /// What to do when a file is created with a key that was already uploaded during this
+    /// session: upload it anyway (`allow`), upload it but log a warning (`warn`), or reject it
+    /// with `EEXIST` (`reject`).
+    ///
+    /// The uploaded keys are kept in a bloom filter, so a small fraction of new keys is mistaken
+    /// for collisions, unless `--verify-key-collisions` is given.
+    #[clap(
+        long = "key-collisions",
+        value_name = "POLICY",
+        default_value = "allow"
+    )]
+    key_collisions: KeyCollisionPolicy,
+    /// The number of uploaded keys the key collision filter is sized for, at a false positive
+    /// rate of 1%. It uses about 1.2 bytes per key; with more keys, false positives become more
+    /// frequent.
+    #[clap(
+        long = "key-collision-filter-size",
+        value_name = "KEYS",
+        default_value_t = 1_000_000,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    key_collision_filter_size: u64,
+    /// Confirm every probable key collision with a `HeadObject`, ruling out false positives of
+    /// the filter.
+    #[clap(long = "verify-key-collisions")]
+    verify_key_collisions: bool,
+    /// How long (in seconds) an uploaded file stays visible after it was closed, such that tools
+    /// can `stat` it or read its ETag. `0` makes files disappear right after they were closed.
+    #[clap(long = "finished-file-retention", value_name = "SECONDS")]
+    finished_file_retention: Option<u64>,
+    /// The inode of the first file created, rather than 10.
+    ///
+    /// Inodes are assigned sequentially from here in every session, so choosing a distinct base
+    /// per session (e.g. derived from the time) keeps inodes in logs apart across restarts.
+    #[clap(long = "inode-base", value_name = "INODE", value_parser = parse_inode_base)]
+    inode_base: Option<u64>,
+    /// Refuse to open more than this many files at the same time, failing with `EMFILE`.
+    ///
+    /// Every open file holds an upload buffer and potentially a connection to S3, so this guards
+    /// against exhausting memory or the file-descriptor limit of the process.
+    #[clap(long = "max-open-files", value_name = "COUNT")]
+    max_open_files: Option<u32>,
+    /// Hold at most this many bytes (at least 5 MiB) in memory for a single file, uploading them
+    /// as a part once they are reached, even if the part size, a size hint or
+    /// `--compress-min-size` would buffer more.
+    #[clap(
+        long = "max-memory-per-file",
+        value_name = "BYTES",
+        value_parser = clap::value_parser!(u64).range(MULTIPART_MINIMUM_PART_SIZE as u64..)
+    )]
+    max_memory_per_file: Option<u64>,
+    /// Spool the buffered data of files into this existing directory once it grows beyond
+    /// `--spool-threshold`, rather than holding it in memory.
+    ///
+    /// Only the part that is being uploaded is read back into memory.
+    #[clap(long = "spool-dir", value_name = "PATH")]
+    spool_dir: Option<PathBuf>,
+    /// Number of bytes of a single file that are held in memory before it is spooled.
+    #[clap(
+        long = "spool-threshold",
+        value_name = "BYTES",
+        default_value_t = 1024 * 1024,
+        requires = "spool_dir"
+    )]
+    spool_threshold: u64,
+    /// Maximum number of bytes spooled across all files. Writes that would exceed it fail with
+    /// `ENOSPC`.
+    #[clap(long = "spool-max-size", value_name = "BYTES", requires = "spool_dir")]
+    spool_max_size: Option<u64>,
+    /// Maximum number of bytes (at least 5 MiB) spooled for a single file, uploading them as a
+    /// part once they are reached, such that one file can't take up the whole spool.
+    #[clap(
+        long = "spool-max-per-file",
+        value_name = "BYTES",
+        requires = "spool_dir",
+        value_parser = clap::value_parser!(u64).range(MULTIPART_MINIMUM_PART_SIZE as u64..)
+    )]
+    spool_max_per_file: Option<u64>,
+    /// Establish a connection to S3 and fetch credentials on startup, such that the first upload
+    /// isn't slowed down by the TLS handshake and credential lookup.
+    #[clap(long = "warm-connections")]
+    warm_connections: bool,
+    /// Maximum number of threads in the blocking thread pool of the runtime that performs the
+    /// uploads (default: 512).
+    ///
+    /// Blocking work like reading credentials runs on this pool, so lowering it limits the threads
+    /// spawned under heavy concurrency.
+    #[clap(
+        long = "max-blocking-threads",
+        value_name = "COUNT",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    max_blocking_threads: Option<u32>,
+    /// Timestamp of the help files in the root directory, in seconds since the Unix epoch.
+    ///
+    /// Defaults to the time the filesystem was mounted.
+    #[clap(long = "help-file-timestamp", value_name = "SECONDS")]
+    help_file_timestamp: Option<u64>,
+    /// Write a marker object with this key (relative to the prefix) once the filesystem is
+    /// unmounted, or whenever SIGUSR2 is received.
+    ///
+    /// The marker is a small JSON document with the status of the session and the number of
+    /// uploaded and failed files, which downstream consumers can use as a trigger.
+    #[clap(long = "completion-marker", value_name = "KEY")]
+    completion_marker: Option<String>,
+    /// Whether to write the completion marker only if no upload failed during the session, or
+    /// always.
+    #[clap(long = "completion-marker-policy", default_value = "on-success")]
+    completion_marker_policy: CompletionMarkerPolicy,
+    /// Contact the bucket, but only perform read-only checks instead of uploading anything.
+    ///
+    /// The bucket is checked with `HeadBucket` on startup, and every file is checked with
+    /// `HeadObject` for collisions with existing objects when it is closed. The intended uploads
+    /// are logged, but nothing is written to the bucket.
+    #[clap(
+        long = "dry-run-against-real-bucket",
+        conflicts_with = "completion_marker"
+    )]
+    dry_run_against_real_bucket: bool,
+    /// Maximum number of attempts for requests to S3 that fail with a transient error.
+    #[clap(long = "max-attempts", default_value_t = 3)]
+    max_attempts: u32,
+    /// Number of times a part of a multipart upload is retried after a transient error, instead
+    /// of `--max-attempts`.
+    ///
+    /// Parts can be re-uploaded freely, so this can be set higher for large files on unreliable
+    /// connections.
+    #[clap(long = "part-retries", value_name = "RETRIES")]
+    part_retries: Option<u32>,
+    /// Number of times completing a multipart upload is retried after a transient error, instead
+    /// of `--max-attempts`.
+    #[clap(long = "completion-retries", value_name = "RETRIES")]
+    completion_retries: Option<u32>,
+    /// Maximum number of retries available to all uploads combined.
+    ///
+    /// Once exhausted, failed requests are not retried anymore and the affected uploads fail with
+    /// `EAGAIN`, rather than amplifying the load on S3 during an outage. The budget is refilled
+    /// over time, see `--retry-budget-refill`. By default, retries are not limited.
+    #[clap(long = "retry-budget", value_name = "RETRIES")]
+    retry_budget: Option<u32>,
+    /// Number of retries per second the retry budget is refilled by.
+    #[clap(
+        long = "retry-budget-refill",
+        value_name = "RETRIES",
+        default_value_t = 1.0
+    )]
+    retry_budget_refill: f64,
+    /// Pause multipart uploads for up to this many seconds while S3 is unreachable, instead of
+    /// failing them.
+    ///
+    /// The data that could not be sent is kept, and writes to the file block until S3 is reachable
+    /// again. If the timeout elapses, the upload is aborted and the file reports `EIO`.
+    #[clap(long = "network-pause-timeout", value_name = "SECONDS")]
+    network_pause_timeout: Option<u64>,
+    /// Only upload to S3 within this daily window in local time, e.g. `22:00-06:00`.
+    ///
+    /// Outside of the window, written data is held back in the spool and closed files are uploaded
+    /// in the background once the window opens. Writes fail with `ENOSPC` once
+    /// `--spool-max-per-file` or `--spool-max-size` is reached.
+    #[clap(
+        long = "upload-window",
+        value_name = "HH:MM-HH:MM",
+        requires = "spool_dir"
+    )]
+    upload_window: Option<UploadWindow>,
+    /// Keep the full content of files of up to this many bytes (256 MiB if no value is given) in
+    /// memory while they are written, to retry the whole file once as a fresh upload if its
+    /// upload fails.
+    ///
+    /// This trades memory for resilience, e.g. against multipart uploads failing midway.
+    #[clap(
+        long = "buffer-full-for-retry",
+        value_name = "MAX_BYTES",
+        num_args = 0..=1,
+        default_missing_value = "268435456"
+    )]
+    buffer_full_for_retry: Option<u64>,
+    /// What to do when a write fails, e.g. because a part could not be uploaded: abort the upload
+    /// (`fail`), or restore the file to its state before the write, such that the writer can retry
+    /// the write (`restore`).
+    ///
+    /// With `restore`, a file whose failed write is not retried is not uploaded when it is closed.
+    /// Compressed files can't be restored and fail regardless.
+    #[clap(
+        long = "interrupted-writes",
+        value_name = "BEHAVIOR",
+        default_value = "fail"
+    )]
+    interrupted_writes: InterruptedWrites,
+    /// After every upload, write a sidecar object next to it containing the SHA-256 digest of
+    /// the content, in the format of `sha256sum`.
+    #[clap(long = "write-checksum-sidecar")]
+    write_checksum_sidecar: bool,
+    /// The suffix appended to the key of an object to get the key of its checksum sidecar.
+    #[clap(
+        long = "checksum-sidecar-suffix",
+        value_name = "SUFFIX",
+        default_value = ".sha256",
+        requires = "write_checksum_sidecar"
+    )]
+    checksum_sidecar_suffix: String,
+    /// What happens if the checksum sidecar can't be written after the object was uploaded: report
+    /// the upload as failed (`fail`), additionally delete the object (`delete`), or only log a
+    /// warning (`warn`).
+    #[clap(
+        long = "checksum-sidecar-failure",
+        value_name = "POLICY",
+        default_value = "fail",
+        requires = "write_checksum_sidecar"
+    )]
+    checksum_sidecar_failure: SidecarFailurePolicy,
+    /// Attach user-defined metadata to every uploaded object, given as `name=value`.
+    ///
+    /// The value can contain the tokens `${filename}`, `${hostname}`, `${timestamp}` and
+    /// `${session_id}`, which are resolved when the object is uploaded. Use `$$` for a literal `$`.
+    /// Can be given multiple times.
+    #[clap(long = "metadata", value_name = "NAME=VALUE", global = true)]
+    metadata: Vec<MetadataEntry>,
+    /// Correlate all objects and log lines of this mount session with the given ID.
+    ///
+    /// The ID is attached to every uploaded object as `x-amz-meta-session-id` and added to every
+    /// log line. Defaults to a random UUID.
+    #[clap(
+        long = "session-id",
+        value_name = "ID",
+        value_parser = parse_session_id,
+        global = true
+    )]
+    session_id: Option<String>,
+    /// Record in the metadata of every object that it is meant to be downloadable for this many
+    /// days after its upload, for tooling that generates presigned URLs.
+    ///
+    /// This is only a hint: S3 doesn't enforce it. The objects get the metadata
+    /// `x-amz-meta-presign-hint-days` and `x-amz-meta-presign-hint-expires` (RFC 3339).
+    #[clap(
+        long = "presign-hint-days",
+        value_name = "DAYS",
+        value_parser = clap::value_parser!(u32).range(1..),
+        global = true
+    )]
+    presign_hint_days: Option<u32>,
+    /// Stamp every object with the given replication group, along with the region it was
+    /// uploaded to and the time of the upload, for tracing replicated objects back to their
+    /// source.
+    ///
+    /// The objects get the metadata `x-amz-meta-replication-group`, `x-amz-meta-source-region`
+    /// and `x-amz-meta-source-uploaded-at`, which S3 replication copies along with the object.
+    #[clap(
+        long = "replication-group",
+        value_name = "ID",
+        value_parser = parse_replication_group,
+        global = true
+    )]
+    replication_group: Option<String>,
+    /// Serve metrics in the Prometheus text format on `http://<ADDRESS>/metrics`, e.g.
+    /// `127.0.0.1:9464`.
+    ///
+    /// The gauges show how much data is held in memory and how many uploads are in progress or
+    /// waiting, i.e. whether data is written faster than it can be uploaded.
+    #[clap(long = "metrics-address", value_name = "ADDRESS")]
+    metrics_address: Option<SocketAddr>,
     /// Tolerate sloppy mount options, i.e. do not fail if unknown options were passed.
     #[clap(hide = true, short = 's')]
     tolerate_sloppy_mount_options: bool,
@@ -65,6 +915,12 @@ struct Opts {
     /// Don't update /etc/mtab.
     #[clap(hide = true, short = 'n')]
     dont_write_mtab: bool,
+    /// Write a detailed trace of every FUSE operation to this file, rather than to the main log.
+    ///
+    /// Traces are written synchronously, which slows down the filesystem, so this is meant for
+    /// debugging a specific issue.
+    #[clap(long = "trace-file", value_name = "PATH")]
+    trace_file: Option<PathBuf>,
     /// Enable verbose output
     #[clap(hide = true, short = 'v')]
     verbose: bool,
@@ -76,58 +932,352 @@ struct Opts {
     options: Vec<OsString>,
 }
 
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Check that a mountpoint is an active s3-write-only-fs mount and that its bucket is
+    /// reachable.
+    ///
+    /// Prints a one-line status and exits with `0` (OK) or `2` (CRITICAL), following the
+    /// conventions of Nagios/Icinga plugins.
+    Check {
+        /// Mountpoint to check.
+        mountpoint: PathBuf,
+    },
+    /// Upload a small object to the bucket, read it back, verify its content and delete it
+    /// again, without mounting the filesystem.
+    ///
+    /// Uses the credentials and the options that affect how objects are written, e.g. `--sse`,
+    /// which have to be given after `self-test`. Exits with `0` on success and `1` otherwise.
+    SelfTest {
+        /// S3 bucket (with optional prefix) to test, in the same format as for mounting.
+        device: String,
+    },
+}
+
 fn main() -> Result<()> {
     // Parse command-line arguments
     let opts = Opts::parse();
 
+    if let Some(Command::Check { mountpoint }) = &opts.command {
+        let s3 = S3Client::new(region(&opts)?);
+        std::process::exit(check::run(&s3, mountpoint));
+    }
+
+    // Block the signals we handle ourselves before any threads are spawned.
+    signals::block()?;
+
+    let session_id = match &opts.session_id {
+        Some(session_id) => session_id.clone(),
+        None => key_pattern::random_uuid()?,
+    };
+
     // Setup logging
+    let trace_file = opts
+        .trace_file
+        .as_deref()
+        .map(trace_file::open)
+        .transpose()?;
     // Setup terminal logger
     let decorator = slog_term::PlainDecorator::new(std::io::stdout());
     let drain = slog_term::CompactFormat::new(decorator).build().fuse();
     let drain = slog_async::Async::new(drain).build().fuse();
+    let drain = OperationTraces::new(
+        LevelFilter::new(drain, MAIN_LOG_MAX_LEVEL),
+        trace_file.clone(),
+    );
     // Create the root slog-logger.
-    let logger = slog::Logger::root(drain, o!());
+    let logger = slog::Logger::root(drain, o!("session_id" => session_id.clone()));
     // Setup bridge between `log` and `slog`.
     slog_stdlog::init_with_level(log::Level::Info).expect("failed to setup logging");
     // Apply the root logger to the global scope.
     let _global_logger_guard = slog_scope::set_global_logger(logger.clone());
 
     info!("Starting application";
-          "version" => env!("CARGO_PKG_VERSION"));
+          "version" => env!("CARGO_PKG_VERSION"),
+          "session_id" => &session_id);
 
     debug!("Creating S3 client");
-    let s3 = S3Client::new(Region::EuCentral1);
+    let region = region(&opts)?;
+    debug!("Using region {}", region.name());
+    let credentials = fd_credentials(&opts)?;
+    let bucket_and_prefix: BucketAndPrefix = match &opts.command {
+        Some(Command::SelfTest { device }) => device,
+        _ => opts
+            .device
+            .as_deref()
+            .expect("device is required without subcommand"),
+    }
+    .parse()?;
+    let s3 = Arc::new(s3_client(
+        &opts,
+        &bucket_and_prefix,
+        region.clone(),
+        credentials.clone(),
+    )?);
+
+    if let Some(Command::SelfTest { .. }) = &opts.command {
+        let upload_options = upload_options(&opts, &session_id, &region)?;
+        std::process::exit(self_test::run(
+            s3.as_ref(),
+            &bucket_and_prefix,
+            &upload_options,
+        ));
+    }
 
-    let bucket_and_prefix = opts.device.parse()?;
+    // Held until the process exits, which also covers the daemonized process.
+    let _exclusive_lock = if opts.exclusive {
+        Some(ExclusiveLock::acquire(&bucket_and_prefix)?)
+    } else {
+        None
+    };
+    if opts.dry_run_against_real_bucket {
+        tokio::runtime::Runtime::new()?
+            .block_on(s3.head_bucket(HeadBucketRequest {
+                bucket: bucket_and_prefix.s3_bucket_name.clone(),
+                ..Default::default()
+            }))
+            .with_context(|| {
+                format!(
+                    "bucket '{}' is not accessible",
+                    bucket_and_prefix.s3_bucket_name
+                )
+            })?;
+        info!("Dry run: bucket is accessible, nothing will be uploaded";
+              "bucket" => &bucket_and_prefix.s3_bucket_name);
+    }
+    let mut upload_options = upload_options(&opts, &session_id, &region)?;
+    if upload_options.acl.is_some() && bucket_owner_enforced(&s3, &bucket_and_prefix.s3_bucket_name)
+    {
+        warn!(
+            "The bucket has ACLs disabled (object ownership BucketOwnerEnforced), ignoring --acl"
+        );
+        upload_options.acl = None;
+    }
     let options = mount_options(&opts, &bucket_and_prefix);
+    debug!("Effective mount options"; "options" => redacted_mount_options(&options));
     let options_ref = options.iter().map(OsString::as_ref).collect::<Vec<_>>();
-    let mountpoint = opts.mountpoint;
+    // Paths that are used after daemonizing have to be absolute, since the working directory
+    // changes.
+    let current_dir = std::env::current_dir()?;
+    let mountpoint = current_dir.join(
+        opts.mountpoint
+            .expect("mountpoint is required without subcommand"),
+    );
+    if let Some(filesystem) = network_mountpoint::network_filesystem(Path::new(&mountpoint))? {
+        if !opts.allow_network_mountpoint {
+            bail!(
+                "mountpoint '{}' resides on a network or virtual filesystem ({}), which can cause \
+                 hangs; use --allow-network-mountpoint to mount anyway",
+                Path::new(&mountpoint).display(),
+                filesystem
+            );
+        }
+        warn!("Mountpoint resides on a network or virtual filesystem, this can cause hangs";
+              "mountpoint" => %Path::new(&mountpoint).display(),
+              "filesystem" => filesystem);
+    }
+    // Mounting fails with a cryptic error otherwise, and only after daemonizing.
+    fuse_device::check()?;
+    let unmount_grace_period = Duration::from_secs(opts.unmount_grace_period);
+    let session_status = Arc::new(SessionStatus::default());
+    let session_outcome = Arc::clone(&session_status);
+    let upload_exit_codes = opts.upload_exit_codes;
+    let completion_marker_policy = opts.completion_marker_policy;
+    let completion_marker = opts.completion_marker.as_ref().map(|key| {
+        Arc::new(CompletionMarker {
+            s3: s3.clone(),
+            bucket: bucket_and_prefix.s3_bucket_name.clone(),
+            key: match &bucket_and_prefix.prefix_path {
+                Some(prefix_path) => [prefix_path.as_str(), key].join("/"),
+                None => key.to_owned(),
+            },
+            policy: completion_marker_policy,
+            upload_options: upload_options.clone(),
+            session_status: Arc::clone(&session_status),
+        })
+    });
+    let aggregate_flush_size = opts.aggregate_flush_size;
+    let aggregate_flush_interval = Duration::from_secs(opts.aggregate_flush_interval);
+    let aggregation_options = opts
+        .aggregate_small_files
+        .map(|max_file_size| AggregationOptions {
+            max_file_size,
+            flush_size: aggregate_flush_size,
+            flush_interval: aggregate_flush_interval,
+        });
+    let aggregator = aggregation_options.map(|aggregation_options| {
+        Arc::new(Aggregator::new(
+            s3.clone(),
+            bucket_and_prefix.s3_bucket_name.clone(),
+            bucket_and_prefix.prefix_path.clone(),
+            aggregation_options,
+            upload_options.clone(),
+            Arc::clone(&session_status),
+        ))
+    });
+    let prefix_map = opts
+        .prefix_map
+        .as_deref()
+        .map(|path| PrefixMap::load(&current_dir.join(path)))
+        .transpose()?
+        .map(|prefix_map| Arc::new(RwLock::new(prefix_map)));
+    let event_bridge = match &opts.eventbridge_bus {
+        Some(event_bus_name) => Some(Arc::new(EventBridge::new(
+            aws_client(credentials)?,
+            region.clone(),
+            event_bus_name,
+            &opts.eventbridge_source,
+            &session_id,
+        ))),
+        None => None,
+    };
+    let receipts = opts
+        .receipts_dir
+        .as_deref()
+        .map(Receipts::new)
+        .transpose()?
+        .map(Arc::new);
+    let filesystem_options = FilesystemOptions {
+        url_printer: opts
+            .print_urls
+            .map(|url_style| UrlPrinter::new(url_style, region)),
+        map_anonymous_uid: opts.map_anonymous_uid,
+        fail_fast: opts.fail_fast,
+        session_status,
+        strict_key_charset: opts.strict_key_charset,
+        key_case: opts.key_case,
+        key_pattern: opts.key_pattern,
+        windows_safe_keys: opts.windows_safe_keys,
+        finalize_on: opts.finalize_on,
+        flush_behavior: opts.flush_behavior,
+        upload_log_level: opts.upload_log_level,
+        concurrent_creates: opts.concurrent_creates,
+        key_collisions: if opts.key_collisions == KeyCollisionPolicy::Allow {
+            None
+        } else {
+            Some(KeyCollisions::new(
+                opts.key_collisions,
+                opts.key_collision_filter_size,
+                opts.verify_key_collisions,
+            ))
+        },
+        event_bridge: event_bridge.clone(),
+        receipts: receipts.clone(),
+        finished_files_retention: opts.finished_file_retention.map(Duration::from_secs),
+        inode_base: opts.inode_base,
+        max_open_files: opts.max_open_files,
+        warm_connections: opts.warm_connections,
+        max_blocking_threads: opts
+            .max_blocking_threads
+            .map(|max_blocking_threads| max_blocking_threads as usize),
+        inventory: opts
+            .inventory_csv
+            .as_deref()
+            .map(InventoryWriter::open)
+            .transpose()?,
+        emf: match &opts.emf_log {
+            Some(path) => Some(EmfWriter::open(path, opts.emf_namespace)?),
+            None => None,
+        },
+        aggregator: aggregator.clone(),
+        help_file_timestamp: opts
+            .help_file_timestamp
+            .map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds)),
+        prefix_map: prefix_map.clone(),
+        direct_io: opts.direct_io,
+        create_directory_markers: opts.create_directory_markers,
+        honor_mode: opts.honor_mode,
+        detect_prefix_shadowing: opts.detect_prefix_shadowing,
+        flatten: opts.flatten,
+        bucket_routes: opts.bucket_routes,
+        upload_sink: opts
+            .upload_router
+            .map(|url| Arc::new(HttpRouter::new(url)) as Arc<dyn UploadSink>),
+    };
+
+    let metrics_address = opts.metrics_address;
+    let metrics = Arc::clone(&upload_options.metrics);
+    let upload_pause = Arc::clone(&upload_options.pause);
+    let pausable = upload_options.spool.is_some();
+    let serve = move || -> Result<()> {
+        if let Some(metrics_address) = metrics_address {
+            metrics.serve(metrics_address)?;
+        }
+        unmount::unmount_on_signal(PathBuf::from(&mountpoint), unmount_grace_period)?;
+        UploadPause::control_on_signal(&upload_pause, pausable)?;
+        if let Some(completion_marker) = &completion_marker {
+            completion_marker.write_on_signal()?;
+        }
+        if let Some(aggregator) = &aggregator {
+            aggregator.flush_periodically()?;
+        }
+        if let Some(prefix_map) = &prefix_map {
+            PrefixMap::reload_on_signal(prefix_map)?;
+        }
+        if let Some(event_bridge) = &event_bridge {
+            event_bridge.send_in_background()?;
+        }
+        if let Some(receipts) = &receipts {
+            receipts.write_in_background()?;
+        }
+        debug!("Creating S3 write-only filesystem");
+        let s3_write_only_filesystem =
+            S3WriteOnlyFilesystem::new(s3, bucket_and_prefix, upload_options, filesystem_options)?;
+        fuse::mount(s3_write_only_filesystem, &mountpoint, &options_ref).with_context(|| {
+            format!(
+                "failed to mount the filesystem at '{}'",
+                Path::new(&mountpoint).display()
+            )
+        })?;
+        if let Some(aggregator) = &aggregator {
+            if let Err(error) = aggregator.finish() {
+                error!("Failed to upload aggregate of small files"; "error" => %error);
+            }
+        }
+        if let Some(event_bridge) = &event_bridge {
+            event_bridge.finish();
+        }
+        if let Some(receipts) = &receipts {
+            receipts.finish();
+        }
+        if let Some(completion_marker) = &completion_marker {
+            completion_marker.write()?;
+        }
+
+        Ok(())
+    };
 
     if opts.foreground {
         debug!("Staying in foreground");
-        debug!("Creating S3 write-only filesystem");
-        let s3_write_only_filesystem = S3WriteOnlyFilesystem::new(s3, bucket_and_prefix)?;
-        fuse::mount(s3_write_only_filesystem, mountpoint, &options_ref).unwrap();
+        serve()?;
     } else {
         info!(
             "Foreground execution not requested, this process will daemonize now! This means that \
              it will continue to run in the background, serving the write-only filesystem under \
              the requested mountpoint."
         );
+        if !opts.working_directory.is_dir() {
+            bail!(
+                "working directory '{}' does not exist or is not a directory",
+                opts.working_directory.display()
+            );
+        }
         match daemonize::Daemonize::new()
-            .working_directory(std::env::current_dir()?)
+            .working_directory(&opts.working_directory)
             .start()
         {
             Ok(_) => {
                 // Reconfigure logging to use journald
-                let logger = slog::Logger::root(slog_journald::JournaldDrain.ignore_res(), o!());
+                let drain = OperationTraces::new(
+                    LevelFilter::new(slog_journald::JournaldDrain, MAIN_LOG_MAX_LEVEL).ignore_res(),
+                    trace_file,
+                );
+                let logger = slog::Logger::root(drain, o!("session_id" => session_id.clone()));
                 // Apply the root logger to the global scope.
                 let _global_logger_guard = slog_scope::set_global_logger(logger.clone());
 
                 debug!("Daemonized into background successfully");
-                debug!("Creating S3 write-only filesystem");
-                let s3_write_only_filesystem = S3WriteOnlyFilesystem::new(s3, bucket_and_prefix)?;
-                fuse::mount(s3_write_only_filesystem, mountpoint, &options_ref).unwrap();
+                serve()?;
             }
             Err(error) => {
                 error!("Failed to daemonize, the filesystem will not be available";
@@ -136,9 +1286,330 @@ fn main() -> Result<()> {
         }
     }
 
+    if upload_exit_codes {
+        let exit_code = session_outcome.exit_code();
+        if exit_code != 0 {
+            warn!("Uploads of this session failed"; "exit_code" => exit_code);
+        }
+        // Exiting doesn't run destructors, so flush the log before.
+        drop(_global_logger_guard);
+        drop(logger);
+        std::process::exit(exit_code);
+    }
+
     Ok(())
 }
 
+/// The region given with `--region`, the mount option `region=` or the environment, falling back
+/// to eu-central-1.
+///
+/// With `--endpoint-url` or the mount option `endpoint=`, a custom region is returned that sends
+/// all requests to that endpoint instead.
+fn region(opts: &Opts) -> Result<Region> {
+    // An empty value counts as unset, falling through to the next source.
+    let non_empty = |name: &String| !name.is_empty();
+    let name = opts
+        .region
+        .clone()
+        .filter(non_empty)
+        .or_else(|| mount_option(&opts.options, "region=").filter(non_empty))
+        .or_else(|| env::var("AWS_REGION").ok().filter(non_empty))
+        .or_else(|| env::var("AWS_DEFAULT_REGION").ok().filter(non_empty));
+    let endpoint = opts
+        .endpoint_url
+        .clone()
+        .or_else(|| mount_option(&opts.options, "endpoint="));
+    match (name, endpoint) {
+        (name, Some(endpoint)) => {
+            let uri = endpoint
+                .parse::<Uri>()
+                .with_context(|| format!("invalid endpoint URL '{}'", endpoint))?;
+            if uri.host().is_none() {
+                bail!("endpoint URL '{}' has no host", endpoint);
+            }
+            Ok(Region::Custom {
+                name: name.unwrap_or_else(|| Region::EuCentral1.name().to_owned()),
+                endpoint: endpoint.trim_end_matches('/').to_owned(),
+            })
+        }
+        (Some(name), None) => name
+            .parse()
+            .with_context(|| format!("invalid region '{}'", name)),
+        (None, None) => Ok(Region::EuCentral1),
+    }
+}
+
+/// The value of the last mount option starting with `prefix`, e.g. `region=`, if any.
+fn mount_option(options: &[OsString], prefix: &str) -> Option<String> {
+    options
+        .iter()
+        .filter_map(|option| option.to_str()?.strip_prefix(prefix))
+        .next_back()
+        .map(str::to_owned)
+}
+
+/// The credentials given with `--credentials-fd`, if any, which can only be read once.
+fn fd_credentials(opts: &Opts) -> Result<Option<StaticProvider>> {
+    opts.credentials_fd
+        .map(|fd| {
+            debug!("Reading credentials from file descriptor {}", fd);
+            credentials::read_from_fd(fd)
+        })
+        .transpose()
+}
+
+/// The S3 client for the bucket, which goes through the zonal endpoint with session credentials
+/// for S3 Express One Zone directory buckets.
+fn s3_client(
+    opts: &Opts,
+    bucket_and_prefix: &BucketAndPrefix,
+    region: Region,
+    credentials: Option<StaticProvider>,
+) -> Result<S3Client> {
+    let dispatcher = ExtraHeadersDispatcher::new(HttpClient::new()?, opts.extra_headers.clone());
+    let dispatcher = RateLimitedDispatcher::new(
+        dispatcher,
+        opts.max_requests_per_second
+            .map(|requests_per_second| Arc::new(RequestRateLimiter::new(requests_per_second))),
+    );
+
+    if let Some(zone_id) = bucket_and_prefix.directory_bucket_zone_id() {
+        if let Region::Custom { endpoint, .. } = &region {
+            bail!(
+                "'{}' is a directory bucket, which is always reached through its zonal \
+                 endpoint, it can't be used with the endpoint URL '{}'",
+                bucket_and_prefix.s3_bucket_name,
+                endpoint
+            );
+        }
+        info!("Using S3 Express One Zone sessions for directory bucket";
+              "bucket" => &bucket_and_prefix.s3_bucket_name,
+              "zone_id" => zone_id);
+        let bucket = &bucket_and_prefix.s3_bucket_name;
+        let client = match credentials {
+            Some(credentials) => Client::new_not_signing(S3ExpressDispatcher::new(
+                dispatcher,
+                credentials,
+                bucket,
+                zone_id,
+                region.clone(),
+            )),
+            None => Client::new_not_signing(S3ExpressDispatcher::new(
+                dispatcher,
+                DefaultCredentialsProvider::new()?,
+                bucket,
+                zone_id,
+                region.clone(),
+            )),
+        };
+        return Ok(S3Client::new_with_client(client, region));
+    }
+
+    Ok(match credentials {
+        Some(credentials) => S3Client::new_with(dispatcher, credentials, region),
+        None => S3Client::new_with(dispatcher, DefaultCredentialsProvider::new()?, region),
+    })
+}
+
+/// A client for AWS services other than S3, using the same credentials.
+fn aws_client(credentials: Option<StaticProvider>) -> Result<Client> {
+    Ok(match credentials {
+        Some(credentials) => Client::new_with(credentials, HttpClient::new()?),
+        None => Client::new_with(DefaultCredentialsProvider::new()?, HttpClient::new()?),
+    })
+}
+
+/// The server-side encryption and KMS key given with `--sse` and `--sse-kms-key-id`, or the mount
+/// options `sse=` and `kms-key-id=`, e.g. `-o sse=aws:kms,kms-key-id=<key-id-or-arn>`.
+fn server_side_encryption(opts: &Opts) -> Result<(Option<ServerSideEncryption>, Option<String>)> {
+    let server_side_encryption = match (
+        opts.server_side_encryption,
+        mount_option(&opts.options, "sse="),
+    ) {
+        (Some(server_side_encryption), _) => Some(server_side_encryption),
+        (None, Some(value)) => Some(
+            ServerSideEncryption::from_str(&value, false)
+                .map_err(|error| anyhow!("invalid mount option 'sse={}': {}", value, error))?,
+        ),
+        (None, None) => None,
+    };
+    let ssekms_key_id = opts
+        .ssekms_key_id
+        .clone()
+        .or_else(|| mount_option(&opts.options, "kms-key-id="));
+
+    Ok((server_side_encryption, ssekms_key_id))
+}
+
+/// The storage class given with `--storage-class` or the mount option `storageclass=`.
+fn storage_class(opts: &Opts) -> Result<Option<StorageClass>> {
+    let storage_class = match (
+        opts.storage_class,
+        mount_option(&opts.options, "storageclass="),
+    ) {
+        (Some(storage_class), _) => Some(storage_class),
+        (None, Some(value)) => Some(StorageClass::from_str(&value, false).map_err(|error| {
+            anyhow!("invalid mount option 'storageclass={}': {}", value, error)
+        })?),
+        (None, None) => None,
+    };
+    if opts.intelligent_tiering
+        && storage_class
+            .is_some_and(|storage_class| storage_class != StorageClass::IntelligentTiering)
+    {
+        bail!("--intelligent-tiering can't be combined with another storage class");
+    }
+
+    Ok(storage_class)
+}
+
+/// The content types given with the mount option `content-type=`, which can be repeated, e.g.
+/// `-o content-type=log:text/plain,content-type=parquet:application/vnd.apache.parquet`.
+fn content_types(opts: &Opts) -> Result<ContentTypes> {
+    let overrides = opts
+        .options
+        .iter()
+        .filter_map(|option| option.to_str()?.strip_prefix("content-type="))
+        .map(|value| {
+            value.parse().map_err(|error| {
+                anyhow!("invalid mount option 'content-type={}': {}", value, error)
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(ContentTypes::new(overrides))
+}
+
+fn upload_options(opts: &Opts, session_id: &str, region: &Region) -> Result<UploadOptions> {
+    let (server_side_encryption, ssekms_key_id) = server_side_encryption(opts)?;
+    let is_sse_kms = server_side_encryption == Some(ServerSideEncryption::AwsKms);
+    if ssekms_key_id.is_some() && !is_sse_kms {
+        let source = if opts.ssekms_key_id.is_some() {
+            "--sse-kms-key-id"
+        } else {
+            "The mount option kms-key-id="
+        };
+        bail!(
+            "{} can only be used together with --sse aws:kms or the mount option sse=aws:kms",
+            source
+        );
+    }
+    if opts.bucket_key_enabled && !is_sse_kms {
+        warn!("--bucket-key-enabled has no effect without --sse aws:kms, ignoring it");
+    }
+
+    Ok(UploadOptions {
+        acl: opts.acl,
+        server_side_encryption,
+        ssekms_key_id,
+        bucket_key_enabled: opts.bucket_key_enabled && is_sse_kms,
+        storage_class: storage_class(opts)?,
+        content_types: content_types(opts)?,
+        intelligent_tiering: opts.intelligent_tiering.then(|| IntelligentTiering {
+            archive_tag: opts.intelligent_tiering_archive_tag.clone(),
+        }),
+        sse_customer_key: opts
+            .sse_customer_key_file
+            .as_deref()
+            .map(SseCustomerKey::read)
+            .transpose()?,
+        duplicate_content_index: if opts.detect_duplicate_content_within_session {
+            Some(Arc::new(ContentIndex::default()))
+        } else {
+            None
+        },
+        skip_empty_files: !opts.upload_empty_files,
+        dry_run: opts.dry_run_against_real_bucket,
+        content_addressed: opts.content_addressed,
+        network_pause_timeout: opts.network_pause_timeout.map(Duration::from_secs),
+        upload_window: opts.upload_window,
+        full_retry_max_size: opts.buffer_full_for_retry,
+        hash_all_content: opts.receipts_dir.is_some(),
+        max_memory_per_file: opts.max_memory_per_file.map(|max| max as usize),
+        spool: opts
+            .spool_dir
+            .as_deref()
+            .map(|directory| {
+                Spool::new(
+                    directory,
+                    opts.spool_threshold as usize,
+                    opts.spool_max_size,
+                    opts.spool_max_per_file,
+                )
+            })
+            .transpose()?
+            .map(Arc::new),
+        interrupted_writes: opts.interrupted_writes,
+        checksum_sidecar: opts.write_checksum_sidecar.then(|| ChecksumSidecar {
+            suffix: opts.checksum_sidecar_suffix.clone(),
+            on_failure: opts.checksum_sidecar_failure,
+        }),
+        metadata: ObjectMetadata::new(
+            opts.metadata.clone(),
+            session_id.to_owned(),
+            opts.presign_hint_days,
+            opts.replication_group.clone().map(|group| Replication {
+                source_region: region.name().to_owned(),
+                group,
+            }),
+        )?,
+        metrics: Arc::new(Metrics::default()),
+        pause: Arc::new(UploadPause::default()),
+        compress_min_size: if opts.compress {
+            Some(opts.compress_min_size)
+        } else {
+            None
+        },
+        compression: Codec::new(opts.compression, opts.zstd_dictionary.as_deref())?,
+        retry: RetryPolicy {
+            max_attempts: opts.max_attempts.max(1),
+            budget: opts
+                .retry_budget
+                .map(|capacity| Arc::new(RetryBudget::new(capacity, opts.retry_budget_refill))),
+            part_max_attempts: opts.part_retries.map(|retries| retries.saturating_add(1)),
+            completion_max_attempts: opts
+                .completion_retries
+                .map(|retries| retries.saturating_add(1)),
+            ..Default::default()
+        },
+    })
+}
+
+/// Check whether the bucket has ACLs disabled, i.e. its object ownership is `BucketOwnerEnforced`.
+///
+/// If the ownership controls can't be retrieved, e.g. because they are not configured or we lack
+/// the `s3:GetBucketOwnershipControls` permission, the bucket is assumed to support ACLs.
+fn bucket_owner_enforced(s3: &S3Client, bucket: &str) -> bool {
+    let result = tokio::runtime::Runtime::new()
+        .map_err(anyhow::Error::from)
+        .and_then(|runtime| {
+            runtime
+                .block_on(
+                    s3.get_bucket_ownership_controls(GetBucketOwnershipControlsRequest {
+                        bucket: bucket.to_owned(),
+                        ..Default::default()
+                    }),
+                )
+                .map_err(anyhow::Error::from)
+        });
+    match result {
+        Ok(output) => output
+            .ownership_controls
+            .map(|ownership_controls| {
+                ownership_controls
+                    .rules
+                    .iter()
+                    .any(|rule| rule.object_ownership == "BucketOwnerEnforced")
+            })
+            .unwrap_or(false),
+        Err(error) => {
+            debug!("Failed to retrieve the bucket ownership controls, assuming ACLs are supported";
+                   "error" => %error);
+            false
+        }
+    }
+}
+
 fn mount_options(opts: &Opts, bucket_and_prefix: &BucketAndPrefix) -> Vec<OsString> {
     let mut options: Vec<OsString> = vec![];
     if opts.tolerate_sloppy_mount_options {
@@ -159,9 +1630,60 @@ fn mount_options(opts: &Opts, bucket_and_prefix: &BucketAndPrefix) -> Vec<OsStri
         "-o".into(),
         "subtype=s3wofs".into(),
     ]);
-    for option in &opts.options {
+    // The options of the S3 client are not meant for FUSE, see `region`.
+    for option in opts.options.iter().filter(|option| {
+        !option.to_str().is_some_and(|option| {
+            CLIENT_MOUNT_OPTIONS
+                .iter()
+                .any(|prefix| option.starts_with(prefix))
+        })
+    }) {
         options.extend_from_slice(&["-o".into(), option.to_owned()]);
     }
 
     options
 }
+
+/// Render the mount options for logging, with the values of options that might carry secrets
+/// replaced.
+fn redacted_mount_options(options: &[OsString]) -> String {
+    const SENSITIVE_NAMES: &[&str] = &["pass", "secret", "token", "key", "credential"];
+
+    options
+        .iter()
+        .map(|option| {
+            option
+                .to_string_lossy()
+                .split(',')
+                .map(|option| match option.split_once('=') {
+                    Some((name, _))
+                        if SENSITIVE_NAMES
+                            .iter()
+                            .any(|sensitive| name.to_lowercase().contains(sensitive)) =>
+                    {
+                        format!("{}=<redacted>", name)
+                    }
+                    _ => option.to_owned(),
+                })
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[test]
+fn sensitive_mount_options_are_redacted() {
+    let options = [
+        OsString::from("-o"),
+        OsString::from("allow_other,aws_secret_access_key=abc123,region=eu-west-1"),
+        OsString::from("-o"),
+        OsString::from("Session_Token=xyz"),
+    ];
+
+    assert_eq!(
+        "-o allow_other,aws_secret_access_key=<redacted>,region=eu-west-1 -o \
+         Session_Token=<redacted>",
+        redacted_mount_options(&options)
+    );
+}
@@ -0,0 +1,197 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{
+    Context,
+    Result,
+};
+use hyper::{
+    service::{
+        make_service_fn,
+        service_fn,
+    },
+    Body,
+    Method,
+    Request,
+    Response,
+    Server,
+    StatusCode,
+};
+use slog_scope::{
+    error,
+    info,
+};
+use std::{
+    convert::Infallible,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{
+        atomic::{
+            AtomicI64,
+            Ordering,
+        },
+        Arc,
+    },
+    thread,
+};
+
+/// Gauges that show whether the filesystem is saturated, i.e. data is written faster than it can be
+/// uploaded.
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    buffered_bytes: AtomicI64,
+    in_flight_parts: AtomicI64,
+    open_uploads: AtomicI64,
+    waiting_uploads: AtomicI64,
+}
+
+/// Decrements a gauge when dropped.
+#[must_use]
+pub(crate) struct GaugeGuard<'a>(&'a AtomicI64);
+
+impl Drop for GaugeGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    /// Record that the number of bytes held in memory changed by `delta`.
+    pub(crate) fn add_buffered_bytes(&self, delta: i64) {
+        self.buffered_bytes.fetch_add(delta, Ordering::Relaxed);
+    }
+
+    pub(crate) fn upload_opened(&self) {
+        self.open_uploads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn upload_closed(&self) {
+        self.open_uploads.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Record that a part is being uploaded, until the returned guard is dropped.
+    pub(crate) fn part_in_flight(&self) -> GaugeGuard<'_> {
+        Self::increment(&self.in_flight_parts)
+    }
+
+    /// Record that an upload is waiting to be allowed to continue, e.g. for S3 to become reachable
+    /// again, until the returned guard is dropped.
+    pub(crate) fn upload_waiting(&self) -> GaugeGuard<'_> {
+        Self::increment(&self.waiting_uploads)
+    }
+
+    fn increment(gauge: &AtomicI64) -> GaugeGuard<'_> {
+        gauge.fetch_add(1, Ordering::Relaxed);
+        GaugeGuard(gauge)
+    }
+
+    /// Render the metrics in the Prometheus text exposition format.
+    pub(crate) fn render(&self) -> String {
+        let gauges = [
+            (
+                "s3wofs_buffered_bytes",
+                "Bytes written to open files that are held in memory until they are uploaded.",
+                &self.buffered_bytes,
+            ),
+            (
+                "s3wofs_in_flight_parts",
+                "Parts of multipart uploads that are currently being uploaded.",
+                &self.in_flight_parts,
+            ),
+            (
+                "s3wofs_open_uploads",
+                "Files that are open for writing and not uploaded yet.",
+                &self.open_uploads,
+            ),
+            (
+                "s3wofs_waiting_uploads",
+                "Uploads that wait for the upload window to open or for S3 to become reachable.",
+                &self.waiting_uploads,
+            ),
+        ];
+        let mut output = String::new();
+        for (name, help, gauge) in gauges {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} gauge", name);
+            let _ = writeln!(output, "{} {}", name, gauge.load(Ordering::Relaxed));
+        }
+
+        output
+    }
+
+    fn respond(&self, request: Request<Body>) -> Response<Body> {
+        let mut response = Response::new(Body::empty());
+        if request.method() != Method::GET || request.uri().path() != "/metrics" {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return response;
+        }
+        response.headers_mut().insert(
+            hyper::header::CONTENT_TYPE,
+            hyper::header::HeaderValue::from_static("text/plain; version=0.0.4"),
+        );
+        *response.body_mut() = Body::from(self.render());
+
+        response
+    }
+
+    /// Serve the metrics on `http://<address>/metrics` in a background thread.
+    pub(crate) fn serve(self: &Arc<Self>, address: SocketAddr) -> Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        // Bind right away, such that an unusable address is reported on startup.
+        let server = runtime
+            .block_on(async { Server::try_bind(&address) })
+            .with_context(|| format!("failed to listen for metrics requests on {}", address))?;
+        let metrics = Arc::clone(self);
+        thread::Builder::new()
+            .name("metrics".to_owned())
+            .spawn(move || {
+                let make_service = make_service_fn(move |_| {
+                    let metrics = Arc::clone(&metrics);
+                    async move {
+                        Ok::<_, Infallible>(service_fn(move |request| {
+                            let response = metrics.respond(request);
+                            async move { Ok::<_, Infallible>(response) }
+                        }))
+                    }
+                });
+                info!("Serving metrics"; "address" => %address);
+                if let Err(error) = runtime.block_on(server.serve(make_service)) {
+                    error!("Failed to serve metrics"; "error" => %error);
+                }
+            })?;
+
+        Ok(())
+    }
+}
+
+#[test]
+fn metrics_are_rendered() {
+    let metrics = Metrics::default();
+    metrics.add_buffered_bytes(42);
+    metrics.upload_opened();
+    let in_flight = metrics.part_in_flight();
+
+    let output = metrics.render();
+    assert!(output.contains("# TYPE s3wofs_buffered_bytes gauge\ns3wofs_buffered_bytes 42\n"));
+    assert!(output.contains("\ns3wofs_in_flight_parts 1\n"));
+    assert!(output.contains("\ns3wofs_open_uploads 1\n"));
+    assert!(output.contains("\ns3wofs_waiting_uploads 0\n"));
+
+    drop(in_flight);
+    assert!(metrics.render().contains("\ns3wofs_in_flight_parts 0\n"));
+}
@@ -0,0 +1,72 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{
+    Context,
+    Result,
+};
+use std::{
+    ffi::CString,
+    io,
+    mem::MaybeUninit,
+    os::unix::ffi::OsStrExt,
+    path::Path,
+};
+
+/// Determine whether `path` resides on a network or virtual filesystem, returning its name if so.
+///
+/// FUSE mounts on top of such filesystems (e.g. an NFS-mounted mountpoint) behave oddly and can
+/// deadlock.
+pub(crate) fn network_filesystem(path: &Path) -> Result<Option<&'static str>> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .with_context(|| format!("invalid mountpoint '{}'", path.display()))?;
+    let mut statfs = MaybeUninit::<libc::statfs>::uninit();
+    if unsafe { libc::statfs(c_path.as_ptr(), statfs.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error())
+            .with_context(|| format!("failed to inspect mountpoint '{}'", path.display()));
+    }
+    let statfs = unsafe { statfs.assume_init() };
+
+    // `f_type` differs in width and signedness between platforms, the magic numbers are 32 bits.
+    Ok(filesystem_name(statfs.f_type as u32))
+}
+
+/// Name of the filesystem with the given magic number, if it is a network or virtual filesystem.
+fn filesystem_name(magic: u32) -> Option<&'static str> {
+    match magic {
+        0x6969 => Some("nfs"),
+        0x517b => Some("smb"),
+        0xff53_4d42 => Some("cifs"),
+        0xfe53_4d42 => Some("smb2"),
+        0x0102_1997 => Some("9p"),
+        0x00c3_6400 => Some("ceph"),
+        0x5346_414f => Some("afs"),
+        0x7375_7245 => Some("coda"),
+        0x6573_5546 => Some("fuse"),
+        _ => None,
+    }
+}
+
+#[test]
+fn network_filesystems_are_detected() {
+    assert_eq!(Some("nfs"), filesystem_name(0x6969));
+    assert_eq!(Some("cifs"), filesystem_name(0xff53_4d42));
+    assert_eq!(Some("fuse"), filesystem_name(0x6573_5546));
+    // ext4
+    assert_eq!(None, filesystem_name(0xef53));
+    // tmpfs
+    assert_eq!(None, filesystem_name(0x0102_1994));
+}
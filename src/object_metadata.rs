@@ -0,0 +1,373 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::key_pattern::hostname;
+use anyhow::{
+    anyhow,
+    bail,
+    Result,
+};
+use chrono::{
+    DateTime,
+    Duration,
+    SecondsFormat,
+    Utc,
+};
+use std::{
+    collections::HashMap,
+    str::FromStr,
+};
+
+/// A user-defined metadata entry attached to every uploaded object, given as `name=value`.
+///
+/// The value can contain tokens that are resolved when the object is uploaded:
+///
+/// * `${filename}`: the name of the file, i.e. the last segment of its key,
+/// * `${hostname}`: the name of the host the filesystem is mounted on,
+/// * `${timestamp}`: the time of the upload in UTC, in RFC 3339 format,
+/// * `${session_id}`: a random UUID identifying the mount session.
+///
+/// A literal `$` is written as `$$`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MetadataEntry {
+    name: String,
+    value: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Token(Token),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Filename,
+    Hostname,
+    Timestamp,
+    SessionId,
+}
+
+impl FromStr for Token {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "filename" => Token::Filename,
+            "hostname" => Token::Hostname,
+            "timestamp" => Token::Timestamp,
+            "session_id" => Token::SessionId,
+            _ => bail!("unknown token '${{{}}}'", s),
+        })
+    }
+}
+
+impl FromStr for MetadataEntry {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("metadata must be given as name=value"))?;
+        // The name becomes part of an `x-amz-meta-*` header, which S3 stores in lowercase.
+        let name = name.trim().to_ascii_lowercase();
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            bail!("invalid metadata name '{}'", name);
+        }
+
+        let mut segments = vec![];
+        let mut literal = String::new();
+        let mut chars = value.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '$' if chars.as_str().starts_with('$') => {
+                    chars.next();
+                    literal.push('$');
+                }
+                '$' if chars.as_str().starts_with('{') => {
+                    let rest = &chars.as_str()[1..];
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| anyhow!("unclosed '${{' in metadata '{}'", name))?;
+                    let token = rest[..end].parse()?;
+                    chars = rest[end + 1..].chars();
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Token(token));
+                }
+                '$' => bail!(
+                    "unescaped '$' in metadata '{}', use '$$' for a literal dollar sign",
+                    name
+                ),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(MetadataEntry {
+            name,
+            value: segments,
+        })
+    }
+}
+
+/// The name of the metadata entry carrying the session ID, i.e. `x-amz-meta-session-id`.
+const SESSION_ID_NAME: &str = "session-id";
+/// The name of the metadata entry carrying the number of days an object is meant to be
+/// downloadable, i.e. `x-amz-meta-presign-hint-days`.
+const PRESIGN_HINT_DAYS_NAME: &str = "presign-hint-days";
+/// The name of the metadata entry carrying the time until which an object is meant to be
+/// downloadable, i.e. `x-amz-meta-presign-hint-expires`.
+const PRESIGN_HINT_EXPIRES_NAME: &str = "presign-hint-expires";
+/// The name of the metadata entry carrying the region the object was originally uploaded to,
+/// i.e. `x-amz-meta-source-region`.
+const SOURCE_REGION_NAME: &str = "source-region";
+/// The name of the metadata entry carrying the replication group of the object, i.e.
+/// `x-amz-meta-replication-group`.
+const REPLICATION_GROUP_NAME: &str = "replication-group";
+/// The name of the metadata entry carrying the time the object was originally uploaded, i.e.
+/// `x-amz-meta-source-uploaded-at`.
+const SOURCE_UPLOADED_AT_NAME: &str = "source-uploaded-at";
+/// The maximum length of IDs given on the command line, keeping them well below the 2 KiB S3
+/// allows for all user-defined metadata.
+const MAX_ID_LEN: usize = 128;
+
+/// Parse a session ID given on the command line, which has to be usable as a header value.
+pub(crate) fn parse_session_id(s: &str) -> Result<String> {
+    parse_id("session ID", s)
+}
+
+/// Parse a replication group given on the command line, which has to be usable as a header value.
+pub(crate) fn parse_replication_group(s: &str) -> Result<String> {
+    parse_id("replication group", s)
+}
+
+fn parse_id(what: &str, s: &str) -> Result<String> {
+    if s.is_empty() || s.len() > MAX_ID_LEN {
+        bail!(
+            "the {} must be between 1 and {} characters long",
+            what,
+            MAX_ID_LEN
+        );
+    }
+    if !s.chars().all(|c| c.is_ascii_graphic()) {
+        bail!(
+            "the {} must only contain printable ASCII characters without spaces",
+            what
+        );
+    }
+    Ok(s.to_owned())
+}
+
+/// Metadata tracing objects that are replicated to other regions back to their source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Replication {
+    /// The region the filesystem uploads to.
+    pub(crate) source_region: String,
+    /// An ID grouping the objects that are replicated together, e.g. named after the pipeline.
+    pub(crate) group: String,
+}
+
+/// The user-defined metadata attached to every uploaded object.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ObjectMetadata {
+    entries: Vec<MetadataEntry>,
+    hostname: String,
+    session_id: String,
+    /// If set, the number of days an object is meant to be downloadable through presigned URLs,
+    /// counted from its upload. This is only a hint for the tooling generating the URLs.
+    presign_hint_days: Option<u32>,
+    /// If set, the objects are stamped with their source region and replication group.
+    replication: Option<Replication>,
+}
+
+impl ObjectMetadata {
+    pub(crate) fn new(
+        entries: Vec<MetadataEntry>,
+        session_id: String,
+        presign_hint_days: Option<u32>,
+        replication: Option<Replication>,
+    ) -> Result<ObjectMetadata> {
+        Ok(ObjectMetadata {
+            entries,
+            hostname: hostname()?,
+            session_id,
+            presign_hint_days,
+            replication,
+        })
+    }
+
+    /// The ID of the mount session, attached to every object as `x-amz-meta-session-id`.
+    pub(crate) fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    /// Resolve the metadata of the object at `key`, which is uploaded at `time`.
+    pub(crate) fn resolve(
+        &self,
+        key: &str,
+        time: DateTime<Utc>,
+    ) -> Option<HashMap<String, String>> {
+        let filename = key.rsplit('/').next().unwrap_or(key);
+        let timestamp = time.to_rfc3339_opts(SecondsFormat::Secs, true);
+
+        let mut resolved = HashMap::new();
+        if !self.session_id.is_empty() {
+            resolved.insert(SESSION_ID_NAME.to_owned(), self.session_id.clone());
+        }
+        if let Some(days) = self.presign_hint_days {
+            let expires = time + Duration::days(days.into());
+            resolved.insert(PRESIGN_HINT_DAYS_NAME.to_owned(), days.to_string());
+            resolved.insert(
+                PRESIGN_HINT_EXPIRES_NAME.to_owned(),
+                expires.to_rfc3339_opts(SecondsFormat::Secs, true),
+            );
+        }
+        if let Some(replication) = &self.replication {
+            resolved.insert(
+                SOURCE_REGION_NAME.to_owned(),
+                replication.source_region.clone(),
+            );
+            resolved.insert(REPLICATION_GROUP_NAME.to_owned(), replication.group.clone());
+            resolved.insert(SOURCE_UPLOADED_AT_NAME.to_owned(), timestamp.clone());
+        }
+        // User-defined entries take precedence.
+        resolved.extend(self.entries.iter().map(|entry| {
+            let value = entry
+                .value
+                .iter()
+                .map(|segment| match segment {
+                    Segment::Literal(literal) => literal.as_str(),
+                    Segment::Token(Token::Filename) => filename,
+                    Segment::Token(Token::Hostname) => &self.hostname,
+                    Segment::Token(Token::Timestamp) => &timestamp,
+                    Segment::Token(Token::SessionId) => &self.session_id,
+                })
+                .collect();
+            (entry.name.clone(), value)
+        }));
+
+        if resolved.is_empty() {
+            None
+        } else {
+            Some(resolved)
+        }
+    }
+}
+
+#[test]
+fn metadata_tokens_are_resolved() {
+    use chrono::TimeZone;
+
+    let metadata = ObjectMetadata {
+        entries: vec![
+            "Source=${hostname}/${filename}".parse().unwrap(),
+            "uploaded=${timestamp}".parse().unwrap(),
+            "session=${session_id}".parse().unwrap(),
+        ],
+        hostname: "uploader".to_owned(),
+        session_id: "0123".to_owned(),
+        presign_hint_days: None,
+        replication: None,
+    };
+    let time = Utc.with_ymd_and_hms(2025, 1, 2, 3, 4, 5).unwrap();
+
+    let resolved = metadata.resolve("prefix/my-file.txt", time).unwrap();
+    assert_eq!(4, resolved.len());
+    assert_eq!("uploader/my-file.txt", resolved["source"]);
+    assert_eq!("2025-01-02T03:04:05Z", resolved["uploaded"]);
+    assert_eq!("0123", resolved["session"]);
+    assert_eq!("0123", resolved["session-id"]);
+    assert_eq!(None, ObjectMetadata::default().resolve("my-file", time));
+}
+
+#[test]
+fn literal_dollar_signs_are_escaped() {
+    let metadata = ObjectMetadata {
+        entries: vec!["price=$$5 ${filename} $${filename}".parse().unwrap()],
+        ..Default::default()
+    };
+
+    let resolved = metadata.resolve("my-file", Utc::now()).unwrap();
+    assert_eq!("$5 my-file ${filename}", resolved["price"]);
+    assert!("price=$5".parse::<MetadataEntry>().is_err());
+    assert!("name=${unknown}".parse::<MetadataEntry>().is_err());
+    assert!("name=${filename".parse::<MetadataEntry>().is_err());
+    assert!("=value".parse::<MetadataEntry>().is_err());
+    assert!("no-value".parse::<MetadataEntry>().is_err());
+}
+
+#[test]
+fn session_ids_are_attached_without_entries() {
+    let metadata = ObjectMetadata {
+        session_id: "nightly-import".to_owned(),
+        ..Default::default()
+    };
+
+    let resolved = metadata.resolve("my-file", Utc::now()).unwrap();
+    assert_eq!(1, resolved.len());
+    assert_eq!("nightly-import", resolved["session-id"]);
+    assert!(parse_session_id("nightly-import").is_ok());
+    assert!(parse_session_id("").is_err());
+    assert!(parse_session_id("with space").is_err());
+    assert!(parse_session_id(&"x".repeat(129)).is_err());
+}
+
+#[test]
+fn presign_hints_are_attached() {
+    use chrono::TimeZone;
+
+    let metadata = ObjectMetadata {
+        presign_hint_days: Some(7),
+        ..Default::default()
+    };
+    let time = Utc.with_ymd_and_hms(2025, 1, 2, 3, 4, 5).unwrap();
+
+    let resolved = metadata.resolve("my-file", time).unwrap();
+    assert_eq!(2, resolved.len());
+    assert_eq!("7", resolved["presign-hint-days"]);
+    assert_eq!("2025-01-09T03:04:05Z", resolved["presign-hint-expires"]);
+}
+
+#[test]
+fn replication_metadata_is_attached() {
+    use chrono::TimeZone;
+
+    let metadata = ObjectMetadata {
+        entries: vec!["replication-group=overridden".parse().unwrap()],
+        replication: Some(Replication {
+            source_region: "eu-central-1".to_owned(),
+            group: "orders".to_owned(),
+        }),
+        ..Default::default()
+    };
+    let time = Utc.with_ymd_and_hms(2025, 1, 2, 3, 4, 5).unwrap();
+
+    let resolved = metadata.resolve("my-file", time).unwrap();
+    assert_eq!(3, resolved.len());
+    assert_eq!("eu-central-1", resolved["source-region"]);
+    assert_eq!("overridden", resolved["replication-group"]);
+    assert_eq!("2025-01-02T03:04:05Z", resolved["source-uploaded-at"]);
+    assert!(parse_replication_group("orders").is_ok());
+    assert!(parse_replication_group("with space").is_err());
+}
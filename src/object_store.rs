@@ -0,0 +1,548 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use async_trait::async_trait;
+use rusoto_core::RusotoError;
+use rusoto_s3::{
+    AbortMultipartUploadError,
+    AbortMultipartUploadOutput,
+    AbortMultipartUploadRequest,
+    CompleteMultipartUploadError,
+    CompleteMultipartUploadOutput,
+    CompleteMultipartUploadRequest,
+    CopyObjectError,
+    CopyObjectOutput,
+    CopyObjectRequest,
+    CreateMultipartUploadError,
+    CreateMultipartUploadOutput,
+    CreateMultipartUploadRequest,
+    DeleteObjectError,
+    DeleteObjectOutput,
+    DeleteObjectRequest,
+    GetObjectError,
+    GetObjectOutput,
+    GetObjectRequest,
+    HeadBucketError,
+    HeadBucketRequest,
+    HeadObjectError,
+    HeadObjectOutput,
+    HeadObjectRequest,
+    ListObjectsV2Error,
+    ListObjectsV2Output,
+    ListObjectsV2Request,
+    PutObjectError,
+    PutObjectOutput,
+    PutObjectRequest,
+    S3Client,
+    UploadPartError,
+    UploadPartOutput,
+    UploadPartRequest,
+    S3,
+};
+
+/// The subset of S3 operations the filesystem relies on.
+///
+/// This exists so that the upload logic can be exercised against a mock rather than a real bucket.
+#[async_trait]
+pub(crate) trait ObjectStore: Send + Sync {
+    async fn put_object(
+        &self,
+        input: PutObjectRequest,
+    ) -> Result<PutObjectOutput, RusotoError<PutObjectError>>;
+
+    async fn create_multipart_upload(
+        &self,
+        input: CreateMultipartUploadRequest,
+    ) -> Result<CreateMultipartUploadOutput, RusotoError<CreateMultipartUploadError>>;
+
+    async fn upload_part(
+        &self,
+        input: UploadPartRequest,
+    ) -> Result<UploadPartOutput, RusotoError<UploadPartError>>;
+
+    async fn complete_multipart_upload(
+        &self,
+        input: CompleteMultipartUploadRequest,
+    ) -> Result<CompleteMultipartUploadOutput, RusotoError<CompleteMultipartUploadError>>;
+
+    async fn abort_multipart_upload(
+        &self,
+        input: AbortMultipartUploadRequest,
+    ) -> Result<AbortMultipartUploadOutput, RusotoError<AbortMultipartUploadError>>;
+
+    async fn copy_object(
+        &self,
+        input: CopyObjectRequest,
+    ) -> Result<CopyObjectOutput, RusotoError<CopyObjectError>>;
+
+    async fn delete_object(
+        &self,
+        input: DeleteObjectRequest,
+    ) -> Result<DeleteObjectOutput, RusotoError<DeleteObjectError>>;
+
+    async fn get_object(
+        &self,
+        input: GetObjectRequest,
+    ) -> Result<GetObjectOutput, RusotoError<GetObjectError>>;
+
+    async fn head_object(
+        &self,
+        input: HeadObjectRequest,
+    ) -> Result<HeadObjectOutput, RusotoError<HeadObjectError>>;
+
+    async fn list_objects_v2(
+        &self,
+        input: ListObjectsV2Request,
+    ) -> Result<ListObjectsV2Output, RusotoError<ListObjectsV2Error>>;
+
+    async fn head_bucket(
+        &self,
+        input: HeadBucketRequest,
+    ) -> Result<(), RusotoError<HeadBucketError>>;
+}
+
+#[async_trait]
+impl ObjectStore for S3Client {
+    async fn put_object(
+        &self,
+        input: PutObjectRequest,
+    ) -> Result<PutObjectOutput, RusotoError<PutObjectError>> {
+        S3::put_object(self, input).await
+    }
+
+    async fn create_multipart_upload(
+        &self,
+        input: CreateMultipartUploadRequest,
+    ) -> Result<CreateMultipartUploadOutput, RusotoError<CreateMultipartUploadError>> {
+        S3::create_multipart_upload(self, input).await
+    }
+
+    async fn upload_part(
+        &self,
+        input: UploadPartRequest,
+    ) -> Result<UploadPartOutput, RusotoError<UploadPartError>> {
+        S3::upload_part(self, input).await
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        input: CompleteMultipartUploadRequest,
+    ) -> Result<CompleteMultipartUploadOutput, RusotoError<CompleteMultipartUploadError>> {
+        S3::complete_multipart_upload(self, input).await
+    }
+
+    async fn abort_multipart_upload(
+        &self,
+        input: AbortMultipartUploadRequest,
+    ) -> Result<AbortMultipartUploadOutput, RusotoError<AbortMultipartUploadError>> {
+        S3::abort_multipart_upload(self, input).await
+    }
+
+    async fn copy_object(
+        &self,
+        input: CopyObjectRequest,
+    ) -> Result<CopyObjectOutput, RusotoError<CopyObjectError>> {
+        S3::copy_object(self, input).await
+    }
+
+    async fn delete_object(
+        &self,
+        input: DeleteObjectRequest,
+    ) -> Result<DeleteObjectOutput, RusotoError<DeleteObjectError>> {
+        S3::delete_object(self, input).await
+    }
+
+    async fn get_object(
+        &self,
+        input: GetObjectRequest,
+    ) -> Result<GetObjectOutput, RusotoError<GetObjectError>> {
+        S3::get_object(self, input).await
+    }
+
+    async fn head_object(
+        &self,
+        input: HeadObjectRequest,
+    ) -> Result<HeadObjectOutput, RusotoError<HeadObjectError>> {
+        S3::head_object(self, input).await
+    }
+
+    async fn list_objects_v2(
+        &self,
+        input: ListObjectsV2Request,
+    ) -> Result<ListObjectsV2Output, RusotoError<ListObjectsV2Error>> {
+        S3::list_objects_v2(self, input).await
+    }
+
+    async fn head_bucket(
+        &self,
+        input: HeadBucketRequest,
+    ) -> Result<(), RusotoError<HeadBucketError>> {
+        S3::head_bucket(self, input).await
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::ObjectStore;
+    use async_trait::async_trait;
+    use futures::TryStreamExt;
+    use hyper::StatusCode;
+    use rusoto_core::{
+        request::{
+            BufferedHttpResponse,
+            HttpDispatchError,
+        },
+        ByteStream,
+        RusotoError,
+    };
+    use rusoto_s3::{
+        AbortMultipartUploadError,
+        AbortMultipartUploadOutput,
+        AbortMultipartUploadRequest,
+        CompleteMultipartUploadError,
+        CompleteMultipartUploadOutput,
+        CompleteMultipartUploadRequest,
+        CopyObjectError,
+        CopyObjectOutput,
+        CopyObjectRequest,
+        CreateMultipartUploadError,
+        CreateMultipartUploadOutput,
+        CreateMultipartUploadRequest,
+        DeleteObjectError,
+        DeleteObjectOutput,
+        DeleteObjectRequest,
+        GetObjectError,
+        GetObjectOutput,
+        GetObjectRequest,
+        HeadBucketError,
+        HeadBucketRequest,
+        HeadObjectError,
+        HeadObjectOutput,
+        HeadObjectRequest,
+        ListObjectsV2Error,
+        ListObjectsV2Output,
+        ListObjectsV2Request,
+        Object,
+        PutObjectError,
+        PutObjectOutput,
+        PutObjectRequest,
+        UploadPartError,
+        UploadPartOutput,
+        UploadPartRequest,
+    };
+    use std::sync::{
+        Mutex,
+        MutexGuard,
+    };
+
+    /// A request received by the [`MockObjectStore`]. Request bodies are taken out of the requests
+    /// and stored alongside them.
+    #[derive(Debug)]
+    #[allow(dead_code)] // Not every recorded field is inspected by the tests.
+    pub(crate) enum Call {
+        PutObject(PutObjectRequest, Vec<u8>),
+        CreateMultipartUpload(CreateMultipartUploadRequest),
+        UploadPart(UploadPartRequest, Vec<u8>),
+        CompleteMultipartUpload(CompleteMultipartUploadRequest),
+        AbortMultipartUpload(AbortMultipartUploadRequest),
+        CopyObject(CopyObjectRequest),
+        DeleteObject(DeleteObjectRequest),
+        GetObject(GetObjectRequest),
+        HeadObject(HeadObjectRequest),
+        ListObjectsV2(ListObjectsV2Request),
+        HeadBucket(HeadBucketRequest),
+    }
+
+    /// An [`ObjectStore`] that records every request it receives and lets them all succeed,
+    /// unless told otherwise.
+    #[derive(Default)]
+    pub(crate) struct MockObjectStore {
+        calls: Mutex<Vec<Call>>,
+        unreachable_upload_parts: Mutex<u32>,
+        lost_completion_responses: Mutex<u32>,
+        failing_aborts: Mutex<u32>,
+        rejected_puts: Mutex<u32>,
+    }
+
+    impl MockObjectStore {
+        pub(crate) fn calls(&self) -> MutexGuard<'_, Vec<Call>> {
+            self.calls.lock().unwrap()
+        }
+
+        /// Let the next `count` `UploadPart` requests fail as if S3 was unreachable. These
+        /// requests are not recorded.
+        pub(crate) fn make_upload_parts_unreachable(&self, count: u32) {
+            *self.unreachable_upload_parts.lock().unwrap() = count;
+        }
+
+        /// Let the next `count` `CompleteMultipartUpload` requests succeed, but fail as if the
+        /// connection was reset before the response arrived.
+        pub(crate) fn lose_completion_responses(&self, count: u32) {
+            *self.lost_completion_responses.lock().unwrap() = count;
+        }
+
+        /// Let the next `count` `AbortMultipartUpload` requests fail as if S3 was unreachable.
+        /// These requests are recorded nonetheless.
+        pub(crate) fn fail_aborts(&self, count: u32) {
+            *self.failing_aborts.lock().unwrap() = count;
+        }
+
+        /// Let the next `count` `PutObject` requests be rejected with `AccessDenied`. These
+        /// requests are not recorded.
+        pub(crate) fn reject_puts(&self, count: u32) {
+            *self.rejected_puts.lock().unwrap() = count;
+        }
+
+        fn record(&self, call: Call) -> usize {
+            let mut calls = self.calls();
+            calls.push(call);
+            calls.len()
+        }
+    }
+
+    async fn read_body(body: Option<ByteStream>) -> Vec<u8> {
+        match body {
+            Some(body) => body
+                .map_ok(|bytes| bytes.to_vec())
+                .try_concat()
+                .await
+                .unwrap(),
+            None => vec![],
+        }
+    }
+
+    #[async_trait]
+    impl ObjectStore for MockObjectStore {
+        async fn put_object(
+            &self,
+            mut input: PutObjectRequest,
+        ) -> Result<PutObjectOutput, RusotoError<PutObjectError>> {
+            {
+                let mut rejected_puts = self.rejected_puts.lock().unwrap();
+                if *rejected_puts > 0 {
+                    *rejected_puts -= 1;
+                    return Err(RusotoError::Unknown(BufferedHttpResponse {
+                        status: StatusCode::FORBIDDEN,
+                        body: "<Error><Code>AccessDenied</Code><Message>Access Denied</Message>\
+                               </Error>"
+                            .into(),
+                        headers: Default::default(),
+                    }));
+                }
+            }
+            let body = read_body(input.body.take()).await;
+            let call_number = self.record(Call::PutObject(input, body));
+            Ok(PutObjectOutput {
+                e_tag: Some(format!("\"etag-{}\"", call_number)),
+                version_id: Some(format!("version-{}", call_number)),
+                ..Default::default()
+            })
+        }
+
+        async fn create_multipart_upload(
+            &self,
+            input: CreateMultipartUploadRequest,
+        ) -> Result<CreateMultipartUploadOutput, RusotoError<CreateMultipartUploadError>> {
+            let call_number = self.record(Call::CreateMultipartUpload(input));
+            Ok(CreateMultipartUploadOutput {
+                upload_id: Some(format!("upload-{}", call_number)),
+                ..Default::default()
+            })
+        }
+
+        async fn upload_part(
+            &self,
+            mut input: UploadPartRequest,
+        ) -> Result<UploadPartOutput, RusotoError<UploadPartError>> {
+            {
+                let mut unreachable_upload_parts = self.unreachable_upload_parts.lock().unwrap();
+                if *unreachable_upload_parts > 0 {
+                    *unreachable_upload_parts -= 1;
+                    return Err(RusotoError::HttpDispatch(HttpDispatchError::new(
+                        "connection refused".to_owned(),
+                    )));
+                }
+            }
+            let body = read_body(input.body.take()).await;
+            let call_number = self.record(Call::UploadPart(input, body));
+            Ok(UploadPartOutput {
+                e_tag: Some(format!("\"etag-{}\"", call_number)),
+                ..Default::default()
+            })
+        }
+
+        async fn complete_multipart_upload(
+            &self,
+            input: CompleteMultipartUploadRequest,
+        ) -> Result<CompleteMultipartUploadOutput, RusotoError<CompleteMultipartUploadError>>
+        {
+            // Like S3, reject completing an upload that was completed already.
+            let completed = self.calls().iter().any(|call| {
+                matches!(call, Call::CompleteMultipartUpload(completed)
+                    if completed.upload_id == input.upload_id)
+            });
+            let call_number = self.record(Call::CompleteMultipartUpload(input));
+            if completed {
+                return Err(RusotoError::Unknown(BufferedHttpResponse {
+                    status: StatusCode::NOT_FOUND,
+                    body: "<Error><Code>NoSuchUpload</Code><Message>The specified upload does \
+                           not exist.</Message></Error>"
+                        .into(),
+                    headers: Default::default(),
+                }));
+            }
+            {
+                let mut lost_completion_responses = self.lost_completion_responses.lock().unwrap();
+                if *lost_completion_responses > 0 {
+                    *lost_completion_responses -= 1;
+                    return Err(RusotoError::HttpDispatch(HttpDispatchError::new(
+                        "connection reset".to_owned(),
+                    )));
+                }
+            }
+            Ok(CompleteMultipartUploadOutput {
+                e_tag: Some(format!("\"etag-{}\"", call_number)),
+                version_id: Some(format!("version-{}", call_number)),
+                ..Default::default()
+            })
+        }
+
+        async fn abort_multipart_upload(
+            &self,
+            input: AbortMultipartUploadRequest,
+        ) -> Result<AbortMultipartUploadOutput, RusotoError<AbortMultipartUploadError>> {
+            self.record(Call::AbortMultipartUpload(input));
+            let mut failing_aborts = self.failing_aborts.lock().unwrap();
+            if *failing_aborts > 0 {
+                *failing_aborts -= 1;
+                return Err(RusotoError::HttpDispatch(HttpDispatchError::new(
+                    "connection refused".to_owned(),
+                )));
+            }
+            Ok(AbortMultipartUploadOutput::default())
+        }
+
+        async fn copy_object(
+            &self,
+            input: CopyObjectRequest,
+        ) -> Result<CopyObjectOutput, RusotoError<CopyObjectError>> {
+            self.record(Call::CopyObject(input));
+            Ok(CopyObjectOutput::default())
+        }
+
+        async fn delete_object(
+            &self,
+            input: DeleteObjectRequest,
+        ) -> Result<DeleteObjectOutput, RusotoError<DeleteObjectError>> {
+            self.record(Call::DeleteObject(input));
+            Ok(DeleteObjectOutput::default())
+        }
+
+        /// Returns the body of the last `PutObject` to the same key.
+        async fn get_object(
+            &self,
+            input: GetObjectRequest,
+        ) -> Result<GetObjectOutput, RusotoError<GetObjectError>> {
+            let body = self.calls().iter().rev().find_map(|call| match call {
+                Call::PutObject(put_object, body) if put_object.key == input.key => {
+                    Some(body.clone())
+                }
+                _ => None,
+            });
+            self.record(Call::GetObject(input));
+            match body {
+                Some(body) => Ok(GetObjectOutput {
+                    body: Some(body.into()),
+                    ..Default::default()
+                }),
+                None => Err(RusotoError::Service(GetObjectError::NoSuchKey(
+                    "The specified key does not exist.".to_owned(),
+                ))),
+            }
+        }
+
+        /// Reports the number of parts of objects written through `CompleteMultipartUpload`.
+        async fn head_object(
+            &self,
+            input: HeadObjectRequest,
+        ) -> Result<HeadObjectOutput, RusotoError<HeadObjectError>> {
+            let output = self
+                .calls()
+                .iter()
+                .enumerate()
+                .rev()
+                .find_map(|(index, call)| match call {
+                    Call::CompleteMultipartUpload(completed) if completed.key == input.key => {
+                        Some(HeadObjectOutput {
+                            e_tag: Some(format!("\"etag-{}\"", index + 1)),
+                            parts_count: completed
+                                .multipart_upload
+                                .as_ref()
+                                .and_then(|multipart_upload| multipart_upload.parts.as_ref())
+                                .map(|parts| parts.len() as i64),
+                            ..Default::default()
+                        })
+                    }
+                    _ => None,
+                })
+                .unwrap_or_default();
+            self.record(Call::HeadObject(input));
+            Ok(output)
+        }
+
+        /// Lists the keys of the objects written through `PutObject` so far.
+        async fn list_objects_v2(
+            &self,
+            input: ListObjectsV2Request,
+        ) -> Result<ListObjectsV2Output, RusotoError<ListObjectsV2Error>> {
+            let prefix = input.prefix.clone().unwrap_or_default();
+            let mut keys = self
+                .calls()
+                .iter()
+                .filter_map(|call| match call {
+                    Call::PutObject(put_object, _) if put_object.key.starts_with(&prefix) => {
+                        Some(put_object.key.clone())
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            keys.sort();
+            keys.dedup();
+            keys.truncate(input.max_keys.unwrap_or(1000) as usize);
+            self.record(Call::ListObjectsV2(input));
+            Ok(ListObjectsV2Output {
+                key_count: Some(keys.len() as i64),
+                contents: Some(
+                    keys.into_iter()
+                        .map(|key| Object {
+                            key: Some(key),
+                            ..Default::default()
+                        })
+                        .collect(),
+                ),
+                ..Default::default()
+            })
+        }
+
+        async fn head_bucket(
+            &self,
+            input: HeadBucketRequest,
+        ) -> Result<(), RusotoError<HeadBucketError>> {
+            self.record(Call::HeadBucket(input));
+            Ok(())
+        }
+    }
+}
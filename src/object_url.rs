@@ -0,0 +1,132 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::upload::KEY_ENCODE_SET;
+use clap::ValueEnum;
+use percent_encoding::utf8_percent_encode;
+use rusoto_core::Region;
+use std::io::{
+    self,
+    Write,
+};
+
+/// How to format the URLs of uploaded objects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum UrlStyle {
+    /// `s3://bucket/key`
+    #[value(name = "s3")]
+    S3,
+    /// `https://bucket.s3.region.amazonaws.com/key`, i.e. a virtual-hosted-style URL, or
+    /// `<endpoint>/bucket/key` with a custom endpoint.
+    #[value(name = "https")]
+    Https,
+}
+
+/// Prints the URL of every uploaded object to stdout.
+#[derive(Debug)]
+pub(crate) struct UrlPrinter {
+    style: UrlStyle,
+    region: Region,
+}
+
+impl UrlPrinter {
+    pub(crate) fn new(style: UrlStyle, region: Region) -> Self {
+        UrlPrinter { style, region }
+    }
+
+    fn url(&self, bucket: &str, key: &str, version_id: Option<&str>) -> String {
+        let url = match self.style {
+            UrlStyle::S3 => format!("s3://{}/{}", bucket, key),
+            // Custom endpoints are addressed path-style, like the S3 client does.
+            UrlStyle::Https => match &self.region {
+                Region::Custom { endpoint, .. } => format!(
+                    "{}{}/{}/{}",
+                    if endpoint.contains("://") {
+                        ""
+                    } else {
+                        "https://"
+                    },
+                    endpoint,
+                    bucket,
+                    utf8_percent_encode(key, KEY_ENCODE_SET)
+                ),
+                region => format!(
+                    "https://{}.s3.{}.amazonaws.com/{}",
+                    bucket,
+                    region.name(),
+                    utf8_percent_encode(key, KEY_ENCODE_SET)
+                ),
+            },
+        };
+        match version_id {
+            Some(version_id) => format!(
+                "{}?versionId={}",
+                url,
+                utf8_percent_encode(version_id, KEY_ENCODE_SET)
+            ),
+            None => url,
+        }
+    }
+
+    /// Print the URL of the object as a single line.
+    ///
+    /// If the bucket is versioned, the URL refers to the exact version that has been written.
+    ///
+    /// Stdout is locked for the whole line, so that concurrent uploads can't interleave their
+    /// output.
+    pub(crate) fn print(
+        &self,
+        bucket: &str,
+        key: &str,
+        version_id: Option<&str>,
+    ) -> io::Result<()> {
+        let url = self.url(bucket, key, version_id);
+        let mut stdout = io::stdout().lock();
+        writeln!(stdout, "{}", url)?;
+        stdout.flush()
+    }
+}
+
+#[test]
+fn urls_are_formatted() {
+    let s3 = UrlPrinter::new(UrlStyle::S3, Region::EuCentral1);
+    assert_eq!(
+        "s3://my-bucket/path/my file.txt",
+        s3.url("my-bucket", "path/my file.txt", None)
+    );
+
+    let https = UrlPrinter::new(UrlStyle::Https, Region::EuCentral1);
+    assert_eq!(
+        "https://my-bucket.s3.eu-central-1.amazonaws.com/path/my%20file.txt",
+        https.url("my-bucket", "path/my file.txt", None)
+    );
+    assert_eq!(
+        "https://my-bucket.s3.eu-central-1.amazonaws.com/my-file?versionId=3HL4kqtJlcpXroDTDmJ%2BrmSpXd3dIbrHY",
+        https.url("my-bucket", "my-file", Some("3HL4kqtJlcpXroDTDmJ+rmSpXd3dIbrHY"))
+    );
+
+    let custom = UrlPrinter::new(
+        UrlStyle::Https,
+        Region::Custom {
+            name: "eu-central-1".to_owned(),
+            endpoint: "http://localhost:9000".to_owned(),
+        },
+    );
+    assert_eq!(
+        "http://localhost:9000/my-bucket/path/my%20file.txt",
+        custom.url("my-bucket", "path/my file.txt", None)
+    );
+}
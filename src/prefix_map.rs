@@ -0,0 +1,370 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    id_generator::IdGenerator,
+    signals,
+};
+use anyhow::{
+    bail,
+    Context,
+    Result,
+};
+use slog_scope::{
+    error,
+    info,
+};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs,
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::{
+        Arc,
+        RwLock,
+    },
+};
+
+/// Inodes of virtual directories are allocated from here, such that they never collide with the
+/// inodes of files.
+pub(crate) const DIRECTORY_INODE_BASE: u64 = 1 << 48;
+
+/// A virtual directory, whose files are uploaded with an aliased prefix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct VirtualDirectory {
+    pub(crate) ino: u64,
+    /// Path of the directory relative to the mountpoint, e.g. `reports/daily`.
+    pub(crate) path: String,
+    /// Prefix within the bucket that files created in the directory are uploaded to.
+    pub(crate) prefix: String,
+    /// Whether the directory was created through the mount with `mkdir`, rather than mapped.
+    pub(crate) created: bool,
+}
+
+impl VirtualDirectory {
+    /// The name of the directory within its parent.
+    pub(crate) fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+
+    /// The path of the parent directory, `None` for directories in the root directory.
+    pub(crate) fn parent(&self) -> Option<&str> {
+        self.path.rsplit_once('/').map(|(parent, _)| parent)
+    }
+}
+
+/// Virtual directories mapped to S3 prefixes, loaded from a JSON file like
+/// `{"reports": "archive/reports", "reports/daily": "daily-reports"}`.
+///
+/// The mapping can be reloaded at runtime, directories that are kept retain their inode.
+/// Directories created through the mount are kept as well, as long as their parent is.
+pub(crate) struct PrefixMap {
+    /// The mapping, `None` if only directories created through the mount exist.
+    path: Option<PathBuf>,
+    directories: BTreeMap<String, VirtualDirectory>,
+    id_generator: IdGenerator,
+}
+
+impl fmt::Debug for PrefixMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PrefixMap")
+            .field("path", &self.path)
+            .field("directories", &self.directories)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PrefixMap {
+    pub(crate) fn load(path: &Path) -> Result<PrefixMap> {
+        let mut prefix_map = PrefixMap {
+            path: Some(path.to_owned()),
+            ..PrefixMap::empty()
+        };
+        prefix_map.reload()?;
+
+        Ok(prefix_map)
+    }
+
+    /// A prefix map without any mapped directories.
+    pub(crate) fn empty() -> PrefixMap {
+        PrefixMap {
+            path: None,
+            directories: BTreeMap::new(),
+            id_generator: IdGenerator::new(DIRECTORY_INODE_BASE),
+        }
+    }
+
+    /// Read the mapping again. If it is invalid, the current mapping is kept.
+    pub(crate) fn reload(&mut self) -> Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read prefix map '{}'", path.display()))?;
+        let mapping =
+            parse(&contents).with_context(|| format!("invalid prefix map '{}'", path.display()))?;
+
+        let mut directories = BTreeMap::new();
+        for (path, prefix) in mapping {
+            let ino = match self.directories.get(&path) {
+                Some(directory) => directory.ino,
+                None => self.id_generator.next(),
+            };
+            directories.insert(
+                path.clone(),
+                VirtualDirectory {
+                    ino,
+                    path,
+                    prefix,
+                    created: false,
+                },
+            );
+        }
+        // Parents are ordered before their children, so they have been kept already if at all.
+        for directory in self
+            .directories
+            .values()
+            .filter(|directory| directory.created)
+        {
+            let parent_kept = directory
+                .parent()
+                .is_none_or(|parent| directories.contains_key(parent));
+            if parent_kept
+                && !directories.contains_key(&directory.path)
+                && !prefix_in_use(directories.values(), &directory.prefix)
+            {
+                directories.insert(directory.path.clone(), directory.clone());
+            }
+        }
+        self.directories = directories;
+
+        Ok(())
+    }
+
+    /// Create the directory called `name` in the directory at `parent`, or the root directory if
+    /// `None`, whose files are uploaded below `prefix`.
+    pub(crate) fn create(
+        &mut self,
+        parent: Option<&str>,
+        name: &str,
+        prefix: String,
+    ) -> Result<VirtualDirectory> {
+        let path = match parent {
+            Some(parent) => format!("{}/{}", parent, name),
+            None => name.to_owned(),
+        };
+        if self.directories.contains_key(&path) {
+            bail!("directory '{}' exists already", path);
+        }
+        if prefix_in_use(self.directories.values(), &prefix) {
+            bail!("prefix '{}' is used by another directory already", prefix);
+        }
+        let directory = VirtualDirectory {
+            ino: self.id_generator.next(),
+            path: path.clone(),
+            prefix,
+            created: true,
+        };
+        self.directories.insert(path, directory.clone());
+
+        Ok(directory)
+    }
+
+    /// Reload the mapping whenever the process receives SIGHUP.
+    pub(crate) fn reload_on_signal(prefix_map: &Arc<RwLock<PrefixMap>>) -> Result<()> {
+        let prefix_map = Arc::clone(prefix_map);
+        signals::spawn_handler("prefix-map-reload", &[libc::SIGHUP], move |_| {
+            let result = match prefix_map.write() {
+                Ok(mut prefix_map) => prefix_map.reload(),
+                Err(_) => Err(anyhow::anyhow!("failed to lock prefix map")),
+            };
+            match result {
+                Ok(()) => info!("Reloaded prefix map"),
+                Err(error) => error!("Failed to reload prefix map"; "error" => %error),
+            }
+        })
+    }
+
+    /// The directory called `name` in the directory at `parent`, or the root directory if `None`.
+    pub(crate) fn lookup(&self, parent: Option<&str>, name: &str) -> Option<&VirtualDirectory> {
+        let path = match parent {
+            Some(parent) => format!("{}/{}", parent, name),
+            None => name.to_owned(),
+        };
+        self.directories.get(&path)
+    }
+
+    /// The directory at `path`, relative to the mountpoint.
+    pub(crate) fn path(&self, path: &str) -> Option<&VirtualDirectory> {
+        self.directories.get(path)
+    }
+
+    pub(crate) fn directory(&self, ino: u64) -> Option<&VirtualDirectory> {
+        self.directories
+            .values()
+            .find(|directory| directory.ino == ino)
+    }
+
+    pub(crate) fn directories(&self) -> impl Iterator<Item = &VirtualDirectory> {
+        self.directories.values()
+    }
+
+    /// The directories within the directory at `parent`, or the root directory if `None`.
+    pub(crate) fn children<'a>(
+        &'a self,
+        parent: Option<&'a str>,
+    ) -> impl Iterator<Item = &'a VirtualDirectory> + 'a {
+        self.directories
+            .values()
+            .filter(move |directory| directory.parent() == parent)
+    }
+}
+
+/// Whether files of any of the `directories` are uploaded below `prefix` already.
+fn prefix_in_use<'a>(
+    mut directories: impl Iterator<Item = &'a VirtualDirectory>,
+    prefix: &str,
+) -> bool {
+    directories.any(|directory| directory.prefix == prefix)
+}
+
+/// Parse and validate the mapping of directory paths to prefixes.
+fn parse(contents: &str) -> Result<BTreeMap<String, String>> {
+    let raw: BTreeMap<String, String> = serde_json::from_str(contents)?;
+    let mut mapping = BTreeMap::new();
+    for (path, prefix) in raw {
+        let path = path.trim_matches('/').to_owned();
+        if path.is_empty()
+            || path
+                .split('/')
+                .any(|component| component.is_empty() || component == "." || component == "..")
+        {
+            bail!("invalid directory path '{}'", path);
+        }
+        let prefix = prefix.trim_matches('/').to_owned();
+        if prefix.is_empty() {
+            bail!("directory '{}' is mapped to an empty prefix", path);
+        }
+        if mapping.insert(path.clone(), prefix).is_some() {
+            bail!("directory '{}' is mapped more than once", path);
+        }
+    }
+
+    for (path, prefix) in &mapping {
+        if let Some((parent, _)) = path.rsplit_once('/') {
+            if !mapping.contains_key(parent) {
+                bail!(
+                    "parent directory '{}' of directory '{}' is not mapped",
+                    parent,
+                    path
+                );
+            }
+        }
+        // Files in two directories must never end up in the same place, which would also be the
+        // case if one prefix contained the other.
+        for (other_path, other_prefix) in &mapping {
+            if path != other_path
+                && (prefix == other_prefix || other_prefix.starts_with(&format!("{}/", prefix)))
+            {
+                bail!(
+                    "directories '{}' and '{}' have overlapping prefixes '{}' and '{}'",
+                    path,
+                    other_path,
+                    prefix,
+                    other_prefix
+                );
+            }
+        }
+    }
+
+    Ok(mapping)
+}
+
+#[test]
+fn nested_directories_are_parsed() {
+    let mapping = parse(r#"{"reports": "archive/reports/", "/reports/daily": "daily"}"#).unwrap();
+    assert_eq!(
+        Some("archive/reports"),
+        mapping.get("reports").map(String::as_str)
+    );
+    assert_eq!(
+        Some("daily"),
+        mapping.get("reports/daily").map(String::as_str)
+    );
+}
+
+#[test]
+fn invalid_mappings_are_rejected() {
+    for invalid in [
+        r#"{"": "prefix"}"#,
+        r#"{"a/../b": "prefix"}"#,
+        r#"{"a": ""}"#,
+        r#"{"a": "prefix", "a/": "other"}"#,
+        r#"{"a/b": "prefix"}"#,
+        r#"{"a": "prefix", "b": "prefix"}"#,
+        r#"{"a": "prefix", "b": "prefix/nested"}"#,
+    ] {
+        assert!(parse(invalid).is_err(), "{} should be rejected", invalid);
+    }
+}
+
+#[test]
+fn reloading_keeps_inodes() {
+    let path = std::env::temp_dir().join(format!("s3wofs-prefix-map-{}.json", std::process::id()));
+    fs::write(&path, r#"{"a": "prefix-a", "b": "prefix-b"}"#).unwrap();
+    let mut prefix_map = PrefixMap::load(&path).unwrap();
+    let a = prefix_map.lookup(None, "a").unwrap().ino;
+
+    fs::write(&path, r#"{"a": "new-prefix-a", "a/c": "prefix-c"}"#).unwrap();
+    prefix_map.reload().unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let reloaded = prefix_map.lookup(None, "a").unwrap();
+    assert_eq!(a, reloaded.ino);
+    assert_eq!("new-prefix-a", reloaded.prefix);
+    assert!(prefix_map.lookup(None, "b").is_none());
+    let children = prefix_map.children(Some("a")).collect::<Vec<_>>();
+    assert_eq!(1, children.len());
+    assert_eq!("c", children[0].name());
+}
+
+#[test]
+fn created_directories_are_kept_with_their_parent() {
+    let path = std::env::temp_dir().join(format!("s3wofs-created-{}.json", std::process::id()));
+    fs::write(&path, r#"{"a": "prefix-a", "b": "prefix-b"}"#).unwrap();
+    let mut prefix_map = PrefixMap::load(&path).unwrap();
+    let c = prefix_map
+        .create(Some("a"), "c", "prefix-a/c".to_owned())
+        .unwrap();
+    prefix_map
+        .create(Some("b"), "d", "prefix-b/d".to_owned())
+        .unwrap();
+    prefix_map.create(None, "e", "e".to_owned()).unwrap();
+    assert!(prefix_map.create(None, "e", "other".to_owned()).is_err());
+    assert!(prefix_map.create(None, "f", "prefix-a".to_owned()).is_err());
+
+    fs::write(&path, r#"{"a": "prefix-a"}"#).unwrap();
+    prefix_map.reload().unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(Some(&c), prefix_map.lookup(Some("a"), "c"));
+    assert!(prefix_map.lookup(Some("b"), "d").is_none());
+    assert!(prefix_map.lookup(None, "e").is_some());
+}
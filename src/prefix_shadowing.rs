@@ -0,0 +1,104 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::object_store::ObjectStore;
+use anyhow::{
+    Context,
+    Result,
+};
+use libc::{
+    c_int,
+    EISDIR,
+    ENOTDIR,
+};
+use rusoto_s3::ListObjectsV2Request;
+use tokio::runtime::Runtime;
+
+/// Check whether an object at `key` would collide with the existing objects, as if the bucket was a
+/// filesystem.
+///
+/// Returns `EISDIR` if objects exist below `key/`, i.e. `key` is a "directory" already, and
+/// `ENOTDIR` if an object exists at one of the parent "directories" of `key`.
+pub(crate) fn check(
+    runtime: &Runtime,
+    s3: &dyn ObjectStore,
+    bucket: &str,
+    key: &str,
+) -> Result<Option<c_int>> {
+    if first_key(runtime, s3, bucket, &format!("{}/", key))?.is_some() {
+        return Ok(Some(EISDIR));
+    }
+    for (index, _) in key.match_indices('/') {
+        let parent = &key[..index];
+        // If an object exists at `parent`, it is listed first, since it sorts before every other
+        // key starting with it.
+        if !parent.is_empty() && first_key(runtime, s3, bucket, parent)?.as_deref() == Some(parent)
+        {
+            return Ok(Some(ENOTDIR));
+        }
+    }
+
+    Ok(None)
+}
+
+/// The first key starting with `prefix`, if any.
+fn first_key(
+    runtime: &Runtime,
+    s3: &dyn ObjectStore,
+    bucket: &str,
+    prefix: &str,
+) -> Result<Option<String>> {
+    let output = runtime
+        .block_on(s3.list_objects_v2(ListObjectsV2Request {
+            bucket: bucket.to_owned(),
+            prefix: Some(prefix.to_owned()),
+            max_keys: Some(1),
+            ..Default::default()
+        }))
+        .with_context(|| format!("failed to list objects with prefix '{}'", prefix))?;
+
+    Ok(output
+        .contents
+        .unwrap_or_default()
+        .into_iter()
+        .next()
+        .and_then(|object| object.key))
+}
+
+#[test]
+fn shadowing_is_detected() {
+    use crate::object_store::mock::MockObjectStore;
+    use rusoto_s3::PutObjectRequest;
+
+    let runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    for key in ["data/file", "data/directory/file", "data/file-2"] {
+        runtime
+            .block_on(s3.put_object(PutObjectRequest {
+                bucket: "my-bucket".to_owned(),
+                key: key.to_owned(),
+                ..Default::default()
+            }))
+            .unwrap();
+    }
+    let check = |key| check(&runtime, &s3, "my-bucket", key).unwrap();
+
+    assert_eq!(Some(EISDIR), check("data/directory"));
+    assert_eq!(Some(ENOTDIR), check("data/file/nested"));
+    assert_eq!(None, check("data/fil"));
+    assert_eq!(None, check("data/other"));
+    assert_eq!(None, check("data/directory/file"));
+}
@@ -0,0 +1,274 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    content_hash::{
+        to_hex,
+        ContentHash,
+    },
+    id_generator::IdGenerator,
+};
+use anyhow::{
+    anyhow,
+    Context,
+    Result,
+};
+use chrono::{
+    DateTime,
+    SecondsFormat,
+    Utc,
+};
+use serde::Serialize;
+use slog_scope::{
+    debug,
+    error,
+    warn,
+};
+use std::{
+    fmt,
+    fs,
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::{
+        mpsc::{
+            self,
+            Receiver,
+            Sender,
+        },
+        Arc,
+        Mutex,
+    },
+    thread::{
+        self,
+        JoinHandle,
+    },
+    time::SystemTime,
+};
+
+/// A receipt for an object that was uploaded successfully.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct Receipt {
+    pub(crate) bucket: String,
+    pub(crate) key: String,
+    pub(crate) size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) e_tag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) version_id: Option<String>,
+    /// When the upload completed, formatted as RFC 3339.
+    pub(crate) uploaded_at: String,
+    /// The SHA-256 digest of the content, as lowercase hexadecimal digits.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) sha256: Option<String>,
+}
+
+impl Receipt {
+    pub(crate) fn new(
+        bucket: &str,
+        key: &str,
+        size: u64,
+        e_tag: Option<&str>,
+        version_id: Option<&str>,
+        uploaded_at: SystemTime,
+        sha256: Option<&ContentHash>,
+    ) -> Self {
+        Receipt {
+            bucket: bucket.to_owned(),
+            key: key.to_owned(),
+            size,
+            e_tag: e_tag.map(|e_tag| e_tag.trim_matches('"').to_owned()),
+            version_id: version_id.map(str::to_owned),
+            uploaded_at: DateTime::<Utc>::from(uploaded_at)
+                .to_rfc3339_opts(SecondsFormat::Millis, true),
+            sha256: sha256.map(to_hex),
+        }
+    }
+}
+
+/// Writes a JSON receipt file into a directory for every uploaded object, for local processes
+/// that watch the directory.
+///
+/// Receipts are written by a background thread, such that a slow or full disk doesn't block the
+/// filesystem. Every receipt is first written to a hidden temporary file and then renamed, so
+/// watchers never see partially written receipts. Failures are only logged.
+pub(crate) struct Receipts {
+    directory: PathBuf,
+    sequence: IdGenerator,
+    sender: Mutex<Option<Sender<Receipt>>>,
+    receiver: Mutex<Option<Receiver<Receipt>>>,
+    worker: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl fmt::Debug for Receipts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receipts")
+            .field("directory", &self.directory)
+            .finish()
+    }
+}
+
+impl Receipts {
+    /// Write receipts into `directory`, which has to exist already.
+    ///
+    /// The path is made absolute, since the working directory changes when daemonizing.
+    pub(crate) fn new(directory: &Path) -> Result<Self> {
+        let directory = fs::canonicalize(directory).with_context(|| {
+            format!(
+                "failed to access receipts directory '{}'",
+                directory.display()
+            )
+        })?;
+        if !directory.is_dir() {
+            return Err(anyhow!(
+                "receipts directory '{}' is not a directory",
+                directory.display()
+            ));
+        }
+        let (sender, receiver) = mpsc::channel();
+
+        Ok(Receipts {
+            directory,
+            sequence: IdGenerator::new(1),
+            sender: Mutex::new(Some(sender)),
+            receiver: Mutex::new(Some(receiver)),
+            worker: Mutex::new(None),
+        })
+    }
+
+    /// Queue the receipt of an uploaded object.
+    pub(crate) fn uploaded(&self, receipt: Receipt) {
+        let sent = match self.sender.lock() {
+            Ok(sender) => sender
+                .as_ref()
+                .is_some_and(|sender| sender.send(receipt).is_ok()),
+            Err(_) => false,
+        };
+        if !sent {
+            warn!("Not writing upload receipt, since receipts are no longer written");
+        }
+    }
+
+    /// Write the queued receipts in a background thread.
+    pub(crate) fn write_in_background(self: &Arc<Self>) -> Result<()> {
+        let receiver = self
+            .receiver
+            .lock()
+            .map_err(|_| anyhow!("failed to acquire lock on receipts queue"))?
+            .take()
+            .ok_or_else(|| anyhow!("receipts are already written in the background"))?;
+        let receipts = Arc::clone(self);
+        let worker = thread::Builder::new()
+            .name("receipts".to_owned())
+            .spawn(move || receipts.write_all(receiver))?;
+        if let Ok(mut slot) = self.worker.lock() {
+            *slot = Some(worker);
+        }
+
+        Ok(())
+    }
+
+    /// Write the receipts that are still queued, and stop writing receipts.
+    pub(crate) fn finish(&self) {
+        if let Ok(mut sender) = self.sender.lock() {
+            sender.take();
+        }
+        let worker = self.worker.lock().ok().and_then(|mut worker| worker.take());
+        if let Some(worker) = worker {
+            if worker.join().is_err() {
+                error!("Failed to write the remaining upload receipts");
+            }
+        }
+    }
+
+    fn write_all(&self, receiver: Receiver<Receipt>) {
+        while let Ok(receipt) = receiver.recv() {
+            match self.write(&receipt) {
+                Ok(path) => debug!("Wrote upload receipt '{}'", path.display()),
+                Err(error) => {
+                    error!("Failed to write upload receipt";
+                           "key" => &receipt.key,
+                           "error" => format!("{:#}", error))
+                }
+            }
+        }
+    }
+
+    /// Write a single receipt, returning its path.
+    fn write(&self, receipt: &Receipt) -> Result<PathBuf> {
+        let name = receipt_file_name(receipt, self.sequence.next());
+        let path = self.directory.join(&name);
+        let temporary_path = self.directory.join(format!(".{}.tmp", name));
+        let written = serde_json::to_vec(receipt)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| Ok(fs::write(&temporary_path, content)?))
+            .and_then(|()| Ok(fs::rename(&temporary_path, &path)?));
+        if let Err(error) = written {
+            // Don't leave a partial receipt behind, e.g. if the disk is full.
+            let _ = fs::remove_file(&temporary_path);
+            return Err(error.context(format!("failed to write '{}'", path.display())));
+        }
+
+        Ok(path)
+    }
+}
+
+/// The name of the receipt file, which sorts by the time of the upload and is unique within the
+/// session thanks to the `sequence` number.
+fn receipt_file_name(receipt: &Receipt, sequence: u64) -> String {
+    let timestamp = receipt
+        .uploaded_at
+        .chars()
+        .filter(char::is_ascii_digit)
+        .collect::<String>();
+    format!("{}-{:08}.json", timestamp, sequence)
+}
+
+#[test]
+fn receipts_are_written_as_json_files() {
+    use std::time::Duration;
+
+    let directory = std::env::temp_dir().join(format!("s3wofs-receipts-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let receipts = Arc::new(Receipts::new(&directory).unwrap());
+    receipts.write_in_background().unwrap();
+
+    receipts.uploaded(Receipt::new(
+        "my-bucket",
+        "prefix/my-file",
+        42,
+        Some("\"etag\""),
+        None,
+        SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+        Some(&[0; 32]),
+    ));
+    receipts.finish();
+
+    let entries = fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(vec!["20231114221320123-00000001.json".to_owned()], entries);
+    assert_eq!(
+        format!(
+            r#"{{"bucket":"my-bucket","key":"prefix/my-file","size":42,"e_tag":"etag","uploaded_at":"2023-11-14T22:13:20.123Z","sha256":"{}"}}"#,
+            "0".repeat(64)
+        ),
+        fs::read_to_string(directory.join(&entries[0])).unwrap()
+    );
+    fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,130 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use rusoto_core::{
+    request::DispatchSignedRequestFuture,
+    signature::SignedRequest,
+    DispatchSignedRequest,
+};
+use std::{
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+/// Limits the number of requests per second with a token bucket, which allows bursts of up to one
+/// second worth of requests.
+#[derive(Debug)]
+pub(crate) struct RequestRateLimiter {
+    requests_per_second: f64,
+    /// The tokens available as of the instant, negative if requests are waiting for tokens.
+    state: Mutex<(f64, Instant)>,
+}
+
+impl RequestRateLimiter {
+    pub(crate) fn new(requests_per_second: u32) -> Self {
+        let requests_per_second = requests_per_second as f64;
+        RequestRateLimiter {
+            requests_per_second,
+            state: Mutex::new((requests_per_second, Instant::now())),
+        }
+    }
+
+    /// Take a token, waiting until one is available.
+    ///
+    /// Tokens are handed out in the order they were requested, by letting the bucket go into debt
+    /// and waiting until the debt is paid off.
+    pub(crate) async fn acquire(&self) {
+        let wait = {
+            let mut state = match self.state.lock() {
+                Ok(state) => state,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            let (tokens, last_refill) = &mut *state;
+            let now = Instant::now();
+            *tokens = (*tokens
+                + now.duration_since(*last_refill).as_secs_f64() * self.requests_per_second)
+                .min(self.requests_per_second);
+            *last_refill = now;
+            *tokens -= 1.0;
+            if *tokens >= 0.0 {
+                return;
+            }
+            Duration::from_secs_f64(-*tokens / self.requests_per_second)
+        };
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Dispatcher that holds back every request until the rate limiter allows it, if any.
+pub(crate) struct RateLimitedDispatcher<D> {
+    inner: D,
+    limiter: Option<Arc<RequestRateLimiter>>,
+}
+
+impl<D> RateLimitedDispatcher<D> {
+    pub(crate) fn new(inner: D, limiter: Option<Arc<RequestRateLimiter>>) -> Self {
+        RateLimitedDispatcher { inner, limiter }
+    }
+}
+
+impl<D: DispatchSignedRequest> DispatchSignedRequest for RateLimitedDispatcher<D> {
+    fn dispatch(
+        &self,
+        request: SignedRequest,
+        timeout: Option<Duration>,
+    ) -> DispatchSignedRequestFuture {
+        let response = self.inner.dispatch(request, timeout);
+        match &self.limiter {
+            Some(limiter) => {
+                let limiter = Arc::clone(limiter);
+                Box::pin(async move {
+                    limiter.acquire().await;
+                    response.await
+                })
+            }
+            None => response,
+        }
+    }
+}
+
+#[test]
+fn requests_are_limited_after_a_burst() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let limiter = RequestRateLimiter::new(20);
+
+    let start = Instant::now();
+    runtime.block_on(async {
+        for _ in 0..20 {
+            limiter.acquire().await;
+        }
+    });
+    assert!(start.elapsed() < Duration::from_millis(40));
+
+    // The burst is used up, so further requests are spaced out.
+    let start = Instant::now();
+    runtime.block_on(async {
+        for _ in 0..4 {
+            limiter.acquire().await;
+        }
+    });
+    assert!(start.elapsed() >= Duration::from_millis(150));
+}
@@ -0,0 +1,257 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::errno::{
+    s3_error,
+    s3_error_element,
+};
+use rusoto_core::RusotoError;
+use slog_scope::warn;
+use std::{
+    error::Error,
+    fmt,
+    future::Future,
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+/// Upper bound for the delay between two attempts, regardless of the number of retries.
+const MAXIMUM_DELAY: Duration = Duration::from_secs(20);
+
+/// How requests to S3 that failed with a transient error are retried.
+#[derive(Debug, Clone)]
+pub(crate) struct RetryPolicy {
+    /// Maximum number of attempts per request, including the first one.
+    pub(crate) max_attempts: u32,
+    /// Delay before the first retry, doubled for every further retry up to [`MAXIMUM_DELAY`].
+    /// The actual delay is randomized to between half of it and all of it.
+    pub(crate) base_delay: Duration,
+    /// Retry budget shared across all uploads, if any.
+    pub(crate) budget: Option<Arc<RetryBudget>>,
+    /// Maximum number of attempts per part upload, if different from `max_attempts`.
+    pub(crate) part_max_attempts: Option<u32>,
+    /// Maximum number of attempts to complete a multipart upload, if different from
+    /// `max_attempts`.
+    pub(crate) completion_max_attempts: Option<u32>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            base_delay: Duration::from_millis(100),
+            budget: None,
+            part_max_attempts: None,
+            completion_max_attempts: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The policy for uploading parts of multipart uploads.
+    pub(crate) fn for_parts(&self) -> RetryPolicy {
+        self.with_max_attempts(self.part_max_attempts)
+    }
+
+    /// The policy for completing multipart uploads.
+    pub(crate) fn for_completion(&self) -> RetryPolicy {
+        self.with_max_attempts(self.completion_max_attempts)
+    }
+
+    fn with_max_attempts(&self, max_attempts: Option<u32>) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: max_attempts.unwrap_or(self.max_attempts),
+            ..self.clone()
+        }
+    }
+
+    /// Run the request produced by `attempt`, retrying it if it fails with a transient error.
+    pub(crate) async fn retry<T, E, F, Fut>(
+        &self,
+        operation: &str,
+        mut attempt: F,
+    ) -> anyhow::Result<T>
+    where
+        E: Error + Send + Sync + 'static,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, RusotoError<E>>>,
+    {
+        let mut attempts = 1;
+        let mut delay = self.base_delay;
+        loop {
+            match attempt().await {
+                Ok(output) => return Ok(output),
+                Err(error) if attempts < self.max_attempts && is_retryable(&error) => {
+                    if let Some(budget) = &self.budget {
+                        if !budget.try_acquire() {
+                            return Err(anyhow::Error::new(error).context(RetryBudgetExhausted));
+                        }
+                    }
+                    let jittered_delay = with_jitter(delay);
+                    warn!("{} failed, retrying in {:?}", operation, jittered_delay;
+                          "attempt" => attempts,
+                          "error" => %error);
+                    tokio::time::sleep(jittered_delay).await;
+                    attempts += 1;
+                    delay = (delay * 2).min(MAXIMUM_DELAY);
+                }
+                Err(error) => {
+                    warn_about_clock_skew(operation, &error);
+                    return Err(error.into());
+                }
+            }
+        }
+    }
+}
+
+/// A random duration between half of `delay` and all of it, such that uploads that failed at the
+/// same time, e.g. because S3 throttled them, don't retry in lockstep.
+fn with_jitter(delay: Duration) -> Duration {
+    let mut bytes = [0; 4];
+    let result = unsafe { libc::getrandom(bytes.as_mut_ptr().cast(), bytes.len(), 0) };
+    if result != bytes.len() as isize {
+        return delay;
+    }
+    let fraction = u32::from_ne_bytes(bytes) as f64 / u32::MAX as f64;
+    delay.mul_f64(0.5 + fraction / 2.0)
+}
+
+/// Explain `RequestTimeTooSkewed` errors, which are otherwise hard to make sense of.
+fn warn_about_clock_skew<E>(operation: &str, error: &RusotoError<E>) {
+    let body = match error {
+        RusotoError::Unknown(response) => response.body_as_str(),
+        _ => return,
+    };
+    if s3_error(body).is_some_and(|(code, _)| code == "RequestTimeTooSkewed") {
+        warn!("{} was rejected because the local clock is skewed, synchronize it (e.g. using NTP)",
+              operation;
+              "request_time" => s3_error_element(body, "RequestTime").unwrap_or_default(),
+              "server_time" => s3_error_element(body, "ServerTime").unwrap_or_default());
+    }
+}
+
+/// Whether the error is transient, i.e. the request might succeed if it is sent again.
+fn is_retryable<E>(error: &RusotoError<E>) -> bool {
+    match error {
+        RusotoError::HttpDispatch(_) => true,
+        RusotoError::Unknown(response) => {
+            response.status.is_server_error()
+                || response.status.as_u16() == 429
+                || s3_error(response.body_as_str())
+                    .is_some_and(|(code, _)| code == "RequestTimeout")
+        }
+        _ => false,
+    }
+}
+
+/// Token bucket limiting the number of retries across all uploads.
+///
+/// During a widespread outage every upload would otherwise retry on its own, amplifying the load
+/// on S3. Once the budget is exhausted, failed requests are not retried anymore until it has been
+/// refilled.
+#[derive(Debug)]
+pub(crate) struct RetryBudget {
+    capacity: f64,
+    refill_per_second: f64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl RetryBudget {
+    pub(crate) fn new(capacity: u32, refill_per_second: f64) -> Self {
+        RetryBudget {
+            capacity: capacity as f64,
+            refill_per_second,
+            state: Mutex::new((capacity as f64, Instant::now())),
+        }
+    }
+
+    fn try_acquire(&self) -> bool {
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let (tokens, last_refill) = &mut *state;
+        let now = Instant::now();
+        *tokens = (*tokens
+            + now.duration_since(*last_refill).as_secs_f64() * self.refill_per_second)
+            .min(self.capacity);
+        *last_refill = now;
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// A request failed and was not retried, because the retry budget was exhausted.
+#[derive(Debug)]
+pub(crate) struct RetryBudgetExhausted;
+
+impl fmt::Display for RetryBudgetExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "retry budget exhausted")
+    }
+}
+
+#[test]
+fn retries_are_limited_by_budget() {
+    use crate::errno::errno;
+    use rusoto_core::request::HttpDispatchError;
+    use std::cell::Cell;
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let policy = RetryPolicy {
+        max_attempts: 3,
+        base_delay: Duration::ZERO,
+        budget: Some(Arc::new(RetryBudget::new(3, 0.0))),
+        ..Default::default()
+    };
+    let attempts = Cell::new(0);
+    let failing = || {
+        attempts.set(attempts.get() + 1);
+        async {
+            Err::<(), RusotoError<std::io::Error>>(RusotoError::HttpDispatch(
+                HttpDispatchError::new("connection reset".to_owned()),
+            ))
+        }
+    };
+
+    // The first request uses up two retries, the second one the last one.
+    assert!(runtime.block_on(policy.retry("Test", failing)).is_err());
+    assert_eq!(3, attempts.get());
+    let error = runtime.block_on(policy.retry("Test", failing)).unwrap_err();
+    assert_eq!(5, attempts.get());
+    assert_eq!(libc::EAGAIN, errno(&error));
+}
+
+#[test]
+fn jitter_keeps_delay_within_bounds() {
+    let delay = Duration::from_millis(100);
+    let delays = (0..100).map(|_| with_jitter(delay)).collect::<Vec<_>>();
+
+    assert!(delays
+        .iter()
+        .all(|jittered| (delay / 2..=delay).contains(jittered)));
+    assert!(delays.iter().any(|jittered| *jittered != delays[0]));
+}
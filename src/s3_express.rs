@@ -0,0 +1,499 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Requests to S3 Express One Zone directory buckets.
+//!
+//! Directory buckets are only reachable through virtual-hosted requests to the zonal endpoint of
+//! their availability zone, and authenticate with short-lived session credentials that are
+//! obtained with `CreateSession`. Requests are signed with SigV4 for the `s3express` service and
+//! carry the session token in `x-amz-s3session-token`.
+
+use crate::errno::s3_error_element;
+use chrono::{
+    DateTime,
+    Utc,
+};
+use hmac::{
+    Hmac,
+    Mac,
+    NewMac,
+};
+use rusoto_core::{
+    credential::{
+        AwsCredentials,
+        ProvideAwsCredentials,
+    },
+    request::{
+        DispatchSignedRequestFuture,
+        HttpResponse,
+    },
+    signature::{
+        string_to_sign,
+        SignedRequest,
+        SignedRequestPayload,
+    },
+    DispatchSignedRequest,
+    HttpDispatchError,
+    Region,
+};
+use sha2::{
+    Digest,
+    Sha256,
+};
+use slog_scope::debug;
+use std::{
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::Mutex;
+
+/// The service requests to directory buckets are signed for.
+const SERVICE: &str = "s3express";
+/// Session credentials are renewed this many seconds before they expire.
+const SESSION_RENEWAL_MARGIN_SECONDS: i64 = 60;
+/// SHA-256 digest of an empty payload.
+const EMPTY_SHA256_HASH: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+/// Payload digest of requests whose body is streamed.
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+/// Session credentials of a directory bucket, see [`S3ExpressDispatcher`].
+#[derive(Clone)]
+struct Session {
+    access_key_id: String,
+    secret_access_key: String,
+    token: String,
+    expiration: DateTime<Utc>,
+}
+
+impl Session {
+    /// Parse the response of `CreateSession`.
+    fn parse(body: &str) -> Result<Session, String> {
+        let element = |name| {
+            s3_error_element(body, name)
+                .ok_or_else(|| format!("CreateSession response lacks <{}>", name))
+        };
+        let expiration = element("Expiration")?;
+        let expiration = DateTime::parse_from_rfc3339(&expiration)
+            .map_err(|error| format!("invalid session expiration '{}': {}", expiration, error))?
+            .with_timezone(&Utc);
+
+        Ok(Session {
+            access_key_id: element("AccessKeyId")?,
+            secret_access_key: element("SecretAccessKey")?,
+            token: element("SessionToken")?,
+            expiration,
+        })
+    }
+
+    fn is_valid(&self, now: DateTime<Utc>) -> bool {
+        now + chrono::Duration::seconds(SESSION_RENEWAL_MARGIN_SECONDS) < self.expiration
+    }
+}
+
+/// Sends the requests for a directory bucket to its zonal endpoint, signed with session
+/// credentials that are created with `CreateSession` and renewed before they expire.
+///
+/// The client has to be created with [`rusoto_core::Client::new_not_signing`], since every
+/// request is signed here. Requests for other buckets, e.g. bucket routes, are signed with the
+/// regular credentials and passed on unchanged.
+pub(crate) struct S3ExpressDispatcher<P, D> {
+    state: Arc<State<P, D>>,
+}
+
+struct State<P, D> {
+    inner: D,
+    credentials: P,
+    bucket: String,
+    /// The zonal endpoint of the bucket, e.g. `bucket--use1-az4--x-s3.s3express-use1-az4.us-east-1.amazonaws.com`.
+    hostname: String,
+    region: Region,
+    session: Mutex<Option<Session>>,
+}
+
+impl<P, D> S3ExpressDispatcher<P, D> {
+    pub(crate) fn new(
+        inner: D,
+        credentials: P,
+        bucket: &str,
+        zone_id: &str,
+        region: Region,
+    ) -> Self {
+        S3ExpressDispatcher {
+            state: Arc::new(State {
+                inner,
+                credentials,
+                bucket: bucket.to_owned(),
+                hostname: format!(
+                    "{}.s3express-{}.{}.amazonaws.com",
+                    bucket,
+                    zone_id,
+                    region.name()
+                ),
+                region,
+                session: Mutex::new(None),
+            }),
+        }
+    }
+}
+
+impl<P, D> DispatchSignedRequest for S3ExpressDispatcher<P, D>
+where
+    P: ProvideAwsCredentials + Send + Sync + 'static,
+    D: DispatchSignedRequest + Send + Sync + 'static,
+{
+    fn dispatch(
+        &self,
+        request: SignedRequest,
+        timeout: Option<Duration>,
+    ) -> DispatchSignedRequestFuture {
+        let state = Arc::clone(&self.state);
+        Box::pin(async move { state.dispatch(request, timeout).await })
+    }
+}
+
+impl<P, D> State<P, D>
+where
+    P: ProvideAwsCredentials + Send + Sync,
+    D: DispatchSignedRequest + Send + Sync,
+{
+    async fn dispatch(
+        &self,
+        mut request: SignedRequest,
+        timeout: Option<Duration>,
+    ) -> Result<HttpResponse, HttpDispatchError> {
+        if !self.to_zonal_endpoint(&mut request) {
+            request.sign(&self.credentials().await?);
+            return self.inner.dispatch(request, timeout).await;
+        }
+        let session = self.session().await?;
+        request.remove_header("x-amz-s3session-token");
+        request.add_header("x-amz-s3session-token", &session.token);
+        sign(
+            &mut request,
+            &session.access_key_id,
+            &session.secret_access_key,
+            Utc::now(),
+        );
+        self.inner.dispatch(request, timeout).await
+    }
+
+    /// Address a path-style request for the bucket to its zonal endpoint instead, returning
+    /// whether the request is for the bucket.
+    fn to_zonal_endpoint(&self, request: &mut SignedRequest) -> bool {
+        let bucket_path = format!("/{}", self.bucket);
+        let path = match request.path.strip_prefix(&bucket_path) {
+            Some("") => "/".to_owned(),
+            Some(path) if path.starts_with('/') => path.to_owned(),
+            _ => return false,
+        };
+        request.path = path;
+        request.hostname = Some(self.hostname.clone());
+        request.complement();
+        true
+    }
+
+    async fn credentials(&self) -> Result<AwsCredentials, HttpDispatchError> {
+        self.credentials.credentials().await.map_err(|error| {
+            HttpDispatchError::new(format!("failed to get credentials: {}", error))
+        })
+    }
+
+    /// The current session credentials, creating a new session if there is none or it is about
+    /// to expire.
+    async fn session(&self) -> Result<Session, HttpDispatchError> {
+        let mut session = self.session.lock().await;
+        if let Some(session) = session
+            .as_ref()
+            .filter(|session| session.is_valid(Utc::now()))
+        {
+            return Ok(session.clone());
+        }
+        let created = self.create_session().await?;
+        debug!("Created S3 Express session for '{}'", self.bucket;
+               "expiration" => %created.expiration);
+        *session = Some(created.clone());
+
+        Ok(created)
+    }
+
+    async fn create_session(&self) -> Result<Session, HttpDispatchError> {
+        let credentials = self.credentials().await?;
+        let mut request = SignedRequest::new("GET", SERVICE, &self.region, "/");
+        request.hostname = Some(self.hostname.clone());
+        request.add_param("session", "");
+        request.add_header("x-amz-create-session-mode", "ReadWrite");
+        if let Some(token) = credentials.token() {
+            request.add_header("x-amz-security-token", token);
+        }
+        request.complement();
+        sign(
+            &mut request,
+            credentials.aws_access_key_id(),
+            credentials.aws_secret_access_key(),
+            Utc::now(),
+        );
+
+        let response = self.inner.dispatch(request, None).await?.buffer().await?;
+        let body = response.body_as_str();
+        if !response.status.is_success() {
+            return Err(HttpDispatchError::new(format!(
+                "CreateSession failed with status {}: {}",
+                response.status, body
+            )));
+        }
+        Session::parse(body).map_err(HttpDispatchError::new)
+    }
+}
+
+/// Sign `request` with SigV4 for the `s3express` service.
+///
+/// rusoto's own signer can't be used, since it encodes the path a second time for every service
+/// other than `s3`, while `s3express` expects it to be encoded once, like `s3`.
+fn sign(
+    request: &mut SignedRequest,
+    access_key_id: &str,
+    secret_access_key: &str,
+    date: DateTime<Utc>,
+) {
+    request.remove_header("x-amz-date");
+    request.add_header("x-amz-date", &date.format("%Y%m%dT%H%M%SZ").to_string());
+    let digest = match &request.payload {
+        None => EMPTY_SHA256_HASH.to_owned(),
+        Some(SignedRequestPayload::Buffer(payload)) => hex(&Sha256::digest(payload)),
+        Some(SignedRequestPayload::Stream(_)) => UNSIGNED_PAYLOAD.to_owned(),
+    };
+    request.remove_header("x-amz-content-sha256");
+    request.add_header("x-amz-content-sha256", &digest);
+
+    let headers: Vec<_> = request
+        .headers()
+        .iter()
+        .filter(|(name, _)| {
+            !["authorization", "content-length", "user-agent"].contains(&name.as_str())
+        })
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, values)| {
+            let values = values
+                .iter()
+                .map(|value| {
+                    String::from_utf8_lossy(value)
+                        .split_whitespace()
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                })
+                .collect::<Vec<_>>()
+                .join(",");
+            format!("{}:{}\n", name, values)
+        })
+        .collect();
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        request.method,
+        request.canonical_uri,
+        request.canonical_query_string,
+        canonical_headers,
+        signed_headers,
+        digest
+    );
+
+    let region = request.region.name().to_owned();
+    let scope = format!(
+        "{}/{}/{}/aws4_request",
+        date.format("%Y%m%d"),
+        region,
+        SERVICE
+    );
+    let string_to_sign = string_to_sign(
+        date,
+        &hex(&Sha256::digest(canonical_request.as_bytes())),
+        &scope,
+    );
+    let signing_key = [
+        date.format("%Y%m%d").to_string().as_bytes(),
+        region.as_bytes(),
+        SERVICE.as_bytes(),
+        b"aws4_request",
+    ]
+    .iter()
+    .fold(
+        format!("AWS4{}", secret_access_key).into_bytes(),
+        |key, data| hmac_sha256(&key, data),
+    );
+    let signature = hex(&hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+
+    request.remove_header("authorization");
+    request.add_header(
+        "authorization",
+        &format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            access_key_id, scope, signed_headers, signature
+        ),
+    );
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+const SESSION_RESPONSE: &str = "<CreateSessionResult><Credentials>\
+                                <SessionToken>session-token</SessionToken>\
+                                <SecretAccessKey>session-secret</SecretAccessKey>\
+                                <AccessKeyId>session-key</AccessKeyId>\
+                                <Expiration>2100-01-01T00:00:00Z</Expiration>\
+                                </Credentials></CreateSessionResult>";
+
+/// Records the requests and answers `CreateSession` with [`SESSION_RESPONSE`].
+#[cfg(test)]
+#[derive(Clone, Default)]
+struct RecordingDispatcher(Arc<std::sync::Mutex<Vec<SignedRequest>>>);
+
+#[cfg(test)]
+impl DispatchSignedRequest for RecordingDispatcher {
+    fn dispatch(
+        &self,
+        request: SignedRequest,
+        _timeout: Option<Duration>,
+    ) -> DispatchSignedRequestFuture {
+        let body = if request.params.contains_key("session") {
+            SESSION_RESPONSE
+        } else {
+            ""
+        };
+        self.0.lock().unwrap().push(request);
+        Box::pin(async move {
+            Ok(HttpResponse {
+                status: hyper::StatusCode::OK,
+                body: body.as_bytes().to_vec().into(),
+                headers: Default::default(),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+fn header(request: &SignedRequest, name: &str) -> String {
+    String::from_utf8(request.headers()[name].concat()).unwrap()
+}
+
+#[test]
+fn requests_are_signed_with_session_credentials() {
+    use rusoto_core::credential::StaticProvider;
+
+    let recorder = RecordingDispatcher::default();
+    let dispatcher = S3ExpressDispatcher::new(
+        recorder.clone(),
+        StaticProvider::new_minimal("AKID".to_owned(), "SECRET".to_owned()),
+        "logs--use1-az4--x-s3",
+        "use1-az4",
+        Region::UsEast1,
+    );
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    for path in &[
+        "/logs--use1-az4--x-s3/some key",
+        "/logs--use1-az4--x-s3/other key",
+        "/other-bucket/key",
+    ] {
+        let request = SignedRequest::new("PUT", "s3", &Region::UsEast1, path);
+        runtime
+            .block_on(dispatcher.dispatch(request, None))
+            .unwrap();
+    }
+
+    let requests = recorder.0.lock().unwrap();
+    let zonal_endpoint = "logs--use1-az4--x-s3.s3express-use1-az4.us-east-1.amazonaws.com";
+    assert_eq!(4, requests.len(), "one session for both requests");
+
+    let create_session = &requests[0];
+    assert_eq!("GET", create_session.method);
+    assert_eq!("/", create_session.path);
+    assert_eq!("session=", create_session.canonical_query_string);
+    assert_eq!(zonal_endpoint, header(create_session, "host"));
+    assert_eq!(
+        "ReadWrite",
+        header(create_session, "x-amz-create-session-mode")
+    );
+    assert!(header(create_session, "authorization").contains("Credential=AKID/"));
+
+    for (request, path) in requests[1..3].iter().zip(&["/some%20key", "/other%20key"]) {
+        assert_eq!(*path, request.canonical_uri);
+        assert_eq!(zonal_endpoint, header(request, "host"));
+        assert_eq!("session-token", header(request, "x-amz-s3session-token"));
+        let authorization = header(request, "authorization");
+        assert!(authorization.contains("Credential=session-key/"));
+        assert!(authorization.contains("/us-east-1/s3express/aws4_request"));
+    }
+
+    let other_bucket = &requests[3];
+    assert_eq!("/other-bucket/key", other_bucket.path);
+    assert!(!other_bucket.headers().contains_key("x-amz-s3session-token"));
+    let authorization = header(other_bucket, "authorization");
+    assert!(authorization.contains("Credential=AKID/"));
+    assert!(authorization.contains("/us-east-1/s3/aws4_request"));
+}
+
+#[test]
+fn request_is_signed_for_s3express() {
+    let mut request = SignedRequest::new("PUT", "s3", &Region::UsEast1, "/some key");
+    request.hostname =
+        Some("logs--use1-az4--x-s3.s3express-use1-az4.us-east-1.amazonaws.com".to_owned());
+    request.set_payload(Some(b"hello".to_vec()));
+    request.add_header("x-amz-s3session-token", "session-token");
+    request.complement();
+    sign(
+        &mut request,
+        "session-key",
+        "session-secret",
+        DateTime::parse_from_rfc3339("2025-01-02T03:04:05Z")
+            .unwrap()
+            .with_timezone(&Utc),
+    );
+
+    assert_eq!(
+        "AWS4-HMAC-SHA256 Credential=session-key/20250102/us-east-1/s3express/aws4_request, \
+         SignedHeaders=content-type;host;x-amz-content-sha256;x-amz-date;x-amz-s3session-token, \
+         Signature=3b5c5024cf7eed68f3cb5fff4dc96f02fee646ebac648c65266354aee4b26547",
+        header(&request, "authorization")
+    );
+}
+
+#[test]
+fn sessions_are_renewed_before_they_expire() {
+    let session = Session::parse(SESSION_RESPONSE).unwrap();
+    assert_eq!("session-key", session.access_key_id);
+    assert_eq!("session-secret", session.secret_access_key);
+    assert_eq!("session-token", session.token);
+
+    let expiration = session.expiration;
+    assert!(session.is_valid(expiration - chrono::Duration::minutes(5)));
+    assert!(!session.is_valid(expiration - chrono::Duration::seconds(30)));
+    assert!(!session.is_valid(expiration));
+
+    assert!(Session::parse("<Error><Code>AccessDenied</Code></Error>").is_err());
+}
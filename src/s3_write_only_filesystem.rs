@@ -15,14 +15,56 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
+    aggregation::Aggregator,
+    bucket_route::{
+        self,
+        BucketRoute,
+    },
+    completion_marker::SessionStatus,
+    content_hash::ContentHash,
+    emf::EmfWriter,
+    errno::errno,
+    event_bridge::EventBridge,
     id_generator::IdGenerator,
-    upload::Upload,
+    inventory::InventoryWriter,
+    key_case::KeyCase,
+    key_charset::KeyCharsetPolicy,
+    key_collisions::KeyCollisions,
+    key_pattern::KeyPattern,
+    metrics::Metrics,
+    object_store::ObjectStore,
+    object_url::UrlPrinter,
+    prefix_map::{
+        PrefixMap,
+        VirtualDirectory,
+        DIRECTORY_INODE_BASE,
+    },
+    prefix_shadowing,
+    receipts::{
+        Receipt,
+        Receipts,
+    },
+    upload::{
+        Finished,
+        Interrupted,
+        Upload,
+        UploadOptions,
+    },
+    upload_sink::{
+        ObjectLocation,
+        UploadSink,
+    },
+    windows_names::WindowsSafeKeys,
 };
 use anyhow::{
+    anyhow,
     Context,
+    Error,
     Result,
 };
+use clap::ValueEnum;
 use fuse::{
+    consts,
     FileAttr,
     FileType,
     Filesystem,
@@ -34,31 +76,67 @@ use fuse::{
     ReplyEntry,
     ReplyOpen,
     ReplyWrite,
+    ReplyXattr,
     Request,
 };
 use libc::{
+    c_int,
     EACCES,
+    EBUSY,
+    EEXIST,
+    EINVAL,
     EIO,
+    EMFILE,
+    ENODATA,
     ENOENT,
+    ENOSYS,
+    ERANGE,
+    ESTALE,
+    R_OK,
+    W_OK,
+    X_OK,
 };
-use rusoto_s3::S3Client;
+use rusoto_s3::HeadBucketRequest;
 use slog_scope::{
     debug,
     error,
     info,
     trace,
+    warn,
 };
 use std::{
-    collections::HashMap,
-    ffi::OsStr,
+    collections::{
+        HashMap,
+        HashSet,
+        VecDeque,
+    },
+    ffi::{
+        OsStr,
+        OsString,
+    },
     ops::DerefMut,
     str::FromStr,
     sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        mpsc::{
+            self,
+            Receiver,
+            Sender,
+        },
         Arc,
         Mutex,
+        RwLock,
+    },
+    thread::{
+        self,
+        JoinHandle,
     },
     time::{
         Duration,
+        Instant,
         SystemTime,
     },
 };
@@ -67,49 +145,67 @@ use tokio::runtime::Runtime;
 const GENERATION: u64 = 0;
 const TTL: Duration = Duration::from_secs(0);
 
+/// Permissions of created files: write-only, since their content can't be read back.
+const FILE_PERM: u16 = 0o220;
+
 const ROOT_DIRECTORY_INODE: u64 = 1;
 const ROOT_DIRECTORY_TTL: Duration = Duration::from_secs(60);
 
 const HELP_EN_INODE: u64 = 2;
 const HELP_EN_NAME: &str = "_Uploaded files will not be visible.txt";
 const HELP_EN_CONTENTS: &str = include_str!("../resources/help_en.txt");
-const HELP_EN_FILEATTR: FileAttr = FileAttr {
-    ino: HELP_EN_INODE,
-    size: HELP_EN_CONTENTS.len() as u64,
-    blocks: 1,
-    atime: SystemTime::UNIX_EPOCH,
-    mtime: SystemTime::UNIX_EPOCH,
-    ctime: SystemTime::UNIX_EPOCH,
-    crtime: SystemTime::UNIX_EPOCH,
-    kind: FileType::RegularFile,
-    perm: 0o644,
-    nlink: 1,
-    uid: 0,
-    gid: 0,
-    rdev: 0,
-    flags: 0,
-};
 const HELP_DE_INODE: u64 = 3;
 const HELP_DE_NAME: &str = "_Hochgeladene Dateien werden nicht sichtbar sein.txt";
 const HELP_DE_CONTENTS: &str = include_str!("../resources/help_de.txt");
-const HELP_DE_FILEATTR: FileAttr = FileAttr {
-    ino: HELP_DE_INODE,
-    size: HELP_DE_CONTENTS.len() as u64,
-    blocks: 1,
-    atime: SystemTime::UNIX_EPOCH,
-    mtime: SystemTime::UNIX_EPOCH,
-    ctime: SystemTime::UNIX_EPOCH,
-    crtime: SystemTime::UNIX_EPOCH,
-    kind: FileType::RegularFile,
-    perm: 0o644,
-    nlink: 1,
-    uid: 0,
-    gid: 0,
-    rdev: 0,
-    flags: 0,
-};
 
 const STATIC_INODES: &[u64] = &[ROOT_DIRECTORY_INODE, HELP_EN_INODE, HELP_DE_INODE];
+/// The inode of the first file created, unless configured otherwise.
+const FILE_INODE_BASE: u64 = 10;
+/// Number of released inodes that are remembered to detect writes to stale file handles.
+const RELEASED_INODES_CAPACITY: usize = 4096;
+/// How long the metadata of an uploaded file is retained after it was released by default, such
+/// that the writer can still look it up, e.g. to read its ETag or `stat` it.
+const FINISHED_FILES_RETENTION: Duration = Duration::from_secs(60);
+/// Name of the extended attribute that holds the ETag of an uploaded file.
+const ETAG_XATTR_NAME: &str = "user.s3wofs.etag";
+
+/// Parse the inode of the first file created, given on the command line.
+///
+/// Inodes of files must neither collide with the static inodes nor with the inodes of virtual
+/// directories, which leaves a range of 2^47 inodes for files in any case.
+pub(crate) fn parse_inode_base(s: &str) -> Result<u64> {
+    let inode_base = s.parse::<u64>()?;
+    let min = STATIC_INODES.iter().max().map_or(1, |ino| ino + 1);
+    let max = DIRECTORY_INODE_BASE / 2;
+    if !(min..=max).contains(&inode_base) {
+        return Err(anyhow!(
+            "the inode base must be between {} and {}",
+            min,
+            max
+        ));
+    }
+
+    Ok(inode_base)
+}
+
+fn help_file_attr(ino: u64, contents: &str, timestamp: SystemTime) -> FileAttr {
+    FileAttr {
+        ino,
+        size: contents.len() as u64,
+        blocks: 1,
+        atime: timestamp,
+        mtime: timestamp,
+        ctime: timestamp,
+        crtime: timestamp,
+        kind: FileType::RegularFile,
+        perm: 0o644,
+        nlink: 1,
+        uid: 0,
+        gid: 0,
+        rdev: 0,
+        flags: 0,
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct BucketAndPrefix {
@@ -117,6 +213,22 @@ pub struct BucketAndPrefix {
     pub prefix_path: Option<String>,
 }
 
+impl BucketAndPrefix {
+    /// If the bucket is an S3 Express One Zone directory bucket, i.e. is named
+    /// `<base-name>--<zone-id>--x-s3`, get its availability zone ID.
+    pub(crate) fn directory_bucket_zone_id(&self) -> Option<&str> {
+        let (base_name, zone_id) = self
+            .s3_bucket_name
+            .strip_suffix("--x-s3")?
+            .rsplit_once("--")?;
+        if base_name.is_empty() || zone_id.is_empty() {
+            None
+        } else {
+            Some(zone_id)
+        }
+    }
+}
+
 impl FromStr for BucketAndPrefix {
     type Err = anyhow::Error;
 
@@ -211,16 +323,305 @@ fn bucket_and_prefix_fromstr() {
     );
 }
 
+#[test]
+fn directory_buckets_are_detected() {
+    let zone_id = |device: &str| {
+        device
+            .parse::<BucketAndPrefix>()
+            .unwrap()
+            .directory_bucket_zone_id()
+            .map(str::to_owned)
+    };
+
+    assert_eq!(
+        Some("usw2-az1".to_owned()),
+        zone_id("my-bucket--usw2-az1--x-s3")
+    );
+    assert_eq!(
+        Some("euc1-az2".to_owned()),
+        zone_id("my--bucket--euc1-az2--x-s3:prefix")
+    );
+    assert_eq!(None, zone_id("my-bucket"));
+    assert_eq!(None, zone_id("my-bucket-x-s3"));
+    assert_eq!(None, zone_id("--usw2-az1--x-s3"));
+}
+
+/// Options that apply to the filesystem as a whole, rather than to the individual uploads.
+#[derive(Debug, Default)]
+pub(crate) struct FilesystemOptions {
+    /// If set, print the URL of every uploaded object.
+    pub(crate) url_printer: Option<UrlPrinter>,
+    /// If set, requests made by this (squashed/anonymous) uid are treated as if they were made by
+    /// the user that mounted the filesystem.
+    pub(crate) map_anonymous_uid: Option<u32>,
+    /// If set, the first failed upload causes all further uploads to be rejected.
+    pub(crate) fail_fast: bool,
+    /// Tracks which uploads of the session succeeded or failed.
+    pub(crate) session_status: Arc<SessionStatus>,
+    /// If set, file names are restricted to a safe alphabet for keys.
+    pub(crate) strict_key_charset: Option<KeyCharsetPolicy>,
+    /// How to fold the case of file names.
+    pub(crate) key_case: KeyCase,
+    /// If set, file names are restricted to names that can be represented on Windows.
+    pub(crate) windows_safe_keys: Option<WindowsSafeKeys>,
+    /// If set, keys are derived from the file name through this pattern.
+    pub(crate) key_pattern: Option<KeyPattern>,
+    /// When an upload is finalized.
+    pub(crate) finalize_on: FinalizeOn,
+    /// What happens to buffered data when a file is flushed or synced.
+    pub(crate) flush_behavior: FlushBehavior,
+    /// What happens when a file is created while another file with the same key is being written.
+    pub(crate) concurrent_creates: ConcurrentCreates,
+    /// If set, detects files that are created with a key that was already uploaded during this
+    /// session.
+    pub(crate) key_collisions: Option<KeyCollisions>,
+    /// If set, emits an EventBridge event for every uploaded file.
+    pub(crate) event_bridge: Option<Arc<EventBridge>>,
+    /// If set, writes a receipt file for every uploaded file.
+    pub(crate) receipts: Option<Arc<Receipts>>,
+    /// How long the metadata of an uploaded file is retained after it was released, if not
+    /// [`FINISHED_FILES_RETENTION`]. A zero duration forgets files right away.
+    pub(crate) finished_files_retention: Option<Duration>,
+    /// The inode of the first file created. If unset, inodes of files start at 10.
+    pub(crate) inode_base: Option<u64>,
+    /// The level at which every successful upload is logged.
+    pub(crate) upload_log_level: UploadLogLevel,
+    /// If set, the maximum number of files that can be open at the same time.
+    pub(crate) max_open_files: Option<u32>,
+    /// If set, a connection to S3 is established and credentials are fetched when the filesystem
+    /// is created, rather than on the first upload.
+    pub(crate) warm_connections: bool,
+    /// If set, the maximum number of threads in the blocking thread pool of the runtime.
+    pub(crate) max_blocking_threads: Option<usize>,
+    /// If set, every uploaded object is recorded in an S3 Inventory-compatible listing.
+    pub(crate) inventory: Option<InventoryWriter>,
+    /// If set, metrics of every uploaded file are logged in the CloudWatch Embedded Metric Format.
+    pub(crate) emf: Option<EmfWriter>,
+    /// If set, small files are aggregated into tar archives instead of being uploaded one by one.
+    pub(crate) aggregator: Option<Arc<Aggregator>>,
+    /// Timestamp of the help files. If unset, the time the filesystem was mounted is used.
+    pub(crate) help_file_timestamp: Option<SystemTime>,
+    /// If set, virtual directories whose files are uploaded with an aliased prefix. Directories
+    /// created with `mkdir` are added to it, an empty one is used if unset.
+    pub(crate) prefix_map: Option<Arc<RwLock<PrefixMap>>>,
+    /// If set, writes to uploads bypass the page cache of the kernel.
+    pub(crate) direct_io: bool,
+    /// If set, created files report the mode they were created with rather than `FILE_PERM`.
+    pub(crate) honor_mode: bool,
+    /// If set, a zero-byte marker object `<prefix>/` is created for every virtual directory, such
+    /// that tools like the S3 console show it as a folder.
+    pub(crate) create_directory_markers: bool,
+    /// If set, files whose key collides with existing objects or prefixes are rejected.
+    pub(crate) detect_prefix_shadowing: bool,
+    /// If set, files created in directories are uploaded as if they were created in the root
+    /// directory.
+    pub(crate) flatten: bool,
+    /// Files matching any of these routes are uploaded to the bucket of the first matching route.
+    pub(crate) bucket_routes: Vec<BucketRoute>,
+    /// If set, decides where files are ultimately uploaded to.
+    pub(crate) upload_sink: Option<Arc<dyn UploadSink>>,
+}
+
+/// When an upload is finalized, i.e. the object is written to S3.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum FinalizeOn {
+    /// Finalize on the first `release`, i.e. when any handle to the file is closed.
+    #[default]
+    #[value(name = "release")]
+    Release,
+    /// Finalize once the last open handle to the file is closed.
+    #[value(name = "last-close")]
+    LastClose,
+    /// Finalize only once the file is committed, by renaming it to its name with
+    /// [`COMMIT_SUFFIX`] appended after the last handle was closed. Deleting the file instead
+    /// discards it.
+    #[value(name = "commit")]
+    Commit,
+}
+
+/// The suffix of the name a file is renamed to in order to commit it, see [`FinalizeOn::Commit`].
+const COMMIT_SUFFIX: &str = ".committed";
+
+/// Whether renaming `name` to `new_name` within the same directory commits the file.
+fn is_commit(name: &OsStr, new_name: &OsStr) -> bool {
+    let mut committed = name.to_owned();
+    committed.push(COMMIT_SUFFIX);
+    committed == new_name
+}
+
+/// What happens to buffered data when a file is flushed or synced.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum FlushBehavior {
+    /// Hold the data until a part is full or the upload is finalized.
+    #[default]
+    #[value(name = "hold")]
+    Hold,
+    /// Upload the data as a part, once there is enough for S3 to accept it as a non-final part.
+    #[value(name = "partial-upload")]
+    PartialUpload,
+}
+
+/// The level at which every successful upload is logged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum UploadLogLevel {
+    /// Don't log successful uploads.
+    #[value(name = "off")]
+    Off,
+    #[value(name = "error")]
+    Error,
+    #[value(name = "warn")]
+    Warn,
+    #[default]
+    #[value(name = "info")]
+    Info,
+    #[value(name = "debug")]
+    Debug,
+    #[value(name = "trace")]
+    Trace,
+}
+
+impl UploadLogLevel {
+    fn log_uploaded(self, key: &str, version_id: Option<&str>) {
+        let version_id = version_id.unwrap_or("null");
+        match self {
+            UploadLogLevel::Off => {}
+            UploadLogLevel::Error => {
+                error!("Uploaded new file: {}", key; "version_id" => version_id)
+            }
+            UploadLogLevel::Warn => warn!("Uploaded new file: {}", key; "version_id" => version_id),
+            UploadLogLevel::Info => info!("Uploaded new file: {}", key; "version_id" => version_id),
+            UploadLogLevel::Debug => {
+                debug!("Uploaded new file: {}", key; "version_id" => version_id)
+            }
+            UploadLogLevel::Trace => {
+                trace!("Uploaded new file: {}", key; "version_id" => version_id)
+            }
+        }
+    }
+}
+
+/// What happens when a file is created while another file with the same key is being written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ConcurrentCreates {
+    /// Upload both files, the one that is finished last replacing the other.
+    #[default]
+    #[value(name = "allow")]
+    Allow,
+    /// Accept the file, but discard everything written to it, such that only the file that was
+    /// created first is uploaded.
+    #[value(name = "discard")]
+    Discard,
+    /// Reject the file with `EBUSY`.
+    #[value(name = "reject")]
+    Reject,
+}
+
+impl ConcurrentCreates {
+    /// Apply the policy to `node`, which was just created, returning the error to reply with if it
+    /// is rejected.
+    fn apply(self, nodes: &HashMap<u64, Node>, node: &mut Node) -> Result<(), c_int> {
+        if self == ConcurrentCreates::Allow {
+            return Ok(());
+        }
+        let in_progress = nodes
+            .values()
+            .any(|other| other.bucket == node.bucket && other.key == node.key && !other.duplicate);
+        if !in_progress {
+            return Ok(());
+        }
+        match self {
+            ConcurrentCreates::Allow => Ok(()),
+            ConcurrentCreates::Discard => {
+                info!(
+                    "Discarding concurrent create of file being written: {}",
+                    node.key
+                );
+                node.duplicate = true;
+                for handle in node.handles.values_mut() {
+                    handle.dirty = false;
+                }
+                Ok(())
+            }
+            ConcurrentCreates::Reject => {
+                warn!(
+                    "Rejecting concurrent create of file being written: {}",
+                    node.key
+                );
+                Err(EBUSY)
+            }
+        }
+    }
+}
+
+/// Remove the file with the given name that was closed but not committed yet, see
+/// [`FinalizeOn::Commit`].
+///
+/// Files that are still open can't be committed or discarded and are rejected with `EBUSY`.
+fn take_uncommitted(
+    nodes: &mut HashMap<u64, Node>,
+    parent: u64,
+    name: &OsStr,
+) -> Result<Option<Node>, c_int> {
+    let ino = match lookup_node(nodes, parent, name) {
+        Some(node) if node.open_handles > 0 => return Err(EBUSY),
+        Some(node) if node.uncommitted => node.file_attr.ino,
+        _ => return Ok(None),
+    };
+    Ok(nodes.remove(&ino))
+}
+
+/// The file that is currently being written with the given name, if any.
+///
+/// A file is visible from its `create` until it is released, after which it only exists in S3. If
+/// a name was created more than once, the most recent file is returned.
+fn lookup_node<'a>(nodes: &'a HashMap<u64, Node>, parent: u64, name: &OsStr) -> Option<&'a Node> {
+    nodes
+        .values()
+        .filter(|node| node.parent == parent && node.name == name)
+        .max_by_key(|node| node.file_attr.ino)
+}
+
 struct Node {
+    /// The directory the file was created in.
+    parent: u64,
+    /// The name the file was created with, before it was mapped to a key.
+    name: OsString,
+    bucket: String,
     key: String,
     file_attr: FileAttr,
-    upload: Mutex<Upload>,
+    /// The uploads of the open file handles, keyed by file handle.
+    ///
+    /// Every `open` gets its own upload, such that independent writers don't corrupt each other's
+    /// data. With `--finalize-on last-close` all opens share the upload of the `create` handle.
+    handles: HashMap<u64, Handle>,
+    /// Number of open handles to the file, counting the one returned by `create`.
+    open_handles: u32,
+    /// Whether the file was created while another file with the same key was being written, and
+    /// everything written to it is discarded, see [`ConcurrentCreates::Discard`].
+    duplicate: bool,
+    /// Whether all handles to the file were closed, and it is waiting to be committed, see
+    /// [`FinalizeOn::Commit`].
+    uncommitted: bool,
 }
 
 impl Node {
-    fn new(id: u64, bucket: &str, key: &str) -> Node {
+    /// Create a new node, along with the handle for `create`, which uses `id` as file handle.
+    fn new(
+        id: u64,
+        (parent, name): (u64, &OsStr),
+        (uid, gid): (u32, u32),
+        bucket: &str,
+        key: &str,
+        upload_options: Arc<UploadOptions>,
+    ) -> Node {
         let now = SystemTime::now();
+        let mut handles = HashMap::new();
+        // A created file is uploaded even if nothing is written to it.
+        handles.insert(id, Handle::new(bucket, key, upload_options, true));
         Node {
+            parent,
+            name: name.to_owned(),
+            bucket: bucket.to_owned(),
             key: key.to_owned(),
             file_attr: FileAttr {
                 ino: id,
@@ -231,64 +632,434 @@ impl Node {
                 ctime: now,
                 crtime: now,
                 kind: FileType::RegularFile,
-                perm: 0o220,
+                perm: FILE_PERM,
                 nlink: 1,
-                uid: 0,
-                gid: 0,
+                uid,
+                gid,
                 rdev: 0,
                 flags: 0,
             },
-            upload: Mutex::new(Upload::new(bucket, key)),
+            handles,
+            open_handles: 1,
+            duplicate: false,
+            uncommitted: false,
+        }
+    }
+
+    /// Open another handle to the file, with its own upload.
+    fn open(&mut self, fh: u64, upload_options: Arc<UploadOptions>) {
+        self.handles.insert(
+            fh,
+            Handle::new(&self.bucket, &self.key, upload_options, false),
+        );
+    }
+
+    fn write(
+        &mut self,
+        runtime: &mut Runtime,
+        s3: &dyn ObjectStore,
+        fh: u64,
+        data: &[u8],
+    ) -> Result<()> {
+        if self.duplicate {
+            return Ok(());
+        }
+        let key = &self.key;
+        self.handles
+            .get_mut(&fh)
+            .ok_or_else(|| anyhow!("unknown file handle {} for '{}'", fh, key))?
+            .write(runtime, s3, data)
+    }
+
+    fn flush(&mut self, runtime: &mut Runtime, s3: &dyn ObjectStore, fh: u64) -> Result<()> {
+        let key = &self.key;
+        self.handles
+            .get_mut(&fh)
+            .ok_or_else(|| anyhow!("unknown file handle {} for '{}'", fh, key))?
+            .flush(runtime, s3)
+    }
+
+    /// Set the size hint of the upload of `fh`, or of all uploads if no file handle is given.
+    fn set_size_hint(&mut self, fh: Option<u64>, size: u64) -> Result<()> {
+        for (_, handle) in self
+            .handles
+            .iter_mut()
+            .filter(|(handle_fh, _)| fh.is_none_or(|fh| fh == **handle_fh))
+        {
+            handle.set_size_hint(size)?;
+        }
+
+        Ok(())
+    }
+
+    /// Destroy the uploads of all handles, returning the first error once every handle has been
+    /// destroyed, such that no multipart upload is left behind.
+    fn destroy(&mut self, runtime: &mut Runtime, s3: &dyn ObjectStore) -> Result<()> {
+        let mut result = Ok(());
+        for (_, mut handle) in self.handles.drain() {
+            let destroyed = handle.destroy(runtime, s3);
+            if result.is_ok() {
+                result = destroyed;
+            }
+        }
+
+        result
+    }
+}
+
+/// The upload belonging to a single open file handle.
+struct Handle {
+    upload: Mutex<Upload>,
+    /// Whether the handle has to be uploaded when it is released, i.e. it has been created or
+    /// written to. Handles that were only opened are discarded, rather than replacing the object
+    /// with an empty one.
+    dirty: bool,
+    /// Number of bytes written through the handle.
+    size: u64,
+    /// Number of bytes of the upload held in memory, as last accounted for in the metrics.
+    buffered: u64,
+    metrics: Arc<Metrics>,
+    /// Everything written through the handle, to retry the whole file once if the upload fails,
+    /// see [`UploadOptions::full_retry_max_size`].
+    retained: Option<Retained>,
+    /// Whether a write failed and the upload was restored to its state before it. The write has
+    /// to be retried before the file can be uploaded, see [`UploadOptions::interrupted_writes`].
+    interrupted: bool,
+}
+
+/// The content of a file retained to retry its upload from scratch.
+struct Retained {
+    bucket: String,
+    key: String,
+    upload_options: Arc<UploadOptions>,
+    max_size: u64,
+    content: Vec<u8>,
+}
+
+impl Handle {
+    fn new(bucket: &str, key: &str, upload_options: Arc<UploadOptions>, dirty: bool) -> Handle {
+        let metrics = Arc::clone(&upload_options.metrics);
+        metrics.upload_opened();
+        let retained = upload_options.full_retry_max_size.map(|max_size| Retained {
+            bucket: bucket.to_owned(),
+            key: key.to_owned(),
+            upload_options: Arc::clone(&upload_options),
+            max_size,
+            content: vec![],
+        });
+        Handle {
+            upload: Mutex::new(Upload::new(bucket, key, upload_options)),
+            dirty,
+            size: 0,
+            buffered: 0,
+            metrics,
+            retained,
+            interrupted: false,
+        }
+    }
+
+    fn set_buffered(&mut self, buffered: u64) {
+        self.metrics
+            .add_buffered_bytes(buffered as i64 - self.buffered as i64);
+        self.buffered = buffered;
+    }
+
+    fn retained_len(&self) -> u64 {
+        self.retained
+            .as_ref()
+            .map_or(0, |retained| retained.content.len() as u64)
+    }
+
+    fn write(&mut self, runtime: &mut Runtime, s3: &dyn ObjectStore, data: &[u8]) -> Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let size = self.size;
+        self.dirty = true;
+        self.size += data.len() as u64;
+        if let Some(retained) = &mut self.retained {
+            if self.size <= retained.max_size {
+                retained.content.extend_from_slice(data);
+            } else {
+                debug!("'{}' is too large to be retried as a whole", retained.key);
+                self.retained = None;
+            }
+        }
+        match self.update(|upload| upload.write(runtime, s3, data)) {
+            Ok(()) => {
+                self.interrupted = false;
+                Ok(())
+            }
+            // The upload was restored to its state before the write, which can be retried.
+            Err(error) if self.interrupted => {
+                self.size = size;
+                if let Some(retained) = &mut self.retained {
+                    retained.content.truncate(size as usize);
+                }
+                self.account_buffered();
+                Err(error)
+            }
+            Err(error) => self.restart(runtime, s3, error),
         }
     }
 
-    fn write(&mut self, runtime: &mut Runtime, s3: &S3Client, data: &[u8]) -> Result<()> {
-        let mut upload = std::mem::take(&mut self.upload)
+    /// Upload the data written so far, as far as S3 allows it.
+    fn flush(&mut self, runtime: &mut Runtime, s3: &dyn ObjectStore) -> Result<()> {
+        self.update(|upload| upload.flush(runtime, s3))
+            .or_else(|error| self.restart(runtime, s3, error))
+    }
+
+    /// Start the upload from scratch with the retained content after it failed with `error`. The
+    /// content is only retained for a single retry.
+    fn restart(&mut self, runtime: &mut Runtime, s3: &dyn ObjectStore, error: Error) -> Result<()> {
+        let retained = match self.retained.take() {
+            Some(retained) => retained,
+            None => return Err(error),
+        };
+        warn!("Upload of '{}' failed, retrying the whole file", retained.key;
+              "size" => retained.content.len(),
+              "error" => format!("{:#}", error));
+        let Retained {
+            bucket,
+            key,
+            upload_options,
+            content,
+            ..
+        } = retained;
+        self.upload = Mutex::new(Upload::new(&bucket, &key, upload_options));
+        self.update(|upload| upload.write(runtime, s3, &content))
+    }
+
+    fn update(&mut self, f: impl FnOnce(Upload) -> Result<Upload, Interrupted>) -> Result<()> {
+        let upload = std::mem::take(&mut self.upload)
             .into_inner()
             .context("failed to lock node.upload")?;
-        upload = upload.write(runtime, s3, data)?;
-        let _ = std::mem::replace(&mut self.upload, Mutex::new(upload));
+        let (upload, result) = match f(upload) {
+            Ok(upload) => (upload, Ok(())),
+            // A failed upload makes further writes and the final release report the error, rather
+            // than silently accepting data that will never be uploaded.
+            Err(Interrupted { upload, error }) => {
+                self.interrupted = !matches!(*upload, Upload::Failed);
+                (*upload, Err(error))
+            }
+        };
+        self.upload = Mutex::new(upload);
+        self.account_buffered();
+
+        result
+    }
+
+    /// Account for the bytes of the upload and the retained content held in memory.
+    fn account_buffered(&mut self) {
+        let upload = self
+            .upload
+            .get_mut()
+            .map_or(0, |upload| upload.buffered_len());
+        self.set_buffered(upload + self.retained_len());
+    }
+
+    fn set_size_hint(&mut self, size: u64) -> Result<()> {
+        self.upload
+            .get_mut()
+            .map_err(|_| anyhow!("failed to lock node.upload"))?
+            .set_size_hint(size);
 
         Ok(())
     }
 
-    fn finish(&mut self, runtime: &mut Runtime, s3: &S3Client) -> Result<()> {
+    /// Take the content written through the handle, if it is small enough to be aggregated.
+    fn take_small_content(&mut self, max_size: u64) -> Result<Option<Vec<u8>>> {
+        if !self.dirty {
+            return Ok(None);
+        }
+
+        let content = self
+            .upload
+            .get_mut()
+            .map_err(|_| anyhow!("failed to lock node.upload"))?
+            .take_buffer(max_size);
+        if content.is_some() {
+            self.retained = None;
+            self.set_buffered(0);
+        }
+
+        Ok(content)
+    }
+
+    fn finish(&mut self, runtime: &mut Runtime, s3: &dyn ObjectStore) -> Result<Finished> {
+        if self.interrupted {
+            self.destroy(runtime, s3)?;
+            return Err(anyhow!(
+                "a write failed and was not retried, refusing to upload the incomplete file"
+            ));
+        }
+        if !self.dirty {
+            self.destroy(runtime, s3)?;
+            return Ok(Finished::Skipped);
+        }
         let upload = std::mem::take(&mut self.upload)
             .into_inner()
             .context("failed to lock node.upload")?;
-        upload.finish(runtime, s3)?;
+        self.set_buffered(self.retained_len());
 
-        Ok(())
+        match upload.finish(runtime, s3) {
+            Err(error) if self.retained.is_some() => {
+                self.restart(runtime, s3, error)?;
+                self.finish(runtime, s3)
+            }
+            finished => {
+                self.retained = None;
+                self.set_buffered(0);
+                finished
+            }
+        }
     }
 
-    fn destroy(&mut self, runtime: &mut Runtime, s3: &S3Client) -> Result<()> {
+    fn destroy(&mut self, runtime: &mut Runtime, s3: &dyn ObjectStore) -> Result<()> {
         let upload = std::mem::take(&mut self.upload)
             .into_inner()
             .context("failed to lock node.upload")?;
+        self.set_buffered(0);
         upload.destroy(runtime, s3)?;
 
         Ok(())
     }
 }
 
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.set_buffered(0);
+        self.metrics.upload_closed();
+    }
+}
+
+/// The most recently released inodes, such that writes to them can be told apart from writes to
+/// inodes that never existed.
+#[derive(Default)]
+struct ReleasedInodes {
+    order: VecDeque<u64>,
+    inodes: HashSet<u64>,
+}
+
+impl ReleasedInodes {
+    fn insert(&mut self, ino: u64) {
+        if !self.inodes.insert(ino) {
+            return;
+        }
+        self.order.push_back(ino);
+        if self.order.len() > RELEASED_INODES_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.inodes.remove(&oldest);
+            }
+        }
+    }
+
+    fn contains(&self, ino: u64) -> bool {
+        self.inodes.contains(&ino)
+    }
+}
+
+/// A file that was uploaded recently, whose metadata is retained for
+/// [`FilesystemOptions::finished_files_retention`].
+struct FinishedFile {
+    parent: u64,
+    name: OsString,
+    file_attr: FileAttr,
+    e_tag: Option<String>,
+    finished_at: Instant,
+}
+
+/// The files that were uploaded within the retention window, oldest first.
+struct FinishedFiles {
+    files: VecDeque<FinishedFile>,
+    retention: Duration,
+}
+
+impl Default for FinishedFiles {
+    fn default() -> Self {
+        FinishedFiles::new(FINISHED_FILES_RETENTION)
+    }
+}
+
+impl FinishedFiles {
+    fn new(retention: Duration) -> Self {
+        FinishedFiles {
+            files: VecDeque::new(),
+            retention,
+        }
+    }
+
+    fn insert(&mut self, file: FinishedFile) {
+        self.prune();
+        if self.retention.is_zero() {
+            return;
+        }
+        // Every handle of a file is uploaded separately, the last upload determines the object.
+        self.files
+            .retain(|finished| finished.file_attr.ino != file.file_attr.ino);
+        self.files.push_back(file);
+    }
+
+    fn prune(&mut self) {
+        while self
+            .files
+            .front()
+            .is_some_and(|file| file.finished_at.elapsed() > self.retention)
+        {
+            self.files.pop_front();
+        }
+    }
+
+    fn get(&mut self, ino: u64) -> Option<&FinishedFile> {
+        self.prune();
+        self.files.iter().find(|file| file.file_attr.ino == ino)
+    }
+
+    fn lookup(&mut self, parent: u64, name: &OsStr) -> Option<&FinishedFile> {
+        self.prune();
+        self.files
+            .iter()
+            .rev()
+            .find(|file| file.parent == parent && file.name == name)
+    }
+}
+
 pub(crate) struct S3WriteOnlyFilesystem {
     root_directory_fileattr: FileAttr,
+    help_en_fileattr: FileAttr,
+    help_de_fileattr: FileAttr,
 
     id_generator: Arc<IdGenerator>,
     nodes: Arc<Mutex<HashMap<u64, Node>>>,
+    released_inodes: ReleasedInodes,
+    finished_files: FinishedFiles,
 
-    s3: S3Client,
+    s3: Arc<dyn ObjectStore>,
     s3_bucket: String,
     s3_prefix_path: Option<String>,
+    upload_options: Arc<UploadOptions>,
+    options: Arc<FilesystemOptions>,
+    /// The uid and gid of the user that mounted the filesystem.
+    owner: (u32, u32),
+    /// Whether an upload failed while fail-fast is enabled.
+    failed: Arc<AtomicBool>,
     runtime: Runtime,
+    /// Finishes uploads while uploads are paused or outside the upload window, if there is a spool
+    /// to hold back their data.
+    deferred_uploads: Option<DeferredUploads>,
 }
 
 impl S3WriteOnlyFilesystem {
     pub(crate) fn new(
-        s3: S3Client,
+        s3: Arc<dyn ObjectStore>,
         bucket_and_prefix: BucketAndPrefix,
+        upload_options: UploadOptions,
+        mut options: FilesystemOptions,
     ) -> Result<S3WriteOnlyFilesystem> {
         let now = SystemTime::now();
+        options
+            .prefix_map
+            .get_or_insert_with(|| Arc::new(RwLock::new(PrefixMap::empty())));
         let root_directory_fileattr = FileAttr {
             ino: ROOT_DIRECTORY_INODE,
             size: 0,
@@ -306,111 +1077,764 @@ impl S3WriteOnlyFilesystem {
             flags: 0,
         };
 
-        let id_generator = Arc::new(IdGenerator::new(10));
+        let help_file_timestamp = options.help_file_timestamp.unwrap_or(now);
+        let help_en_fileattr = help_file_attr(HELP_EN_INODE, HELP_EN_CONTENTS, help_file_timestamp);
+        let help_de_fileattr = help_file_attr(HELP_DE_INODE, HELP_DE_CONTENTS, help_file_timestamp);
+
+        let id_generator = Arc::new(IdGenerator::new(
+            options.inode_base.unwrap_or(FILE_INODE_BASE),
+        ));
         let nodes = Arc::new(Mutex::new(HashMap::new()));
-        let runtime = Runtime::new()?;
+        let runtime = build_runtime(&options)?;
+        if options.warm_connections {
+            // The connection pool is bound to the runtime, so the connection has to be established
+            // on the runtime that is used for the uploads.
+            Self::warm_connections(&runtime, s3.as_ref(), &bucket_and_prefix.s3_bucket_name);
+        }
+
+        let options = Arc::new(options);
+        let failed = Arc::new(AtomicBool::new(false));
+        let deferred_uploads = match upload_options.spool {
+            Some(_) => Some(DeferredUploads::start(
+                Arc::clone(&s3),
+                Arc::clone(&options),
+                Arc::clone(&failed),
+            )?),
+            None => None,
+        };
 
-        Ok(S3WriteOnlyFilesystem {
+        let filesystem = S3WriteOnlyFilesystem {
             root_directory_fileattr,
+            help_en_fileattr,
+            help_de_fileattr,
             id_generator,
             nodes,
+            released_inodes: ReleasedInodes::default(),
+            finished_files: FinishedFiles::new(
+                options
+                    .finished_files_retention
+                    .unwrap_or(FINISHED_FILES_RETENTION),
+            ),
             s3,
             s3_bucket: bucket_and_prefix.s3_bucket_name,
             s3_prefix_path: bucket_and_prefix.prefix_path,
+            upload_options: Arc::new(upload_options),
+            options,
+            owner: unsafe { (libc::getuid(), libc::getgid()) },
+            failed,
             runtime,
-        })
+            deferred_uploads,
+        };
+        if filesystem.options.create_directory_markers {
+            filesystem.create_directory_markers();
+        }
+
+        Ok(filesystem)
     }
 }
 
-impl Drop for S3WriteOnlyFilesystem {
-    fn drop(&mut self) {
-        trace!("S3WriteOnlyFilesystem::drop()");
-        match self.nodes.lock() {
-            Ok(mut nodes) => {
-                for node in nodes.values_mut() {
-                    if let Err(error) = node.destroy(&mut self.runtime, &self.s3) {
-                        error!("Failed to destroy node '{}'", node.key; "error" => %error);
-                    }
-                }
-            }
-            Err(error) => {
-                error!("failed to acquire lock on filesystem nodes"; "error" => %error);
-            }
+impl S3WriteOnlyFilesystem {
+    /// Pre-establish a connection to S3 and fetch credentials with a cheap `HeadBucket`, such that
+    /// the first upload isn't slowed down by the TLS handshake and credential lookup.
+    fn warm_connections(runtime: &Runtime, s3: &dyn ObjectStore, bucket: &str) {
+        let start = Instant::now();
+        match runtime.block_on(s3.head_bucket(HeadBucketRequest {
+            bucket: bucket.to_owned(),
+            ..Default::default()
+        })) {
+            Ok(()) => debug!("Warmed up connection to S3"; "duration" => ?start.elapsed()),
+            Err(error) => warn!("Failed to warm up connection to S3"; "error" => %error),
         }
     }
-}
 
-impl Filesystem for S3WriteOnlyFilesystem {
-    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
-        trace!("lookup(parent={}, name={:?})", parent, name);
-        if parent != ROOT_DIRECTORY_INODE {
-            reply.error(ENOENT);
-            return;
+    /// Create the marker objects of all virtual directories, see
+    /// [`FilesystemOptions::create_directory_markers`].
+    fn create_directory_markers(&self) {
+        let directories: Vec<_> = match &self.options.prefix_map {
+            Some(prefix_map) => match prefix_map.read() {
+                Ok(prefix_map) => prefix_map.directories().cloned().collect(),
+                Err(error) => {
+                    error!("failed to acquire lock on prefix map"; "error" => %error);
+                    return;
+                }
+            },
+            None => return,
+        };
+        for directory in directories {
+            self.create_directory_marker(&directory);
         }
+    }
 
-        if name == HELP_EN_NAME {
-            reply.entry(&TTL, &HELP_EN_FILEATTR, GENERATION);
-        } else if name == HELP_DE_NAME {
-            reply.entry(&TTL, &HELP_DE_FILEATTR, GENERATION);
-        } else {
-            reply.error(ENOENT);
+    /// Create the zero-byte marker object for `directory`. Failures are only logged, since the
+    /// directory is usable without it.
+    fn create_directory_marker(&self, directory: &VirtualDirectory) {
+        let key = format!("{}/", directory.prefix);
+        let result = self
+            .runtime
+            .block_on(self.upload_options.retry.retry("PutObject", || {
+                self.s3.put_object(self.upload_options.put_object_request(
+                    self.s3_bucket.clone(),
+                    key.clone(),
+                    vec![],
+                ))
+            }));
+        match result {
+            Ok(_) => debug!("Created directory marker: {}", key),
+            Err(error) => {
+                warn!("Failed to create directory marker: {}", key; "error" => format!("{:#}", error))
+            }
         }
     }
 
-    fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
-        trace!("getattr(ino={})", ino);
+    /// The attributes of `ino` along with how long they may be cached, if it exists.
+    fn attr(&mut self, ino: u64) -> Option<(Duration, FileAttr)> {
         match ino {
-            ROOT_DIRECTORY_INODE => reply.attr(&ROOT_DIRECTORY_TTL, &self.root_directory_fileattr),
-            HELP_EN_INODE => reply.attr(&ROOT_DIRECTORY_TTL, &HELP_EN_FILEATTR),
-            HELP_DE_INODE => reply.attr(&ROOT_DIRECTORY_TTL, &HELP_DE_FILEATTR),
+            ROOT_DIRECTORY_INODE => Some((
+                ROOT_DIRECTORY_TTL,
+                FileAttr {
+                    nlink: self.directory_nlink(None),
+                    ..self.root_directory_fileattr
+                },
+            )),
+            HELP_EN_INODE => Some((ROOT_DIRECTORY_TTL, self.help_en_fileattr)),
+            HELP_DE_INODE => Some((ROOT_DIRECTORY_TTL, self.help_de_fileattr)),
             _ => {
+                if let Some(directory) = self.virtual_directory(ino) {
+                    return Some((
+                        ROOT_DIRECTORY_TTL,
+                        self.virtual_directory_fileattr(&directory),
+                    ));
+                }
                 match self.nodes.lock() {
                     Ok(nodes) => {
                         if let Some(node) = nodes.get(&ino) {
-                            reply.attr(&TTL, &node.file_attr);
-                            return;
+                            return Some((TTL, node.file_attr));
                         }
                     }
                     Err(error) => {
                         error!("failed to acquire lock on filesystem nodes"; "error" => %error);
                     }
                 }
-                reply.error(ENOENT);
+                self.finished_files
+                    .get(ino)
+                    .map(|file| (TTL, file.file_attr))
             }
         }
     }
 
-    fn setattr(
+    /// The permissions of a file created with `mode`, see [`FilesystemOptions::honor_mode`].
+    fn file_perm(&self, mode: u32) -> u16 {
+        let perm = (mode & 0o777) as u16;
+        if self.options.honor_mode && perm != 0 {
+            perm
+        } else {
+            FILE_PERM
+        }
+    }
+
+    /// Record that an upload failed, which puts the filesystem into the failed state if fail-fast
+    /// is enabled.
+    fn upload_failed(options: &FilesystemOptions, failed: &AtomicBool) {
+        if options.fail_fast && !failed.swap(true, Ordering::SeqCst) {
+            error!("An upload failed, rejecting all further uploads since fail-fast is enabled");
+        }
+    }
+
+    /// Finalize the upload of a released `handle`, and reply accordingly.
+    fn finalize(
         &mut self,
-        _req: &Request<'_>,
-        ino: u64,
-        _mode: Option<u32>,
-        _uid: Option<u32>,
-        _gid: Option<u32>,
-        _size: Option<u64>,
-        _atime: Option<SystemTime>,
-        _mtime: Option<SystemTime>,
-        _fh: Option<u64>,
-        _crtime: Option<SystemTime>,
-        _chgtime: Option<SystemTime>,
-        _bkuptime: Option<SystemTime>,
-        _flags: Option<u32>,
-        reply: ReplyAttr,
+        (parent, name): (u64, OsString),
+        bucket: String,
+        key: String,
+        mut file_attr: FileAttr,
+        mut handle: Handle,
+        reply: ReplyEmpty,
     ) {
-        trace!(
-            "setattr(ino={}, mode={:?}, uid={:?}, gid={:?}, size={:?}, atime={:?}, mtime={:?}, fh={:?}, crtime={:?}, chgtime={:?}, bkuptime={:?}, flags={:?})",
-            ino, _mode, _uid, _gid, _size, _atime, _mtime, _fh, _crtime, _chgtime, _bkuptime, _flags,
-        );
-
-        match self.nodes.lock() {
-            Ok(nodes) => {
-                if let Some(node) = nodes.get(&ino) {
-                    reply.attr(&TTL, &node.file_attr);
+        if self.failed.load(Ordering::SeqCst) {
+            // Don't complete uploads once another one has failed.
+            self.options.session_status.upload_failed();
+            if let Err(error) = handle.destroy(&mut self.runtime, self.s3.as_ref()) {
+                error!("Failed to destroy node '{}'", key; "error" => %error);
+            }
+            reply.error(EIO);
+            return;
+        }
+        if let Some(aggregator) = &self.options.aggregator {
+            match handle.take_small_content(aggregator.max_file_size()) {
+                Ok(Some(content)) => {
+                    let name = match &self.s3_prefix_path {
+                        Some(prefix) => key
+                            .strip_prefix(prefix.as_str())
+                            .map(|name| name.trim_start_matches('/'))
+                            .unwrap_or(&key),
+                        None => &key,
+                    };
+                    if let Err(error) = aggregator.append(name, SystemTime::now(), &content) {
+                        error!("failed to upload aggregate of small files"; "error" => %error);
+                        Self::upload_failed(&self.options, &self.failed);
+                        reply.error(errno(&error));
+                        return;
+                    }
+                    debug!("Aggregated small file: {}", key);
+                    reply.ok();
                     return;
                 }
+                Ok(None) => {}
+                Err(error) => {
+                    error!("failed to take content of small file"; "error" => %error);
+                }
             }
-            Err(error) => {
-                error!("failed to acquire lock on filesystem nodes"; "error" => %error);
+        }
+        if handle.dirty && !self.upload_options.uploads_allowed() {
+            if let Some(deferred_uploads) = &self.deferred_uploads {
+                file_attr.size = handle.size;
+                deferred_uploads.queue(DeferredUpload {
+                    bucket,
+                    key,
+                    crtime: file_attr.crtime,
+                    handle,
+                });
+                self.finished_files.insert(FinishedFile {
+                    parent,
+                    name,
+                    file_attr,
+                    e_tag: None,
+                    finished_at: Instant::now(),
+                });
+                reply.ok();
+                return;
+            }
+        }
+        match handle.finish(&mut self.runtime, self.s3.as_ref()) {
+            Ok(Finished::Skipped) => {
+                info!("Discarded file without uploading it: {}", key);
+                reply.ok();
+            }
+            Ok(Finished::Uploaded {
+                key,
+                version_id,
+                e_tag,
+                sha256,
+            }) => {
+                record_upload(
+                    &self.options,
+                    &bucket,
+                    &key,
+                    handle.size,
+                    file_attr.crtime,
+                    version_id.as_deref(),
+                    e_tag.as_deref(),
+                    sha256.as_ref(),
+                );
+                file_attr.size = handle.size;
+                self.finished_files.insert(FinishedFile {
+                    parent,
+                    name,
+                    file_attr,
+                    e_tag,
+                    finished_at: Instant::now(),
+                });
+                reply.ok();
+            }
+            Err(error) => {
+                error!("failed to finalize node"; "error" => %error);
+                self.options.session_status.upload_failed_with(&error);
+                Self::upload_failed(&self.options, &self.failed);
+                reply.error(errno(&error));
+            }
+        }
+    }
+
+    /// Handle `flush` and `fsync`, which upload the buffered data with `--flush-behavior
+    /// partial-upload` and are no-ops otherwise.
+    fn flush_node(&mut self, ino: u64, fh: u64, reply: ReplyEmpty) {
+        if self.options.flush_behavior == FlushBehavior::Hold {
+            reply.ok();
+            return;
+        }
+
+        match self.nodes.lock() {
+            Ok(mut nodes) => {
+                if let Some(node) = nodes.get_mut(&ino) {
+                    if let Err(error) = node.flush(&mut self.runtime, self.s3.as_ref(), fh) {
+                        error!("failed to flush node"; "key" => &node.key, "error" => %error);
+                        Self::upload_failed(&self.options, &self.failed);
+                        reply.error(errno(&error));
+                        return;
+                    }
+                }
+                reply.ok();
+            }
+            Err(error) => {
+                error!("failed to acquire lock on filesystem nodes"; "error" => %error);
+                reply.error(ENOENT);
+            }
+        }
+    }
+
+    /// The uid and gid to use for permission purposes for the given request.
+    fn requester(&self, req: &Request<'_>) -> (u32, u32) {
+        if self.options.map_anonymous_uid == Some(req.uid()) {
+            self.owner
+        } else {
+            (req.uid(), req.gid())
+        }
+    }
+
+    /// The virtual directory from the prefix map with the given inode, if any.
+    fn virtual_directory(&self, ino: u64) -> Option<VirtualDirectory> {
+        let prefix_map = self.options.prefix_map.as_ref()?;
+        match prefix_map.read() {
+            Ok(prefix_map) => prefix_map.directory(ino).cloned(),
+            Err(error) => {
+                error!("failed to acquire lock on prefix map"; "error" => %error);
+                None
+            }
+        }
+    }
+
+    /// The virtual directories within the given directory, `None` being the root directory.
+    fn virtual_subdirectories(&self, parent: Option<&str>) -> Vec<VirtualDirectory> {
+        let prefix_map = match &self.options.prefix_map {
+            Some(prefix_map) => prefix_map,
+            None => return vec![],
+        };
+        match prefix_map.read() {
+            Ok(prefix_map) => prefix_map.children(parent).cloned().collect(),
+            Err(error) => {
+                error!("failed to acquire lock on prefix map"; "error" => %error);
+                vec![]
+            }
+        }
+    }
+
+    /// The inode of the directory containing the given virtual directory.
+    fn virtual_parent_inode(&self, directory: &VirtualDirectory) -> u64 {
+        let parent = match directory.parent() {
+            Some(parent) => parent,
+            None => return ROOT_DIRECTORY_INODE,
+        };
+        self.options
+            .prefix_map
+            .as_ref()
+            .and_then(|prefix_map| {
+                prefix_map
+                    .read()
+                    .ok()?
+                    .path(parent)
+                    .map(|parent| parent.ino)
+            })
+            .unwrap_or(ROOT_DIRECTORY_INODE)
+    }
+
+    /// Create the virtual directory called `name` in `parent`, whose files are uploaded below the
+    /// prefix of `parent` followed by `name`.
+    fn make_directory(&mut self, parent: u64, name: &OsStr) -> Result<VirtualDirectory, c_int> {
+        let parent_directory = if parent == ROOT_DIRECTORY_INODE {
+            None
+        } else {
+            Some(self.virtual_directory(parent).ok_or(ENOENT)?)
+        };
+        let parent_path = parent_directory
+            .as_ref()
+            .map(|directory| directory.path.as_str());
+        let name = name.to_string_lossy();
+        let is_file = match self.nodes.lock() {
+            Ok(nodes) => lookup_node(&nodes, parent, OsStr::new(&*name)).is_some(),
+            Err(_) => return Err(EIO),
+        };
+        let is_help_file = parent_path.is_none() && (name == HELP_EN_NAME || name == HELP_DE_NAME);
+        if is_file || is_help_file {
+            return Err(EEXIST);
+        }
+        // The name becomes part of the key of every file below the directory.
+        let mut prefix_name = name.clone().into_owned();
+        if let Some(strict_key_charset) = self.options.strict_key_charset {
+            match strict_key_charset.apply(&prefix_name) {
+                Some(safe_name) => prefix_name = safe_name,
+                None => {
+                    warn!(
+                        "Rejecting directory with unsafe characters in its name: {}",
+                        name
+                    );
+                    return Err(EINVAL);
+                }
+            }
+        }
+        let prefix = match upload_prefix(
+            self.s3_prefix_path.as_deref(),
+            parent_directory.as_ref(),
+            false,
+        ) {
+            Some(prefix) => format!("{}/{}", prefix, prefix_name),
+            None => prefix_name,
+        };
+
+        let prefix_map = self.options.prefix_map.as_ref().ok_or(EACCES)?;
+        let created = match prefix_map.write() {
+            Ok(mut prefix_map) => {
+                if prefix_map.lookup(parent_path, &name).is_some() {
+                    return Err(EEXIST);
+                }
+                prefix_map.create(parent_path, &name, prefix)
+            }
+            Err(error) => {
+                error!("failed to acquire lock on prefix map"; "error" => %error);
+                return Err(EIO);
+            }
+        };
+        let directory = created.map_err(|error| {
+            warn!("Refusing to create directory '{}'", name; "error" => %error);
+            EACCES
+        })?;
+        info!(
+            "Created directory '{}' for prefix '{}'",
+            directory.path, directory.prefix
+        );
+        if self.options.create_directory_markers {
+            self.create_directory_marker(&directory);
+        }
+
+        Ok(directory)
+    }
+
+    fn virtual_directory_fileattr(&self, directory: &VirtualDirectory) -> FileAttr {
+        FileAttr {
+            ino: directory.ino,
+            nlink: self.directory_nlink(Some(&directory.path)),
+            ..self.root_directory_fileattr
+        }
+    }
+
+    /// The link count of the given directory, `None` being the root directory: one for its entry
+    /// in the parent, one for `.` and one for the `..` of every subdirectory.
+    fn directory_nlink(&self, path: Option<&str>) -> u32 {
+        2 + self.virtual_subdirectories(path).len() as u32
+    }
+
+    /// The flags to reply with when an upload is created or opened.
+    fn open_flags(&self) -> u32 {
+        if self.options.direct_io {
+            consts::FOPEN_DIRECT_IO
+        } else {
+            0
+        }
+    }
+
+    /// Whether opening another file would exceed `--max-open-files`.
+    fn too_many_open_files(&self, nodes: &HashMap<u64, Node>) -> bool {
+        let max_open_files = match self.options.max_open_files {
+            Some(max_open_files) => max_open_files,
+            None => return false,
+        };
+        let open_files: u32 = nodes.values().map(|node| node.open_handles).sum();
+        if open_files >= max_open_files {
+            warn!("Refusing to open another file, the maximum number of open files is reached";
+                  "max_open_files" => max_open_files);
+            return true;
+        }
+
+        false
+    }
+}
+
+/// Record the successful upload of `size` bytes to `key` wherever the options ask for it, e.g. in
+/// the upload log and receipts.
+#[allow(clippy::too_many_arguments)]
+fn record_upload(
+    options: &FilesystemOptions,
+    bucket: &str,
+    key: &str,
+    size: u64,
+    crtime: SystemTime,
+    version_id: Option<&str>,
+    e_tag: Option<&str>,
+    sha256: Option<&ContentHash>,
+) {
+    options.upload_log_level.log_uploaded(key, version_id);
+    options.session_status.upload_succeeded();
+    if let Some(key_collisions) = &options.key_collisions {
+        key_collisions.record(bucket, key);
+    }
+    if let Some(event_bridge) = &options.event_bridge {
+        event_bridge.uploaded(bucket, key, size, version_id);
+    }
+    if let Some(receipts) = &options.receipts {
+        receipts.uploaded(Receipt::new(
+            bucket,
+            key,
+            size,
+            e_tag,
+            version_id,
+            SystemTime::now(),
+            sha256,
+        ));
+    }
+    if let Some(url_printer) = &options.url_printer {
+        if let Err(error) = url_printer.print(bucket, key, version_id) {
+            error!("failed to print URL of uploaded file"; "error" => %error);
+        }
+    }
+    if let Some(inventory) = &options.inventory {
+        if let Err(error) = inventory.record(bucket, key, size, SystemTime::now(), e_tag) {
+            error!("failed to record uploaded file in inventory"; "error" => %error);
+        }
+    }
+    if let Some(emf) = &options.emf {
+        let duration = SystemTime::now().duration_since(crtime).unwrap_or_default();
+        if let Err(error) = emf.record_upload(bucket, key, size, duration) {
+            error!("failed to record metrics of uploaded file"; "error" => %error);
+        }
+    }
+}
+
+/// The runtime the uploads are driven on.
+fn build_runtime(options: &FilesystemOptions) -> Result<Runtime> {
+    let mut runtime_builder = tokio::runtime::Builder::new_multi_thread();
+    runtime_builder.enable_all();
+    if let Some(max_blocking_threads) = options.max_blocking_threads {
+        runtime_builder.max_blocking_threads(max_blocking_threads);
+    }
+
+    Ok(runtime_builder.build()?)
+}
+
+/// A released file whose upload is finished once uploads are allowed again.
+struct DeferredUpload {
+    bucket: String,
+    key: String,
+    crtime: SystemTime,
+    handle: Handle,
+}
+
+/// Finishes the uploads of files released while uploads are paused or outside the upload window in
+/// a background thread, one after the other, such that `release` replies right away rather than
+/// blocking the filesystem until uploads are allowed, see [`UploadOptions::pause`] and
+/// [`UploadOptions::upload_window`].
+///
+/// The data of the queued files stays in their buffers in the meantime, which is bounded by the
+/// spool.
+struct DeferredUploads {
+    sender: Option<Sender<DeferredUpload>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl DeferredUploads {
+    fn start(
+        s3: Arc<dyn ObjectStore>,
+        options: Arc<FilesystemOptions>,
+        failed: Arc<AtomicBool>,
+    ) -> Result<Self> {
+        // The uploads are driven on a runtime of their own, since the one of the filesystem is
+        // busy with the requests of the kernel.
+        let runtime = build_runtime(&options)?;
+        let (sender, receiver) = mpsc::channel();
+        let worker = thread::Builder::new()
+            .name("deferred-uploads".to_owned())
+            .spawn(move || Self::finish_all(receiver, runtime, s3.as_ref(), &options, &failed))?;
+
+        Ok(DeferredUploads {
+            sender: Some(sender),
+            worker: Some(worker),
+        })
+    }
+
+    fn queue(&self, deferred_upload: DeferredUpload) {
+        let sent = self
+            .sender
+            .as_ref()
+            .is_some_and(|sender| sender.send(deferred_upload).is_ok());
+        if !sent {
+            error!("Failed to queue upload until uploads are allowed");
+        }
+    }
+
+    fn finish_all(
+        receiver: Receiver<DeferredUpload>,
+        mut runtime: Runtime,
+        s3: &dyn ObjectStore,
+        options: &FilesystemOptions,
+        failed: &AtomicBool,
+    ) {
+        while let Ok(DeferredUpload {
+            bucket,
+            key,
+            crtime,
+            mut handle,
+        }) = receiver.recv()
+        {
+            if failed.load(Ordering::SeqCst) {
+                // Don't complete uploads once another one has failed.
+                options.session_status.upload_failed();
+                if let Err(error) = handle.destroy(&mut runtime, s3) {
+                    error!("Failed to destroy node '{}'", key; "error" => %error);
+                }
+                continue;
+            }
+            match handle.finish(&mut runtime, s3) {
+                Ok(Finished::Skipped) => info!("Discarded file without uploading it: {}", key),
+                Ok(Finished::Uploaded {
+                    key,
+                    version_id,
+                    e_tag,
+                    sha256,
+                }) => record_upload(
+                    options,
+                    &bucket,
+                    &key,
+                    handle.size,
+                    crtime,
+                    version_id.as_deref(),
+                    e_tag.as_deref(),
+                    sha256.as_ref(),
+                ),
+                Err(error) => {
+                    error!("failed to finalize deferred upload of '{}'", key;
+                           "error" => format!("{:#}", error));
+                    options.session_status.upload_failed_with(&error);
+                    S3WriteOnlyFilesystem::upload_failed(options, failed);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for DeferredUploads {
+    /// Wait for the queued uploads, which may take until uploads are allowed.
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(worker) = self.worker.take() {
+            debug!("Waiting for the deferred uploads to finish");
+            if worker.join().is_err() {
+                error!("Failed to finish the deferred uploads");
+            }
+        }
+    }
+}
+
+impl Drop for S3WriteOnlyFilesystem {
+    fn drop(&mut self) {
+        trace!("S3WriteOnlyFilesystem::drop()");
+        match self.nodes.lock() {
+            Ok(mut nodes) => {
+                for node in nodes.values_mut() {
+                    // The upload of files that are still open is incomplete.
+                    self.options.session_status.upload_failed();
+                    if let Err(error) = node.destroy(&mut self.runtime, self.s3.as_ref()) {
+                        error!("Failed to destroy node '{}'", node.key; "error" => %error);
+                    }
+                }
+            }
+            Err(error) => {
+                error!("failed to acquire lock on filesystem nodes"; "error" => %error);
+            }
+        }
+    }
+}
+
+impl Filesystem for S3WriteOnlyFilesystem {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        trace!("lookup(parent={}, name={:?})", parent, name);
+        let parent_path = if parent == ROOT_DIRECTORY_INODE {
+            if name == HELP_EN_NAME {
+                reply.entry(&TTL, &self.help_en_fileattr, GENERATION);
+                return;
+            } else if name == HELP_DE_NAME {
+                reply.entry(&TTL, &self.help_de_fileattr, GENERATION);
+                return;
+            }
+            None
+        } else {
+            match self.virtual_directory(parent) {
+                Some(directory) => Some(directory.path),
+                None => {
+                    reply.error(ENOENT);
+                    return;
+                }
+            }
+        };
+
+        let directory = self.options.prefix_map.as_ref().and_then(|prefix_map| {
+            prefix_map
+                .read()
+                .ok()?
+                .lookup(parent_path.as_deref(), &name.to_string_lossy())
+                .cloned()
+        });
+        if let Some(directory) = directory {
+            reply.entry(
+                &ROOT_DIRECTORY_TTL,
+                &self.virtual_directory_fileattr(&directory),
+                GENERATION,
+            );
+            return;
+        }
+
+        match self.nodes.lock() {
+            Ok(nodes) => {
+                if let Some(node) = lookup_node(&nodes, parent, name) {
+                    reply.entry(&TTL, &node.file_attr, GENERATION);
+                    return;
+                }
+            }
+            Err(error) => {
+                error!("failed to acquire lock on filesystem nodes"; "error" => %error);
+                reply.error(ENOENT);
+                return;
+            }
+        }
+        match self.finished_files.lookup(parent, name) {
+            Some(file) => reply.entry(&TTL, &file.file_attr, GENERATION),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
+        trace!("getattr(ino={})", ino);
+        match self.attr(ino) {
+            Some((ttl, file_attr)) => reply.attr(&ttl, &file_attr),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn setattr(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<SystemTime>,
+        _mtime: Option<SystemTime>,
+        fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        trace!(
+            "setattr(ino={}, mode={:?}, uid={:?}, gid={:?}, size={:?}, atime={:?}, mtime={:?}, fh={:?}, crtime={:?}, chgtime={:?}, bkuptime={:?}, flags={:?})",
+            ino, _mode, _uid, _gid, size, _atime, _mtime, fh, _crtime, _chgtime, _bkuptime, _flags,
+        );
+
+        match self.nodes.lock() {
+            Ok(mut nodes) => {
+                if let Some(node) = nodes.get_mut(&ino) {
+                    // Copy tools may declare the size of the file (e.g. through `ftruncate`)
+                    // before writing to it, which allows us to avoid a multipart upload.
+                    if let Some(size) = size {
+                        if let Err(error) = node.set_size_hint(fh, size) {
+                            error!("failed to set size hint for node"; "error" => %error);
+                        }
+                    }
+                    reply.attr(&TTL, &node.file_attr);
+                    return;
+                }
+            }
+            Err(error) => {
+                error!("failed to acquire lock on filesystem nodes"; "error" => %error);
             }
         }
 
@@ -420,18 +1844,21 @@ impl Filesystem for S3WriteOnlyFilesystem {
     fn mkdir(
         &mut self,
         _req: &Request<'_>,
-        _parent: u64,
-        _name: &OsStr,
+        parent: u64,
+        name: &OsStr,
         _mode: u32,
         reply: ReplyEntry,
     ) {
-        trace!(
-            "mkdir(parent={}, name={:?}, mode={})",
-            _parent,
-            _name,
-            _mode
-        );
-        reply.error(EACCES);
+        trace!("mkdir(parent={}, name={:?}, mode={})", parent, name, _mode);
+
+        match self.make_directory(parent, name) {
+            Ok(directory) => reply.entry(
+                &ROOT_DIRECTORY_TTL,
+                &self.virtual_directory_fileattr(&directory),
+                GENERATION,
+            ),
+            Err(error) => reply.error(error),
+        }
     }
 
     fn open(&mut self, _req: &Request<'_>, ino: u64, _flags: u32, reply: ReplyOpen) {
@@ -449,9 +1876,25 @@ impl Filesystem for S3WriteOnlyFilesystem {
         }
 
         match self.nodes.lock() {
-            Ok(nodes) => {
-                if nodes.get(&ino).is_some() {
-                    reply.opened(ino, 0);
+            Ok(mut nodes) => {
+                if nodes.contains_key(&ino) && self.too_many_open_files(&nodes) {
+                    reply.error(EMFILE);
+                    return;
+                }
+                if let Some(node) = nodes.get_mut(&ino) {
+                    node.open_handles += 1;
+                    node.uncommitted = false;
+                    match self.options.finalize_on {
+                        // Share the upload of the `create` handle.
+                        FinalizeOn::LastClose | FinalizeOn::Commit => {
+                            reply.opened(ino, self.open_flags())
+                        }
+                        FinalizeOn::Release => {
+                            let fh = self.id_generator.next();
+                            node.open(fh, Arc::clone(&self.upload_options));
+                            reply.opened(fh, self.open_flags());
+                        }
+                    }
                     return;
                 }
             }
@@ -508,7 +1951,7 @@ impl Filesystem for S3WriteOnlyFilesystem {
         &mut self,
         _req: &Request<'_>,
         ino: u64,
-        _fh: u64,
+        fh: u64,
         _offset: i64,
         data: &[u8],
         _flags: u32,
@@ -517,23 +1960,35 @@ impl Filesystem for S3WriteOnlyFilesystem {
         trace!(
             "write(ino={}, fh={}, offset={}, len(data)={}, flags={})",
             ino,
-            _fh,
+            fh,
             _offset,
             data.len(),
             _flags,
         );
 
+        if self.failed.load(Ordering::SeqCst) {
+            reply.error(EIO);
+            return;
+        }
+
+        // Zero-length writes don't change the upload, so there is no need to wait for the lock.
+        if data.is_empty() {
+            reply.written(0);
+            return;
+        }
+
         match self.nodes.lock() {
             Ok(mut nodes) => {
                 if let Some(node) = nodes.deref_mut().get_mut(&ino) {
-                    match node.write(&mut self.runtime, &self.s3, data) {
+                    match node.write(&mut self.runtime, self.s3.as_ref(), fh, data) {
                         Ok(_) => {
                             trace!("written {} bytes to node for '{}'", data.len(), node.key);
                             reply.written(data.len() as u32);
                         }
                         Err(error) => {
                             error!("failed to write data to node"; "error" => %error);
-                            reply.error(EIO);
+                            Self::upload_failed(&self.options, &self.failed);
+                            reply.error(errno(&error));
                         }
                     }
                     return;
@@ -541,29 +1996,41 @@ impl Filesystem for S3WriteOnlyFilesystem {
             }
             Err(error) => {
                 error!("failed to acquire lock on filesystem nodes"; "error" => %error);
+                reply.error(ENOENT);
+                return;
             }
         }
 
-        reply.error(ENOENT);
+        if self.released_inodes.contains(ino) {
+            warn!("Rejecting write to file that has already been released"; "ino" => ino);
+            reply.error(ESTALE);
+        } else {
+            reply.error(ENOENT);
+        }
     }
 
     fn flush(
         &mut self,
         _req: &Request<'_>,
         ino: u64,
-        _fh: u64,
+        fh: u64,
         _lock_owner: u64,
         reply: ReplyEmpty,
     ) {
-        trace!("flush(ino={}, fh={}, lock_owner={})", ino, _fh, _lock_owner);
-        reply.ok();
+        trace!("flush(ino={}, fh={}, lock_owner={})", ino, fh, _lock_owner);
+        self.flush_node(ino, fh, reply);
+    }
+
+    fn fsync(&mut self, _req: &Request<'_>, ino: u64, fh: u64, _datasync: bool, reply: ReplyEmpty) {
+        trace!("fsync(ino={}, fh={}, datasync={})", ino, fh, _datasync);
+        self.flush_node(ino, fh, reply);
     }
 
     fn release(
         &mut self,
         _req: &Request<'_>,
         ino: u64,
-        _fh: u64,
+        fh: u64,
         _flags: u32,
         _lock_owner: u64,
         _flush: bool,
@@ -572,7 +2039,7 @@ impl Filesystem for S3WriteOnlyFilesystem {
         trace!(
             "release(ino={}, fh={}, flags={}, lock_owner={}, flush={})",
             ino,
-            _fh,
+            fh,
             _flags,
             _lock_owner,
             _flush
@@ -583,36 +2050,203 @@ impl Filesystem for S3WriteOnlyFilesystem {
             return;
         }
 
-        match self.nodes.lock() {
+        let (bucket, key, parent, name, file_attr, handle) = match self.nodes.lock() {
             Ok(mut nodes) => {
-                if let Some(mut node) = nodes.remove(&ino) {
-                    match node.finish(&mut self.runtime, &self.s3) {
-                        Ok(_) => {
-                            info!("Uploaded new file: {}", node.key);
-                            reply.ok();
-                        }
-                        Err(error) => {
-                            error!("failed to finalize node"; "error" => %error);
-                            reply.error(EIO);
-                        }
+                let node = match nodes.get_mut(&ino) {
+                    Some(node) => node,
+                    None => {
+                        reply.error(ENOENT);
+                        return;
+                    }
+                };
+                node.open_handles = node.open_handles.saturating_sub(1);
+                if self.options.finalize_on == FinalizeOn::LastClose && node.open_handles > 0 {
+                    // Other handles to the file are still open, finalize on the last one.
+                    reply.ok();
+                    return;
+                }
+                if self.options.finalize_on == FinalizeOn::Commit {
+                    if node.open_handles == 0 {
+                        debug!("Waiting for file to be committed: {}", node.key);
+                        node.uncommitted = true;
                     }
+                    reply.ok();
                     return;
                 }
+                let bucket = node.bucket.clone();
+                let key = node.key.clone();
+                let parent = node.parent;
+                let name = node.name.clone();
+                let file_attr = node.file_attr;
+                let handle = node.handles.remove(&fh);
+                if node.open_handles == 0 {
+                    nodes.remove(&ino);
+                    self.released_inodes.insert(ino);
+                }
+                match handle {
+                    Some(handle) => (bucket, key, parent, name, file_attr, handle),
+                    None => {
+                        reply.error(ENOENT);
+                        return;
+                    }
+                }
             }
             Err(error) => {
                 error!("failed to acquire lock on filesystem nodes"; "error" => %error);
+                reply.error(ENOENT);
+                return;
             }
-        }
+        };
 
-        reply.error(ENOENT);
+        self.finalize((parent, name), bucket, key, file_attr, handle, reply);
     }
 
-    fn opendir(&mut self, _req: &Request<'_>, ino: u64, _flags: u32, reply: ReplyOpen) {
-        trace!("opendir(ino={}, flags={})", ino, _flags);
-
-        if ino == ROOT_DIRECTORY_INODE {
-            reply.opened(ROOT_DIRECTORY_INODE, 0);
-        } else {
+    fn rename(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        reply: ReplyEmpty,
+    ) {
+        trace!(
+            "rename(parent={}, name={:?}, newparent={}, newname={:?})",
+            parent,
+            name,
+            newparent,
+            newname
+        );
+
+        // Files can only be renamed to commit them.
+        if self.options.finalize_on != FinalizeOn::Commit
+            || parent != newparent
+            || !is_commit(name, newname)
+        {
+            reply.error(ENOSYS);
+            return;
+        }
+        let node = match self.nodes.lock() {
+            Ok(mut nodes) => take_uncommitted(&mut nodes, parent, name),
+            Err(error) => {
+                error!("failed to acquire lock on filesystem nodes"; "error" => %error);
+                reply.error(EIO);
+                return;
+            }
+        };
+        let mut node = match node {
+            Ok(Some(node)) => node,
+            Ok(None) => {
+                reply.error(ENOENT);
+                return;
+            }
+            Err(errno) => {
+                reply.error(errno);
+                return;
+            }
+        };
+        self.released_inodes.insert(node.file_attr.ino);
+        match node.handles.remove(&node.file_attr.ino) {
+            Some(handle) => {
+                debug!("Committing file: {}", node.key);
+                self.finalize(
+                    (node.parent, node.name),
+                    node.bucket,
+                    node.key,
+                    node.file_attr,
+                    handle,
+                    reply,
+                );
+            }
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn unlink(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        trace!("unlink(parent={}, name={:?})", parent, name);
+
+        // Only files waiting to be committed can be deleted, which discards them.
+        if self.options.finalize_on != FinalizeOn::Commit {
+            reply.error(ENOSYS);
+            return;
+        }
+        let node = match self.nodes.lock() {
+            Ok(mut nodes) => take_uncommitted(&mut nodes, parent, name),
+            Err(error) => {
+                error!("failed to acquire lock on filesystem nodes"; "error" => %error);
+                reply.error(EIO);
+                return;
+            }
+        };
+        match node {
+            Ok(Some(mut node)) => {
+                self.released_inodes.insert(node.file_attr.ino);
+                match node.destroy(&mut self.runtime, self.s3.as_ref()) {
+                    Ok(()) => {
+                        info!("Discarded uncommitted file: {}", node.key);
+                        reply.ok();
+                    }
+                    Err(error) => {
+                        error!("Failed to discard uncommitted file '{}'", node.key; "error" => %error);
+                        reply.error(errno(&error));
+                    }
+                }
+            }
+            Ok(None) => reply.error(ENOENT),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn getxattr(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        name: &OsStr,
+        size: u32,
+        reply: ReplyXattr,
+    ) {
+        trace!("getxattr(ino={}, name={:?}, size={})", ino, name, size);
+
+        let e_tag = match self.finished_files.get(ino) {
+            Some(FinishedFile {
+                e_tag: Some(e_tag), ..
+            }) if name == ETAG_XATTR_NAME => e_tag.as_bytes(),
+            _ => {
+                reply.error(ENODATA);
+                return;
+            }
+        };
+        if size == 0 {
+            reply.size(e_tag.len() as u32);
+        } else if (size as usize) < e_tag.len() {
+            reply.error(ERANGE);
+        } else {
+            reply.data(e_tag);
+        }
+    }
+
+    fn listxattr(&mut self, _req: &Request<'_>, ino: u64, size: u32, reply: ReplyXattr) {
+        trace!("listxattr(ino={}, size={})", ino, size);
+
+        let names = match self.finished_files.get(ino) {
+            Some(FinishedFile { e_tag: Some(_), .. }) => format!("{}\0", ETAG_XATTR_NAME),
+            _ => String::new(),
+        };
+        if size == 0 {
+            reply.size(names.len() as u32);
+        } else if (size as usize) < names.len() {
+            reply.error(ERANGE);
+        } else {
+            reply.data(names.as_bytes());
+        }
+    }
+
+    fn opendir(&mut self, _req: &Request<'_>, ino: u64, _flags: u32, reply: ReplyOpen) {
+        trace!("opendir(ino={}, flags={})", ino, _flags);
+
+        if ino == ROOT_DIRECTORY_INODE || self.virtual_directory(ino).is_some() {
+            reply.opened(ino, 0);
+        } else {
             reply.error(EACCES);
         }
     }
@@ -627,26 +2261,110 @@ impl Filesystem for S3WriteOnlyFilesystem {
     ) {
         trace!("readdir(ino={}, fh={}, offset={})", ino, _fh, offset);
 
-        if ino != ROOT_DIRECTORY_INODE {
-            reply.error(ENOENT);
-            return;
-        }
+        let directory = if ino == ROOT_DIRECTORY_INODE {
+            None
+        } else {
+            match self.virtual_directory(ino) {
+                Some(directory) => Some(directory),
+                None => {
+                    reply.error(ENOENT);
+                    return;
+                }
+            }
+        };
 
         if offset == 0 {
-            reply.add(ROOT_DIRECTORY_INODE, 0, FileType::Directory, ".");
-            reply.add(ROOT_DIRECTORY_INODE, 1, FileType::Directory, "..");
-            reply.add(HELP_EN_INODE, 2, FileType::RegularFile, HELP_EN_NAME);
-            reply.add(HELP_DE_INODE, 3, FileType::RegularFile, HELP_DE_NAME);
+            let mut entries = vec![];
+            match &directory {
+                None => {
+                    entries.push((ROOT_DIRECTORY_INODE, FileType::Directory, ".".to_owned()));
+                    entries.push((ROOT_DIRECTORY_INODE, FileType::Directory, "..".to_owned()));
+                    entries.push((
+                        HELP_EN_INODE,
+                        FileType::RegularFile,
+                        HELP_EN_NAME.to_owned(),
+                    ));
+                    entries.push((
+                        HELP_DE_INODE,
+                        FileType::RegularFile,
+                        HELP_DE_NAME.to_owned(),
+                    ));
+                }
+                Some(directory) => {
+                    entries.push((ino, FileType::Directory, ".".to_owned()));
+                    entries.push((
+                        self.virtual_parent_inode(directory),
+                        FileType::Directory,
+                        "..".to_owned(),
+                    ));
+                }
+            }
+            for subdirectory in self
+                .virtual_subdirectories(directory.as_ref().map(|directory| directory.path.as_str()))
+            {
+                entries.push((
+                    subdirectory.ino,
+                    FileType::Directory,
+                    subdirectory.name().to_owned(),
+                ));
+            }
+            for (offset, (ino, kind, name)) in entries.into_iter().enumerate() {
+                if reply.add(ino, offset as i64, kind, name) {
+                    break;
+                }
+            }
         }
         reply.ok();
     }
 
+    fn access(&mut self, req: &Request<'_>, ino: u64, mask: u32, reply: ReplyEmpty) {
+        trace!("access(ino={}, mask={})", ino, mask);
+
+        let mask = mask as i32;
+        match ino {
+            // Everyone can list the root directory and create files in it.
+            ROOT_DIRECTORY_INODE => reply.ok(),
+            // The same applies to the virtual directories.
+            ino if self.virtual_directory(ino).is_some() => reply.ok(),
+            // The help files are read-only.
+            HELP_EN_INODE | HELP_DE_INODE => {
+                if mask & (W_OK | X_OK) == 0 {
+                    reply.ok()
+                } else {
+                    reply.error(EACCES)
+                }
+            }
+            _ => {
+                match self.nodes.lock() {
+                    Ok(nodes) => {
+                        if let Some(node) = nodes.get(&ino) {
+                            // Files that are being uploaded are write-only, and only for the
+                            // user that created them.
+                            let (uid, _) = self.requester(req);
+                            if mask & (R_OK | X_OK) == 0 && (uid == 0 || uid == node.file_attr.uid)
+                            {
+                                reply.ok();
+                            } else {
+                                reply.error(EACCES);
+                            }
+                            return;
+                        }
+                    }
+                    Err(error) => {
+                        error!("failed to acquire lock on filesystem nodes"; "error" => %error);
+                    }
+                }
+                reply.error(ENOENT);
+            }
+        }
+    }
+
     fn create(
         &mut self,
-        _req: &Request<'_>,
+        req: &Request<'_>,
         parent: u64,
         name: &OsStr,
-        _mode: u32,
+        mode: u32,
         _flags: u32,
         reply: ReplyCreate,
     ) {
@@ -654,27 +2372,162 @@ impl Filesystem for S3WriteOnlyFilesystem {
             "create(parent={}, name={:?}, mode={}, flags={})",
             parent,
             name,
-            _mode,
+            mode,
             _flags
         );
 
-        if parent != ROOT_DIRECTORY_INODE {
-            reply.error(ENOENT);
+        let directory = if parent == ROOT_DIRECTORY_INODE {
+            None
+        } else {
+            match self.virtual_directory(parent) {
+                Some(directory) => Some(directory),
+                None => {
+                    reply.error(ENOENT);
+                    return;
+                }
+            }
+        };
+        let s3_prefix = upload_prefix(
+            self.s3_prefix_path.as_deref(),
+            directory.as_ref(),
+            self.options.flatten,
+        );
+
+        if self.failed.load(Ordering::SeqCst) {
+            reply.error(EIO);
             return;
         }
 
+        let mut filename = name.to_string_lossy().into_owned();
+        if let Some(strict_key_charset) = self.options.strict_key_charset {
+            match strict_key_charset.apply(&filename) {
+                Some(safe_filename) => filename = safe_filename,
+                None => {
+                    warn!(
+                        "Rejecting file with unsafe characters in its name: {}",
+                        filename
+                    );
+                    reply.error(EINVAL);
+                    return;
+                }
+            }
+        }
+
+        if let Some(windows_safe_keys) = self.options.windows_safe_keys {
+            match windows_safe_keys.apply(&filename) {
+                Some(safe_filename) => filename = safe_filename,
+                None => {
+                    warn!(
+                        "Rejecting file with a name that is not valid on Windows: {}",
+                        filename
+                    );
+                    reply.error(EINVAL);
+                    return;
+                }
+            }
+        }
+        filename = self.options.key_case.apply(&filename);
+        if let Some(key_pattern) = &self.options.key_pattern {
+            match key_pattern.expand(&filename) {
+                Ok(key) => filename = key,
+                Err(error) => {
+                    error!("Failed to derive key from pattern"; "error" => %error);
+                    reply.error(EIO);
+                    return;
+                }
+            }
+        }
+
+        if let Some(s3_prefix) = &s3_prefix {
+            filename = [s3_prefix, &*filename].join("/")
+        };
+        let mut bucket = bucket_route::route(&self.options.bucket_routes, &filename)
+            .unwrap_or(&self.s3_bucket)
+            .to_owned();
+        if let Some(upload_sink) = &self.options.upload_sink {
+            let location = ObjectLocation {
+                bucket,
+                key: filename,
+            };
+            match self.runtime.block_on(upload_sink.resolve(location)) {
+                Ok(location) => {
+                    debug!(
+                        "Routing upload to 's3://{}/{}'",
+                        location.bucket, location.key
+                    );
+                    bucket = location.bucket;
+                    filename = location.key;
+                }
+                Err(error) => {
+                    error!("Failed to route upload"; "error" => format!("{:#}", error));
+                    reply.error(EIO);
+                    return;
+                }
+            }
+        }
+        if self.options.detect_prefix_shadowing {
+            match prefix_shadowing::check(&self.runtime, self.s3.as_ref(), &bucket, &filename) {
+                Ok(None) => {}
+                Ok(Some(errno)) => {
+                    warn!(
+                        "Rejecting file that collides with existing objects: s3://{}/{}",
+                        bucket, filename
+                    );
+                    reply.error(errno);
+                    return;
+                }
+                Err(error) => {
+                    error!("Failed to check for prefix shadowing"; "error" => format!("{:#}", error));
+                    reply.error(EIO);
+                    return;
+                }
+            }
+        }
+        if let Some(key_collisions) = &self.options.key_collisions {
+            match key_collisions.check(&self.runtime, self.s3.as_ref(), &bucket, &filename) {
+                Ok(None) => {}
+                Ok(Some(errno)) => {
+                    warn!(
+                        "Rejecting file that was already uploaded in this session: s3://{}/{}",
+                        bucket, filename
+                    );
+                    reply.error(errno);
+                    return;
+                }
+                Err(error) => {
+                    error!("Failed to check for key collisions"; "error" => format!("{:#}", error));
+                    reply.error(EIO);
+                    return;
+                }
+            }
+        }
+
         match self.nodes.lock() {
             Ok(mut nodes) => {
+                if self.too_many_open_files(&nodes) {
+                    reply.error(EMFILE);
+                    return;
+                }
                 let id = self.id_generator.next();
-                let mut filename = name.to_string_lossy().into_owned();
-                if let Some(s3_prefix) = &self.s3_prefix_path {
-                    filename = [s3_prefix, &*filename].join("/")
-                };
-                let node = Node::new(id, &self.s3_bucket, &filename);
-                reply.created(&TTL, &node.file_attr, GENERATION, id, 0);
-
+                let mut node = Node::new(
+                    id,
+                    (parent, name),
+                    self.requester(req),
+                    &bucket,
+                    &filename,
+                    Arc::clone(&self.upload_options),
+                );
+                node.file_attr.perm = self.file_perm(mode);
+                if let Err(errno) = self.options.concurrent_creates.apply(&nodes, &mut node) {
+                    reply.error(errno);
+                    return;
+                }
+                let file_attr = node.file_attr;
                 debug!("Started new upload for file: {}", node.key);
+                // Insert the node before replying, such that a `lookup` of the name right after
+                // `create` returned always finds it.
                 nodes.insert(id, node);
+                reply.created(&TTL, &file_attr, GENERATION, id, self.open_flags());
             }
             Err(error) => {
                 error!("failed to acquire lock on filesystem nodes"; "error" => %error);
@@ -683,3 +2536,888 @@ impl Filesystem for S3WriteOnlyFilesystem {
         }
     }
 }
+
+/// The prefix that files created in `directory` are uploaded below, the root directory being `None`.
+///
+/// With `flatten`, the directory is disregarded and files are uploaded below the prefix of the
+/// mount, such that the key is derived from the file name only.
+fn upload_prefix(
+    mount_prefix: Option<&str>,
+    directory: Option<&VirtualDirectory>,
+    flatten: bool,
+) -> Option<String> {
+    match directory {
+        Some(directory) if !flatten => Some(directory.prefix.clone()),
+        _ => mount_prefix.map(str::to_owned),
+    }
+}
+
+#[test]
+fn drop_aborts_multipart_uploads() {
+    use crate::{
+        object_store::mock::{
+            Call,
+            MockObjectStore,
+        },
+        upload::MULTIPART_MINIMUM_PART_SIZE,
+    };
+
+    let s3 = Arc::new(MockObjectStore::default());
+    let mut filesystem = S3WriteOnlyFilesystem::new(
+        s3.clone(),
+        "my-bucket".parse().unwrap(),
+        UploadOptions::default(),
+        FilesystemOptions::default(),
+    )
+    .unwrap();
+
+    // Writing a full part switches the upload into a multipart upload.
+    let id = filesystem.id_generator.next();
+    let mut node = Node::new(
+        id,
+        (ROOT_DIRECTORY_INODE, OsStr::new("my-file")),
+        (0, 0),
+        "my-bucket",
+        "my-file",
+        Arc::clone(&filesystem.upload_options),
+    );
+    node.write(
+        &mut filesystem.runtime,
+        filesystem.s3.as_ref(),
+        id,
+        &vec![0; MULTIPART_MINIMUM_PART_SIZE],
+    )
+    .unwrap();
+    filesystem.nodes.lock().unwrap().insert(id, node);
+
+    drop(filesystem);
+
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::CreateMultipartUpload(_), Call::UploadPart(upload_part, _), Call::AbortMultipartUpload(abort)] =>
+        {
+            assert_eq!(abort.bucket, "my-bucket");
+            assert_eq!(abort.key, "my-file");
+            assert_eq!(abort.upload_id, upload_part.upload_id);
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn handles_upload_independently() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let upload_options = Arc::new(UploadOptions::default());
+
+    let mut node = Node::new(
+        10,
+        (ROOT_DIRECTORY_INODE, OsStr::new("my-file")),
+        (0, 0),
+        "my-bucket",
+        "my-file",
+        Arc::clone(&upload_options),
+    );
+    node.open(11, Arc::clone(&upload_options));
+    node.open(12, Arc::clone(&upload_options));
+    node.write(&mut runtime, &s3, 10, b"first").unwrap();
+    node.write(&mut runtime, &s3, 11, b"second").unwrap();
+    for fh in [10, 11, 12] {
+        node.handles
+            .remove(&fh)
+            .unwrap()
+            .finish(&mut runtime, &s3)
+            .unwrap();
+    }
+
+    // The handle that was only opened is discarded, rather than uploading an empty object.
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::PutObject(first, first_body), Call::PutObject(second, second_body)] => {
+            assert_eq!(first.key, "my-file");
+            assert_eq!(first_body, b"first");
+            assert_eq!(second.key, "my-file");
+            assert_eq!(second_body, b"second");
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn connections_are_warmed_up() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let s3 = Arc::new(MockObjectStore::default());
+    let _filesystem = S3WriteOnlyFilesystem::new(
+        s3.clone(),
+        "my-bucket:prefix".parse().unwrap(),
+        UploadOptions::default(),
+        FilesystemOptions {
+            warm_connections: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::HeadBucket(head_bucket)] => assert_eq!(head_bucket.bucket, "my-bucket"),
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn zero_length_writes_are_ignored() {
+    use crate::object_store::mock::MockObjectStore;
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let upload_options = Arc::new(UploadOptions::default());
+
+    let mut node = Node::new(
+        10,
+        (ROOT_DIRECTORY_INODE, OsStr::new("my-file")),
+        (0, 0),
+        "my-bucket",
+        "my-file",
+        Arc::clone(&upload_options),
+    );
+    node.open(11, upload_options);
+    node.write(&mut runtime, &s3, 10, b"content").unwrap();
+    node.write(&mut runtime, &s3, 10, &[]).unwrap();
+    node.write(&mut runtime, &s3, 11, &[]).unwrap();
+
+    let created = node.handles.get(&10).unwrap();
+    assert_eq!(created.size, 7);
+    let opened = node.handles.get(&11).unwrap();
+    assert_eq!(opened.size, 0);
+    assert!(!opened.dirty);
+    assert!(s3.calls().is_empty());
+}
+
+#[test]
+fn released_inodes_are_bounded() {
+    let mut released_inodes = ReleasedInodes::default();
+    for ino in 10..10 + RELEASED_INODES_CAPACITY as u64 {
+        released_inodes.insert(ino);
+    }
+    released_inodes.insert(10);
+    assert!(released_inodes.contains(10));
+
+    released_inodes.insert(10 + RELEASED_INODES_CAPACITY as u64);
+    assert!(!released_inodes.contains(10));
+    assert!(released_inodes.contains(11));
+    assert!(released_inodes.contains(10 + RELEASED_INODES_CAPACITY as u64));
+    assert_eq!(RELEASED_INODES_CAPACITY, released_inodes.order.len());
+}
+
+#[test]
+fn created_files_can_be_looked_up_until_released() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let upload_options = Arc::new(UploadOptions::default());
+    let mut nodes = HashMap::new();
+    let name = OsStr::new("my-file");
+    assert!(lookup_node(&nodes, ROOT_DIRECTORY_INODE, name).is_none());
+
+    // A tool creating the same name twice in a row writes to the most recent file.
+    for id in [10, 20] {
+        let node = Node::new(
+            id,
+            (ROOT_DIRECTORY_INODE, name),
+            (0, 0),
+            "my-bucket",
+            "my-file",
+            Arc::clone(&upload_options),
+        );
+        nodes.insert(id, node);
+    }
+    let ino = lookup_node(&nodes, ROOT_DIRECTORY_INODE, name)
+        .unwrap()
+        .file_attr
+        .ino;
+    assert_eq!(20, ino);
+    assert!(lookup_node(&nodes, ROOT_DIRECTORY_INODE, OsStr::new("other-file")).is_none());
+
+    let node = nodes.get_mut(&ino).unwrap();
+    node.open(21, Arc::clone(&upload_options));
+    node.write(&mut runtime, &s3, 21, b"content").unwrap();
+    node.handles
+        .remove(&21)
+        .unwrap()
+        .finish(&mut runtime, &s3)
+        .unwrap();
+    nodes.remove(&ino);
+    assert_eq!(
+        10,
+        lookup_node(&nodes, ROOT_DIRECTORY_INODE, name)
+            .unwrap()
+            .file_attr
+            .ino
+    );
+
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::PutObject(put_object, body)] => {
+            assert_eq!(put_object.key, "my-file");
+            assert_eq!(body, b"content");
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn finished_files_keep_the_last_upload() {
+    let file_attr = help_file_attr(10, "", SystemTime::now());
+    let mut finished_files = FinishedFiles::default();
+    for e_tag in ["\"first\"", "\"second\""] {
+        finished_files.insert(FinishedFile {
+            parent: ROOT_DIRECTORY_INODE,
+            name: OsString::from("my-file"),
+            file_attr,
+            e_tag: Some(e_tag.to_owned()),
+            finished_at: Instant::now(),
+        });
+    }
+
+    assert_eq!(1, finished_files.files.len());
+    let file = finished_files
+        .lookup(ROOT_DIRECTORY_INODE, OsStr::new("my-file"))
+        .unwrap();
+    assert_eq!(Some("\"second\""), file.e_tag.as_deref());
+    assert!(finished_files.get(10).is_some());
+    assert!(finished_files.get(11).is_none());
+}
+
+#[test]
+fn finished_files_are_forgotten_after_the_retention() {
+    let file = |ino, finished_at| FinishedFile {
+        parent: ROOT_DIRECTORY_INODE,
+        name: OsString::from(format!("file-{}", ino)),
+        file_attr: help_file_attr(ino, "", SystemTime::now()),
+        e_tag: None,
+        finished_at,
+    };
+    let mut finished_files = FinishedFiles::new(Duration::from_secs(5));
+    finished_files.insert(file(10, Instant::now() - Duration::from_secs(10)));
+    finished_files.insert(file(11, Instant::now()));
+
+    assert!(finished_files.get(10).is_none());
+    assert!(finished_files.get(11).is_some());
+
+    let mut finished_files = FinishedFiles::new(Duration::ZERO);
+    finished_files.insert(file(10, Instant::now()));
+    assert!(finished_files.get(10).is_none());
+}
+
+#[test]
+fn buffered_bytes_are_accounted_for() {
+    use crate::object_store::mock::MockObjectStore;
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let upload_options = Arc::new(UploadOptions::default());
+    let metrics = &upload_options.metrics;
+
+    let mut node = Node::new(
+        10,
+        (ROOT_DIRECTORY_INODE, OsStr::new("my-file")),
+        (0, 0),
+        "my-bucket",
+        "my-file",
+        Arc::clone(&upload_options),
+    );
+    node.open(11, Arc::clone(&upload_options));
+    node.write(&mut runtime, &s3, 10, b"first").unwrap();
+    node.write(&mut runtime, &s3, 11, b"second").unwrap();
+    assert!(metrics.render().contains("\ns3wofs_buffered_bytes 11\n"));
+    assert!(metrics.render().contains("\ns3wofs_open_uploads 2\n"));
+
+    node.handles
+        .remove(&10)
+        .unwrap()
+        .finish(&mut runtime, &s3)
+        .unwrap();
+    assert!(metrics.render().contains("\ns3wofs_buffered_bytes 6\n"));
+    assert!(metrics.render().contains("\ns3wofs_open_uploads 1\n"));
+
+    drop(node);
+    assert!(metrics.render().contains("\ns3wofs_buffered_bytes 0\n"));
+    assert!(metrics.render().contains("\ns3wofs_open_uploads 0\n"));
+}
+
+#[test]
+fn nested_directories_are_preserved_unless_flattened() {
+    let path = std::env::temp_dir().join(format!("s3wofs-flatten-{}.json", std::process::id()));
+    std::fs::write(
+        &path,
+        r#"{"reports": "archive/reports", "reports/daily": "daily-reports"}"#,
+    )
+    .unwrap();
+    let prefix_map = PrefixMap::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let reports = prefix_map.lookup(None, "reports").unwrap();
+    let daily = prefix_map.lookup(Some("reports"), "daily").unwrap();
+
+    assert_eq!(
+        Some("daily-reports".to_owned()),
+        upload_prefix(Some("uploads"), Some(daily), false)
+    );
+    assert_eq!(
+        Some("archive/reports".to_owned()),
+        upload_prefix(Some("uploads"), Some(reports), false)
+    );
+    assert_eq!(
+        Some("uploads".to_owned()),
+        upload_prefix(Some("uploads"), None, false)
+    );
+
+    assert_eq!(
+        Some("uploads".to_owned()),
+        upload_prefix(Some("uploads"), Some(daily), true)
+    );
+    assert_eq!(None, upload_prefix(None, Some(daily), true));
+    assert_eq!(None, upload_prefix(None, None, false));
+}
+
+#[test]
+fn failed_uploads_are_retried_from_scratch() {
+    use crate::{
+        object_store::mock::{
+            Call,
+            MockObjectStore,
+        },
+        upload::MULTIPART_MINIMUM_PART_SIZE,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let upload_options = Arc::new(UploadOptions {
+        full_retry_max_size: Some(2 * MULTIPART_MINIMUM_PART_SIZE as u64),
+        ..Default::default()
+    });
+    s3.make_upload_parts_unreachable(1);
+
+    let mut node = Node::new(
+        10,
+        (ROOT_DIRECTORY_INODE, OsStr::new("my-file")),
+        (0, 0),
+        "my-bucket",
+        "my-file",
+        upload_options,
+    );
+    let content = vec![1; MULTIPART_MINIMUM_PART_SIZE];
+    node.write(&mut runtime, &s3, 10, &content).unwrap();
+    node.write(&mut runtime, &s3, 10, b"tail").unwrap();
+    node.handles
+        .remove(&10)
+        .unwrap()
+        .finish(&mut runtime, &s3)
+        .unwrap();
+
+    // The failed part upload is not recorded by the mock.
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::CreateMultipartUpload(_), Call::AbortMultipartUpload(abort), Call::CreateMultipartUpload(_), Call::UploadPart(first, first_body), Call::UploadPart(_, last_body), Call::CompleteMultipartUpload(complete)] =>
+        {
+            assert_ne!(abort.upload_id, first.upload_id);
+            assert_eq!(complete.upload_id, first.upload_id);
+            assert_eq!(first_body, &content);
+            assert_eq!(last_body, b"tail");
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn concurrent_creates_of_the_same_key_are_detected() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let upload_options = Arc::new(UploadOptions::default());
+    let create = |id, bucket| {
+        Node::new(
+            id,
+            (ROOT_DIRECTORY_INODE, OsStr::new("my-file")),
+            (0, 0),
+            bucket,
+            "my-file",
+            Arc::clone(&upload_options),
+        )
+    };
+
+    let mut nodes = HashMap::new();
+    for (id, bucket) in [(10, "my-bucket"), (11, "other-bucket"), (12, "my-bucket")] {
+        let mut node = create(id, bucket);
+        ConcurrentCreates::Discard.apply(&nodes, &mut node).unwrap();
+        nodes.insert(id, node);
+    }
+    assert!(!nodes[&10].duplicate);
+    assert!(!nodes[&11].duplicate);
+    assert!(nodes[&12].duplicate);
+    assert_eq!(
+        Err(EBUSY),
+        ConcurrentCreates::Reject.apply(&nodes, &mut create(13, "my-bucket"))
+    );
+    ConcurrentCreates::Allow
+        .apply(&nodes, &mut create(13, "my-bucket"))
+        .unwrap();
+
+    // The writers interleave, but only the file that was created first is uploaded.
+    for (id, content) in [
+        (10, b"first"),
+        (12, b"later"),
+        (10, b"-more"),
+        (12, b"-more"),
+    ] {
+        let node = nodes.get_mut(&id).unwrap();
+        node.write(&mut runtime, &s3, id, content).unwrap();
+    }
+    for id in [12, 10] {
+        let finished = nodes
+            .get_mut(&id)
+            .unwrap()
+            .handles
+            .remove(&id)
+            .unwrap()
+            .finish(&mut runtime, &s3)
+            .unwrap();
+        assert_eq!(id == 12, matches!(finished, Finished::Skipped));
+    }
+
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::PutObject(put_object, body)] => {
+            assert_eq!(put_object.bucket, "my-bucket");
+            assert_eq!(body, b"first-more");
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn interrupted_writes_must_be_retried() {
+    use crate::{
+        object_store::mock::{
+            Call,
+            MockObjectStore,
+        },
+        upload::{
+            InterruptedWrites,
+            MULTIPART_MINIMUM_PART_SIZE,
+        },
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let upload_options = Arc::new(UploadOptions {
+        interrupted_writes: InterruptedWrites::Restore,
+        ..Default::default()
+    });
+    let content = vec![1; MULTIPART_MINIMUM_PART_SIZE];
+
+    let mut handle = Handle::new("my-bucket", "my-file", upload_options, true);
+    s3.make_upload_parts_unreachable(1);
+    assert!(handle.write(&mut runtime, &s3, &content).is_err());
+    assert_eq!(0, handle.size);
+    handle.write(&mut runtime, &s3, &content).unwrap();
+    s3.make_upload_parts_unreachable(1);
+    assert!(handle.write(&mut runtime, &s3, &content).is_err());
+    assert!(handle.finish(&mut runtime, &s3).is_err());
+
+    // The incomplete file is not uploaded.
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::CreateMultipartUpload(_), Call::UploadPart(part, _), Call::AbortMultipartUpload(abort)] =>
+        {
+            assert_eq!(abort.upload_id, part.upload_id);
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+/// Mounts the filesystem, which requires FUSE to be available, hence only run with the
+/// `fuse-tests` feature.
+#[cfg(feature = "fuse-tests")]
+#[test]
+fn files_written_through_the_mount_are_uploaded() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+    use std::time::Instant;
+
+    let directory = std::env::temp_dir().join(format!("s3-write-only-fs-{}", std::process::id()));
+    let mountpoint = directory.join("mnt");
+    std::fs::create_dir_all(&mountpoint).unwrap();
+    let source = directory.join("source");
+    std::fs::write(&source, b"copied content").unwrap();
+
+    let s3 = Arc::new(MockObjectStore::default());
+    let filesystem = S3WriteOnlyFilesystem::new(
+        s3.clone(),
+        "my-bucket:prefix".parse().unwrap(),
+        UploadOptions::default(),
+        FilesystemOptions::default(),
+    )
+    .unwrap();
+    let session = unsafe { fuse::spawn_mount(filesystem, &mountpoint, &[]) }.unwrap();
+
+    std::fs::write(mountpoint.join("written"), b"written content").unwrap();
+    std::fs::copy(&source, mountpoint.join("copied")).unwrap();
+
+    // The kernel releases files asynchronously after they were closed.
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut uploads = loop {
+        let uploads: Vec<_> = s3
+            .calls()
+            .iter()
+            .filter_map(|call| match call {
+                Call::PutObject(put_object, body) => Some((put_object.key.clone(), body.clone())),
+                _ => None,
+            })
+            .collect();
+        if uploads.len() >= 2 || Instant::now() > deadline {
+            break uploads;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    drop(session);
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    uploads.sort();
+    assert_eq!(
+        uploads,
+        [
+            ("prefix/copied".to_owned(), b"copied content".to_vec()),
+            ("prefix/written".to_owned(), b"written content".to_vec()),
+        ]
+    );
+}
+
+#[test]
+fn directory_markers_are_created() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let path = std::env::temp_dir().join(format!("s3wofs-markers-{}.json", std::process::id()));
+    std::fs::write(
+        &path,
+        r#"{"reports": "archive/reports", "reports/daily": "daily-reports"}"#,
+    )
+    .unwrap();
+    let prefix_map = PrefixMap::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let s3 = Arc::new(MockObjectStore::default());
+    let _filesystem = S3WriteOnlyFilesystem::new(
+        s3.clone(),
+        "my-bucket".parse().unwrap(),
+        UploadOptions::default(),
+        FilesystemOptions {
+            prefix_map: Some(Arc::new(RwLock::new(prefix_map))),
+            create_directory_markers: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::PutObject(reports, reports_body), Call::PutObject(daily, daily_body)] => {
+            assert_eq!(reports.key, "archive/reports/");
+            assert!(reports_body.is_empty());
+            assert_eq!(daily.key, "daily-reports/");
+            assert!(daily_body.is_empty());
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn created_files_report_their_mode() {
+    use crate::object_store::mock::MockObjectStore;
+
+    let filesystem = |honor_mode| {
+        S3WriteOnlyFilesystem::new(
+            Arc::new(MockObjectStore::default()),
+            "my-bucket".parse().unwrap(),
+            UploadOptions::default(),
+            FilesystemOptions {
+                honor_mode,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    };
+    // Creates a file the way `create` does and returns the permissions `getattr` reports.
+    let create = |filesystem: &mut S3WriteOnlyFilesystem, mode| {
+        let id = filesystem.id_generator.next();
+        let mut node = Node::new(
+            id,
+            (ROOT_DIRECTORY_INODE, OsStr::new("my-file")),
+            (0, 0),
+            "my-bucket",
+            "my-file",
+            Arc::clone(&filesystem.upload_options),
+        );
+        node.file_attr.perm = filesystem.file_perm(mode);
+        filesystem.nodes.lock().unwrap().insert(id, node);
+        let (_, file_attr) = filesystem.attr(id).unwrap();
+        file_attr.perm
+    };
+
+    let mut honoring = filesystem(true);
+    assert_eq!(0o640, create(&mut honoring, libc::S_IFREG | 0o640));
+    assert_eq!(0o600, create(&mut honoring, 0o4600));
+    assert_eq!(FILE_PERM, create(&mut honoring, libc::S_IFREG));
+    let mut ignoring = filesystem(false);
+    assert_eq!(FILE_PERM, create(&mut ignoring, libc::S_IFREG | 0o640));
+}
+
+#[test]
+fn only_closed_files_can_be_committed() {
+    let mut nodes = HashMap::new();
+    let mut node = Node::new(
+        10,
+        (ROOT_DIRECTORY_INODE, OsStr::new("my-file")),
+        (0, 0),
+        "my-bucket",
+        "my-file",
+        Arc::new(UploadOptions::default()),
+    );
+    node.uncommitted = true;
+    nodes.insert(10, node);
+
+    assert!(is_commit(
+        OsStr::new("my-file"),
+        OsStr::new("my-file.committed")
+    ));
+    assert!(!is_commit(OsStr::new("my-file"), OsStr::new("other-file")));
+
+    nodes.get_mut(&10).unwrap().open_handles = 1;
+    assert!(matches!(
+        take_uncommitted(&mut nodes, ROOT_DIRECTORY_INODE, OsStr::new("my-file")),
+        Err(EBUSY)
+    ));
+    nodes.get_mut(&10).unwrap().open_handles = 0;
+    assert!(matches!(
+        take_uncommitted(&mut nodes, ROOT_DIRECTORY_INODE, OsStr::new("other-file")),
+        Ok(None)
+    ));
+    let node = take_uncommitted(&mut nodes, ROOT_DIRECTORY_INODE, OsStr::new("my-file"))
+        .unwrap()
+        .unwrap();
+    assert_eq!("my-file", node.key);
+    assert!(nodes.is_empty());
+}
+
+#[test]
+fn inode_base_must_not_collide_with_other_inodes() {
+    assert_eq!(4, parse_inode_base("4").unwrap());
+    assert_eq!(1_000_000, parse_inode_base("1000000").unwrap());
+    assert!(parse_inode_base("3").is_err());
+    assert!(parse_inode_base("0").is_err());
+    assert!(parse_inode_base(&DIRECTORY_INODE_BASE.to_string()).is_err());
+    assert!(parse_inode_base("ten").is_err());
+}
+
+#[test]
+fn directories_can_be_created() {
+    use crate::object_store::mock::MockObjectStore;
+
+    let mut filesystem = S3WriteOnlyFilesystem::new(
+        Arc::new(MockObjectStore::default()),
+        "my-bucket:uploads".parse().unwrap(),
+        UploadOptions::default(),
+        FilesystemOptions::default(),
+    )
+    .unwrap();
+
+    let invoices = filesystem
+        .make_directory(ROOT_DIRECTORY_INODE, OsStr::new("invoices"))
+        .unwrap();
+    let year = filesystem
+        .make_directory(invoices.ino, OsStr::new("2024"))
+        .unwrap();
+    assert_eq!("invoices/2024", year.path);
+    assert_eq!(
+        Some("uploads/invoices/2024".to_owned()),
+        upload_prefix(Some("uploads"), Some(&year), false)
+    );
+    assert_eq!(Some(year.clone()), filesystem.virtual_directory(year.ino));
+    assert_eq!(
+        vec![year],
+        filesystem.virtual_subdirectories(Some("invoices"))
+    );
+
+    assert_eq!(
+        Err(EEXIST),
+        filesystem.make_directory(ROOT_DIRECTORY_INODE, OsStr::new("invoices"))
+    );
+    assert_eq!(
+        Err(EEXIST),
+        filesystem.make_directory(ROOT_DIRECTORY_INODE, OsStr::new(HELP_EN_NAME))
+    );
+    assert_eq!(
+        Err(ENOENT),
+        filesystem.make_directory(12345, OsStr::new("other"))
+    );
+}
+
+#[test]
+fn all_handles_are_destroyed_despite_errors() {
+    use crate::{
+        object_store::mock::{
+            Call,
+            MockObjectStore,
+        },
+        upload::MULTIPART_MINIMUM_PART_SIZE,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let upload_options = Arc::new(UploadOptions::default());
+
+    let mut node = Node::new(
+        10,
+        (ROOT_DIRECTORY_INODE, OsStr::new("my-file")),
+        (0, 0),
+        "my-bucket",
+        "my-file",
+        Arc::clone(&upload_options),
+    );
+    node.open(11, Arc::clone(&upload_options));
+    for fh in [10, 11] {
+        node.write(&mut runtime, &s3, fh, &vec![0; MULTIPART_MINIMUM_PART_SIZE])
+            .unwrap();
+    }
+    s3.fail_aborts(1);
+
+    assert!(node.destroy(&mut runtime, &s3).is_err());
+    assert!(node.handles.is_empty());
+    let aborts = s3
+        .calls()
+        .iter()
+        .filter(|call| matches!(call, Call::AbortMultipartUpload(_)))
+        .count();
+    assert_eq!(2, aborts);
+}
+
+#[test]
+fn directory_nlink_counts_subdirectories() {
+    use crate::object_store::mock::MockObjectStore;
+
+    let mut filesystem = S3WriteOnlyFilesystem::new(
+        Arc::new(MockObjectStore::default()),
+        "my-bucket".parse().unwrap(),
+        UploadOptions::default(),
+        FilesystemOptions::default(),
+    )
+    .unwrap();
+    let nlink = |filesystem: &mut S3WriteOnlyFilesystem, ino| filesystem.attr(ino).unwrap().1.nlink;
+
+    assert_eq!(2, nlink(&mut filesystem, ROOT_DIRECTORY_INODE));
+    let invoices = filesystem
+        .make_directory(ROOT_DIRECTORY_INODE, OsStr::new("invoices"))
+        .unwrap();
+    filesystem
+        .make_directory(ROOT_DIRECTORY_INODE, OsStr::new("reports"))
+        .unwrap();
+    filesystem
+        .make_directory(invoices.ino, OsStr::new("2024"))
+        .unwrap();
+
+    assert_eq!(4, nlink(&mut filesystem, ROOT_DIRECTORY_INODE));
+    assert_eq!(3, nlink(&mut filesystem, invoices.ino));
+}
+
+#[test]
+fn directory_names_follow_the_key_charset() {
+    use crate::object_store::mock::MockObjectStore;
+
+    let mut filesystem = S3WriteOnlyFilesystem::new(
+        Arc::new(MockObjectStore::default()),
+        "my-bucket".parse().unwrap(),
+        UploadOptions::default(),
+        FilesystemOptions {
+            strict_key_charset: Some(KeyCharsetPolicy::Reject),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        Err(EINVAL),
+        filesystem.make_directory(ROOT_DIRECTORY_INODE, OsStr::new("Rechnungen 2024"))
+    );
+
+    Arc::get_mut(&mut filesystem.options)
+        .unwrap()
+        .strict_key_charset = Some(KeyCharsetPolicy::Transliterate);
+    let directory = filesystem
+        .make_directory(ROOT_DIRECTORY_INODE, OsStr::new("Rechnungen 2024"))
+        .unwrap();
+    assert_eq!("Rechnungen 2024", directory.path);
+    assert_eq!("Rechnungen_2024", directory.prefix);
+}
+
+#[test]
+fn deferred_uploads_are_finished_before_unmounting() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = Arc::new(MockObjectStore::default());
+    let options = Arc::new(FilesystemOptions::default());
+    let deferred_uploads = DeferredUploads::start(
+        s3.clone(),
+        Arc::clone(&options),
+        Arc::new(AtomicBool::new(false)),
+    )
+    .unwrap();
+
+    let mut handle = Handle::new(
+        "my-bucket",
+        "my-file",
+        Arc::new(UploadOptions::default()),
+        false,
+    );
+    handle.write(&mut runtime, s3.as_ref(), b"content").unwrap();
+    deferred_uploads.queue(DeferredUpload {
+        bucket: "my-bucket".to_owned(),
+        key: "my-file".to_owned(),
+        crtime: SystemTime::now(),
+        handle,
+    });
+    drop(deferred_uploads);
+
+    match &s3.calls()[..] {
+        [Call::PutObject(put_object, body)] => {
+            assert_eq!(put_object.key, "my-file");
+            assert_eq!(body, b"content");
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+    assert_eq!(0, options.session_status.exit_code());
+}
@@ -0,0 +1,214 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    errno,
+    key_pattern::random_uuid,
+    object_store::ObjectStore,
+    s3_write_only_filesystem::BucketAndPrefix,
+    upload::{
+        Finished,
+        Upload,
+        UploadOptions,
+    },
+};
+use anyhow::{
+    bail,
+    Context,
+    Result,
+};
+use rusoto_s3::{
+    DeleteObjectRequest,
+    GetObjectRequest,
+};
+use std::sync::Arc;
+use tokio::{
+    io::AsyncReadExt,
+    runtime::Runtime,
+};
+
+/// Exit code for a successful self-test.
+const SUCCESS: i32 = 0;
+/// Exit code for a failed self-test.
+const FAILURE: i32 = 1;
+
+/// Upload a small object to the bucket, read it back, verify its content and delete it again.
+///
+/// Prints a one-line status, followed by the likely cause of a failure if it can be determined,
+/// and returns the exit code.
+pub(crate) fn run(
+    s3: &dyn ObjectStore,
+    bucket_and_prefix: &BucketAndPrefix,
+    upload_options: &UploadOptions,
+) -> i32 {
+    let result = Runtime::new()
+        .context("failed to create runtime")
+        .and_then(|mut runtime| self_test(&mut runtime, s3, bucket_and_prefix, upload_options));
+    match result {
+        Ok(key) => {
+            println!(
+                "OK - uploaded, read back and deleted 's3://{}/{}'",
+                bucket_and_prefix.s3_bucket_name, key
+            );
+            SUCCESS
+        }
+        Err(error) => {
+            println!("FAILED - {:#}", error);
+            if let Some(cause) = likely_cause(&error) {
+                println!("{}", cause);
+            }
+            FAILURE
+        }
+    }
+}
+
+fn self_test(
+    runtime: &mut Runtime,
+    s3: &dyn ObjectStore,
+    bucket_and_prefix: &BucketAndPrefix,
+    upload_options: &UploadOptions,
+) -> Result<String> {
+    let bucket = &bucket_and_prefix.s3_bucket_name;
+    let id = random_uuid()?;
+    let name = format!(".s3wofs-self-test-{}", id);
+    let key = match &bucket_and_prefix.prefix_path {
+        Some(prefix) => format!("{}/{}", prefix, name),
+        None => name,
+    };
+    let content = format!("s3-write-only-fs self-test {}\n", id).into_bytes();
+
+    // Everything that changes the object, rather than how it is written, would make the
+    // comparison fail.
+    let upload_options = Arc::new(UploadOptions {
+        duplicate_content_index: None,
+        skip_empty_files: false,
+        dry_run: false,
+        compress_min_size: None,
+        upload_window: None,
+        ..upload_options.clone()
+    });
+    let key = match Upload::new(bucket, &key, Arc::clone(&upload_options))
+        .write(runtime, s3, &content)
+        .map_err(|interrupted| interrupted.error)
+        .and_then(|upload| upload.finish(runtime, s3))
+        .with_context(|| format!("uploading '{}' failed", key))?
+    {
+        Finished::Uploaded { key, .. } => key,
+        Finished::Skipped => bail!("'{}' was not uploaded", key),
+    };
+
+    let verified = runtime
+        .block_on(read(s3, bucket, &key, &upload_options))
+        .with_context(|| format!("reading '{}' back failed", key))
+        .and_then(|read| {
+            if read != content {
+                bail!("'{}' was read back with different content", key);
+            }
+            Ok(())
+        });
+    // Don't leave the object behind, even if it could not be verified.
+    let deleted = runtime
+        .block_on(s3.delete_object(DeleteObjectRequest {
+            bucket: bucket.to_owned(),
+            key: key.clone(),
+            ..Default::default()
+        }))
+        .with_context(|| format!("deleting '{}' failed", key));
+    verified?;
+    deleted?;
+
+    Ok(key)
+}
+
+async fn read(
+    s3: &dyn ObjectStore,
+    bucket: &str,
+    key: &str,
+    upload_options: &UploadOptions,
+) -> Result<Vec<u8>> {
+    let sse_customer_key = upload_options.sse_customer_key.as_ref();
+    let output = s3
+        .get_object(GetObjectRequest {
+            bucket: bucket.to_owned(),
+            key: key.to_owned(),
+            sse_customer_algorithm: sse_customer_key.and_then(|key| key.algorithm()),
+            sse_customer_key: sse_customer_key.and_then(|key| key.key()),
+            sse_customer_key_md5: sse_customer_key.and_then(|key| key.key_md5()),
+            ..Default::default()
+        })
+        .await?;
+    let mut content = vec![];
+    if let Some(body) = output.body {
+        body.into_async_read().read_to_end(&mut content).await?;
+    }
+
+    Ok(content)
+}
+
+/// Describe what most likely caused the self-test to fail, if it can be determined.
+fn likely_cause(error: &anyhow::Error) -> Option<&'static str> {
+    if errno::is_unreachable(error) {
+        return Some("Endpoint: S3 could not be reached, check the network and the endpoint.");
+    }
+    if errno::kms_access_denied(error).is_some() {
+        return Some(
+            "Encryption: S3 was denied access to the KMS key, check its key policy and grants.",
+        );
+    }
+    match errno::s3_error_response(error)? {
+        (_, code, message)
+            if message.to_lowercase().contains("encrypt")
+                || code == "InvalidEncryptionAlgorithmError" =>
+        {
+            Some("Encryption: S3 rejected the requested server-side encryption.")
+        }
+        (403, _, _) => Some(
+            "Permissions: access was denied, check that the IAM and bucket policies allow \
+             s3:PutObject, s3:GetObject and s3:DeleteObject.",
+        ),
+        (301, _, _) => Some("Endpoint: the bucket resides in a different region."),
+        _ => None,
+    }
+}
+
+#[test]
+fn self_test_round_trips_an_object() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let s3 = MockObjectStore::default();
+    let bucket_and_prefix = "my-bucket:prefix".parse().unwrap();
+    let upload_options = UploadOptions {
+        compress_min_size: Some(0),
+        ..Default::default()
+    };
+
+    assert_eq!(SUCCESS, run(&s3, &bucket_and_prefix, &upload_options));
+
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::PutObject(put_object, _), Call::GetObject(get_object), Call::DeleteObject(delete_object)] =>
+        {
+            assert!(put_object.key.starts_with("prefix/.s3wofs-self-test-"));
+            assert_eq!(None, put_object.content_encoding);
+            assert_eq!(put_object.key, get_object.key);
+            assert_eq!(put_object.key, delete_object.key);
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
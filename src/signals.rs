@@ -0,0 +1,85 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{
+    bail,
+    Result,
+};
+use libc::c_int;
+use slog_scope::error;
+use std::thread;
+
+/// Signals that are handled by dedicated threads rather than by signal handlers.
+const HANDLED_SIGNALS: &[c_int] = &[
+    libc::SIGTERM,
+    libc::SIGINT,
+    libc::SIGUSR1,
+    libc::SIGUSR2,
+    libc::SIGHUP,
+    libc::SIGCONT,
+];
+
+fn signal_set(signals: &[c_int]) -> libc::sigset_t {
+    unsafe {
+        let mut set: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        for &signal in signals {
+            libc::sigaddset(&mut set, signal);
+        }
+        set
+    }
+}
+
+/// Block all signals that are handled by dedicated threads.
+///
+/// The signal mask is inherited by all threads spawned afterwards (and survives daemonizing), so
+/// this has to be called before any other thread is spawned. Otherwise, the signals could be
+/// delivered to a thread that doesn't block them, terminating the process.
+pub(crate) fn block() -> Result<()> {
+    let set = signal_set(HANDLED_SIGNALS);
+    let result = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut()) };
+    if result != 0 {
+        bail!("failed to block signals: error code {}", result);
+    }
+
+    Ok(())
+}
+
+/// Spawn a thread that calls `handler` for every one of the given `signals` received.
+///
+/// The signals must have been blocked with [`block`] before.
+pub(crate) fn spawn_handler<F>(name: &str, signals: &[c_int], mut handler: F) -> Result<()>
+where
+    F: FnMut(c_int) + Send + 'static,
+{
+    debug_assert!(signals
+        .iter()
+        .all(|signal| HANDLED_SIGNALS.contains(signal)));
+    let set = signal_set(signals);
+    thread::Builder::new()
+        .name(name.to_owned())
+        .spawn(move || loop {
+            let mut signal = 0;
+            let result = unsafe { libc::sigwait(&set, &mut signal) };
+            if result != 0 {
+                error!("Failed to wait for signals"; "error_code" => result);
+                return;
+            }
+            handler(signal);
+        })?;
+
+    Ok(())
+}
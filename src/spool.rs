@@ -0,0 +1,424 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    id_generator::IdGenerator,
+    upload::split_part,
+};
+use anyhow::{
+    anyhow,
+    Context,
+    Result,
+};
+use std::{
+    fmt,
+    fs::{
+        self,
+        File,
+        OpenOptions,
+    },
+    os::unix::fs::FileExt,
+    path::{
+        Path,
+        PathBuf,
+    },
+    process,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        Arc,
+    },
+};
+
+/// A directory that the buffered data of uploads is spooled to, such that it doesn't have to be
+/// held in memory.
+///
+/// Spool files are removed right after they have been created, so their space is freed once they
+/// are closed, even if the process is killed.
+pub(crate) struct Spool {
+    directory: PathBuf,
+    /// Buffers are moved to a spool file once they would grow beyond this many bytes.
+    threshold: usize,
+    /// Maximum number of bytes held in spool files across all uploads, if limited.
+    max_size: Option<u64>,
+    /// Maximum number of bytes held in a single spool file, if limited.
+    max_file_size: Option<u64>,
+    used: AtomicU64,
+    sequence: IdGenerator,
+}
+
+impl fmt::Debug for Spool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Spool")
+            .field("directory", &self.directory)
+            .field("threshold", &self.threshold)
+            .field("max_size", &self.max_size)
+            .field("max_file_size", &self.max_file_size)
+            .finish()
+    }
+}
+
+impl Spool {
+    /// Spool buffers into `directory`, which has to exist already.
+    ///
+    /// The path is made absolute, since the working directory changes when daemonizing.
+    pub(crate) fn new(
+        directory: &Path,
+        threshold: usize,
+        max_size: Option<u64>,
+        max_file_size: Option<u64>,
+    ) -> Result<Self> {
+        let directory = fs::canonicalize(directory).with_context(|| {
+            format!("failed to access spool directory '{}'", directory.display())
+        })?;
+        let spool = Spool {
+            directory,
+            threshold,
+            max_size,
+            max_file_size,
+            used: AtomicU64::new(0),
+            sequence: IdGenerator::new(1),
+        };
+        // Fail right away rather than on the first large write.
+        spool.create_file()?;
+
+        Ok(spool)
+    }
+
+    pub(crate) fn max_file_size(&self) -> Option<u64> {
+        self.max_file_size
+    }
+
+    fn create_file(&self) -> Result<File> {
+        let path = self.directory.join(format!(
+            ".s3-write-only-fs-{}-{}.spool",
+            process::id(),
+            self.sequence.next()
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .with_context(|| format!("failed to create spool file '{}'", path.display()))?;
+        fs::remove_file(&path)
+            .with_context(|| format!("failed to remove spool file '{}'", path.display()))?;
+
+        Ok(file)
+    }
+
+    /// Account for `size` more bytes in spool files, unless that would exceed `max_size`.
+    fn reserve(&self, size: u64) -> Result<()> {
+        self.used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                let used = used.checked_add(size)?;
+                match self.max_size {
+                    Some(max_size) if used > max_size => None,
+                    _ => Some(used),
+                }
+            })
+            .map(|_| ())
+            .map_err(|used| {
+                anyhow!(
+                    "can't spool {} more bytes, {} of {} bytes are in use",
+                    size,
+                    used,
+                    self.max_size.unwrap_or(u64::MAX)
+                )
+                .context(SpoolFull)
+            })
+    }
+
+    fn release(&self, size: u64) {
+        self.used.fetch_sub(size, Ordering::SeqCst);
+    }
+}
+
+/// Data couldn't be spooled, because the spool has reached its maximum size.
+#[derive(Debug)]
+pub(crate) struct SpoolFull;
+
+impl fmt::Display for SpoolFull {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "spool is full")
+    }
+}
+
+/// The data buffered for an upload, held in memory until it grows beyond the threshold of the
+/// spool, if any, and in a spool file from then on.
+#[derive(Debug)]
+pub(crate) enum Buffer {
+    Memory(Vec<u8>),
+    Spooled(SpoolFile),
+}
+
+impl Default for Buffer {
+    fn default() -> Self {
+        Buffer::Memory(vec![])
+    }
+}
+
+impl Buffer {
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Buffer::Memory(buffer) => buffer.len(),
+            Buffer::Spooled(file) => (file.end - file.start) as usize,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of bytes held in memory rather than in a spool file.
+    pub(crate) fn memory_len(&self) -> usize {
+        match self {
+            Buffer::Memory(buffer) => buffer.len(),
+            Buffer::Spooled(_) => 0,
+        }
+    }
+
+    /// Reserve memory for `additional` bytes, unless they would be spooled anyway.
+    pub(crate) fn reserve_exact(&mut self, additional: usize, spool: Option<&Arc<Spool>>) {
+        if let Buffer::Memory(buffer) = self {
+            if spool.is_none_or(|spool| buffer.len() + additional <= spool.threshold) {
+                buffer.reserve_exact(additional);
+            }
+        }
+    }
+
+    /// Append `data`, moving the buffer to a spool file once it grows beyond the threshold of
+    /// `spool`. If this fails, the buffer is left unchanged.
+    pub(crate) fn extend_from_slice(
+        &mut self,
+        data: &[u8],
+        spool: Option<&Arc<Spool>>,
+    ) -> Result<()> {
+        match self {
+            Buffer::Memory(buffer) => match spool {
+                Some(spool) if buffer.len() + data.len() > spool.threshold => {
+                    let mut file = SpoolFile::new(spool)?;
+                    file.append(buffer)?;
+                    file.append(data)?;
+                    *self = Buffer::Spooled(file);
+                }
+                _ => buffer.extend_from_slice(data),
+            },
+            Buffer::Spooled(file) => file.append(data)?,
+        }
+
+        Ok(())
+    }
+
+    /// Shorten the buffer to `len` bytes.
+    pub(crate) fn truncate(&mut self, len: usize) -> Result<()> {
+        match self {
+            Buffer::Memory(buffer) => buffer.truncate(len),
+            Buffer::Spooled(file) => file.truncate(file.start + len as u64)?,
+        }
+
+        Ok(())
+    }
+
+    /// Take all of the buffered data, leaving the buffer empty.
+    pub(crate) fn take(&mut self) -> Result<Vec<u8>> {
+        match self {
+            Buffer::Memory(buffer) => Ok(std::mem::take(buffer)),
+            Buffer::Spooled(file) => {
+                let data = file.read(file.start, (file.end - file.start) as usize)?;
+                *self = Buffer::default();
+                Ok(data)
+            }
+        }
+    }
+
+    /// Take the front of the buffer, up to `max_size` bytes, once it holds at least `threshold`
+    /// bytes, see [`split_part`].
+    ///
+    /// A spool file keeps the data that was taken, such that it can be put back with
+    /// [`Buffer::unsplit`], until more data is appended.
+    pub(crate) fn split_part(
+        &mut self,
+        threshold: usize,
+        max_size: usize,
+    ) -> Result<Option<Vec<u8>>> {
+        match self {
+            Buffer::Memory(buffer) => Ok(split_part(buffer, threshold, max_size)),
+            Buffer::Spooled(file) => {
+                let len = (file.end - file.start) as usize;
+                if len < threshold {
+                    return Ok(None);
+                }
+                let part = file.read(file.start, len.min(max_size))?;
+                file.start += part.len() as u64;
+                Ok(Some(part))
+            }
+        }
+    }
+
+    /// Put `part`, just taken with [`Buffer::split_part`], back to the front of the buffer.
+    pub(crate) fn unsplit(&mut self, mut part: Vec<u8>) -> Result<()> {
+        match self {
+            Buffer::Memory(buffer) => {
+                part.append(buffer);
+                *buffer = part;
+            }
+            Buffer::Spooled(file) => {
+                let start = file
+                    .start
+                    .checked_sub(part.len() as u64)
+                    .ok_or_else(|| anyhow!("part doesn't fit in front of the spooled data"))?;
+                file.file
+                    .write_all_at(&part, start)
+                    .context("failed to write to spool file")?;
+                file.start = start;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A spool file holding the data between `start` and `end`. The data before `start` has been
+/// taken already.
+#[derive(Debug)]
+pub(crate) struct SpoolFile {
+    spool: Arc<Spool>,
+    file: File,
+    start: u64,
+    end: u64,
+}
+
+impl SpoolFile {
+    fn new(spool: &Arc<Spool>) -> Result<Self> {
+        Ok(SpoolFile {
+            spool: Arc::clone(spool),
+            file: spool.create_file()?,
+            start: 0,
+            end: 0,
+        })
+    }
+
+    fn append(&mut self, data: &[u8]) -> Result<()> {
+        // The data that was taken is only kept until more data is appended.
+        if self.start == self.end && self.end > 0 {
+            self.truncate(0)?;
+            self.start = 0;
+        }
+        // Uploads switch to parts before reaching the limit, this only guards against a single
+        // file taking up the whole spool.
+        if let Some(max_file_size) = self.spool.max_file_size {
+            if self.end + data.len() as u64 > max_file_size {
+                return Err(anyhow!(
+                    "can't spool {} more bytes, the file would exceed {} bytes",
+                    data.len(),
+                    max_file_size
+                )
+                .context(SpoolFull));
+            }
+        }
+        self.spool.reserve(data.len() as u64)?;
+        if let Err(error) = self.file.write_all_at(data, self.end) {
+            self.spool.release(data.len() as u64);
+            // Drop whatever was written partially.
+            let _ = self.file.set_len(self.end);
+            return Err(anyhow::Error::new(error).context("failed to write to spool file"));
+        }
+        self.end += data.len() as u64;
+
+        Ok(())
+    }
+
+    fn truncate(&mut self, end: u64) -> Result<()> {
+        if end >= self.end {
+            return Ok(());
+        }
+        self.file
+            .set_len(end)
+            .context("failed to truncate spool file")?;
+        self.spool.release(self.end - end);
+        self.end = end;
+
+        Ok(())
+    }
+
+    fn read(&self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        let mut data = vec![0; len];
+        self.file
+            .read_exact_at(&mut data, offset)
+            .context("failed to read from spool file")?;
+
+        Ok(data)
+    }
+}
+
+impl Drop for SpoolFile {
+    fn drop(&mut self) {
+        self.spool.release(self.end);
+    }
+}
+
+#[cfg(test)]
+fn test_spool(threshold: usize, max_size: Option<u64>, max_file_size: Option<u64>) -> Arc<Spool> {
+    Arc::new(Spool::new(&std::env::temp_dir(), threshold, max_size, max_file_size).unwrap())
+}
+
+#[test]
+fn buffers_are_spooled_beyond_threshold() {
+    let spool = test_spool(4, None, None);
+    let mut buffer = Buffer::default();
+
+    buffer.extend_from_slice(b"1234", Some(&spool)).unwrap();
+    assert_eq!(4, buffer.memory_len());
+    buffer.extend_from_slice(b"56", Some(&spool)).unwrap();
+    assert_eq!(0, buffer.memory_len());
+    assert_eq!(6, buffer.len());
+    assert_eq!(6, spool.used.load(Ordering::SeqCst));
+
+    assert_eq!(None, buffer.split_part(7, 4).unwrap());
+    let part = buffer.split_part(2, 4).unwrap().unwrap();
+    assert_eq!(b"1234".to_vec(), part);
+    buffer.unsplit(part).unwrap();
+    buffer.truncate(5).unwrap();
+    assert_eq!(5, spool.used.load(Ordering::SeqCst));
+    assert_eq!(b"12345".to_vec(), buffer.take().unwrap());
+    assert_eq!(0, spool.used.load(Ordering::SeqCst));
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn spool_size_is_limited() {
+    let spool = test_spool(0, Some(8), Some(6));
+    let mut first = Buffer::default();
+    let mut second = Buffer::default();
+
+    first.extend_from_slice(b"1234", Some(&spool)).unwrap();
+    let error = first.extend_from_slice(b"567", Some(&spool)).unwrap_err();
+    assert!(error.downcast_ref::<SpoolFull>().is_some());
+    assert_eq!(4, first.len());
+    first.truncate(0).unwrap();
+
+    first.extend_from_slice(b"123456", Some(&spool)).unwrap();
+    let error = second.extend_from_slice(b"789", Some(&spool)).unwrap_err();
+    assert!(error.downcast_ref::<SpoolFull>().is_some());
+    assert!(second.is_empty());
+
+    drop(first);
+    second.extend_from_slice(b"789", Some(&spool)).unwrap();
+    assert_eq!(3, spool.used.load(Ordering::SeqCst));
+}
@@ -0,0 +1,117 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{
+    bail,
+    Context,
+    Result,
+};
+use md5::{
+    Digest,
+    Md5,
+};
+use std::{
+    fmt,
+    fs,
+    path::Path,
+};
+use zeroize::{
+    Zeroize,
+    Zeroizing,
+};
+
+/// The only algorithm supported by S3 for customer-provided keys.
+const ALGORITHM: &str = "AES256";
+/// Length of an AES-256 key in bytes.
+const KEY_LENGTH: usize = 32;
+
+/// A customer-provided key for server-side encryption (SSE-C), which has to accompany every
+/// request that writes the object.
+///
+/// The key is zeroed when dropped. Note that the requests sent to S3 necessarily hold copies of
+/// the (base64-encoded) key for as long as they exist.
+#[derive(Clone)]
+pub(crate) struct SseCustomerKey {
+    /// The base64-encoded key.
+    key: Zeroizing<String>,
+    /// The base64-encoded MD5 digest of the key, which S3 uses to verify the key was transmitted
+    /// intact.
+    key_md5: String,
+}
+
+impl SseCustomerKey {
+    /// Read the base64-encoded key from the file at `path`.
+    pub(crate) fn read(path: &Path) -> Result<SseCustomerKey> {
+        let contents = Zeroizing::new(
+            fs::read_to_string(path)
+                .with_context(|| format!("failed to read SSE-C key from '{}'", path.display()))?,
+        );
+        Self::from_base64(contents.trim())
+    }
+
+    pub(crate) fn from_base64(key: &str) -> Result<SseCustomerKey> {
+        let mut decoded = base64::decode(key).context("the SSE-C key is not valid base64")?;
+        let length = decoded.len();
+        let key_md5 = base64::encode(Md5::digest(&decoded));
+        decoded.zeroize();
+        if length != KEY_LENGTH {
+            bail!(
+                "the SSE-C key has to be {} bytes long, but is {} bytes long",
+                KEY_LENGTH,
+                length
+            );
+        }
+
+        Ok(SseCustomerKey {
+            key: Zeroizing::new(key.to_owned()),
+            key_md5,
+        })
+    }
+
+    pub(crate) fn algorithm(&self) -> Option<String> {
+        Some(ALGORITHM.to_owned())
+    }
+
+    pub(crate) fn key(&self) -> Option<String> {
+        Some(self.key.as_str().to_owned())
+    }
+
+    pub(crate) fn key_md5(&self) -> Option<String> {
+        Some(self.key_md5.clone())
+    }
+}
+
+impl fmt::Debug for SseCustomerKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SseCustomerKey")
+            .field("key_md5", &self.key_md5)
+            .finish_non_exhaustive()
+    }
+}
+
+#[test]
+fn key_md5_is_derived() {
+    let key = SseCustomerKey::from_base64("MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=").unwrap();
+    assert_eq!(Some("AES256".to_owned()), key.algorithm());
+    assert_eq!(Some("hRasmdxgYDKV3nvbahU1MA==".to_owned()), key.key_md5());
+    assert!(!format!("{:?}", key).contains("MDEy"));
+}
+
+#[test]
+fn invalid_keys_are_rejected() {
+    assert!(SseCustomerKey::from_base64("not base64!").is_err());
+    assert!(SseCustomerKey::from_base64("c2hvcnQ=").is_err());
+}
@@ -0,0 +1,138 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{
+    Context,
+    Result,
+};
+use slog::{
+    Drain,
+    IgnoreResult,
+    Level,
+    OwnedKVList,
+    Record,
+};
+use std::{
+    fs::{
+        File,
+        OpenOptions,
+    },
+    path::Path,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+/// The module whose traces are the FUSE operations.
+const OPERATIONS_MODULE: &str = "::s3_write_only_filesystem";
+
+/// Writes the traces of FUSE operations to a file, see [`OperationTraces`].
+///
+/// Records are written synchronously, such that the drain survives daemonizing, which doesn't
+/// carry over any threads.
+pub(crate) type TraceFile =
+    Arc<IgnoreResult<Mutex<slog_term::FullFormat<slog_term::PlainDecorator<File>>>>>;
+
+/// Open the trace file at `path`, appending to it if it already exists.
+pub(crate) fn open(path: &Path) -> Result<TraceFile> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("failed to open trace file '{}'", path.display()))?;
+    let decorator = slog_term::PlainDecorator::new(file);
+    let drain = slog_term::FullFormat::new(decorator).build();
+
+    Ok(Arc::new(Mutex::new(drain).ignore_res()))
+}
+
+/// Routes the traces of FUSE operations to a separate drain, if any, and all other records to the
+/// main drain.
+///
+/// This allows capturing a detailed trace of the operations without flooding the main log.
+pub(crate) struct OperationTraces<M, T> {
+    main: M,
+    traces: Option<T>,
+}
+
+impl<M, T> OperationTraces<M, T> {
+    pub(crate) fn new(main: M, traces: Option<T>) -> Self {
+        OperationTraces { main, traces }
+    }
+}
+
+impl<M, T> Drain for OperationTraces<M, T>
+where
+    M: Drain,
+    T: Drain,
+{
+    type Ok = ();
+    type Err = M::Err;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), M::Err> {
+        match &self.traces {
+            Some(traces) if is_operation_trace(record) => {
+                // Failing to write a trace must not affect the main log.
+                let _ = traces.log(record, values);
+                Ok(())
+            }
+            _ => self.main.log(record, values).map(|_| ()),
+        }
+    }
+}
+
+fn is_operation_trace(record: &Record) -> bool {
+    record.level() == Level::Trace && record.module().ends_with(OPERATIONS_MODULE)
+}
+
+#[test]
+fn only_traces_of_operations_are_routed() {
+    use slog::{
+        RecordLocation,
+        RecordStatic,
+    };
+
+    let is_trace = |level, module| {
+        let location = RecordLocation {
+            file: "",
+            line: 0,
+            column: 0,
+            function: "",
+            module,
+        };
+        let record_static = RecordStatic {
+            location: &location,
+            tag: "",
+            level,
+        };
+        is_operation_trace(&Record::new(
+            &record_static,
+            &format_args!("lookup(parent=1)"),
+            slog::b!(),
+        ))
+    };
+
+    assert!(is_trace(
+        Level::Trace,
+        "s3_write_only_fs::s3_write_only_filesystem"
+    ));
+    assert!(!is_trace(
+        Level::Debug,
+        "s3_write_only_fs::s3_write_only_filesystem"
+    ));
+    assert!(!is_trace(Level::Trace, "s3_write_only_fs::upload"));
+}
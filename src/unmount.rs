@@ -0,0 +1,106 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::signals;
+use anyhow::{
+    bail,
+    Result,
+};
+use slog_scope::{
+    debug,
+    error,
+    info,
+    warn,
+};
+use std::{
+    io::ErrorKind,
+    path::{
+        Path,
+        PathBuf,
+    },
+    process::Command,
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+const RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Unmount the filesystem at `mountpoint` when the process receives SIGTERM or SIGINT.
+///
+/// Once the filesystem is unmounted, `fuse::mount` returns and the process can shut down cleanly.
+/// If the mountpoint is still busy after `grace_period`, it is unmounted lazily instead.
+pub(crate) fn unmount_on_signal(mountpoint: PathBuf, grace_period: Duration) -> Result<()> {
+    signals::spawn_handler(
+        "unmount-on-signal",
+        &[libc::SIGTERM, libc::SIGINT],
+        move |signal| {
+            info!("Received signal, unmounting filesystem";
+                  "signal" => signal,
+                  "mountpoint" => %mountpoint.display());
+            unmount(&mountpoint, grace_period);
+        },
+    )
+}
+
+fn unmount(mountpoint: &Path, grace_period: Duration) {
+    let deadline = Instant::now() + grace_period;
+    loop {
+        match run_unmount(mountpoint, false) {
+            Ok(()) => return,
+            Err(error) => debug!("Failed to unmount filesystem"; "error" => %error),
+        }
+        if Instant::now() >= deadline {
+            break;
+        }
+        thread::sleep(RETRY_INTERVAL);
+    }
+
+    warn!(
+        "Filesystem is still busy after the grace period of {} seconds, unmounting lazily",
+        grace_period.as_secs()
+    );
+    if let Err(error) = run_unmount(mountpoint, true) {
+        error!("Failed to unmount filesystem lazily"; "error" => %error);
+    }
+}
+
+fn run_unmount(mountpoint: &Path, lazy: bool) -> Result<()> {
+    let mut fusermount = Command::new("fusermount");
+    fusermount.arg("-u");
+    if lazy {
+        fusermount.arg("-z");
+    }
+    let status = match fusermount.arg(mountpoint).status() {
+        Ok(status) => status,
+        // Fall back to a regular umount, which works if we are running as root.
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            let mut umount = Command::new("umount");
+            if lazy {
+                umount.arg("-l");
+            }
+            umount.arg(mountpoint).status()?
+        }
+        Err(error) => return Err(error.into()),
+    };
+    if !status.success() {
+        bail!("unmounting '{}' failed: {}", mountpoint.display(), status);
+    }
+
+    Ok(())
+}
@@ -14,27 +14,681 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::id_generator::IdGenerator;
+use crate::{
+    checksum_sidecar::{
+        ChecksumSidecar,
+        SidecarFailurePolicy,
+    },
+    compression::{
+        Codec,
+        Encoder,
+    },
+    content_hash::{
+        to_hex,
+        ContentHash,
+        ContentHasher,
+        ContentIndex,
+        IndexedObject,
+    },
+    content_type::ContentTypes,
+    errno,
+    id_generator::IdGenerator,
+    intelligent_tiering::{
+        self,
+        IntelligentTiering,
+    },
+    metrics::Metrics,
+    object_metadata::ObjectMetadata,
+    object_store::ObjectStore,
+    retry::RetryPolicy,
+    spool::{
+        Buffer,
+        Spool,
+    },
+    sse_customer::SseCustomerKey,
+    upload_pause::UploadPause,
+    upload_window::UploadWindow,
+};
 use anyhow::{
     anyhow,
     Result,
 };
+use chrono::Utc;
+use clap::ValueEnum;
+use percent_encoding::{
+    utf8_percent_encode,
+    AsciiSet,
+    NON_ALPHANUMERIC,
+};
+use rusoto_core::RusotoError;
 use rusoto_s3::{
     AbortMultipartUploadRequest,
+    CompleteMultipartUploadOutput,
     CompleteMultipartUploadRequest,
     CompletedMultipartUpload,
     CompletedPart,
+    CopyObjectRequest,
     CreateMultipartUploadRequest,
+    DeleteObjectRequest,
+    HeadObjectError,
+    HeadObjectRequest,
     PutObjectRequest,
-    S3Client,
+    UploadPartError,
     UploadPartRequest,
-    S3,
 };
-use slog_scope::debug;
-use std::sync::Arc;
+use slog_scope::{
+    debug,
+    error,
+    info,
+    warn,
+};
+use std::{
+    fmt,
+    io::Write,
+    sync::Arc,
+    time::{
+        Duration,
+        Instant,
+    },
+};
 use tokio::runtime::Runtime;
 
-const MULTIPART_MINIMUM_PART_SIZE: usize = 5 * 1024 * 1024;
+pub(crate) const MULTIPART_MINIMUM_PART_SIZE: usize = 5 * 1024 * 1024;
+/// Largest part S3 accepts, which is also the largest object that can be uploaded with a single
+/// `PutObject`.
+pub(crate) const MULTIPART_MAXIMUM_PART_SIZE: usize = 5 * 1024 * 1024 * 1024;
+/// Upper bound for the interval in which S3 is probed while a multipart upload is paused.
+const NETWORK_PAUSE_MAXIMUM_PROBE_INTERVAL: Duration = Duration::from_secs(5);
+/// Maximum number of parts a multipart upload can consist of.
+const MULTIPART_MAXIMUM_PARTS: u64 = 10_000;
+/// Files up to this size are uploaded with a single `PutObject` if their size is known up front.
+const SIZE_HINT_SINGLE_PUT_MAXIMUM_SIZE: u64 = 64 * 1024 * 1024;
+/// Largest object that can be copied with a single `CopyObject` request.
+const COPY_OBJECT_MAXIMUM_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+
+/// Characters that have to be percent-encoded when an object key is part of a URL or the
+/// `x-amz-copy-source` header.
+pub(crate) const KEY_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'/')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// Server-side encryption to request for uploaded objects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ServerSideEncryption {
+    /// SSE-S3, i.e. encryption with keys managed by S3.
+    #[value(name = "AES256")]
+    Aes256,
+    /// SSE-KMS, i.e. encryption with a key managed in AWS KMS.
+    #[value(name = "aws:kms")]
+    AwsKms,
+}
+
+impl ServerSideEncryption {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Aes256 => "AES256",
+            Self::AwsKms => "aws:kms",
+        }
+    }
+}
+
+/// Storage class to upload objects with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum StorageClass {
+    #[value(name = "STANDARD")]
+    Standard,
+    #[value(name = "STANDARD_IA")]
+    StandardIa,
+    #[value(name = "ONEZONE_IA")]
+    OnezoneIa,
+    #[value(name = "INTELLIGENT_TIERING")]
+    IntelligentTiering,
+    #[value(name = "GLACIER_IR")]
+    GlacierIr,
+    #[value(name = "GLACIER")]
+    Glacier,
+    #[value(name = "DEEP_ARCHIVE")]
+    DeepArchive,
+    #[value(name = "REDUCED_REDUNDANCY")]
+    ReducedRedundancy,
+}
+
+impl StorageClass {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Standard => "STANDARD",
+            Self::StandardIa => "STANDARD_IA",
+            Self::OnezoneIa => "ONEZONE_IA",
+            Self::IntelligentTiering => intelligent_tiering::STORAGE_CLASS,
+            Self::GlacierIr => "GLACIER_IR",
+            Self::Glacier => "GLACIER",
+            Self::DeepArchive => "DEEP_ARCHIVE",
+            Self::ReducedRedundancy => "REDUCED_REDUNDANCY",
+        }
+    }
+}
+
+/// Canned ACL to apply to uploaded objects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum CannedAcl {
+    #[value(name = "private")]
+    Private,
+    #[value(name = "public-read")]
+    PublicRead,
+    #[value(name = "public-read-write")]
+    PublicReadWrite,
+    #[value(name = "authenticated-read")]
+    AuthenticatedRead,
+    #[value(name = "aws-exec-read")]
+    AwsExecRead,
+    #[value(name = "bucket-owner-read")]
+    BucketOwnerRead,
+    #[value(name = "bucket-owner-full-control")]
+    BucketOwnerFullControl,
+}
+
+impl CannedAcl {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Private => "private",
+            Self::PublicRead => "public-read",
+            Self::PublicReadWrite => "public-read-write",
+            Self::AuthenticatedRead => "authenticated-read",
+            Self::AwsExecRead => "aws-exec-read",
+            Self::BucketOwnerRead => "bucket-owner-read",
+            Self::BucketOwnerFullControl => "bucket-owner-full-control",
+        }
+    }
+}
+
+/// What happens to an upload when a write to it fails, e.g. because a part could not be uploaded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum InterruptedWrites {
+    /// Abort the upload, failing all further writes.
+    #[default]
+    #[value(name = "fail")]
+    Fail,
+    /// Restore the upload to its state before the write, such that the write can be retried.
+    ///
+    /// Uploads of compressed files can't be restored, and fail instead.
+    #[value(name = "restore")]
+    Restore,
+}
+
+/// Options that apply to every object uploaded through the filesystem.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct UploadOptions {
+    /// Canned ACL to apply. Must be `None` for buckets that have ACLs disabled, i.e. with object
+    /// ownership `BucketOwnerEnforced`.
+    pub(crate) acl: Option<CannedAcl>,
+    pub(crate) server_side_encryption: Option<ServerSideEncryption>,
+    pub(crate) ssekms_key_id: Option<String>,
+    /// If set, objects are encrypted with this customer-provided key (SSE-C).
+    pub(crate) sse_customer_key: Option<SseCustomerKey>,
+    /// Whether to use an S3 Bucket Key. Only meaningful in combination with SSE-KMS.
+    pub(crate) bucket_key_enabled: bool,
+    /// If set, objects are uploaded with the `INTELLIGENT_TIERING` storage class.
+    pub(crate) intelligent_tiering: Option<IntelligentTiering>,
+    /// If set, objects are uploaded with this storage class. Must agree with
+    /// `intelligent_tiering`, if that is set as well.
+    pub(crate) storage_class: Option<StorageClass>,
+    /// Infers the `Content-Type` of objects from the extension of their key.
+    pub(crate) content_types: ContentTypes,
+    /// If set, files whose content was already uploaded during this session are copied from the
+    /// existing object rather than being uploaded again.
+    pub(crate) duplicate_content_index: Option<Arc<ContentIndex>>,
+    /// If set, files that are released without any data written to them are discarded instead of
+    /// being uploaded as empty objects.
+    pub(crate) skip_empty_files: bool,
+    /// How requests that failed with a transient error are retried.
+    pub(crate) retry: RetryPolicy,
+    /// If set, nothing is written to the bucket. Instead, the intended uploads are logged and
+    /// checked for collisions with existing objects.
+    pub(crate) dry_run: bool,
+    /// If set, multipart uploads are paused for up to this long while S3 is unreachable, rather
+    /// than failing right away.
+    pub(crate) network_pause_timeout: Option<Duration>,
+    /// If set, files of at least this many bytes are compressed with `compression` and uploaded
+    /// with the matching `Content-Encoding`.
+    pub(crate) compress_min_size: Option<u64>,
+    /// How files are compressed, see `compress_min_size`.
+    pub(crate) compression: Codec,
+    /// If set, objects are written to a key derived from the SHA-256 hash of their content rather
+    /// than to the key of the upload, whose file name is replaced by the hash.
+    pub(crate) content_addressed: bool,
+    /// If set, files of up to this many bytes are additionally retained in full while they are
+    /// written, such that a failed upload can be retried once from scratch.
+    pub(crate) full_retry_max_size: Option<u64>,
+    /// If set, the data buffered for a single file is uploaded as a part once it reaches this
+    /// many bytes, regardless of the part size, a size hint or `compress_min_size`.
+    pub(crate) max_memory_per_file: Option<usize>,
+    /// If set, buffers that grow beyond the threshold of the spool are held in a spool file
+    /// rather than in memory.
+    pub(crate) spool: Option<Arc<Spool>>,
+    /// If set, the content of every upload is hashed, such that its digest is reported when the
+    /// upload has finished.
+    pub(crate) hash_all_content: bool,
+    /// What happens to an upload when a write to it fails.
+    pub(crate) interrupted_writes: InterruptedWrites,
+    /// If set, a sidecar object with the SHA-256 digest of the content is written next to every
+    /// uploaded object.
+    pub(crate) checksum_sidecar: Option<ChecksumSidecar>,
+    /// If set, uploads to S3 are deferred until the time of day is within this window. Outside of
+    /// it, written data is held back in the buffer of the upload rather than uploaded in parts.
+    pub(crate) upload_window: Option<UploadWindow>,
+    /// User-defined metadata attached to every uploaded object.
+    pub(crate) metadata: ObjectMetadata,
+    /// Gauges describing the uploads in progress.
+    pub(crate) metrics: Arc<Metrics>,
+    /// Holds back uploads to S3 while paused. Like outside of the upload window, written data is
+    /// held back in the buffer of the upload in the meantime.
+    pub(crate) pause: Arc<UploadPause>,
+}
+
+impl UploadOptions {
+    fn acl(&self) -> Option<String> {
+        self.acl.map(|acl| acl.as_str().to_owned())
+    }
+
+    fn server_side_encryption(&self) -> Option<String> {
+        self.server_side_encryption
+            .map(|server_side_encryption| server_side_encryption.as_str().to_owned())
+    }
+
+    fn sse_customer_algorithm(&self) -> Option<String> {
+        self.sse_customer_key
+            .as_ref()
+            .and_then(SseCustomerKey::algorithm)
+    }
+
+    fn sse_customer_key(&self) -> Option<String> {
+        self.sse_customer_key.as_ref().and_then(SseCustomerKey::key)
+    }
+
+    fn sse_customer_key_md5(&self) -> Option<String> {
+        self.sse_customer_key
+            .as_ref()
+            .and_then(SseCustomerKey::key_md5)
+    }
+
+    fn bucket_key_enabled(&self) -> Option<bool> {
+        if self.bucket_key_enabled {
+            Some(true)
+        } else {
+            None
+        }
+    }
+
+    fn storage_class(&self) -> Option<String> {
+        match (self.storage_class, &self.intelligent_tiering) {
+            (Some(storage_class), _) => Some(storage_class.as_str().to_owned()),
+            (None, Some(_)) => Some(intelligent_tiering::STORAGE_CLASS.to_owned()),
+            (None, None) => None,
+        }
+    }
+
+    fn tagging(&self) -> Option<String> {
+        self.intelligent_tiering
+            .as_ref()
+            .and_then(IntelligentTiering::tagging)
+    }
+
+    pub(crate) fn put_object_request(
+        &self,
+        bucket: String,
+        key: String,
+        body: Vec<u8>,
+    ) -> PutObjectRequest {
+        PutObjectRequest {
+            bucket,
+            key,
+            body: Some(body.into()),
+            acl: self.acl(),
+            server_side_encryption: self.server_side_encryption(),
+            ssekms_key_id: self.ssekms_key_id.clone(),
+            bucket_key_enabled: self.bucket_key_enabled(),
+            sse_customer_algorithm: self.sse_customer_algorithm(),
+            sse_customer_key: self.sse_customer_key(),
+            sse_customer_key_md5: self.sse_customer_key_md5(),
+            storage_class: self.storage_class(),
+            tagging: self.tagging(),
+            ..Default::default()
+        }
+    }
+
+    fn create_multipart_upload_request(
+        &self,
+        bucket: String,
+        key: String,
+    ) -> CreateMultipartUploadRequest {
+        CreateMultipartUploadRequest {
+            bucket,
+            key,
+            acl: self.acl(),
+            server_side_encryption: self.server_side_encryption(),
+            ssekms_key_id: self.ssekms_key_id.clone(),
+            bucket_key_enabled: self.bucket_key_enabled(),
+            sse_customer_algorithm: self.sse_customer_algorithm(),
+            sse_customer_key: self.sse_customer_key(),
+            sse_customer_key_md5: self.sse_customer_key_md5(),
+            storage_class: self.storage_class(),
+            tagging: self.tagging(),
+            ..Default::default()
+        }
+    }
+
+    fn copy_object_request(
+        &self,
+        bucket: String,
+        key: String,
+        source_bucket: &str,
+        source_key: &str,
+    ) -> CopyObjectRequest {
+        CopyObjectRequest {
+            bucket,
+            key,
+            copy_source: format!(
+                "{}/{}",
+                source_bucket,
+                utf8_percent_encode(source_key, KEY_ENCODE_SET)
+            ),
+            acl: self.acl(),
+            server_side_encryption: self.server_side_encryption(),
+            ssekms_key_id: self.ssekms_key_id.clone(),
+            bucket_key_enabled: self.bucket_key_enabled(),
+            sse_customer_algorithm: self.sse_customer_algorithm(),
+            sse_customer_key: self.sse_customer_key(),
+            sse_customer_key_md5: self.sse_customer_key_md5(),
+            // The source has been written by us as well, i.e. with the same key.
+            copy_source_sse_customer_algorithm: self.sse_customer_algorithm(),
+            copy_source_sse_customer_key: self.sse_customer_key(),
+            copy_source_sse_customer_key_md5: self.sse_customer_key_md5(),
+            // Tags are copied from the source, which has been tagged by us as well.
+            storage_class: self.storage_class(),
+            ..Default::default()
+        }
+    }
+
+    /// Whether it is outside the upload window, if any, see [`UploadOptions::upload_window`].
+    pub(crate) fn outside_upload_window(&self) -> bool {
+        self.upload_window
+            .is_some_and(|upload_window| !upload_window.is_open())
+    }
+
+    /// Whether uploads are allowed right now, i.e. they are neither paused nor outside the upload
+    /// window.
+    pub(crate) fn uploads_allowed(&self) -> bool {
+        !self.pause.is_paused() && !self.outside_upload_window()
+    }
+
+    /// Block until uploads are allowed, see [`UploadOptions::pause`] and
+    /// [`UploadOptions::upload_window`].
+    pub(crate) fn wait_until_uploads_allowed(&self, runtime: &Runtime, key: &str) {
+        if self.pause.is_paused() {
+            let _waiting = self.metrics.upload_waiting();
+            self.pause.wait(key);
+        }
+        if let Some(upload_window) = &self.upload_window {
+            let _waiting = self.metrics.upload_waiting();
+            upload_window.wait(runtime, key);
+        }
+    }
+
+    fn hash_content(&self) -> bool {
+        self.hash_all_content
+            || self.duplicate_content_index.is_some()
+            || self.content_addressed
+            || self.checksum_sidecar.is_some()
+    }
+
+    /// The key the object of the upload with `key` is written to.
+    fn final_key(&self, key: &str, content: Option<(ContentHash, u64)>) -> String {
+        match content {
+            Some((hash, _)) if self.content_addressed => content_addressed_key(key, &hash),
+            _ => key.to_owned(),
+        }
+    }
+
+    /// Whether a file of `size` bytes is compressed.
+    fn compress(&self, size: u64) -> bool {
+        self.compress_min_size
+            .is_some_and(|compress_min_size| size > 0 && size >= compress_min_size)
+    }
+
+    /// Number of bytes after which a file is switched to a multipart upload.
+    ///
+    /// Whether a file is compressed is decided at this point, so the switch is delayed until the
+    /// file has reached the size from which files are compressed.
+    fn multipart_threshold(&self, part_size: usize) -> usize {
+        let threshold = match self.compress_min_size {
+            Some(compress_min_size) => part_size.max(compress_min_size as usize),
+            None => part_size,
+        };
+        threshold.min(MULTIPART_MAXIMUM_PART_SIZE)
+    }
+
+    /// The number of bytes to buffer before uploading a part, given the `threshold` that applies
+    /// otherwise.
+    fn buffer_limit(&self, threshold: usize) -> usize {
+        match self.max_buffer_per_file() {
+            Some(max_buffer_per_file) => threshold.min(max_buffer_per_file),
+            None => threshold,
+        }
+    }
+
+    /// The number of bytes buffered for a single file, in memory or spooled, after which they are
+    /// uploaded as a part, if limited.
+    fn max_buffer_per_file(&self) -> Option<usize> {
+        let max_file_size = self
+            .spool
+            .as_ref()
+            .and_then(|spool| spool.max_file_size())
+            .map(|max| max as usize);
+        match (self.max_memory_per_file, max_file_size) {
+            (Some(max_memory_per_file), Some(max_file_size)) => {
+                Some(max_memory_per_file.min(max_file_size))
+            }
+            (max_memory_per_file, max_file_size) => max_memory_per_file.or(max_file_size),
+        }
+    }
+}
+
+/// Whether S3 is reachable for a multipart upload.
+///
+/// If S3 becomes unreachable while a part is uploaded, the part is kept and the upload is paused,
+/// which blocks further writes to the file, until S3 is reachable again or the pause times out.
+#[derive(Debug, Default)]
+pub(crate) struct Connectivity {
+    /// Since when S3 has been unreachable, if it is.
+    lost_since: Option<Instant>,
+}
+
+impl Connectivity {
+    /// Whether to pause the upload after it failed with `error`, rather than failing it.
+    fn pause(&mut self, key: &str, error: &anyhow::Error, timeout: Option<Duration>) -> bool {
+        let timeout = match timeout {
+            Some(timeout) => timeout,
+            None => return false,
+        };
+        let is_unreachable = matches!(
+            error.downcast_ref::<RusotoError<UploadPartError>>(),
+            Some(RusotoError::HttpDispatch(_))
+        );
+        if !is_unreachable {
+            return false;
+        }
+        let lost_since = match self.lost_since {
+            Some(lost_since) => lost_since,
+            None => {
+                warn!("S3 is unreachable, pausing upload of '{}' until it is reachable again", key;
+                      "error" => %error);
+                *self.lost_since.insert(Instant::now())
+            }
+        };
+        if lost_since.elapsed() >= timeout {
+            error!(
+                "S3 has been unreachable for {:?}, giving up on '{}'",
+                timeout, key
+            );
+            return false;
+        }
+
+        true
+    }
+
+    fn restored(&mut self, key: &str) {
+        if let Some(lost_since) = self.lost_since.take() {
+            info!("S3 is reachable again, resuming upload of '{}'", key;
+                  "paused" => ?lost_since.elapsed());
+        }
+    }
+}
+
+/// Durations of the part uploads of a multipart upload, to spot single slow parts.
+#[derive(Debug, Default)]
+pub(crate) struct PartTimings {
+    count: u32,
+    total: Duration,
+    min: Option<Duration>,
+    max: Duration,
+}
+
+impl PartTimings {
+    fn record(&mut self, duration: Duration) {
+        self.count += 1;
+        self.total += duration;
+        self.min = Some(self.min.map_or(duration, |min| min.min(duration)));
+        self.max = self.max.max(duration);
+    }
+}
+
+impl fmt::Display for PartTimings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.min {
+            Some(min) => write!(
+                f,
+                "{} parts, min {:?}, max {:?}, avg {:?}",
+                self.count,
+                min,
+                self.max,
+                self.total / self.count
+            ),
+            None => write!(f, "no parts"),
+        }
+    }
+}
+
+/// The outcome of a finished upload.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Finished {
+    /// Nothing has been uploaded, e.g. because the file was empty, see
+    /// [`UploadOptions::skip_empty_files`].
+    Skipped,
+    /// The object has been written to S3.
+    Uploaded {
+        /// The key of the written object. Differs from the key of the upload in content-addressed
+        /// mode, see [`UploadOptions::content_addressed`].
+        key: String,
+        /// The version of the written object, if the bucket is versioned.
+        version_id: Option<String>,
+        /// The entity tag of the written object.
+        e_tag: Option<String>,
+        /// The SHA-256 digest of the content, if it has been hashed, see
+        /// [`UploadOptions::hash_all_content`].
+        sha256: Option<ContentHash>,
+    },
+}
+
+/// A failed write, see [`Upload::write`].
+pub(crate) struct Interrupted {
+    /// The upload in its state before the write, or `Failed` if it could not be restored.
+    pub(crate) upload: Box<Upload>,
+    pub(crate) error: anyhow::Error,
+}
+
+impl Interrupted {
+    /// Give up on the upload, aborting any multipart upload.
+    fn fail(self, runtime: &mut Runtime, s3: &dyn ObjectStore) -> Interrupted {
+        let error = match *self.upload {
+            Upload::Multipart {
+                bucket,
+                key,
+                multipart_upload_id,
+                ..
+            } => Upload::abort_after_error(
+                runtime,
+                s3,
+                &bucket,
+                &key,
+                &multipart_upload_id,
+                self.error,
+            ),
+            _ => self.error,
+        };
+        Interrupted {
+            upload: Box::new(Upload::Failed),
+            error,
+        }
+    }
+
+    /// Restore the buffered data to `checkpoint`, taken before the write, or give up on the upload
+    /// if that is not possible.
+    fn restore(
+        mut self,
+        runtime: &mut Runtime,
+        s3: &dyn ObjectStore,
+        checkpoint: Option<Checkpoint>,
+    ) -> Interrupted {
+        let checkpoint = match checkpoint {
+            Some(checkpoint) => checkpoint,
+            None => return self.fail(runtime, s3),
+        };
+        let restored = match &mut *self.upload {
+            Upload::Regular {
+                current_buffer,
+                hasher,
+                ..
+            }
+            | Upload::Multipart {
+                current_buffer,
+                hasher,
+                encoder: None,
+                ..
+            } => match current_buffer.truncate(checkpoint.buffered) {
+                Ok(()) => {
+                    *hasher = checkpoint.hasher;
+                    true
+                }
+                Err(error) => {
+                    warn!("Failed to restore the buffered data after a failed write";
+                          "error" => %error);
+                    false
+                }
+            },
+            _ => false,
+        };
+        if restored {
+            self
+        } else {
+            self.fail(runtime, s3)
+        }
+    }
+}
+
+impl fmt::Debug for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.error, f)
+    }
+}
+
+/// The buffered data of an upload before a write, to restore it if the write fails.
+struct Checkpoint {
+    buffered: usize,
+    hasher: Option<ContentHasher>,
+}
 
 #[derive(Default)]
 pub(crate) enum Upload {
@@ -43,218 +697,2334 @@ pub(crate) enum Upload {
     Regular {
         bucket: String,
         key: String,
-        current_buffer: Vec<u8>,
+        options: Arc<UploadOptions>,
+        current_buffer: Buffer,
+        hasher: Option<ContentHasher>,
+        /// The size the file was declared to have before any data was written.
+        size_hint: Option<u64>,
+        /// Size of the parts, should the file be uploaded with a multipart upload.
+        part_size: usize,
     },
     Multipart {
         bucket: String,
         key: String,
+        options: Arc<UploadOptions>,
         multipart_upload_id: String,
         multipart_part_number_generator: Arc<IdGenerator>,
-        current_buffer: Vec<u8>,
+        current_buffer: Buffer,
         parts: Vec<CompletedPart>,
+        hasher: Option<ContentHasher>,
+        part_timings: PartTimings,
+        part_size: usize,
+        connectivity: Connectivity,
+        /// If the file is compressed, the compressor, holding the compressed data that has not
+        /// been uploaded yet. `current_buffer` is unused in that case.
+        encoder: Option<Box<Encoder>>,
+    },
+    /// A previous write to this upload failed. Any multipart upload has already been aborted, and
+    /// any further writes or attempts to finish the upload will be rejected.
+    Failed,
+    /// The upload is only logged, see [`UploadOptions::dry_run`].
+    DryRun {
+        bucket: String,
+        key: String,
+        size: u64,
     },
 }
 
 impl Upload {
-    pub(crate) fn new(bucket: &str, key: &str) -> Self {
+    pub(crate) fn new(bucket: &str, key: &str, options: Arc<UploadOptions>) -> Self {
+        if options.dry_run {
+            return Upload::DryRun {
+                bucket: bucket.to_owned(),
+                key: key.to_owned(),
+                size: 0,
+            };
+        }
+        let hasher = if options.hash_content() {
+            Some(ContentHasher::default())
+        } else {
+            None
+        };
         Upload::Regular {
             bucket: bucket.to_owned(),
             key: key.to_owned(),
-            current_buffer: vec![],
+            options,
+            current_buffer: Buffer::default(),
+            hasher,
+            size_hint: None,
+            part_size: MULTIPART_MINIMUM_PART_SIZE,
+        }
+    }
+
+    /// Declare the final size of the file before any data has been written.
+    ///
+    /// If the file is small enough, it will be buffered completely and uploaded with a single
+    /// `PutObject`, rather than switching to a multipart upload once the first part is full.
+    /// Larger files are uploaded in evenly-sized parts, which are chosen as small as possible
+    /// while staying within the limit of 10,000 parts.
+    pub(crate) fn set_size_hint(&mut self, size: u64) {
+        if let Self::Regular {
+            key,
+            options,
+            current_buffer,
+            size_hint,
+            part_size,
+            ..
+        } = self
+        {
+            if !current_buffer.is_empty() {
+                return;
+            }
+            let fits_in_buffer = options
+                .max_buffer_per_file()
+                .is_none_or(|max| size <= max as u64);
+            if size <= SIZE_HINT_SINGLE_PUT_MAXIMUM_SIZE && fits_in_buffer {
+                debug!(
+                    "Expecting {} bytes for '{}', using a single upload",
+                    size, key
+                );
+                current_buffer.reserve_exact(size as usize, options.spool.as_ref());
+                *size_hint = Some(size);
+            } else {
+                *part_size = optimal_part_size(size);
+                debug!(
+                    "Expecting {} bytes for '{}', using parts of {} bytes",
+                    size, key, part_size
+                );
+            }
+        }
+    }
+
+    /// Number of bytes held in memory until they are uploaded, not counting spooled data.
+    pub(crate) fn buffered_len(&self) -> u64 {
+        match self {
+            Self::Regular { current_buffer, .. } => current_buffer.memory_len() as u64,
+            Self::Multipart {
+                current_buffer,
+                encoder,
+                ..
+            } => {
+                let encoded = encoder
+                    .as_ref()
+                    .map_or(0, |encoder| encoder.get_ref().len());
+                (current_buffer.memory_len() + encoded) as u64
+            }
+            Self::Empty | Self::Failed | Self::DryRun { .. } => 0,
+        }
+    }
+
+    /// Take the buffered content of the file, if it has been buffered completely and is at most
+    /// `max_size` bytes large, leaving the upload to be discarded.
+    ///
+    /// Empty files that are skipped anyway are never taken.
+    pub(crate) fn take_buffer(&mut self, max_size: u64) -> Option<Vec<u8>> {
+        match self {
+            Self::Regular {
+                current_buffer,
+                options,
+                ..
+            } if current_buffer.len() as u64 <= max_size
+                && !(current_buffer.is_empty() && options.skip_empty_files) =>
+            {
+                match current_buffer.take() {
+                    Ok(buffer) => {
+                        *self = Self::Empty;
+                        Some(buffer)
+                    }
+                    Err(error) => {
+                        warn!("Failed to read back the spooled data, not aggregating the file";
+                              "error" => %error);
+                        None
+                    }
+                }
+            }
+            _ => None,
         }
     }
 
     fn create_multipart_upload(
         runtime: &mut Runtime,
-        s3: &S3Client,
+        s3: &dyn ObjectStore,
         bucket: &str,
         key: &str,
+        options: &UploadOptions,
+        compressed: bool,
     ) -> Result<String> {
+        let metadata = options.metadata.resolve(key, Utc::now());
+        let content_type = options.content_types.infer(key);
         runtime
-            .block_on(s3.create_multipart_upload(CreateMultipartUploadRequest {
-                bucket: bucket.to_owned(),
-                key: key.to_owned(),
-                ..Default::default()
+            .block_on(options.retry.retry("CreateMultipartUpload", || {
+                let mut create_multipart_upload_request =
+                    options.create_multipart_upload_request(bucket.to_owned(), key.to_owned());
+                create_multipart_upload_request.metadata = metadata.clone();
+                create_multipart_upload_request.content_type = content_type.clone();
+                if compressed {
+                    create_multipart_upload_request.content_encoding =
+                        Some(options.compression.content_encoding().to_owned());
+                }
+                s3.create_multipart_upload(create_multipart_upload_request)
             }))?
             .upload_id
             .ok_or_else(|| anyhow!("upload id was unset after multipart upload was created"))
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn upload_part(
         runtime: &mut Runtime,
-        s3: &S3Client,
+        s3: &dyn ObjectStore,
+        options: &UploadOptions,
+        connectivity: &mut Connectivity,
         bucket: &str,
         key: &str,
         upload_id: &str,
         part_number: i64,
-        body: Vec<u8>,
-    ) -> Result<CompletedPart> {
-        let e_tag = runtime
-            .block_on(s3.upload_part(UploadPartRequest {
-                bucket: bucket.to_owned(),
-                key: key.to_owned(),
-                upload_id: upload_id.to_owned(),
-                body: Some(body.into()),
-                part_number,
-                ..Default::default()
-            }))?
+        body: &[u8],
+    ) -> Result<(CompletedPart, Duration)> {
+        let size = body.len();
+        let _in_flight = options.metrics.part_in_flight();
+        let start = Instant::now();
+        let mut probe_interval = options.retry.base_delay;
+        let upload_part_output = loop {
+            let result = runtime.block_on(options.retry.for_parts().retry("UploadPart", || {
+                s3.upload_part(UploadPartRequest {
+                    bucket: bucket.to_owned(),
+                    key: key.to_owned(),
+                    upload_id: upload_id.to_owned(),
+                    body: Some(body.to_vec().into()),
+                    part_number,
+                    sse_customer_algorithm: options.sse_customer_algorithm(),
+                    sse_customer_key: options.sse_customer_key(),
+                    sse_customer_key_md5: options.sse_customer_key_md5(),
+                    ..Default::default()
+                })
+            }));
+            match result {
+                Ok(upload_part_output) => break upload_part_output,
+                // Keep the part and block the writer until S3 is reachable again.
+                Err(error) if connectivity.pause(key, &error, options.network_pause_timeout) => {
+                    let _waiting = options.metrics.upload_waiting();
+                    runtime.block_on(async { tokio::time::sleep(probe_interval).await });
+                    probe_interval = (probe_interval * 2).min(NETWORK_PAUSE_MAXIMUM_PROBE_INTERVAL);
+                }
+                Err(error) => return Err(error),
+            }
+        };
+        connectivity.restored(key);
+        let e_tag = upload_part_output
             .e_tag
             .ok_or_else(|| anyhow!("uploaded multipart did not return e-tag"))?;
-        debug!("Uploaded multipart {} for '{}'", part_number, key);
+        let duration = start.elapsed();
+        debug!(
+            "Uploaded multipart {} for '{}' ({} bytes in {:?})",
+            part_number, key, size, duration
+        );
 
-        Ok(CompletedPart {
-            e_tag: Some(e_tag),
-            part_number: Some(part_number),
-        })
+        Ok((
+            CompletedPart {
+                e_tag: Some(e_tag),
+                part_number: Some(part_number),
+            },
+            duration,
+        ))
     }
 
-    pub(crate) fn write(self, runtime: &mut Runtime, s3: &S3Client, data: &[u8]) -> Result<Upload> {
-        Ok(match self {
-            Self::Regular {
+    fn abort_multipart_upload(
+        runtime: &mut Runtime,
+        s3: &dyn ObjectStore,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+    ) -> Result<()> {
+        runtime.block_on(s3.abort_multipart_upload(AbortMultipartUploadRequest {
+            bucket: bucket.to_owned(),
+            key: key.to_owned(),
+            upload_id: upload_id.to_owned(),
+            ..Default::default()
+        }))?;
+        debug!("Successfully aborted multipart upload for '{}'", key);
+
+        Ok(())
+    }
+
+    /// Copy an object that was uploaded earlier in this session with the same content as the upload
+    /// to `key`, if there is one. Returns whether the object was copied.
+    fn copy_duplicate(
+        runtime: &mut Runtime,
+        s3: &dyn ObjectStore,
+        bucket: &str,
+        key: &str,
+        options: &UploadOptions,
+        content: Option<(ContentHash, u64)>,
+    ) -> Result<Option<Finished>> {
+        let (index, hash, length) = match (&options.duplicate_content_index, content) {
+            (Some(index), Some((hash, length))) => (index, hash, length),
+            _ => return Ok(None),
+        };
+        if length > COPY_OBJECT_MAXIMUM_SIZE {
+            return Ok(None);
+        }
+        let source = index.get(&hash);
+        if source
+            .as_ref()
+            .is_some_and(|source| source.bucket == bucket && source.key == key)
+        {
+            // In content-addressed mode, identical content is written to the same key.
+            debug!(
+                "Not uploading '{}', the content has already been uploaded",
+                key
+            );
+            return Ok(Some(Finished::Uploaded {
+                key: key.to_owned(),
+                version_id: None,
+                e_tag: None,
+                sha256: Some(hash),
+            }));
+        }
+        // The object at `key` is about to be replaced, so its former content can't be copied
+        // from it anymore.
+        index.forget(bucket, key);
+        let source = match source {
+            Some(source) => source,
+            None => return Ok(None),
+        };
+
+        let mut copy_object_request = options.copy_object_request(
+            bucket.to_owned(),
+            key.to_owned(),
+            &source.bucket,
+            &source.key,
+        );
+        if let Some(version_id) = &source.version_id {
+            copy_object_request.copy_source = format!(
+                "{}?versionId={}",
+                copy_object_request.copy_source,
+                utf8_percent_encode(version_id, KEY_ENCODE_SET)
+            );
+        }
+        // Fails if the source has been replaced outside of this session.
+        copy_object_request.copy_source_if_match = source.e_tag.clone();
+        let copied = runtime.block_on(
+            options
+                .retry
+                .retry("CopyObject", || s3.copy_object(copy_object_request.clone())),
+        );
+        let copy_object_output = match copied {
+            Ok(copy_object_output) => copy_object_output,
+            Err(error) => {
+                warn!("Failed to copy '{}' from '{}', uploading it instead", key, source.key;
+                      "error" => format!("{:#}", error));
+                index.forget(&source.bucket, &source.key);
+                return Ok(None);
+            }
+        };
+        debug!(
+            "Copied '{}' from '{}' instead of uploading it, the content is identical",
+            key, source.key
+        );
+        Self::write_checksum_sidecar(runtime, s3, bucket, key, options, content)?;
+
+        Ok(Some(Finished::Uploaded {
+            key: key.to_owned(),
+            version_id: copy_object_output.version_id,
+            e_tag: copy_object_output
+                .copy_object_result
+                .and_then(|copy_object_result| copy_object_result.e_tag),
+            sha256: Some(hash),
+        }))
+    }
+
+    /// Move the object at `key` to `final_key` by copying and deleting it, since the final key of
+    /// a content-addressed multipart upload is only known once all of its content has been
+    /// uploaded. Returns the version and entity tag of the copy.
+    fn rename(
+        runtime: &mut Runtime,
+        s3: &dyn ObjectStore,
+        bucket: &str,
+        key: &str,
+        final_key: &str,
+        options: &UploadOptions,
+        content: Option<(ContentHash, u64)>,
+    ) -> Result<(Option<String>, Option<String>)> {
+        if content.is_some_and(|(_, length)| length > COPY_OBJECT_MAXIMUM_SIZE) {
+            return Err(anyhow!(
+                "'{}' is too large to be copied to its content-addressed key '{}'",
+                key,
+                final_key
+            ));
+        }
+        let copy_object_output = runtime.block_on(options.retry.retry("CopyObject", || {
+            s3.copy_object(options.copy_object_request(
+                bucket.to_owned(),
+                final_key.to_owned(),
                 bucket,
                 key,
-                mut current_buffer,
-            } => {
-                current_buffer.extend_from_slice(data);
-                if current_buffer.len() >= MULTIPART_MINIMUM_PART_SIZE {
-                    debug!(
-                        "Switching to multipart-upload for '{}', more than {} bytes written",
-                        key, MULTIPART_MINIMUM_PART_SIZE
-                    );
-                    let multipart_part_number_generator = Arc::new(IdGenerator::new(1));
-                    let multipart_upload_id: String =
-                        Self::create_multipart_upload(runtime, s3, &bucket, &key)?;
-                    let completed_part: CompletedPart = Self::upload_part(
-                        runtime,
-                        s3,
-                        &bucket,
-                        &key,
-                        &multipart_upload_id,
-                        multipart_part_number_generator.next() as i64,
-                        current_buffer,
-                    )?;
-                    Self::Multipart {
-                        bucket,
-                        key,
-                        multipart_upload_id,
-                        multipart_part_number_generator,
-                        current_buffer: vec![],
-                        parts: vec![completed_part],
-                    }
-                } else {
-                    Self::Regular {
-                        bucket,
-                        key,
-                        current_buffer,
+            ))
+        }))?;
+        runtime.block_on(options.retry.retry("DeleteObject", || {
+            s3.delete_object(DeleteObjectRequest {
+                bucket: bucket.to_owned(),
+                key: key.to_owned(),
+                ..Default::default()
+            })
+        }))?;
+        debug!(
+            "Moved '{}' to its content-addressed key '{}'",
+            key, final_key
+        );
+
+        Ok((
+            copy_object_output.version_id,
+            copy_object_output
+                .copy_object_result
+                .and_then(|copy_object_result| copy_object_result.e_tag),
+        ))
+    }
+
+    /// Log whether an object already exists at `key`, i.e. whether the upload would overwrite it.
+    fn check_collision(runtime: &mut Runtime, s3: &dyn ObjectStore, bucket: &str, key: &str) {
+        let result = runtime.block_on(s3.head_object(HeadObjectRequest {
+            bucket: bucket.to_owned(),
+            key: key.to_owned(),
+            ..Default::default()
+        }));
+        match result {
+            Ok(_) => warn!(
+                "Dry run: 's3://{}/{}' already exists and would be overwritten",
+                bucket, key
+            ),
+            // `HEAD` responses have no body, so a missing object is usually reported as a bare 404.
+            Err(RusotoError::Service(HeadObjectError::NoSuchKey(_))) => {
+                debug!("Dry run: 's3://{}/{}' does not exist yet", bucket, key)
+            }
+            Err(RusotoError::Unknown(response)) if response.status.as_u16() == 404 => {
+                debug!("Dry run: 's3://{}/{}' does not exist yet", bucket, key)
+            }
+            Err(error) => {
+                warn!("Dry run: failed to check whether 's3://{}/{}' exists", bucket, key;
+                                "error" => %error)
+            }
+        }
+    }
+
+    /// Write the checksum sidecar of the object at `key`, if enabled, handling a failure according
+    /// to [`ChecksumSidecar::on_failure`].
+    fn write_checksum_sidecar(
+        runtime: &mut Runtime,
+        s3: &dyn ObjectStore,
+        bucket: &str,
+        key: &str,
+        options: &UploadOptions,
+        content: Option<(ContentHash, u64)>,
+    ) -> Result<()> {
+        let (sidecar, hash) = match (&options.checksum_sidecar, content) {
+            (Some(sidecar), Some((hash, _))) => (sidecar, hash),
+            _ => return Ok(()),
+        };
+        let sidecar_key = sidecar.key(key);
+        let body = sidecar.body(key, &hash);
+        let result = runtime.block_on(options.retry.retry("PutObject", || {
+            let mut put_object_request =
+                options.put_object_request(bucket.to_owned(), sidecar_key.clone(), body.clone());
+            put_object_request.content_type = Some("text/plain".to_owned());
+            s3.put_object(put_object_request)
+        }));
+        let error = match result {
+            Ok(_) => {
+                debug!("Wrote checksum sidecar '{}'", sidecar_key);
+                return Ok(());
+            }
+            Err(error) => error.context(format!(
+                "failed to write checksum sidecar '{}'",
+                sidecar_key
+            )),
+        };
+
+        match sidecar.on_failure {
+            SidecarFailurePolicy::Warn => {
+                warn!("Uploaded '{}' without its checksum sidecar", key;
+                      "error" => format!("{:#}", error));
+                Ok(())
+            }
+            SidecarFailurePolicy::Fail => Err(error),
+            SidecarFailurePolicy::Delete => {
+                let delete_result = runtime.block_on(options.retry.retry("DeleteObject", || {
+                    s3.delete_object(DeleteObjectRequest {
+                        bucket: bucket.to_owned(),
+                        key: key.to_owned(),
+                        ..Default::default()
+                    })
+                }));
+                match delete_result {
+                    Ok(_) => info!(
+                        "Deleted '{}', since its checksum sidecar could not be written",
+                        key
+                    ),
+                    Err(delete_error) => {
+                        error!("Failed to delete '{}' without checksum sidecar", key;
+                                                "error" => %delete_error)
                     }
                 }
+                Err(error)
             }
-            Self::Multipart {
-                bucket,
-                key,
-                multipart_upload_id,
-                multipart_part_number_generator,
-                mut current_buffer,
-                mut parts,
+        }
+    }
+
+    /// Complete a multipart upload, retrying it with the policy for completions.
+    ///
+    /// Completing is idempotent, but if the response to an attempt that succeeded got lost, S3 can
+    /// reject the retries with `NoSuchUpload`, since the upload doesn't exist anymore. In that case,
+    /// the upload is considered complete if the object consists of as many parts as were uploaded.
+    fn complete_multipart_upload(
+        runtime: &mut Runtime,
+        s3: &dyn ObjectStore,
+        options: &UploadOptions,
+        bucket: &str,
+        key: &str,
+        multipart_upload_id: &str,
+        multipart_upload: CompletedMultipartUpload,
+    ) -> Result<CompleteMultipartUploadOutput> {
+        let mut attempts = 0;
+        let result = runtime.block_on(options.retry.for_completion().retry(
+            "CompleteMultipartUpload",
+            || {
+                attempts += 1;
+                s3.complete_multipart_upload(CompleteMultipartUploadRequest {
+                    bucket: bucket.to_owned(),
+                    key: key.to_owned(),
+                    upload_id: multipart_upload_id.to_owned(),
+                    multipart_upload: Some(multipart_upload.clone()),
+                    ..Default::default()
+                })
+            },
+        ));
+        let error = match result {
+            Err(error) if attempts > 1 && errno::is_no_such_upload(&error) => error,
+            result => return result,
+        };
+
+        let parts = multipart_upload.parts.as_ref().map_or(0, Vec::len) as i64;
+        let head_object_output = runtime
+            .block_on(s3.head_object(HeadObjectRequest {
+                bucket: bucket.to_owned(),
+                key: key.to_owned(),
+                part_number: Some(1),
+                sse_customer_algorithm: options.sse_customer_algorithm(),
+                sse_customer_key: options.sse_customer_key(),
+                sse_customer_key_md5: options.sse_customer_key_md5(),
+                ..Default::default()
+            }))
+            .ok();
+        match head_object_output {
+            Some(head_object_output) if head_object_output.parts_count == Some(parts) => {
+                info!("Multipart upload of '{}' was completed by an earlier attempt", key;
+                      "attempts" => attempts);
+                Ok(CompleteMultipartUploadOutput {
+                    e_tag: head_object_output.e_tag,
+                    version_id: head_object_output.version_id,
+                    ..Default::default()
+                })
+            }
+            _ => Err(error),
+        }
+    }
+
+    /// Abort the multipart upload after `error` occurred, such that no stale multipart upload is
+    /// left behind in the bucket. The original error is always returned.
+    fn abort_after_error(
+        runtime: &mut Runtime,
+        s3: &dyn ObjectStore,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        error: anyhow::Error,
+    ) -> anyhow::Error {
+        if let Some(message) = errno::kms_access_denied(&error) {
+            error!("Access to the KMS key was denied while uploading '{}', check that the key is \
+                    enabled and its key policy and grants permit the uploader to use it", key;
+                   "message" => message);
+        }
+        if let Err(abort_error) = Self::abort_multipart_upload(runtime, s3, bucket, key, upload_id)
+        {
+            error!("Failed to abort multipart upload for '{}' after error", key;
+                   "error" => %abort_error);
+        }
+        error
+    }
+
+    /// Write `data` to the upload, uploading a part once one is full.
+    ///
+    /// If the write fails, the upload is returned along with the error: restored to its state
+    /// before the write with [`InterruptedWrites::Restore`], or `Failed` otherwise.
+    pub(crate) fn write(
+        mut self,
+        runtime: &mut Runtime,
+        s3: &dyn ObjectStore,
+        data: &[u8],
+    ) -> Result<Upload, Interrupted> {
+        let checkpoint = self.checkpoint();
+        let written = match &mut self {
+            Self::Regular {
+                options,
+                current_buffer,
+                hasher,
+                ..
+            } => current_buffer
+                .extend_from_slice(data, options.spool.as_ref())
+                .map(|()| {
+                    if let Some(hasher) = hasher {
+                        hasher.update(data);
+                    }
+                }),
+            Self::Multipart {
+                options,
+                current_buffer,
+                hasher,
+                encoder,
+                ..
             } => {
-                current_buffer.extend_from_slice(data);
-                if current_buffer.len() >= MULTIPART_MINIMUM_PART_SIZE {
-                    let completed_part: CompletedPart = Self::upload_part(
+                let written = match encoder {
+                    Some(encoder) => encoder.write_all(data).map_err(anyhow::Error::from),
+                    None => current_buffer.extend_from_slice(data, options.spool.as_ref()),
+                };
+                if let (Ok(()), Some(hasher)) = (&written, hasher) {
+                    hasher.update(data);
+                }
+                written
+            }
+            Self::Failed => Err(anyhow!(
+                "Upload has failed previously, refusing to accept further writes"
+            )),
+            Self::DryRun { size, .. } => {
+                *size += data.len() as u64;
+                Ok(())
+            }
+            Self::Empty => Ok(()),
+        };
+        // While uploads aren't allowed, the data is held back until the file is finished.
+        let held_back = self.held_back();
+        let started = match written {
+            Ok(()) if !held_back && self.should_start_multipart(false) => {
+                self.start_multipart(runtime, s3)
+            }
+            Ok(()) => Ok(self),
+            Err(error) => Err(Interrupted {
+                upload: Box::new(self),
+                error,
+            }),
+        };
+        let uploaded = match started {
+            Ok(upload) if held_back => Ok(upload),
+            Ok(upload) => upload.upload_pending_part(runtime, s3, None),
+            Err(interrupted) => Err(interrupted),
+        };
+
+        uploaded.map_err(|interrupted| interrupted.restore(runtime, s3, checkpoint))
+    }
+
+    /// The buffered data before a write, if the upload is to be restored should the write fail.
+    fn checkpoint(&self) -> Option<Checkpoint> {
+        match self {
+            Self::Regular {
+                options,
+                current_buffer,
+                hasher,
+                ..
+            }
+            | Self::Multipart {
+                options,
+                current_buffer,
+                hasher,
+                encoder: None,
+                ..
+            } if options.interrupted_writes == InterruptedWrites::Restore => Some(Checkpoint {
+                buffered: current_buffer.len(),
+                hasher: hasher.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Upload the data buffered so far as a part, if S3 allows it, rather than holding it until
+    /// the part is full.
+    ///
+    /// Only the last part of a multipart upload may be smaller than 5 MiB, so less data than that
+    /// is held, since the file might still grow.
+    ///
+    /// If the flush fails, the upload is returned as `Failed` along with the error.
+    pub(crate) fn flush(
+        mut self,
+        runtime: &mut Runtime,
+        s3: &dyn ObjectStore,
+    ) -> Result<Upload, Interrupted> {
+        if self.held_back() {
+            return Ok(self);
+        }
+        if self.should_start_multipart(true) {
+            self = self
+                .start_multipart(runtime, s3)
+                .map_err(|interrupted| interrupted.fail(runtime, s3))?;
+        }
+
+        self.upload_pending_part(runtime, s3, Some(MULTIPART_MINIMUM_PART_SIZE))
+            .map_err(|interrupted| interrupted.fail(runtime, s3))
+    }
+
+    /// Whether the upload of buffered data is held back, since uploads are paused or it is outside
+    /// the upload window, see [`UploadOptions::pause`] and [`UploadOptions::upload_window`].
+    fn held_back(&self) -> bool {
+        match self {
+            Self::Regular { options, .. } | Self::Multipart { options, .. } => {
+                !options.uploads_allowed()
+            }
+            _ => false,
+        }
+    }
+
+    /// Upload the data that was held back while uploads weren't allowed in parts, rather than
+    /// uploading all of it at once when the upload is finished.
+    fn upload_held_back(self, runtime: &mut Runtime, s3: &dyn ObjectStore) -> Result<Upload> {
+        let started = if self.should_start_multipart(false) {
+            self.start_multipart(runtime, s3)
+        } else {
+            Ok(self)
+        };
+        started
+            .and_then(|upload| upload.upload_pending_part(runtime, s3, None))
+            .map_err(|interrupted| interrupted.fail(runtime, s3).error)
+    }
+
+    /// Whether a regular upload has buffered enough data to switch to a multipart upload, either
+    /// because a part is full or, when `flushing`, because a part may be uploaded.
+    fn should_start_multipart(&self, flushing: bool) -> bool {
+        match self {
+            Self::Regular {
+                options,
+                current_buffer,
+                size_hint,
+                part_size,
+                ..
+            } => {
+                if flushing {
+                    current_buffer.len() >= options.multipart_threshold(MULTIPART_MINIMUM_PART_SIZE)
+                } else {
+                    let within_size_hint =
+                        size_hint.is_some_and(|size_hint| current_buffer.len() as u64 <= size_hint);
+                    let over_buffer_limit = options
+                        .max_buffer_per_file()
+                        .is_some_and(|max| current_buffer.len() >= max);
+                    (current_buffer.len() >= options.multipart_threshold(*part_size)
+                        && !within_size_hint)
+                        || over_buffer_limit
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// Switch a regular upload to a multipart upload. If the multipart upload can't be created,
+    /// the regular upload is returned unchanged along with the error.
+    fn start_multipart(
+        self,
+        runtime: &mut Runtime,
+        s3: &dyn ObjectStore,
+    ) -> Result<Upload, Interrupted> {
+        let (bucket, key, options, current_buffer) = match &self {
+            Self::Regular {
+                bucket,
+                key,
+                options,
+                current_buffer,
+                ..
+            } => (bucket, key, options, current_buffer),
+            _ => return Ok(self),
+        };
+        debug!(
+            "Switching to multipart-upload for '{}', more than {} bytes written",
+            key,
+            current_buffer.len()
+        );
+        let compress = options.compress(current_buffer.len() as u64);
+        options.wait_until_uploads_allowed(runtime, key);
+        let multipart_upload_id =
+            match Self::create_multipart_upload(runtime, s3, bucket, key, options, compress) {
+                Ok(multipart_upload_id) => multipart_upload_id,
+                Err(error) => {
+                    return Err(Interrupted {
+                        upload: Box::new(self),
+                        error,
+                    })
+                }
+            };
+        let (bucket, key, options, mut current_buffer, hasher, part_size) = match self {
+            Self::Regular {
+                bucket,
+                key,
+                options,
+                current_buffer,
+                hasher,
+                part_size,
+                ..
+            } => (bucket, key, options, current_buffer, hasher, part_size),
+            _ => unreachable!("the upload was checked to be a regular upload"),
+        };
+        let mut encoder = if compress {
+            match options.compression.encoder() {
+                Ok(encoder) => Some(Box::new(encoder)),
+                Err(error) => {
+                    return Err(Interrupted {
+                        upload: Box::new(Upload::Failed),
+                        error: Self::abort_after_error(
+                            runtime,
+                            s3,
+                            &bucket,
+                            &key,
+                            &multipart_upload_id,
+                            error.into(),
+                        ),
+                    })
+                }
+            }
+        } else {
+            None
+        };
+        if let Some(encoder) = &mut encoder {
+            let written = current_buffer
+                .take()
+                .and_then(|buffered| Ok(encoder.write_all(&buffered)?));
+            if let Err(error) = written {
+                return Err(Interrupted {
+                    upload: Box::new(Upload::Failed),
+                    error: Self::abort_after_error(
                         runtime,
                         s3,
                         &bucket,
                         &key,
                         &multipart_upload_id,
-                        multipart_part_number_generator.next() as i64,
-                        current_buffer,
-                    )?;
-                    parts.push(completed_part);
-                    current_buffer = vec![];
+                        error,
+                    ),
+                });
+            }
+        }
+
+        Ok(Self::Multipart {
+            bucket,
+            key,
+            options,
+            multipart_upload_id,
+            multipart_part_number_generator: Arc::new(IdGenerator::new(1)),
+            current_buffer,
+            parts: vec![],
+            hasher,
+            part_timings: PartTimings::default(),
+            part_size,
+            connectivity: Connectivity::default(),
+            encoder,
+        })
+    }
+
+    /// Upload the pending data of a multipart upload as the next part, once at least `threshold`
+    /// bytes are pending, or the part size if `None`.
+    ///
+    /// If the part can't be uploaded, its data is put back and the upload is returned along with
+    /// the error, such that the caller can decide whether to abort it.
+    fn upload_pending_part(
+        self,
+        runtime: &mut Runtime,
+        s3: &dyn ObjectStore,
+        threshold: Option<usize>,
+    ) -> Result<Upload, Interrupted> {
+        match self {
+            Self::Multipart {
+                bucket,
+                key,
+                options,
+                multipart_upload_id,
+                multipart_part_number_generator,
+                mut current_buffer,
+                mut parts,
+                hasher,
+                mut part_timings,
+                part_size,
+                mut connectivity,
+                mut encoder,
+            } => {
+                let threshold = options.buffer_limit(threshold.unwrap_or(part_size));
+                let mut error = None;
+                let mut lost_part = false;
+                // A single large write may have buffered more than a part can hold.
+                loop {
+                    let part =
+                        match take_part(&mut current_buffer, &mut encoder, threshold, part_size) {
+                            Ok(Some(part)) => part,
+                            Ok(None) => break,
+                            Err(part_error) => {
+                                error = Some(part_error);
+                                break;
+                            }
+                        };
+                    options.wait_until_uploads_allowed(runtime, &key);
+                    let uploaded = next_part_number(&multipart_part_number_generator, &parts)
+                        .and_then(|part_number| {
+                            Self::upload_part(
+                                runtime,
+                                s3,
+                                &options,
+                                &mut connectivity,
+                                &bucket,
+                                &key,
+                                &multipart_upload_id,
+                                part_number,
+                                &part,
+                            )
+                        });
+                    match uploaded {
+                        Ok((completed_part, duration)) => {
+                            part_timings.record(duration);
+                            parts.push(completed_part);
+                        }
+                        Err(part_error) => {
+                            let put_back = match &mut encoder {
+                                Some(encoder) => {
+                                    let buffer = encoder.get_mut();
+                                    let mut pending = part;
+                                    pending.append(buffer);
+                                    *buffer = pending;
+                                    Ok(())
+                                }
+                                None => current_buffer.unsplit(part),
+                            };
+                            if let Err(put_back_error) = put_back {
+                                warn!("Failed to put back the data of a part that could not be \
+                                       uploaded";
+                                      "error" => %put_back_error);
+                                lost_part = true;
+                            }
+                            error = Some(part_error);
+                            break;
+                        }
+                    }
                 }
-                Self::Multipart {
+
+                let upload = Self::Multipart {
                     bucket,
                     key,
+                    options,
                     multipart_upload_id,
                     multipart_part_number_generator,
                     current_buffer,
                     parts,
+                    hasher,
+                    part_timings,
+                    part_size,
+                    connectivity,
+                    encoder,
+                };
+                match error {
+                    // Without the data of the part, the upload can't be continued.
+                    Some(error) if lost_part => Err(Interrupted {
+                        upload: Box::new(upload),
+                        error,
+                    }
+                    .fail(runtime, s3)),
+                    Some(error) => Err(Interrupted {
+                        upload: Box::new(upload),
+                        error,
+                    }),
+                    None => Ok(upload),
                 }
             }
-            any => any,
-        })
+            upload => Ok(upload),
+        }
     }
 
-    pub(crate) fn finish(self, runtime: &mut Runtime, s3: &S3Client) -> Result<()> {
-        match self {
+    /// Finish the upload, returning whether an object was created.
+    pub(crate) fn finish(self, runtime: &mut Runtime, s3: &dyn ObjectStore) -> Result<Finished> {
+        let (key, version_id, e_tag, content) = match self.upload_held_back(runtime, s3)? {
             Self::Empty => return Err(anyhow!("Upload is in invalid state, cannot finish")),
+            Self::Failed => return Err(anyhow!("Upload has failed previously, cannot finish")),
+            Self::DryRun { bucket, key, size } => {
+                Self::check_collision(runtime, s3, &bucket, &key);
+                info!(
+                    "Dry run: would upload {} bytes to 's3://{}/{}'",
+                    size, bucket, key
+                );
+                return Ok(Finished::Skipped);
+            }
             Self::Regular {
                 bucket,
                 key,
-                current_buffer,
+                options,
+                mut current_buffer,
+                hasher,
+                size_hint,
+                ..
             } => {
-                runtime.block_on(s3.put_object(PutObjectRequest {
-                    bucket,
-                    key: key.clone(),
-                    body: Some(current_buffer.into()),
-                    ..Default::default()
-                }))?;
+                if current_buffer.is_empty() && options.skip_empty_files {
+                    debug!("Not uploading '{}', since it is empty", key);
+                    return Ok(Finished::Skipped);
+                }
+                options.wait_until_uploads_allowed(runtime, &key);
+                let content = hasher.map(ContentHasher::finalize);
+                // Inferred before the key is replaced in content-addressed mode.
+                let content_type = options.content_types.infer(&key);
+                let key = options.final_key(&key, content);
+                if let Some(finished) =
+                    Self::copy_duplicate(runtime, s3, &bucket, &key, &options, content)?
+                {
+                    return Ok(finished);
+                }
+                let current_buffer = current_buffer.take()?;
+                let compressed = options.compress(current_buffer.len() as u64);
+                let body = if compressed {
+                    options.compression.compress(&current_buffer)?
+                } else {
+                    current_buffer
+                };
+                let content_length = body.len() as i64;
+                let metadata = options.metadata.resolve(&key, Utc::now());
+                let put_object_output =
+                    runtime.block_on(options.retry.retry("PutObject", || {
+                        let mut put_object_request =
+                            options.put_object_request(bucket.clone(), key.clone(), body.clone());
+                        if size_hint.is_some() {
+                            put_object_request.content_length = Some(content_length);
+                        }
+                        if compressed {
+                            put_object_request.content_encoding =
+                                Some(options.compression.content_encoding().to_owned());
+                        }
+                        put_object_request.metadata = metadata.clone();
+                        put_object_request.content_type = content_type.clone();
+                        s3.put_object(put_object_request)
+                    }))?;
                 debug!("Finished regular upload for '{}'", key);
+                Self::write_checksum_sidecar(runtime, s3, &bucket, &key, &options, content)?;
+                if let (Some(index), Some((hash, _))) = (&options.duplicate_content_index, content)
+                {
+                    index.insert(
+                        hash,
+                        IndexedObject {
+                            bucket: bucket.clone(),
+                            key: key.clone(),
+                            e_tag: put_object_output.e_tag.clone(),
+                            version_id: put_object_output.version_id.clone(),
+                        },
+                    );
+                }
+                (
+                    key,
+                    put_object_output.version_id,
+                    put_object_output.e_tag,
+                    content,
+                )
             }
             Self::Multipart {
                 bucket,
                 key,
+                options,
                 multipart_upload_id,
                 multipart_part_number_generator,
-                current_buffer,
+                mut current_buffer,
                 mut parts,
+                hasher,
+                mut part_timings,
+                mut connectivity,
+                encoder,
+                ..
             } => {
-                if !current_buffer.is_empty() {
-                    let completed_part: CompletedPart = Self::upload_part(
-                        runtime,
-                        s3,
-                        &bucket,
-                        &key,
-                        &multipart_upload_id,
-                        multipart_part_number_generator.next() as i64,
-                        current_buffer,
-                    )?;
+                options.wait_until_uploads_allowed(runtime, &key);
+                let content = hasher.map(ContentHasher::finalize);
+                let final_key = options.final_key(&key, content);
+                match Self::copy_duplicate(runtime, s3, &bucket, &final_key, &options, content) {
+                    Ok(Some(finished)) => {
+                        // The parts uploaded so far are not needed anymore.
+                        Self::abort_multipart_upload(
+                            runtime,
+                            s3,
+                            &bucket,
+                            &key,
+                            &multipart_upload_id,
+                        )?;
+                        return Ok(finished);
+                    }
+                    Ok(None) => {}
+                    Err(error) => {
+                        return Err(Self::abort_after_error(
+                            runtime,
+                            s3,
+                            &bucket,
+                            &key,
+                            &multipart_upload_id,
+                            error,
+                        ))
+                    }
+                }
+                let last_part = match encoder {
+                    Some(encoder) => encoder.finish().map_err(|error| {
+                        Self::abort_after_error(
+                            runtime,
+                            s3,
+                            &bucket,
+                            &key,
+                            &multipart_upload_id,
+                            error.into(),
+                        )
+                    })?,
+                    None => current_buffer.take().map_err(|error| {
+                        Self::abort_after_error(
+                            runtime,
+                            s3,
+                            &bucket,
+                            &key,
+                            &multipart_upload_id,
+                            error,
+                        )
+                    })?,
+                };
+                // The encoder may emit more than a part can hold when it is finished.
+                for last_part in last_part.chunks(MULTIPART_MAXIMUM_PART_SIZE) {
+                    let (completed_part, duration) =
+                        next_part_number(&multipart_part_number_generator, &parts)
+                            .and_then(|part_number| {
+                                Self::upload_part(
+                                    runtime,
+                                    s3,
+                                    &options,
+                                    &mut connectivity,
+                                    &bucket,
+                                    &key,
+                                    &multipart_upload_id,
+                                    part_number,
+                                    last_part,
+                                )
+                            })
+                            .map_err(|error| {
+                                Self::abort_after_error(
+                                    runtime,
+                                    s3,
+                                    &bucket,
+                                    &key,
+                                    &multipart_upload_id,
+                                    error,
+                                )
+                            })?;
+                    part_timings.record(duration);
                     parts.push(completed_part);
                 }
-                runtime.block_on(
-                    s3.complete_multipart_upload(CompleteMultipartUploadRequest {
-                        bucket,
-                        key: key.clone(),
-                        upload_id: multipart_upload_id,
-                        multipart_upload: Some(CompletedMultipartUpload { parts: Some(parts) }),
-                        ..Default::default()
-                    }),
-                )?;
-                debug!("Finished multipart upload for '{}'", key);
+                let complete_multipart_upload_output = completed_multipart_upload(parts)
+                    .and_then(|completed| {
+                        Self::complete_multipart_upload(
+                            runtime,
+                            s3,
+                            &options,
+                            &bucket,
+                            &key,
+                            &multipart_upload_id,
+                            completed,
+                        )
+                    })
+                    .map_err(|error| {
+                        Self::abort_after_error(
+                            runtime,
+                            s3,
+                            &bucket,
+                            &key,
+                            &multipart_upload_id,
+                            error,
+                        )
+                    })?;
+                debug!("Finished multipart upload for '{}' ({})", key, part_timings);
+                let (version_id, e_tag) = if final_key == key {
+                    (
+                        complete_multipart_upload_output.version_id,
+                        complete_multipart_upload_output.e_tag,
+                    )
+                } else {
+                    Self::rename(runtime, s3, &bucket, &key, &final_key, &options, content)?
+                };
+                Self::write_checksum_sidecar(runtime, s3, &bucket, &final_key, &options, content)?;
+                if let (Some(index), Some((hash, _))) = (&options.duplicate_content_index, content)
+                {
+                    index.insert(
+                        hash,
+                        IndexedObject {
+                            bucket: bucket.clone(),
+                            key: final_key.clone(),
+                            e_tag: e_tag.clone(),
+                            version_id: version_id.clone(),
+                        },
+                    );
+                }
+                (final_key, version_id, e_tag, content)
             }
-        }
+        };
 
-        Ok(())
+        Ok(Finished::Uploaded {
+            key,
+            version_id,
+            e_tag,
+            sha256: content.map(|(hash, _)| hash),
+        })
     }
 
-    pub(crate) fn destroy(self, runtime: &mut Runtime, s3: &S3Client) -> Result<()> {
+    pub(crate) fn destroy(self, runtime: &mut Runtime, s3: &dyn ObjectStore) -> Result<()> {
         match self {
             Self::Empty => {}
             Self::Regular { .. } => {}
+            Self::Failed => {}
+            Self::DryRun { .. } => {}
             Self::Multipart {
                 bucket,
                 key,
                 multipart_upload_id,
                 ..
             } => {
-                runtime.block_on(s3.abort_multipart_upload(AbortMultipartUploadRequest {
-                    bucket,
-                    key: key.clone(),
-                    upload_id: multipart_upload_id,
-                    ..Default::default()
-                }))?;
-                debug!("Successfully aborted multipart upload for '{}'", key);
+                Self::abort_multipart_upload(runtime, s3, &bucket, &key, &multipart_upload_id)?;
             }
         }
         Ok(())
     }
 }
+
+/// The key of an object with the given content hash: the file name of `key` is replaced by the
+/// hexadecimal hash.
+fn content_addressed_key(key: &str, hash: &ContentHash) -> String {
+    let hash = to_hex(hash);
+    match key.rsplit_once('/') {
+        Some((directory, _)) => format!("{}/{}", directory, hash),
+        None => hash,
+    }
+}
+
+/// Take the next part to upload, once at least `threshold` bytes have been written, from
+/// `current_buffer` or, if the file is compressed, from the output of `encoder`.
+///
+/// Parts taken from a spool file hold at most `part_size` bytes, or `threshold` if that is larger,
+/// such that data held back outside the upload window isn't read into memory all at once.
+fn take_part(
+    current_buffer: &mut Buffer,
+    encoder: &mut Option<Box<Encoder>>,
+    threshold: usize,
+    part_size: usize,
+) -> Result<Option<Vec<u8>>> {
+    match (encoder, &current_buffer) {
+        (Some(encoder), _) => Ok(split_part(
+            encoder.get_mut(),
+            threshold,
+            MULTIPART_MAXIMUM_PART_SIZE,
+        )),
+        (None, Buffer::Spooled(_)) => {
+            current_buffer.split_part(threshold, threshold.max(part_size))
+        }
+        (None, Buffer::Memory(_)) => {
+            current_buffer.split_part(threshold, MULTIPART_MAXIMUM_PART_SIZE)
+        }
+    }
+}
+
+/// Take the front of `buffer`, up to `max_size` bytes, once it holds at least `threshold` bytes.
+pub(crate) fn split_part(
+    buffer: &mut Vec<u8>,
+    threshold: usize,
+    max_size: usize,
+) -> Option<Vec<u8>> {
+    if buffer.len() < threshold {
+        None
+    } else if buffer.len() <= max_size {
+        Some(std::mem::take(buffer))
+    } else {
+        let rest = buffer.split_off(max_size);
+        Some(std::mem::replace(buffer, rest))
+    }
+}
+
+/// The smallest part size, rounded up to whole MiB, with which `size` bytes fit into the maximum
+/// number of parts.
+fn optimal_part_size(size: u64) -> usize {
+    const MIB: u64 = 1024 * 1024;
+    let part_size = size.div_ceil(MULTIPART_MAXIMUM_PARTS).div_ceil(MIB) * MIB;
+    (part_size as usize).clamp(MULTIPART_MINIMUM_PART_SIZE, MULTIPART_MAXIMUM_PART_SIZE)
+}
+
+/// The number of the next part of a multipart upload, given the `parts` uploaded so far.
+///
+/// Part numbers have to be strictly increasing: should a number ever be generated that is not
+/// greater than that of an uploaded part, S3 would silently replace that part, corrupting the
+/// object. This is refused instead.
+fn next_part_number(generator: &IdGenerator, parts: &[CompletedPart]) -> Result<i64> {
+    let part_number = generator.next() as i64;
+    match parts.iter().filter_map(|part| part.part_number).max() {
+        Some(uploaded) if uploaded >= part_number => Err(anyhow!(
+            "generated part number {} after part {} was already uploaded",
+            part_number,
+            uploaded
+        )),
+        _ => Ok(part_number),
+    }
+}
+
+/// Assemble the completed parts for `CompleteMultipartUpload`.
+///
+/// S3 requires the parts to be listed in ascending order of their part number, which is not
+/// necessarily the order in which their uploads completed. Parts with the same number are
+/// refused, since only one of them would end up in the object.
+fn completed_multipart_upload(mut parts: Vec<CompletedPart>) -> Result<CompletedMultipartUpload> {
+    parts.sort_by_key(|part| part.part_number);
+    if let Some(duplicate) = parts
+        .windows(2)
+        .find(|pair| pair[0].part_number == pair[1].part_number)
+    {
+        return Err(anyhow!(
+            "part {} was uploaded more than once",
+            duplicate[0].part_number.unwrap_or_default()
+        ));
+    }
+    Ok(CompletedMultipartUpload { parts: Some(parts) })
+}
+
+#[test]
+fn completed_parts_are_ordered_by_part_number() {
+    let part = |part_number: i64| CompletedPart {
+        e_tag: Some(format!("\"etag-{}\"", part_number)),
+        part_number: Some(part_number),
+    };
+
+    let completed = completed_multipart_upload(vec![part(3), part(1), part(4), part(2)]).unwrap();
+
+    assert_eq!(
+        Some(vec![part(1), part(2), part(3), part(4)]),
+        completed.parts
+    );
+}
+
+#[test]
+fn duplicate_part_numbers_are_refused() {
+    let part = |part_number: i64| CompletedPart {
+        e_tag: Some(format!("\"etag-{}\"", part_number)),
+        part_number: Some(part_number),
+    };
+
+    assert!(completed_multipart_upload(vec![part(1), part(2), part(1)]).is_err());
+
+    let generator = IdGenerator::new(1);
+    assert_eq!(1, next_part_number(&generator, &[]).unwrap());
+    assert_eq!(2, next_part_number(&generator, &[part(1)]).unwrap());
+    // A generator that is reused, e.g. because it was shared by mistake, starts over.
+    let generator = IdGenerator::new(1);
+    assert!(next_part_number(&generator, &[part(1), part(2)]).is_err());
+}
+
+#[test]
+fn size_hint_avoids_multipart_upload() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let size = 2 * MULTIPART_MINIMUM_PART_SIZE;
+
+    let mut upload = Upload::new("my-bucket", "my-file", Default::default());
+    upload.set_size_hint(size as u64);
+    upload
+        .write(&mut runtime, &s3, &vec![0; MULTIPART_MINIMUM_PART_SIZE])
+        .unwrap()
+        .write(&mut runtime, &s3, &vec![0; MULTIPART_MINIMUM_PART_SIZE])
+        .unwrap()
+        .finish(&mut runtime, &s3)
+        .unwrap();
+
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::PutObject(put_object, body)] => {
+            assert_eq!(put_object.content_length, Some(size as i64));
+            assert_eq!(body.len(), size);
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn optimal_part_size_stays_within_part_limit() {
+    const MIB: u64 = 1024 * 1024;
+
+    assert_eq!(optimal_part_size(100 * MIB), MULTIPART_MINIMUM_PART_SIZE);
+    assert_eq!(optimal_part_size(50_000 * MIB), 5 * MIB as usize);
+    assert_eq!(optimal_part_size(50_000 * MIB + 1), 6 * MIB as usize);
+    assert_eq!(optimal_part_size(100 * 1024 * MIB), 11 * MIB as usize);
+    assert_eq!(
+        optimal_part_size(100 * 1024 * 1024 * MIB),
+        MULTIPART_MAXIMUM_PART_SIZE
+    );
+}
+
+#[test]
+fn parts_never_exceed_the_maximum_part_size() {
+    let mut buffer = vec![1, 2, 3, 4, 5];
+    assert_eq!(None, split_part(&mut buffer, 6, 2));
+    assert_eq!(Some(vec![1, 2]), split_part(&mut buffer, 2, 2));
+    assert_eq!(Some(vec![3, 4]), split_part(&mut buffer, 2, 2));
+    assert_eq!(None, split_part(&mut buffer, 2, 2));
+    assert_eq!(vec![5], buffer);
+    assert_eq!(Some(vec![5]), split_part(&mut buffer, 1, 2));
+    assert!(buffer.is_empty());
+
+    let options = UploadOptions {
+        compress_min_size: Some(2 * MULTIPART_MAXIMUM_PART_SIZE as u64),
+        ..Default::default()
+    };
+    assert_eq!(
+        MULTIPART_MAXIMUM_PART_SIZE,
+        options.multipart_threshold(MULTIPART_MINIMUM_PART_SIZE)
+    );
+    let options = UploadOptions {
+        compress_min_size: Some(MULTIPART_MAXIMUM_PART_SIZE as u64 - 1),
+        ..Default::default()
+    };
+    assert_eq!(
+        MULTIPART_MAXIMUM_PART_SIZE - 1,
+        options.multipart_threshold(MULTIPART_MINIMUM_PART_SIZE)
+    );
+}
+
+#[test]
+fn size_hint_sets_part_size() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let part_size = 6 * 1024 * 1024;
+
+    let mut upload = Upload::new("my-bucket", "my-file", Default::default());
+    upload.set_size_hint(MULTIPART_MAXIMUM_PARTS * part_size as u64);
+    upload
+        .write(&mut runtime, &s3, &vec![0; MULTIPART_MINIMUM_PART_SIZE])
+        .unwrap()
+        .write(
+            &mut runtime,
+            &s3,
+            &vec![0; part_size - MULTIPART_MINIMUM_PART_SIZE],
+        )
+        .unwrap()
+        .write(&mut runtime, &s3, &vec![0; part_size])
+        .unwrap();
+
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::CreateMultipartUpload(_), Call::UploadPart(_, first), Call::UploadPart(_, second)] =>
+        {
+            assert_eq!(first.len(), part_size);
+            assert_eq!(second.len(), part_size);
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn empty_files_are_uploaded_by_default() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+
+    let finished = Upload::new("my-bucket", "my-file", Default::default())
+        .finish(&mut runtime, &s3)
+        .unwrap();
+
+    assert!(matches!(finished, Finished::Uploaded { .. }));
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::PutObject(put_object, body)] => {
+            assert_eq!(put_object.key, "my-file");
+            assert!(body.is_empty());
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn empty_files_are_skipped() {
+    use crate::object_store::mock::MockObjectStore;
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let options = Arc::new(UploadOptions {
+        skip_empty_files: true,
+        ..Default::default()
+    });
+
+    let finished = Upload::new("my-bucket", "my-file", options)
+        .write(&mut runtime, &s3, &[])
+        .unwrap()
+        .finish(&mut runtime, &s3)
+        .unwrap();
+
+    assert_eq!(finished, Finished::Skipped);
+    assert!(s3.calls().is_empty());
+}
+
+#[test]
+fn duplicate_content_is_copied() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let options = Arc::new(UploadOptions {
+        duplicate_content_index: Some(Arc::new(ContentIndex::default())),
+        ..Default::default()
+    });
+
+    for (key, data) in [
+        ("first", &b"content"[..]),
+        ("second", &b"other content"[..]),
+        ("third", &b"content"[..]),
+    ] {
+        Upload::new("my-bucket", key, Arc::clone(&options))
+            .write(&mut runtime, &s3, data)
+            .unwrap()
+            .finish(&mut runtime, &s3)
+            .unwrap();
+    }
+
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::PutObject(first, _), Call::PutObject(second, _), Call::CopyObject(third)] => {
+            assert_eq!(first.key, "first");
+            assert_eq!(second.key, "second");
+            assert_eq!(third.key, "third");
+            assert_eq!(third.copy_source, "my-bucket/first?versionId=version-1");
+            assert_eq!(third.copy_source_if_match.as_deref(), Some("\"etag-1\""));
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn overwritten_content_is_not_copied() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let options = Arc::new(UploadOptions {
+        duplicate_content_index: Some(Arc::new(ContentIndex::default())),
+        ..Default::default()
+    });
+
+    for (key, data) in [
+        ("first", &b"content"[..]),
+        ("first", &b"other content"[..]),
+        ("second", &b"content"[..]),
+    ] {
+        Upload::new("my-bucket", key, Arc::clone(&options))
+            .write(&mut runtime, &s3, data)
+            .unwrap()
+            .finish(&mut runtime, &s3)
+            .unwrap();
+    }
+
+    // The first object doesn't hold the content anymore, so it has to be uploaded again.
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::PutObject(..), Call::PutObject(..), Call::PutObject(second, body)] => {
+            assert_eq!(second.key, "second");
+            assert_eq!(body, b"content");
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn version_id_is_returned() {
+    use crate::object_store::mock::MockObjectStore;
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+
+    let regular = Upload::new("my-bucket", "regular", Default::default())
+        .write(&mut runtime, &s3, b"content")
+        .unwrap()
+        .finish(&mut runtime, &s3)
+        .unwrap();
+    assert_eq!(
+        regular,
+        Finished::Uploaded {
+            key: "regular".to_owned(),
+            version_id: Some("version-1".to_owned()),
+            e_tag: Some("\"etag-1\"".to_owned()),
+            sha256: None,
+        }
+    );
+
+    let multipart = Upload::new("my-bucket", "multipart", Default::default())
+        .write(&mut runtime, &s3, &vec![0; MULTIPART_MINIMUM_PART_SIZE])
+        .unwrap()
+        .finish(&mut runtime, &s3)
+        .unwrap();
+    assert_eq!(
+        multipart,
+        Finished::Uploaded {
+            key: "multipart".to_owned(),
+            version_id: Some("version-4".to_owned()),
+            e_tag: Some("\"etag-4\"".to_owned()),
+            sha256: None,
+        }
+    );
+}
+
+#[test]
+fn dry_run_only_checks_for_collisions() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let options = Arc::new(UploadOptions {
+        dry_run: true,
+        ..Default::default()
+    });
+
+    let finished = Upload::new("my-bucket", "my-file", options)
+        .write(&mut runtime, &s3, &vec![0; 2 * MULTIPART_MINIMUM_PART_SIZE])
+        .unwrap()
+        .finish(&mut runtime, &s3)
+        .unwrap();
+
+    assert_eq!(finished, Finished::Skipped);
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::HeadObject(head_object)] => {
+            assert_eq!(head_object.bucket, "my-bucket");
+            assert_eq!(head_object.key, "my-file");
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[cfg(test)]
+fn gunzip(body: &[u8]) -> Vec<u8> {
+    use std::io::Read;
+
+    let mut content = vec![];
+    flate2::read::GzDecoder::new(body)
+        .read_to_end(&mut content)
+        .unwrap();
+    content
+}
+
+#[test]
+fn only_files_from_minimum_size_are_compressed() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let options = Arc::new(UploadOptions {
+        compress_min_size: Some(10),
+        ..Default::default()
+    });
+    let mut upload = |content: &[u8]| {
+        let s3 = MockObjectStore::default();
+        Upload::new("my-bucket", "my-file", Arc::clone(&options))
+            .write(&mut runtime, &s3, content)
+            .unwrap()
+            .finish(&mut runtime, &s3)
+            .unwrap();
+        let calls = s3.calls();
+        match &calls[..] {
+            [Call::PutObject(put_object, body)] => {
+                (put_object.content_encoding.clone(), body.clone())
+            }
+            calls => panic!("unexpected calls: {:?}", calls),
+        }
+    };
+
+    assert_eq!((None, b"123456789".to_vec()), upload(b"123456789"));
+    let (content_encoding, body) = upload(b"1234567890");
+    assert_eq!(Some("gzip".to_owned()), content_encoding);
+    assert_eq!(b"1234567890".to_vec(), gunzip(&body));
+}
+
+#[test]
+fn multipart_uploads_are_compressed_once_minimum_size_is_reached() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let compress_min_size = 2 * MULTIPART_MINIMUM_PART_SIZE;
+    let options = Arc::new(UploadOptions {
+        compress_min_size: Some(compress_min_size as u64),
+        ..Default::default()
+    });
+
+    let upload = Upload::new("my-bucket", "my-file", options)
+        .write(&mut runtime, &s3, &vec![0; compress_min_size - 1])
+        .unwrap();
+    assert!(s3.calls().is_empty());
+    upload
+        .write(&mut runtime, &s3, &[0])
+        .unwrap()
+        .write(&mut runtime, &s3, &[0])
+        .unwrap()
+        .finish(&mut runtime, &s3)
+        .unwrap();
+
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::CreateMultipartUpload(create_multipart_upload), Call::UploadPart(_, body), Call::CompleteMultipartUpload(_)] =>
+        {
+            assert_eq!(
+                Some("gzip".to_owned()),
+                create_multipart_upload.content_encoding
+            );
+            assert_eq!(vec![0; compress_min_size + 1], gunzip(body));
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn flush_uploads_parts_only_from_minimum_part_size() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+
+    let upload = Upload::new(
+        "my-bucket",
+        "small-file",
+        Arc::new(UploadOptions::default()),
+    )
+    .write(&mut runtime, &s3, &vec![0; MULTIPART_MINIMUM_PART_SIZE - 1])
+    .unwrap()
+    .flush(&mut runtime, &s3)
+    .unwrap();
+    assert!(s3.calls().is_empty());
+    drop(upload);
+
+    let mut upload = Upload::new(
+        "my-bucket",
+        "large-file",
+        Arc::new(UploadOptions::default()),
+    );
+    upload.set_size_hint(100 * 1024 * 1024 * 1024);
+    upload
+        .write(&mut runtime, &s3, &vec![0; MULTIPART_MINIMUM_PART_SIZE + 1])
+        .unwrap()
+        .flush(&mut runtime, &s3)
+        .unwrap()
+        .write(&mut runtime, &s3, &[0])
+        .unwrap()
+        .flush(&mut runtime, &s3)
+        .unwrap()
+        .finish(&mut runtime, &s3)
+        .unwrap();
+
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::CreateMultipartUpload(_), Call::UploadPart(_, first), Call::UploadPart(_, last), Call::CompleteMultipartUpload(_)] =>
+        {
+            assert_eq!(MULTIPART_MINIMUM_PART_SIZE + 1, first.len());
+            assert_eq!(1, last.len());
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn multipart_uploads_are_paused_while_s3_is_unreachable() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let options = Arc::new(UploadOptions {
+        network_pause_timeout: Some(Duration::from_secs(60)),
+        retry: RetryPolicy {
+            base_delay: Duration::ZERO,
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+
+    s3.make_upload_parts_unreachable(3);
+    Upload::new("my-bucket", "my-file", options)
+        .write(&mut runtime, &s3, &vec![0; MULTIPART_MINIMUM_PART_SIZE])
+        .unwrap()
+        .finish(&mut runtime, &s3)
+        .unwrap();
+
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::CreateMultipartUpload(_), Call::UploadPart(_, body), Call::CompleteMultipartUpload(_)] =>
+        {
+            assert_eq!(MULTIPART_MINIMUM_PART_SIZE, body.len());
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn checksum_sidecar_is_written_after_the_object() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let options = Arc::new(UploadOptions {
+        checksum_sidecar: Some(ChecksumSidecar {
+            suffix: ".sha256".to_owned(),
+            on_failure: SidecarFailurePolicy::Fail,
+        }),
+        ..Default::default()
+    });
+
+    Upload::new("my-bucket", "dir/my-file", options)
+        .write(&mut runtime, &s3, b"content")
+        .unwrap()
+        .finish(&mut runtime, &s3)
+        .unwrap();
+
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::PutObject(object, _), Call::PutObject(sidecar, body)] => {
+            assert_eq!("dir/my-file", object.key);
+            assert_eq!("dir/my-file.sha256", sidecar.key);
+            assert_eq!(
+                "ed7002b439e9ac845f22357d822bac1444730fbdb6016d3ec9432297b9ec9f73  my-file\n",
+                String::from_utf8_lossy(body)
+            );
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn completion_is_retried_separately_from_parts() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let options = Arc::new(UploadOptions {
+        retry: RetryPolicy {
+            base_delay: Duration::ZERO,
+            part_max_attempts: Some(1),
+            completion_max_attempts: Some(3),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+
+    // The first attempt completes the upload, but its response is lost. The retry is rejected,
+    // since the upload doesn't exist anymore, which is recognized as success.
+    s3.lose_completion_responses(1);
+    let finished = Upload::new("my-bucket", "my-file", Arc::clone(&options))
+        .write(&mut runtime, &s3, &vec![0; MULTIPART_MINIMUM_PART_SIZE])
+        .unwrap()
+        .finish(&mut runtime, &s3)
+        .unwrap();
+    assert!(matches!(
+        finished,
+        Finished::Uploaded { e_tag: Some(_), .. }
+    ));
+    match &s3.calls()[..] {
+        [Call::CreateMultipartUpload(_), Call::UploadPart(..), Call::CompleteMultipartUpload(_), Call::CompleteMultipartUpload(_), Call::HeadObject(head_object)] =>
+        {
+            assert_eq!(Some(1), head_object.part_number);
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+
+    // Parts are not retried at all.
+    let s3 = MockObjectStore::default();
+    s3.make_upload_parts_unreachable(1);
+    assert!(Upload::new("my-bucket", "my-file", options)
+        .write(&mut runtime, &s3, &vec![0; MULTIPART_MINIMUM_PART_SIZE])
+        .is_err());
+}
+
+#[test]
+fn paused_multipart_uploads_are_aborted_after_timeout() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let options = Arc::new(UploadOptions {
+        network_pause_timeout: Some(Duration::ZERO),
+        ..Default::default()
+    });
+
+    s3.make_upload_parts_unreachable(1);
+    let result = Upload::new("my-bucket", "my-file", options).write(
+        &mut runtime,
+        &s3,
+        &vec![0; MULTIPART_MINIMUM_PART_SIZE],
+    );
+
+    assert!(result.is_err());
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::CreateMultipartUpload(_), Call::AbortMultipartUpload(_)] => {}
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn content_addressed_uploads_are_written_to_hash_key() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let options = Arc::new(UploadOptions {
+        content_addressed: true,
+        ..Default::default()
+    });
+    // SHA-256 of "content".
+    let hash = "ed7002b439e9ac845f22357d822bac1444730fbdb6016d3ec9432297b9ec9f73";
+
+    let finished = Upload::new("my-bucket", "prefix/my-file", Arc::clone(&options))
+        .write(&mut runtime, &s3, b"content")
+        .unwrap()
+        .finish(&mut runtime, &s3)
+        .unwrap();
+    assert!(
+        matches!(finished, Finished::Uploaded { key, .. } if key == format!("prefix/{}", hash))
+    );
+
+    Upload::new("my-bucket", "multipart", options)
+        .write(&mut runtime, &s3, &vec![0; MULTIPART_MINIMUM_PART_SIZE])
+        .unwrap()
+        .finish(&mut runtime, &s3)
+        .unwrap();
+
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::PutObject(put_object, _), Call::CreateMultipartUpload(create_multipart_upload), Call::UploadPart(..), Call::CompleteMultipartUpload(_), Call::CopyObject(copy_object), Call::DeleteObject(delete_object)] =>
+        {
+            assert_eq!(format!("prefix/{}", hash), put_object.key);
+            assert_eq!("multipart", create_multipart_upload.key);
+            assert_eq!("my-bucket/multipart", copy_object.copy_source);
+            assert_eq!(64, copy_object.key.len());
+            assert_eq!("multipart", delete_object.key);
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn sse_customer_key_is_sent_with_every_request() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let sse_customer_key =
+        SseCustomerKey::from_base64("MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=").unwrap();
+    let options = Arc::new(UploadOptions {
+        sse_customer_key: Some(sse_customer_key.clone()),
+        // Moving the multipart upload to its final key covers `CopyObject` as well.
+        content_addressed: true,
+        ..Default::default()
+    });
+
+    Upload::new("my-bucket", "my-file", Arc::clone(&options))
+        .write(&mut runtime, &s3, b"content")
+        .unwrap()
+        .finish(&mut runtime, &s3)
+        .unwrap();
+    Upload::new("my-bucket", "multipart", options)
+        .write(&mut runtime, &s3, &vec![0; MULTIPART_MINIMUM_PART_SIZE])
+        .unwrap()
+        .finish(&mut runtime, &s3)
+        .unwrap();
+
+    let expected = (
+        sse_customer_key.algorithm(),
+        sse_customer_key.key(),
+        sse_customer_key.key_md5(),
+    );
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::PutObject(put_object, _), Call::CreateMultipartUpload(create_multipart_upload), Call::UploadPart(upload_part, _), Call::CompleteMultipartUpload(_), Call::CopyObject(copy_object), Call::DeleteObject(_)] =>
+        {
+            assert_eq!(
+                expected,
+                (
+                    put_object.sse_customer_algorithm.clone(),
+                    put_object.sse_customer_key.clone(),
+                    put_object.sse_customer_key_md5.clone(),
+                )
+            );
+            assert_eq!(
+                expected,
+                (
+                    create_multipart_upload.sse_customer_algorithm.clone(),
+                    create_multipart_upload.sse_customer_key.clone(),
+                    create_multipart_upload.sse_customer_key_md5.clone(),
+                )
+            );
+            assert_eq!(
+                expected,
+                (
+                    upload_part.sse_customer_algorithm.clone(),
+                    upload_part.sse_customer_key.clone(),
+                    upload_part.sse_customer_key_md5.clone(),
+                )
+            );
+            assert_eq!(
+                expected,
+                (
+                    copy_object.sse_customer_algorithm.clone(),
+                    copy_object.sse_customer_key.clone(),
+                    copy_object.sse_customer_key_md5.clone(),
+                )
+            );
+            assert_eq!(
+                expected,
+                (
+                    copy_object.copy_source_sse_customer_algorithm.clone(),
+                    copy_object.copy_source_sse_customer_key.clone(),
+                    copy_object.copy_source_sse_customer_key_md5.clone(),
+                )
+            );
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn interrupted_writes_can_be_retried() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let options = Arc::new(UploadOptions {
+        interrupted_writes: InterruptedWrites::Restore,
+        ..Default::default()
+    });
+
+    let upload = Upload::new("my-bucket", "my-file", options)
+        .write(&mut runtime, &s3, b"head")
+        .unwrap();
+    s3.make_upload_parts_unreachable(1);
+    let data = vec![1; MULTIPART_MINIMUM_PART_SIZE];
+    let Interrupted { upload, error } = match upload.write(&mut runtime, &s3, &data) {
+        Ok(_) => panic!("the part upload should have failed"),
+        Err(interrupted) => interrupted,
+    };
+    assert!(format!("{:#}", error).contains("connection refused"));
+    assert_eq!(4, upload.buffered_len());
+    upload
+        .write(&mut runtime, &s3, &data)
+        .unwrap()
+        .finish(&mut runtime, &s3)
+        .unwrap();
+
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::CreateMultipartUpload(_), Call::UploadPart(_, body), Call::CompleteMultipartUpload(_)] =>
+        {
+            assert_eq!(&body[..4], b"head");
+            assert_eq!(&body[4..], &data[..]);
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn buffers_are_uploaded_once_the_memory_limit_is_reached() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let options = Arc::new(UploadOptions {
+        max_memory_per_file: Some(MULTIPART_MINIMUM_PART_SIZE),
+        ..Default::default()
+    });
+
+    let mut upload = Upload::new("my-bucket", "my-file", options);
+    upload.set_size_hint(4 * MULTIPART_MINIMUM_PART_SIZE as u64);
+    let upload = upload
+        .write(&mut runtime, &s3, &vec![0; MULTIPART_MINIMUM_PART_SIZE + 1])
+        .unwrap();
+    assert_eq!(0, upload.buffered_len());
+    upload
+        .write(&mut runtime, &s3, &[0])
+        .unwrap()
+        .finish(&mut runtime, &s3)
+        .unwrap();
+
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::CreateMultipartUpload(_), Call::UploadPart(_, first), Call::UploadPart(_, last), Call::CompleteMultipartUpload(_)] =>
+        {
+            assert_eq!(MULTIPART_MINIMUM_PART_SIZE + 1, first.len());
+            assert_eq!(1, last.len());
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn server_side_encryption_is_requested_for_every_upload() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let options = Arc::new(UploadOptions {
+        server_side_encryption: Some(ServerSideEncryption::AwsKms),
+        ssekms_key_id: Some("my-key".to_owned()),
+        ..Default::default()
+    });
+
+    Upload::new("my-bucket", "regular", Arc::clone(&options))
+        .write(&mut runtime, &s3, b"content")
+        .unwrap()
+        .finish(&mut runtime, &s3)
+        .unwrap();
+    Upload::new("my-bucket", "multipart", options)
+        .write(&mut runtime, &s3, &vec![0; MULTIPART_MINIMUM_PART_SIZE])
+        .unwrap()
+        .finish(&mut runtime, &s3)
+        .unwrap();
+
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::PutObject(put_object, _), Call::CreateMultipartUpload(create_multipart_upload), Call::UploadPart(..), Call::CompleteMultipartUpload(_)] =>
+        {
+            assert_eq!(
+                Some("aws:kms"),
+                put_object.server_side_encryption.as_deref()
+            );
+            assert_eq!(Some("my-key"), put_object.ssekms_key_id.as_deref());
+            assert_eq!(
+                Some("aws:kms"),
+                create_multipart_upload.server_side_encryption.as_deref()
+            );
+            assert_eq!(
+                Some("my-key"),
+                create_multipart_upload.ssekms_key_id.as_deref()
+            );
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn storage_class_is_requested_for_every_upload() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let options = Arc::new(UploadOptions {
+        storage_class: Some(StorageClass::StandardIa),
+        ..Default::default()
+    });
+
+    Upload::new("my-bucket", "regular", Arc::clone(&options))
+        .write(&mut runtime, &s3, b"content")
+        .unwrap()
+        .finish(&mut runtime, &s3)
+        .unwrap();
+    Upload::new("my-bucket", "multipart", options)
+        .write(&mut runtime, &s3, &vec![0; MULTIPART_MINIMUM_PART_SIZE])
+        .unwrap()
+        .finish(&mut runtime, &s3)
+        .unwrap();
+
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::PutObject(put_object, _), Call::CreateMultipartUpload(create_multipart_upload), Call::UploadPart(..), Call::CompleteMultipartUpload(_)] =>
+        {
+            assert_eq!(Some("STANDARD_IA"), put_object.storage_class.as_deref());
+            assert_eq!(
+                Some("STANDARD_IA"),
+                create_multipart_upload.storage_class.as_deref()
+            );
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn content_type_is_inferred_for_every_upload() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let options = Arc::new(UploadOptions::default());
+
+    Upload::new("my-bucket", "report.pdf", Arc::clone(&options))
+        .write(&mut runtime, &s3, b"content")
+        .unwrap()
+        .finish(&mut runtime, &s3)
+        .unwrap();
+    Upload::new("my-bucket", "video.mp4", Arc::clone(&options))
+        .write(&mut runtime, &s3, &vec![0; MULTIPART_MINIMUM_PART_SIZE])
+        .unwrap()
+        .finish(&mut runtime, &s3)
+        .unwrap();
+    Upload::new("my-bucket", "unknown", options)
+        .write(&mut runtime, &s3, b"content")
+        .unwrap()
+        .finish(&mut runtime, &s3)
+        .unwrap();
+
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::PutObject(put_object, _), Call::CreateMultipartUpload(create_multipart_upload), Call::UploadPart(..), Call::CompleteMultipartUpload(_), Call::PutObject(unknown, _)] =>
+        {
+            assert_eq!(Some("application/pdf"), put_object.content_type.as_deref());
+            assert_eq!(
+                Some("video/mp4"),
+                create_multipart_upload.content_type.as_deref()
+            );
+            assert_eq!(None, unknown.content_type);
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn spooled_buffers_are_uploaded_intact() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let spool = Arc::new(Spool::new(&std::env::temp_dir(), 1024, None, None).unwrap());
+    let options = Arc::new(UploadOptions {
+        spool: Some(spool),
+        ..Default::default()
+    });
+    let content = (0..MULTIPART_MINIMUM_PART_SIZE + 2048)
+        .map(|i| i as u8)
+        .collect::<Vec<_>>();
+
+    let mut upload = Upload::new("my-bucket", "my-file", options);
+    for chunk in content.chunks(4096) {
+        upload = upload.write(&mut runtime, &s3, chunk).unwrap();
+        assert!(upload.buffered_len() <= 1024);
+    }
+    upload.finish(&mut runtime, &s3).unwrap();
+
+    let calls = s3.calls();
+    match &calls[..] {
+        [Call::CreateMultipartUpload(_), Call::UploadPart(_, first), Call::UploadPart(_, last), Call::CompleteMultipartUpload(_)] =>
+        {
+            assert_eq!(content, [&first[..], &last[..]].concat());
+        }
+        calls => panic!("unexpected calls: {:?}", calls),
+    }
+}
+
+#[test]
+fn uploads_are_held_back_outside_the_upload_window() {
+    use crate::object_store::mock::MockObjectStore;
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let spool = Arc::new(Spool::new(&std::env::temp_dir(), 1024, None, None).unwrap());
+    let options = Arc::new(UploadOptions {
+        spool: Some(spool),
+        upload_window: Some(crate::upload_window::closed_for_an_hour()),
+        ..Default::default()
+    });
+
+    // Neither writing more than a part nor flushing blocks or uploads anything.
+    let mut upload = Upload::new("my-bucket", "my-file", options);
+    for chunk in vec![0; 3 * MULTIPART_MINIMUM_PART_SIZE].chunks(64 * 1024) {
+        upload = upload.write(&mut runtime, &s3, chunk).unwrap();
+    }
+    upload = upload.flush(&mut runtime, &s3).unwrap();
+    assert!(matches!(upload, Upload::Regular { .. }));
+    assert!(upload.buffered_len() <= 1024);
+    assert!(s3.calls().is_empty());
+}
+
+#[test]
+fn uploads_are_held_back_while_paused() {
+    use crate::object_store::mock::{
+        Call,
+        MockObjectStore,
+    };
+
+    let mut runtime = Runtime::new().unwrap();
+    let s3 = MockObjectStore::default();
+    let spool = Arc::new(Spool::new(&std::env::temp_dir(), 1024, None, None).unwrap());
+    let options = Arc::new(UploadOptions {
+        spool: Some(spool),
+        ..Default::default()
+    });
+
+    options.pause.pause();
+    let mut upload = Upload::new("my-bucket", "my-file", Arc::clone(&options));
+    for chunk in vec![0; 3 * MULTIPART_MINIMUM_PART_SIZE].chunks(64 * 1024) {
+        upload = upload.write(&mut runtime, &s3, chunk).unwrap();
+    }
+    assert!(s3.calls().is_empty());
+
+    // Once resumed, the held back data is uploaded in parts.
+    options.pause.resume();
+    upload.finish(&mut runtime, &s3).unwrap();
+    let parts = s3
+        .calls()
+        .iter()
+        .filter(|call| matches!(call, Call::UploadPart(..)))
+        .count();
+    assert_eq!(3, parts);
+}
@@ -0,0 +1,130 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::signals;
+use anyhow::Result;
+use libc::{
+    SIGCONT,
+    SIGUSR1,
+};
+use slog_scope::{
+    info,
+    warn,
+};
+use std::sync::{
+    Arc,
+    Condvar,
+    Mutex,
+    PoisonError,
+};
+
+/// Holds back all requests to S3 while paused, while the filesystem keeps accepting writes.
+///
+/// The data written in the meantime is held back in the spool, and released files are uploaded in
+/// the background once uploads are resumed, such that no request of the kernel waits for the
+/// pause.
+#[derive(Debug, Default)]
+pub(crate) struct UploadPause {
+    paused: Mutex<bool>,
+    resumed: Condvar,
+}
+
+impl UploadPause {
+    fn set_paused(&self, paused: bool) {
+        let mut current = self.paused.lock().unwrap_or_else(PoisonError::into_inner);
+        if *current == paused {
+            return;
+        }
+        *current = paused;
+        if paused {
+            info!("Paused uploads, no requests are made to S3 until resumed");
+        } else {
+            info!("Resumed uploads");
+            self.resumed.notify_all();
+        }
+    }
+
+    pub(crate) fn pause(&self) {
+        self.set_paused(true);
+    }
+
+    pub(crate) fn resume(&self) {
+        self.set_paused(false);
+    }
+
+    pub(crate) fn is_paused(&self) -> bool {
+        *self.paused.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Block until uploads are resumed, if they are paused.
+    pub(crate) fn wait(&self, key: &str) {
+        let mut paused = self.paused.lock().unwrap_or_else(PoisonError::into_inner);
+        if *paused {
+            info!("Holding upload of '{}' until uploads are resumed", key);
+        }
+        while *paused {
+            paused = self
+                .resumed
+                .wait(paused)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// Pause uploads whenever the process receives SIGUSR1, and resume them on SIGCONT.
+    ///
+    /// Unless `pausable`, i.e. there is no spool to hold back the data written while paused,
+    /// SIGUSR1 is ignored.
+    pub(crate) fn control_on_signal(upload_pause: &Arc<UploadPause>, pausable: bool) -> Result<()> {
+        let upload_pause = Arc::clone(upload_pause);
+        signals::spawn_handler("upload-pause", &[SIGUSR1, SIGCONT], move |signal| {
+            if signal == SIGUSR1 && !pausable {
+                warn!("Ignoring SIGUSR1, pausing uploads requires --spool-dir");
+            } else if signal == SIGUSR1 {
+                upload_pause.pause();
+            } else {
+                upload_pause.resume();
+            }
+        })
+    }
+}
+
+#[test]
+fn paused_uploads_wait_until_resumed() {
+    use std::{
+        sync::mpsc,
+        thread,
+        time::Duration,
+    };
+
+    let upload_pause = Arc::new(UploadPause::default());
+    // Not paused, so this returns right away.
+    upload_pause.wait("my-file");
+
+    upload_pause.pause();
+    let (sender, receiver) = mpsc::channel();
+    let waiting = {
+        let upload_pause = Arc::clone(&upload_pause);
+        thread::spawn(move || {
+            upload_pause.wait("my-file");
+            sender.send(()).unwrap();
+        })
+    };
+    assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+
+    upload_pause.resume();
+    receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    waiting.join().unwrap();
+}
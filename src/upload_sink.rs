@@ -0,0 +1,195 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{
+    bail,
+    Context,
+    Result,
+};
+use async_trait::async_trait;
+use hyper::{
+    client::HttpConnector,
+    header::CONTENT_TYPE,
+    Body,
+    Client,
+    Method,
+    Request,
+    Uri,
+};
+use hyper_tls::HttpsConnector;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use std::{
+    fmt,
+    time::Duration,
+};
+
+/// How long to wait for the upload router to respond.
+const ROUTER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The bucket and key of an object.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ObjectLocation {
+    pub(crate) bucket: String,
+    pub(crate) key: String,
+}
+
+/// Decides where a file is ultimately uploaded to, instead of the location derived from the mount.
+#[async_trait]
+pub(crate) trait UploadSink: fmt::Debug + Send + Sync {
+    /// Resolve the final location of a file that would otherwise be uploaded to `location`.
+    async fn resolve(&self, location: ObjectLocation) -> Result<ObjectLocation>;
+}
+
+/// Asks an HTTP endpoint where to upload every file.
+///
+/// The location derived from the mount is `POST`ed as `{"bucket": "...", "key": "..."}`, and the
+/// endpoint responds with the final location in the same format.
+#[derive(Debug)]
+pub(crate) struct HttpRouter {
+    client: Client<HttpsConnector<HttpConnector>>,
+    url: Uri,
+}
+
+impl HttpRouter {
+    pub(crate) fn new(url: Uri) -> Self {
+        HttpRouter {
+            client: Client::builder().build(HttpsConnector::new()),
+            url,
+        }
+    }
+
+    async fn request(&self, location: &ObjectLocation) -> Result<ObjectLocation> {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(self.url.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_vec(location)?))?;
+        let response = self.client.request(request).await?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        if !status.is_success() {
+            bail!(
+                "upload router responded with {}: {}",
+                status,
+                String::from_utf8_lossy(&body)
+            );
+        }
+        let destination: ObjectLocation =
+            serde_json::from_slice(&body).context("invalid response from upload router")?;
+        if destination.bucket.is_empty() || destination.key.is_empty() {
+            bail!("upload router responded with an empty bucket or key");
+        }
+
+        Ok(destination)
+    }
+}
+
+#[async_trait]
+impl UploadSink for HttpRouter {
+    async fn resolve(&self, location: ObjectLocation) -> Result<ObjectLocation> {
+        tokio::time::timeout(ROUTER_TIMEOUT, self.request(&location))
+            .await
+            .context("upload router did not respond in time")?
+            .with_context(|| {
+                format!(
+                    "failed to route upload of 's3://{}/{}'",
+                    location.bucket, location.key
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+fn serve_once(response: &'static str) -> (Uri, std::thread::JoinHandle<String>) {
+    use std::{
+        io::{
+            Read,
+            Write,
+        },
+        net::TcpListener,
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/route", listener.local_addr().unwrap())
+        .parse()
+        .unwrap();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = vec![0; 4096];
+        let mut length = 0;
+        // Read until the end of the JSON body, which is the end of the request.
+        while !request[..length].ends_with(b"}") {
+            length += stream.read(&mut request[length..]).unwrap();
+        }
+        stream.write_all(response.as_bytes()).unwrap();
+        String::from_utf8_lossy(&request[..length]).into_owned()
+    });
+
+    (url, server)
+}
+
+#[test]
+fn http_router_returns_final_location() {
+    let body = r#"{"bucket":"processed","key":"incoming/report.pdf"}"#;
+    let response = Box::leak(
+        format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .into_boxed_str(),
+    );
+    let (url, server) = serve_once(response);
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let destination = runtime
+        .block_on(HttpRouter::new(url).resolve(ObjectLocation {
+            bucket: "my-bucket".to_owned(),
+            key: "report.pdf".to_owned(),
+        }))
+        .unwrap();
+
+    assert_eq!(
+        ObjectLocation {
+            bucket: "processed".to_owned(),
+            key: "incoming/report.pdf".to_owned(),
+        },
+        destination
+    );
+    let request = server.join().unwrap();
+    assert!(request.starts_with("POST /route "));
+    assert!(request.ends_with(r#"{"bucket":"my-bucket","key":"report.pdf"}"#));
+}
+
+#[test]
+fn http_router_errors_are_reported() {
+    let (url, server) =
+        serve_once("HTTP/1.1 500 Internal Server Error\r\ncontent-length: 4\r\n\r\nfail");
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let error = runtime
+        .block_on(HttpRouter::new(url).resolve(ObjectLocation {
+            bucket: "my-bucket".to_owned(),
+            key: "report.pdf".to_owned(),
+        }))
+        .unwrap_err();
+
+    assert!(format!("{:#}", error).contains("500 Internal Server Error: fail"));
+    server.join().unwrap();
+}
@@ -0,0 +1,148 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{
+    anyhow,
+    bail,
+    Context,
+    Result,
+};
+use chrono::{
+    Local,
+    NaiveTime,
+};
+use slog_scope::info;
+use std::{
+    fmt,
+    str::FromStr,
+    time::Duration,
+};
+use tokio::runtime::Runtime;
+
+const TIME_FORMAT: &str = "%H:%M";
+
+/// A daily time window in local time, e.g. `22:00-06:00`, outside of which nothing is uploaded.
+///
+/// Windows that end before they start span midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct UploadWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl FromStr for UploadWindow {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| anyhow!("expected an upload window in the format HH:MM-HH:MM"))?;
+        let start = NaiveTime::parse_from_str(start, TIME_FORMAT)
+            .with_context(|| format!("invalid start of upload window '{}'", start))?;
+        let end = NaiveTime::parse_from_str(end, TIME_FORMAT)
+            .with_context(|| format!("invalid end of upload window '{}'", end))?;
+        if start == end {
+            bail!("the upload window must not be empty");
+        }
+
+        Ok(UploadWindow { start, end })
+    }
+}
+
+impl fmt::Display for UploadWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format(TIME_FORMAT),
+            self.end.format(TIME_FORMAT)
+        )
+    }
+}
+
+impl UploadWindow {
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+
+    /// How long it takes from `time` until the window opens, zero if it is open.
+    fn time_until_open(&self, time: NaiveTime) -> Duration {
+        if self.contains(time) {
+            return Duration::ZERO;
+        }
+        let mut until_open = self.start.signed_duration_since(time);
+        if until_open < chrono::Duration::zero() {
+            until_open += chrono::Duration::days(1);
+        }
+
+        until_open.to_std().unwrap_or_default()
+    }
+
+    /// Whether the window is open right now.
+    pub(crate) fn is_open(&self) -> bool {
+        self.contains(Local::now().time())
+    }
+
+    /// Block until the window is open, before uploading (part of) the object at `key`.
+    pub(crate) fn wait(&self, runtime: &Runtime, key: &str) {
+        let until_open = self.time_until_open(Local::now().time());
+        if until_open.is_zero() {
+            return;
+        }
+        info!("Deferring upload of '{}' until the upload window opens", key;
+              "upload_window" => %self, "wait" => ?until_open);
+        runtime.block_on(async { tokio::time::sleep(until_open).await });
+    }
+}
+
+/// A window that is closed for the next hour, no matter the time of day.
+#[cfg(test)]
+pub(crate) fn closed_for_an_hour() -> UploadWindow {
+    let now = Local::now().time();
+    UploadWindow {
+        start: now + chrono::Duration::hours(1),
+        end: now + chrono::Duration::hours(2),
+    }
+}
+
+#[test]
+fn upload_window_is_parsed() {
+    let window: UploadWindow = "22:00-06:30".parse().unwrap();
+    assert_eq!("22:00-06:30", window.to_string());
+    assert!("22:00".parse::<UploadWindow>().is_err());
+    assert!("25:00-06:00".parse::<UploadWindow>().is_err());
+    assert!("06:00-06:00".parse::<UploadWindow>().is_err());
+}
+
+#[test]
+fn time_until_window_opens() {
+    let time = |time: &str| NaiveTime::parse_from_str(time, TIME_FORMAT).unwrap();
+    let hours = |hours: u64| Duration::from_secs(hours * 60 * 60);
+
+    let day: UploadWindow = "08:00-18:00".parse().unwrap();
+    assert_eq!(Duration::ZERO, day.time_until_open(time("08:00")));
+    assert_eq!(hours(1), day.time_until_open(time("07:00")));
+    assert_eq!(hours(14), day.time_until_open(time("18:00")));
+
+    let night: UploadWindow = "22:00-06:00".parse().unwrap();
+    assert_eq!(Duration::ZERO, night.time_until_open(time("23:00")));
+    assert_eq!(Duration::ZERO, night.time_until_open(time("05:59")));
+    assert_eq!(hours(16), night.time_until_open(time("06:00")));
+}
@@ -0,0 +1,127 @@
+// Copyright 2025 TAKKT Industrial & Packaging GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use clap::ValueEnum;
+
+/// Device names that Windows reserves in every directory, regardless of the extension.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// How to handle file names that can't be represented on Windows, i.e. reserved device names and
+/// names with trailing dots or spaces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum WindowsSafeKeys {
+    /// Refuse to create the file.
+    #[value(name = "reject")]
+    Reject,
+    /// Rewrite the name, e.g. `CON.txt` to `CON_.txt` and `report.` to `report`.
+    #[value(name = "rewrite")]
+    Rewrite,
+}
+
+impl WindowsSafeKeys {
+    /// Apply the policy to a file name, returning `None` if it has to be rejected.
+    pub(crate) fn apply(&self, name: &str) -> Option<String> {
+        match self {
+            Self::Reject if is_windows_safe(name) => Some(name.to_owned()),
+            Self::Reject => None,
+            Self::Rewrite => Some(rewrite(name)),
+        }
+    }
+}
+
+/// The part of the name that Windows compares against the reserved device names, i.e. everything
+/// before the first dot.
+fn stem(name: &str) -> &str {
+    name.split('.').next().unwrap_or(name)
+}
+
+fn is_reserved(name: &str) -> bool {
+    let stem = stem(name).trim_end_matches(' ');
+    RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+}
+
+fn is_windows_safe(name: &str) -> bool {
+    !is_reserved(name) && !name.ends_with(['.', ' '])
+}
+
+fn rewrite(name: &str) -> String {
+    let mut name = name.trim_end_matches(['.', ' ']).to_owned();
+    if name.is_empty() {
+        return "_".to_owned();
+    }
+    if is_reserved(&name) {
+        let stem_length = stem(&name).len();
+        name.insert(stem_length, '_');
+    }
+    name
+}
+
+#[test]
+fn regular_names_are_accepted() {
+    for name in [
+        "report.csv",
+        "CONTENT.txt",
+        "console.log",
+        "LPT10",
+        "a.CON",
+        ".hidden",
+    ] {
+        assert_eq!(Some(name.to_owned()), WindowsSafeKeys::Reject.apply(name));
+        assert_eq!(Some(name.to_owned()), WindowsSafeKeys::Rewrite.apply(name));
+    }
+}
+
+#[test]
+fn reserved_names_are_rejected_or_rewritten() {
+    for (name, rewritten) in [
+        ("CON", "CON_"),
+        ("con", "con_"),
+        ("Prn.txt", "Prn_.txt"),
+        ("AUX.tar.gz", "AUX_.tar.gz"),
+        ("NUL", "NUL_"),
+        ("COM1", "COM1_"),
+        ("lpt9.log", "lpt9_.log"),
+        ("CON .txt", "CON _.txt"),
+    ] {
+        assert_eq!(None, WindowsSafeKeys::Reject.apply(name));
+        assert_eq!(
+            Some(rewritten.to_owned()),
+            WindowsSafeKeys::Rewrite.apply(name)
+        );
+    }
+}
+
+#[test]
+fn trailing_dots_and_spaces_are_rejected_or_removed() {
+    for (name, rewritten) in [
+        ("report.", "report"),
+        ("report ", "report"),
+        ("report. . ", "report"),
+        ("CON.", "CON_"),
+        ("...", "_"),
+    ] {
+        assert_eq!(None, WindowsSafeKeys::Reject.apply(name));
+        assert_eq!(
+            Some(rewritten.to_owned()),
+            WindowsSafeKeys::Rewrite.apply(name)
+        );
+    }
+}